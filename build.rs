@@ -1,11 +1,42 @@
 extern crate rustc_version;
 
+use std::env;
+
 fn main() {
     check_rustc_version();
+    emit_lua_version();
+    emit_luajit();
 }
 
 fn check_rustc_version() {
+    println!("cargo:rustc-check-cfg=cfg(rust_nightly)");
     if let rustc_version::Channel::Nightly = rustc_version::version_meta().unwrap().channel {
         println!("cargo:rustc-cfg=rust_nightly");
     }
 }
+
+/// Forwards the Lua version selected by lua-sys,
+/// emits LUA_VERSION with values from 5.0 to the actual version, like lua-sys does.
+fn emit_lua_version() {
+    println!(
+        "cargo:rustc-check-cfg=cfg(LUA_VERSION, values(\"5.0\", \"5.1\", \"5.2\", \"5.3\", \"5.4\"))"
+    );
+    let version = env::var("DEP_LUA_VERSION").unwrap_or_else(|_| "5.3".to_owned());
+    let minor: u32 = version
+        .split('.')
+        .nth(1)
+        .and_then(|minor| minor.parse().ok())
+        .expect("invalid DEP_LUA_VERSION");
+
+    for m in 0..=minor {
+        println!("cargo:rustc-cfg=LUA_VERSION=\"5.{}\"", m);
+    }
+}
+
+/// Forwards whether lua-sys links against LuaJIT.
+fn emit_luajit() {
+    println!("cargo:rustc-check-cfg=cfg(LUAJIT)");
+    if env::var_os("DEP_LUA_LUAJIT").is_some() {
+        println!("cargo:rustc-cfg=LUAJIT");
+    }
+}
@@ -57,6 +57,7 @@ pub fn configure(mut config: LuaConfig) {
     emit_lua_version(&mut config);
 }
 
+/// Version of the embedded Lua used by default.
 const EMBEDDED_VERSION: (u32, u32, u32) = (5, 3, 5);
 
 fn emit_lua_version(config: &mut LuaConfig) {
@@ -66,30 +67,38 @@ fn emit_lua_version(config: &mut LuaConfig) {
     use std::path::Path;
 
     let path = Path::new(&env::var("OUT_DIR").unwrap()).join("lua_version.rs");
-    let version_str = config.env("LUA_VERSION").or_else(|| config.version.clone());
-
-    let (major, minor, patch) = match version_str {
-        Some(v) => {
+    // the embedded release is set by use_embedded_lua
+    #[cfg(not(feature = "system-lua"))]
+    let (major, minor, patch) = {
+        let embedded = config
+            .version
+            .as_ref()
+            .map_or(EMBEDDED_VERSION, |v| parse_lua_version(v));
+        if let Some(v) = config.env("LUA_VERSION") {
             let version = parse_lua_version(&v);
-            #[cfg(feature = "system-lua")]
-            {
+            if version != embedded {
                 println!(
                     "cargo:warning=Tried to override LUA_VERSION of embedded Lua from {}.{}.{} to {}.{}.{}",
-                    EMBEDDED_VERSION.0,
-                    EMBEDDED_VERSION.1,
-                    EMBEDDED_VERSION.2,
+                    embedded.0,
+                    embedded.1,
+                    embedded.2,
                     version.0,
                     version.1,
                     version.2,
                 );
-                EMBEDDED_VERSION
-            }
-            #[cfg(not(feature = "system-lua"))]
-            {
-                println!("Lua version: {}.{}.{}", version.0, version.1, version.2);
-                version
             }
         }
+        embedded
+    };
+    #[cfg(feature = "system-lua")]
+    let version_str = config.env("LUA_VERSION").or_else(|| config.version.clone());
+    #[cfg(feature = "system-lua")]
+    let (major, minor, patch) = match version_str {
+        Some(v) => {
+            let version = parse_lua_version(&v);
+            println!("Lua version: {}.{}.{}", version.0, version.1, version.2);
+            version
+        }
         None => EMBEDDED_VERSION,
     };
 
@@ -101,6 +110,8 @@ fn emit_lua_version(config: &mut LuaConfig) {
     for m in 0..=minor {
         println!("cargo:rustc-cfg=LUA_VERSION=\"{}.{}\"", major, m);
     }
+    // exposed to dependents as DEP_LUA_VERSION
+    println!("cargo:version={}.{}", major, minor);
 
     let mut out =
         BufWriter::new(File::create(&path).expect(&format!("Could not create {}", path.display())));
@@ -159,13 +170,13 @@ fn parse_lua_version(version: &str) -> (u32, u32, u32) {
 pub struct LuaConfig<'a> {
     build: &'a mut cc::Build,
     prefix: Option<String>,
-    version: Option<String>,
+    pub version: Option<String>,
 }
 
 #[cfg(feature = "system-lua")]
 pub struct LuaConfig<'a> {
     prefix: Option<String>,
-    version: Option<String>,
+    pub version: Option<String>,
     _marker: ::std::marker::PhantomData<&'a ()>,
 }
 
@@ -13,8 +13,20 @@ mod luaconf;
 
 use luaconf::LuaConfig;
 
+/// Features selecting the Lua version, with the versions they select.
+/// LuaJIT implements the API of Lua 5.1.
+const VERSION_FEATURES: [(&str, (u32, u32)); 5] = [
+    ("LUA51", (5, 1)),
+    ("LUA52", (5, 2)),
+    ("LUA53", (5, 3)),
+    ("LUA54", (5, 4)),
+    ("LUAJIT", (5, 1)),
+];
+
 fn main() {
     println!("cargo:rustc-cfg=lua_64_bits");
+    // set by use_system_lua when linking against LuaJIT
+    println!("cargo:rustc-check-cfg=cfg(LUAJIT)");
     check_rustc_version();
     println!("cargo:rerun-if-changed=src");
 
@@ -30,23 +42,113 @@ fn check_rustc_version() {
     }
 }
 
+/// Returns the version selected by the `lua51`, `lua52`, `lua53`, `lua54` and `luajit` features.
+///
+/// panics if more than one of them is enabled.
+fn selected_version() -> Option<(u32, u32)> {
+    let mut selected = VERSION_FEATURES
+        .iter()
+        .filter(|(feature, _)| std::env::var_os(format!("CARGO_FEATURE_{}", feature)).is_some())
+        .map(|&(_, version)| version);
+    let version = selected.next();
+    if selected.next().is_some() {
+        panic!("only one of the features lua51, lua52, lua53, lua54 and luajit can be enabled");
+    }
+    version
+}
+
+/// C sources of every embedded Lua release.
 #[cfg(not(feature = "system-lua"))]
-macro_rules! add_lua_sources {
-    ($cfg:ident, $root:expr, [$($file:expr),*]) => {
-        $($cfg.file(::std::path::Path::new($root).join($file)));*
-    };
+const CORE_SOURCES: [&str; 29] = [
+    "lapi.c",
+    "lauxlib.c",
+    "lbaselib.c",
+    "lcode.c",
+    "ldblib.c",
+    "ldebug.c",
+    "ldo.c",
+    "ldump.c",
+    "lfunc.c",
+    "lgc.c",
+    "linit.c",
+    "liolib.c",
+    "llex.c",
+    "lmathlib.c",
+    "lmem.c",
+    "loadlib.c",
+    "lobject.c",
+    "lopcodes.c",
+    "loslib.c",
+    "lparser.c",
+    "lstate.c",
+    "lstring.c",
+    "lstrlib.c",
+    "ltable.c",
+    "ltablib.c",
+    "ltm.c",
+    "lundump.c",
+    "lvm.c",
+    "lzio.c",
+];
+
+/// An embedded Lua release.
+#[cfg(not(feature = "system-lua"))]
+struct Embedded {
+    dir: &'static str,
+    release: &'static str,
+    /// Sources in addition to `CORE_SOURCES`.
+    sources: &'static [&'static str],
+}
+
+/// Returns the embedded release of Lua `version`, if any.
+#[cfg(not(feature = "system-lua"))]
+fn embedded(version: (u32, u32)) -> Option<Embedded> {
+    match version {
+        (5, 1) => Some(Embedded {
+            dir: "embedded-5.1",
+            release: "5.1.5",
+            sources: &[],
+        }),
+        (5, 2) => Some(Embedded {
+            dir: "embedded-5.2",
+            release: "5.2.4",
+            sources: &["lbitlib.c", "lcorolib.c", "lctype.c"],
+        }),
+        (5, 3) => Some(Embedded {
+            dir: "embedded",
+            release: "5.3.5",
+            sources: &["lbitlib.c", "lcorolib.c", "lctype.c", "lutf8lib.c"],
+        }),
+        (5, 4) => Some(Embedded {
+            dir: "embedded-5.4",
+            release: "5.4.7",
+            sources: &["lcorolib.c", "lctype.c", "lutf8lib.c"],
+        }),
+        _ => None,
+    }
 }
 
 #[cfg(not(feature = "system-lua"))]
 fn use_embedded_lua() {
     use std::env;
+    use std::path::Path;
 
     let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap_or("".to_string());
     let target_family = env::var("CARGO_CFG_TARGET_FAMILY").unwrap_or("".to_string());
 
+    let (major, minor) = selected_version().unwrap_or((5, 3));
+    let lua = embedded((major, minor)).unwrap_or_else(|| {
+        panic!(
+            "Lua {}.{} is not embedded, enable the system-lua feature to use it",
+            major, minor
+        )
+    });
+
     let mut cc_config = cc::Build::new();
 
-    luaconf::configure(LuaConfig::new(&mut cc_config));
+    let mut config = LuaConfig::new(&mut cc_config);
+    config.set_version(lua.release);
+    luaconf::configure(config);
     if let Some(define) = match (target_os.as_str(), target_family.as_str()) {
         ("linux", _) => Some("LUA_USE_LINUX"),
         ("macos", _) => Some("LUA_USE_MACOSX"),
@@ -61,79 +163,68 @@ fn use_embedded_lua() {
         cc_config.define("LUA_USE_API_CHECK", None);
     }
 
-    cc_config.include("embedded");
-    add_lua_sources!(
-        cc_config,
-        "embedded",
-        [
-            "lapi.c",
-            "lauxlib.c",
-            "lbaselib.c",
-            "lbitlib.c",
-            "lcode.c",
-            "lcorolib.c",
-            "lctype.c",
-            "ldblib.c",
-            "ldebug.c",
-            "ldo.c",
-            "ldump.c",
-            "lfunc.c",
-            "lgc.c",
-            "linit.c",
-            "liolib.c",
-            "llex.c",
-            "lmathlib.c",
-            "lmem.c",
-            "loadlib.c",
-            "lobject.c",
-            "lopcodes.c",
-            "loslib.c",
-            "lparser.c",
-            "lstate.c",
-            "lstring.c",
-            "lstrlib.c",
-            "ltable.c",
-            "ltablib.c",
-            "ltm.c",
-            "lundump.c",
-            "lutf8lib.c",
-            "lvm.c",
-            "lzio.c"
-        ]
-    );
-
-    cc_config.compile("liblua5.3.a");
+    cc_config.include(lua.dir);
+    for file in CORE_SOURCES.iter().chain(lua.sources) {
+        cc_config.file(Path::new(lua.dir).join(file));
+    }
+
+    cc_config.compile(&format!("liblua{}.{}.a", major, minor));
 }
 
 #[cfg(feature = "system-lua")]
 fn use_system_lua() {
     let mut config = LuaConfig::new();
+    let selected = selected_version();
+
+    if cfg!(feature = "luajit") {
+        if !find_vcpkg("luajit") {
+            find_pkg_config(&mut config, &["luajit".to_owned()], None);
+        }
+        // the version of LuaJIT is not the version of the Lua API it implements
+        config.set_version("5.1.0");
+        println!("cargo:rustc-cfg=LUAJIT");
+        // exposed to dependents as DEP_LUA_LUAJIT
+        println!("cargo:luajit=true");
+        luaconf::configure(config);
+        return;
+    }
 
-    if !find_vcpkg() {
-        find_pkg_config(&mut config);
+    let candidates: Vec<String> = match selected {
+        Some((major, minor)) => vec![
+            format!("lua{}.{}", major, minor),
+            format!("lua-{}.{}", major, minor),
+            format!("lua{}{}", major, minor),
+        ],
+        None => ["lua5.3", "lua5.2", "lua5.1", "lua"]
+            .iter()
+            .map(|&candidate| candidate.to_owned())
+            .collect(),
+    };
+    if !find_vcpkg("lua") {
+        find_pkg_config(&mut config, &candidates, selected);
+    }
+    if let (None, Some((major, minor))) = (&config.version, selected) {
+        config.set_version(&format!("{}.{}.0", major, minor));
     }
     luaconf::configure(config);
 }
 
-/// Attempts to find the Lua package with vcpkg.
-///
-/// panics if the package was not found.
+/// Attempts to find the `package` with vcpkg.
 #[cfg(all(target_env = "msvc", feature = "system-lua",))]
-fn find_vcpkg() -> bool {
-    vcpkg::Config::new().probe("lua").is_ok()
+fn find_vcpkg(package: &str) -> bool {
+    vcpkg::Config::new().probe(package).is_ok()
 }
 #[cfg(all(not(target_env = "msvc"), feature = "system-lua",))]
-fn find_vcpkg() -> bool {
+fn find_vcpkg(_package: &str) -> bool {
     false
 }
 
-/// Attempts to find the Lua package using pkg-config.
+/// Attempts to find the Lua package using pkg-config, trying each of the `candidates`.
+/// If a version is `selected`, the package found must be of that version.
 ///
 /// panics if the package was not found.
 #[cfg(feature = "system-lua")]
-fn find_pkg_config(config: &mut LuaConfig) {
-    let candidates = ["lua5.3", "lua5.2", "lua5.1", "lua"];
-
+fn find_pkg_config(config: &mut LuaConfig, candidates: &[String], selected: Option<(u32, u32)>) {
     match candidates.iter().try_fold(None, |_, candidate| {
         match pkg_config::Config::new().probe(candidate) {
             Ok(lib) => Err(lib),
@@ -142,6 +233,14 @@ fn find_pkg_config(config: &mut LuaConfig) {
     }) {
         Ok(err) => panic!("pkg-config did not find the lua package: {}", err.unwrap()),
         Err(lib) => {
+            if let Some((major, minor)) = selected {
+                if !lib.version.starts_with(&format!("{}.{}", major, minor)) {
+                    panic!(
+                        "pkg-config found Lua {} instead of Lua {}.{}",
+                        lib.version, major, minor
+                    );
+                }
+            }
             if !lib.version.is_empty() {
                 config.set_version(&lib.version);
             }
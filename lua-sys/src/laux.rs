@@ -2,6 +2,7 @@
 
 use crate::*;
 use core::fmt;
+#[cfg(LUA_VERSION = "5.2")]
 use core::mem;
 use core::ptr;
 
@@ -11,13 +12,12 @@ use core::ptr;
 
 pub const LUA_ERRFILE: libc::c_int = LUA_ERRERR + 1;
 
-cfg_if::cfg_if! {
-    if #[cfg(LUA_VERSION = "5.3")] {
-        pub const LUA_LOADED_TABLE: &str = "_LOADED";
+// Named in Lua 5.3, the registry key is the same in earlier versions
+pub const LUA_LOADED_TABLE: &str = "_LOADED";
 
-        pub const LUA_PRELOAD_TABLE: &str = "_PRELOAD";
-    }
-}
+// Introduced in Lua 5.2
+#[cfg(LUA_VERSION = "5.2")]
+pub const LUA_PRELOAD_TABLE: &str = "_PRELOAD";
 
 pub const LUA_NOREF: libc::c_int = -2;
 pub const LUA_REFNIL: libc::c_int = -1;
@@ -100,6 +100,15 @@ struct luaL_Stream {
 // //////////////////////////////////////////// //
 
 extern "C" {
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn luaL_addgsub(
+        B: *mut luaL_Buffer,
+        s: *const libc::c_char,
+        p: *const libc::c_char,
+        r: *const libc::c_char,
+    );
+
     pub fn luaL_addlstring(B: *mut luaL_Buffer, s: *const libc::c_char, l: usize);
     pub fn luaL_addstring(B: *mut luaL_Buffer, s: *const libc::c_char);
     pub fn luaL_addvalue(B: *mut luaL_Buffer);
@@ -148,7 +157,12 @@ extern "C" {
     ))]
     pub fn luaL_checkunsigned(L: *mut lua_State, arg: libc::c_int) -> lua_Unsigned;
 
+    // Introduced in Lua 5.2, checks the sizes of numbers as of Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     fn luaL_checkversion_(L: *mut lua_State, ver: lua_Number, sz: usize);
+    #[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.3")))]
+    fn luaL_checkversion_(L: *mut lua_State, ver: lua_Number);
+
     pub fn luaL_error(L: *mut lua_State, fmt: *const libc::c_char, ...) -> !;
 
     // Introduced in Lua 5.2
@@ -163,6 +177,8 @@ extern "C" {
         fname: *const libc::c_char,
     ) -> libc::c_int;
 
+    // Returns the type of the field as of Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn luaL_getmetafield(
         L: *mut lua_State,
         obj: libc::c_int,
@@ -283,13 +299,21 @@ extern "C" {
         l: *const luaL_Reg,
     ) -> libc::c_int;
 
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn luaL_requiref(
         L: *mut lua_State,
         modname: *const libc::c_char,
         openf: lua_CFunction,
         glb: libc::c_int,
     );
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn luaL_setfuncs(L: *mut lua_State, l: *const luaL_Reg, nup: libc::c_int);
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn luaL_setmetatable(L: *mut lua_State, tname: *const libc::c_char);
 
     // Deprecated in Lua 5.1
@@ -299,12 +323,15 @@ extern "C" {
     ))]
     pub fn luaL_setn(L: *mut lua_State, t: libc::c_int, n: libc::c_int);
 
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn luaL_testudata(
         L: *mut lua_State,
         ud: libc::c_int,
         tname: *const libc::c_char,
     ) -> *mut libc::c_void;
 
+    // Introduced in Lua 5.2
     #[cfg(LUA_VERSION = "5.2")]
     pub fn luaL_tolstring(
         L: *mut lua_State,
@@ -312,6 +339,8 @@ extern "C" {
         len: *mut usize,
     ) -> *const libc::c_char;
 
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn luaL_traceback(
         L: *mut lua_State,
         L1: *mut lua_State,
@@ -319,6 +348,10 @@ extern "C" {
         level: libc::c_int,
     );
 
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn luaL_typeerror(L: *mut lua_State, arg: libc::c_int, tname: *const libc::c_char) -> !;
+
     // Removed in Lua 5.2
     #[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
     pub fn luaL_typerror(L: *mut lua_State, narg: libc::c_int, tname: *const libc::c_char) -> !;
@@ -327,6 +360,179 @@ extern "C" {
     pub fn luaL_where(L: *mut lua_State, lvl: libc::c_int);
 }
 
+// Returns nothing before Lua 5.3, wrapped below to return the type of the field
+#[cfg(not(LUA_VERSION = "5.3"))]
+mod untyped {
+    use crate::*;
+
+    extern "C" {
+        pub fn luaL_getmetafield(
+            L: *mut lua_State,
+            obj: libc::c_int,
+            e: *const libc::c_char,
+        ) -> libc::c_int;
+    }
+}
+
+// //////////////////////////////////////////// //
+// Compatibility with Lua 5.3                   //
+// //////////////////////////////////////////// //
+
+// Returns the type of the field as of Lua 5.3
+#[inline]
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn luaL_getmetafield(
+    L: *mut lua_State,
+    obj: libc::c_int,
+    e: *const libc::c_char,
+) -> libc::c_int {
+    if untyped::luaL_getmetafield(L, obj, e) != 0 {
+        lua_type(L, -1)
+    } else {
+        LUA_TNIL
+    }
+}
+
+// Introduced in Lua 5.2
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn luaL_getsubtable(
+    L: *mut lua_State,
+    idx: libc::c_int,
+    fname: *const libc::c_char,
+) -> libc::c_int {
+    let idx = lua_absindex(L, idx);
+    if lua_getfield(L, idx, fname) == LUA_TTABLE {
+        return 1;
+    }
+    lua_pop(L, 1);
+    lua_newtable(L);
+    lua_pushvalue(L, -1);
+    lua_setfield(L, idx, fname);
+    0
+}
+
+// Introduced in Lua 5.2
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn luaL_loadbufferx(
+    L: *mut lua_State,
+    buff: *const libc::c_char,
+    sz: usize,
+    name: *const libc::c_char,
+    mode: *const libc::c_char,
+) -> libc::c_int {
+    // binary chunks start with the escape character of LUA_SIGNATURE
+    let (kind, modename): (u8, &[u8]) = if sz > 0 && *buff == 0x1b {
+        (b'b', b"binary\0")
+    } else {
+        (b't', b"text\0")
+    };
+    if !mode.is_null() && libc::strchr(mode, kind as libc::c_int).is_null() {
+        lua_pushfstring(
+            L,
+            b"attempt to load a %s chunk (mode is '%s')\0".as_ptr() as *const libc::c_char,
+            modename.as_ptr() as *const libc::c_char,
+            mode,
+        );
+        return LUA_ERRSYNTAX;
+    }
+    luaL_loadbuffer(L, buff, sz, name)
+}
+
+// Introduced in Lua 5.2
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn luaL_requiref(
+    L: *mut lua_State,
+    modname: *const libc::c_char,
+    openf: lua_CFunction,
+    glb: libc::c_int,
+) {
+    luaL_checkstack(L, 3, b"not enough stack slots\0".as_ptr() as *const libc::c_char);
+    luaL_getsubtable(L, LUA_REGISTRYINDEX, b"_LOADED\0".as_ptr() as *const libc::c_char);
+    if lua_getfield(L, -1, modname) == LUA_TNIL {
+        lua_pop(L, 1);
+        lua_pushcfunction(L, openf);
+        lua_pushstring(L, modname);
+        lua_call(L, 1, 1);
+        lua_pushvalue(L, -1);
+        lua_setfield(L, -3, modname);
+    }
+    if glb != 0 {
+        lua_pushvalue(L, -1);
+        lua_setglobal(L, modname);
+    }
+    lua_replace(L, -2);
+}
+
+// Introduced in Lua 5.2
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn luaL_testudata(
+    L: *mut lua_State,
+    ud: libc::c_int,
+    tname: *const libc::c_char,
+) -> *mut libc::c_void {
+    let p = lua_touserdata(L, ud);
+    if !p.is_null() && lua_getmetatable(L, ud) != 0 {
+        luaL_getmetatable(L, tname);
+        let matches = lua_rawequal(L, -1, -2) != 0;
+        lua_pop(L, 2);
+        if matches {
+            return p;
+        }
+    }
+    ptr::null_mut()
+}
+
+// Introduced in Lua 5.2
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn luaL_tolstring(
+    L: *mut lua_State,
+    idx: libc::c_int,
+    len: *mut usize,
+) -> *const libc::c_char {
+    let idx = lua_absindex(L, idx);
+    if luaL_callmeta(L, idx, b"__tostring\0".as_ptr() as *const libc::c_char) != 0 {
+        if lua_isstring(L, -1) == 0 {
+            luaL_error(
+                L,
+                b"'__tostring' must return a string\0".as_ptr() as *const libc::c_char,
+            );
+        }
+    } else {
+        match lua_type(L, idx) {
+            LUA_TNIL => {
+                lua_pushstring(L, b"nil\0".as_ptr() as *const libc::c_char);
+            }
+            LUA_TNUMBER | LUA_TSTRING => lua_pushvalue(L, idx),
+            LUA_TBOOLEAN => {
+                let s: &[u8] = if lua_toboolean(L, idx) != 0 {
+                    b"true\0"
+                } else {
+                    b"false\0"
+                };
+                lua_pushstring(L, s.as_ptr() as *const libc::c_char);
+            }
+            tp => {
+                let field = luaL_getmetafield(L, idx, b"__name\0".as_ptr() as *const libc::c_char);
+                let name = if field == LUA_TSTRING {
+                    lua_tostring(L, -1)
+                } else {
+                    lua_typename(L, tp)
+                };
+                lua_pushfstring(
+                    L,
+                    b"%s: %p\0".as_ptr() as *const libc::c_char,
+                    name,
+                    lua_topointer(L, idx),
+                );
+                if field != LUA_TNIL {
+                    lua_remove(L, -2);
+                }
+            }
+        }
+    }
+    lua_tolstring(L, -1, len)
+}
+
 // //////////////////////////////////////////// //
 // Macros (represented as inline functions)     //
 // //////////////////////////////////////////// //
@@ -413,6 +619,7 @@ pub unsafe fn luaL_checkstring(L: *mut lua_State, arg: libc::c_int) {
 }
 
 #[inline]
+#[cfg(LUA_VERSION = "5.3")]
 pub unsafe fn luaL_checkversion(L: *mut lua_State) {
     luaL_checkversion_(
         L,
@@ -420,6 +627,15 @@ pub unsafe fn luaL_checkversion(L: *mut lua_State) {
         mem::size_of::<lua_Integer>() * 16 + mem::size_of::<lua_Number>(),
     );
 }
+#[inline]
+#[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.3")))]
+pub unsafe fn luaL_checkversion(L: *mut lua_State) {
+    luaL_checkversion_(L, LUA_VERSION_NUM);
+}
+// Introduced in Lua 5.2, nothing to check before
+#[inline]
+#[cfg(not(LUA_VERSION = "5.2"))]
+pub unsafe fn luaL_checkversion(_L: *mut lua_State) {}
 
 #[inline]
 pub unsafe fn luaL_dofile(L: *mut lua_State, filename: *const libc::c_char) -> libc::c_int {
@@ -1,5 +1,7 @@
 use crate::*;
 
+// Used by LUAL_BUFFERSIZE since Lua 5.3
+#[cfg(LUA_VERSION = "5.3")]
 use core::mem;
 
 // Defines values for the LUA_VERSION_* constants
@@ -36,7 +38,14 @@ cfg_if::cfg_if! {
 
 // Integer types
 cfg_if::cfg_if! {
-    if #[cfg(LUA_INT_TYPE="LUA_INT_INT")] {
+    if #[cfg(not(LUA_VERSION = "5.3"))] {
+        // a ptrdiff_t before Lua 5.3, lua_Unsigned of Lua 5.2 is 32 bits wide
+        pub type lua_Integer = libc::ptrdiff_t;
+        pub type lua_Unsigned = libc::c_uint;
+        pub const LUA_INTEGER_FRMLEN: &str = "l";
+
+        pub const LUA_INTEGER_FMT: &str = "%ld";
+    } else if #[cfg(LUA_INT_TYPE="LUA_INT_INT")] {
         pub type lua_Integer = libc::c_int;
         pub type lua_Unsigned = libc::c_uint;
         pub const LUA_INTEGER_FRMLEN: &str = "";
@@ -70,7 +79,12 @@ cfg_if::cfg_if! {
 
 // luaxlib buffer size
 cfg_if::cfg_if! {
-    if #[cfg(LUA_FLOAT_TYPE="LUA_FLOAT_LONGDOUBLE")] {
+    if #[cfg(LUA_VERSION = "5.4")] {
+        pub const LUAL_BUFFERSIZE: usize =
+            16 * mem::size_of::<*const libc::c_void>() * mem::size_of::<lua_Number>();
+    } else if #[cfg(not(LUA_VERSION = "5.3"))] {
+        pub const LUAL_BUFFERSIZE: usize = libc::BUFSIZ as usize;
+    } else if #[cfg(LUA_FLOAT_TYPE="LUA_FLOAT_LONGDOUBLE")] {
         pub const LUAL_BUFFERSIZE: usize = 8192;
     } else {
         pub const LUAL_BUFFERSIZE: usize =
@@ -25,11 +25,16 @@ pub const LUA_ERRSYNTAX: libc::c_int = 3;
 pub const LUA_ERRMEM: libc::c_int = 4;
 
 cfg_if::cfg_if! {
-    if #[cfg(LUA_VERSION = "5.2")] {
+    if #[cfg(LUA_VERSION = "5.4")] {
+        pub const LUA_OK: libc::c_int = 0;
+        pub const LUA_ERRERR: libc::c_int = 5;
+    } else if #[cfg(LUA_VERSION = "5.2")] {
         pub const LUA_OK: libc::c_int = 0;
         pub const LUA_ERRGCMM: libc::c_int = 5;
         pub const LUA_ERRERR: libc::c_int = 6;
     } else {
+        // Not defined in Lua 5.1, where 0 also means success
+        pub const LUA_OK: libc::c_int = 0;
         pub const LUA_ERRERR: libc::c_int = 5;
     }
 }
@@ -99,6 +104,9 @@ pub const LUA_GCCOUNTB: libc::c_int = 4;
 pub const LUA_GCSTEP: libc::c_int = 5;
 pub const LUA_GCSETPAUSE: libc::c_int = 6;
 pub const LUA_GCSETSTEPMUL: libc::c_int = 7;
+
+// Introduced in Lua 5.2
+#[cfg(LUA_VERSION = "5.2")]
 pub const LUA_GCISRUNNING: libc::c_int = 9;
 
 // Only defined in Lua 5.2
@@ -110,6 +118,14 @@ cfg_if::cfg_if! {
     }
 }
 
+// Reintroduced in Lua 5.4
+cfg_if::cfg_if! {
+    if #[cfg(LUA_VERSION = "5.4")] {
+        pub const LUA_GCGEN: libc::c_int = 10;
+        pub const LUA_GCINC: libc::c_int = 11;
+    }
+}
+
 pub const LUA_HOOKCALL: libc::c_int = 0;
 pub const LUA_HOOKRET: libc::c_int = 1;
 pub const LUA_HOOKLINE: libc::c_int = 2;
@@ -173,13 +189,25 @@ pub type lua_Writer = Option<
     ) -> libc::c_int,
 >;
 
+// Introduced in Lua 5.4
+#[cfg(LUA_VERSION = "5.4")]
+pub type lua_WarnFunction = Option<
+    unsafe extern "C" fn(ud: *mut libc::c_void, msg: *const libc::c_char, tocont: libc::c_int),
+>;
+
 // //////////////////////////////////////////// //
 // Lua Functions                                //
 // //////////////////////////////////////////// //
 
 extern "C" {
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_absindex(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_arith(L: *mut lua_State, op: libc::c_int);
+
     pub fn lua_atpanic(L: *mut lua_State, panicf: lua_CFunction) -> lua_CFunction;
 
     // Defined as a macro as of Lua 5.2
@@ -198,13 +226,20 @@ extern "C" {
 
     pub fn lua_checkstack(L: *mut lua_State, n: libc::c_int) -> libc::c_int;
     pub fn lua_close(L: *mut lua_State);
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_compare(
         L: *mut lua_State,
         idx1: libc::c_int,
         idx2: libc::c_int,
         op: libc::c_int,
     ) -> libc::c_int;
+
     pub fn lua_concat(L: *mut lua_State, n: libc::c_int);
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_copy(L: *mut lua_State, fromidx: libc::c_int, toidx: libc::c_int);
 
     // Deprecated in Lua 5.2
@@ -230,7 +265,11 @@ extern "C" {
     pub fn lua_equal(L: *mut lua_State, idx1: libc::c_int, idx2: libc::c_int) -> libc::c_int;
 
     pub fn lua_error(L: *mut lua_State) -> !;
+    // Takes a variable number of arguments in Lua 5.4
+    #[cfg(not(LUA_VERSION = "5.4"))]
     pub fn lua_gc(L: *mut lua_State, what: libc::c_int, data: libc::c_int) -> libc::c_int;
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_gc(L: *mut lua_State, what: libc::c_int, ...) -> libc::c_int;
     pub fn lua_getallocf(L: *mut lua_State, ud: *mut *mut libc::c_void) -> lua_Alloc;
 
     // Only present in Lua 5.2
@@ -241,20 +280,55 @@ extern "C" {
     #[cfg(not(LUA_VERSION = "5.2"))]
     pub fn lua_getfenv(L: *mut lua_State, index: libc::c_int);
 
+    // Returns the type of the value as of Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_getfield(L: *mut lua_State, idx: libc::c_int, k: *const libc::c_char)
         -> libc::c_int;
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_getglobal(L: *mut lua_State, name: *const libc::c_char) -> libc::c_int;
+
+    // Introduced in Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_geti(L: *mut lua_State, idx: libc::c_int, n: lua_Integer) -> libc::c_int;
+
     pub fn lua_getmetatable(L: *mut lua_State, objindex: libc::c_int) -> libc::c_int;
+
+    // Returns the type of the value as of Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_gettable(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
+
     pub fn lua_gettop(L: *mut lua_State) -> libc::c_int;
+
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_getiuservalue(L: *mut lua_State, idx: libc::c_int, n: libc::c_int) -> libc::c_int;
+
+    // Returns the type of the value as of Lua 5.3, defined as a macro in Lua 5.4
+    #[cfg(all(LUA_VERSION = "5.3", not(LUA_VERSION = "5.4")))]
     pub fn lua_getuservalue(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
+    #[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.3")))]
+    pub fn lua_getuservalue(L: *mut lua_State, idx: libc::c_int);
+
+    // Defined as a macro as of Lua 5.3
+    #[cfg(not(LUA_VERSION = "5.3"))]
+    pub fn lua_insert(L: *mut lua_State, idx: libc::c_int);
+
     pub fn lua_iscfunction(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
+
+    // Introduced in Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_isinteger(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
+
     pub fn lua_isnumber(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
     pub fn lua_isstring(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
     pub fn lua_isuserdata(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
+
+    // Introduced in Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_isyieldable(L: *mut lua_State) -> libc::c_int;
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_len(L: *mut lua_State, idx: libc::c_int);
 
     // Deprecated in Lua 5.2
@@ -283,7 +357,18 @@ extern "C" {
     pub fn lua_newstate(f: lua_Alloc, ud: *mut libc::c_void) -> *mut lua_State;
 
     pub fn lua_newthread(L: *mut lua_State) -> *mut lua_State;
+    // Defined as a macro in Lua 5.4
+    #[cfg(not(LUA_VERSION = "5.4"))]
     pub fn lua_newuserdata(L: *mut lua_State, sz: usize) -> *mut libc::c_void;
+
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_newuserdatauv(
+        L: *mut lua_State,
+        sz: usize,
+        nuvalue: libc::c_int,
+    ) -> *mut libc::c_void;
+
     pub fn lua_next(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
 
     // Introduced in Lua 5.1, renamed to lua_rawlen in 5.2
@@ -344,11 +429,13 @@ extern "C" {
     ) -> *const libc::c_char;
 
     pub fn lua_rawequal(L: *mut lua_State, idx1: libc::c_int, idx2: libc::c_int) -> libc::c_int;
+
+    // Returns the type of the value as of Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_rawget(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_rawgeti(L: *mut lua_State, idx: libc::c_int, n: lua_Integer) -> libc::c_int;
-
-    // Introduced in Lua 5.2
-    #[cfg(LUA_VERSION = "5.2")]
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_rawgetp(L: *mut lua_State, idx: libc::c_int, p: *const libc::c_void) -> libc::c_int;
 
     // Renamed from lua_objlen in Lua 5.2
@@ -356,19 +443,43 @@ extern "C" {
     pub fn lua_rawlen(L: *mut lua_State, idx: libc::c_int) -> usize;
 
     pub fn lua_rawset(L: *mut lua_State, idx: libc::c_int);
+
+    // Takes a lua_Integer as of Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_rawseti(L: *mut lua_State, idx: libc::c_int, n: lua_Integer);
 
     // Introduced in Lua 5.2
     #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_rawsetp(L: *mut lua_State, idx: libc::c_int, p: *const libc::c_void);
 
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_resetthread(L: *mut lua_State) -> libc::c_int;
+
+    // Returns the number of results through 'nres' in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_resume(
+        L: *mut lua_State,
+        from: *mut lua_State,
+        narg: libc::c_int,
+        nres: *mut libc::c_int,
+    ) -> libc::c_int;
     // Takes an extra parameter 'from' in Lua 5.2+
-    #[cfg(LUA_VERSION = "5.2")]
+    #[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.4")))]
     pub fn lua_resume(L: *mut lua_State, from: *mut lua_State, narg: libc::c_int) -> libc::c_int;
     #[cfg(not(LUA_VERSION = "5.2"))]
     pub fn lua_resume(L: *mut lua_State, narg: libc::c_int) -> libc::c_int;
 
+    // Defined as macros as of Lua 5.3
+    #[cfg(not(LUA_VERSION = "5.3"))]
+    pub fn lua_remove(L: *mut lua_State, idx: libc::c_int);
+    #[cfg(not(LUA_VERSION = "5.3"))]
+    pub fn lua_replace(L: *mut lua_State, idx: libc::c_int);
+
+    // Introduced in Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_rotate(L: *mut lua_State, idx: libc::c_int, n: libc::c_int);
+
     pub fn lua_setallocf(L: *mut lua_State, f: lua_Alloc, ud: *mut libc::c_void);
 
     // Removed in Lua 5.2
@@ -376,28 +487,54 @@ extern "C" {
     pub fn lua_setfenv(L: *mut lua_State, index: libc::c_int) -> libc::c_int;
 
     pub fn lua_setfield(L: *mut lua_State, idx: libc::c_int, k: *const libc::c_char);
+
+    // Defined as a macro in Lua 5.1
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_setglobal(L: *mut lua_State, name: *const libc::c_char);
+
+    // Introduced in Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_seti(L: *mut lua_State, idx: libc::c_int, n: lua_Integer);
+
     pub fn lua_setmetatable(L: *mut lua_State, objindex: libc::c_int) -> libc::c_int;
     pub fn lua_settable(L: *mut lua_State, idx: libc::c_int);
     pub fn lua_settop(L: *mut lua_State, idx: libc::c_int);
+
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_setiuservalue(L: *mut lua_State, idx: libc::c_int, n: libc::c_int) -> libc::c_int;
+
+    // Introduced in Lua 5.2, defined as a macro in Lua 5.4
+    #[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.4")))]
     pub fn lua_setuservalue(L: *mut lua_State, idx: libc::c_int);
+
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_setwarnf(L: *mut lua_State, f: lua_WarnFunction, ud: *mut libc::c_void);
+
     pub fn lua_status(L: *mut lua_State) -> libc::c_int;
 
     // Only defined as a function in Lua 5.0
     #[cfg(all(LUA_VERSION = "5.0", not(LUA_VERSION = "5.1")))]
     pub fn lua_strlen(L: *mut lua_State, idx: libc::c_int) -> usize;
 
+    // Introduced in Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_stringtonumber(L: *mut lua_State, s: *const libc::c_char) -> usize;
+
     pub fn lua_toboolean(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
     pub fn lua_tocfunction(L: *mut lua_State, idx: libc::c_int) -> lua_CFunction;
 
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_toclose(L: *mut lua_State, idx: libc::c_int);
+
     // Defined as a macro in Lua 5.2
     #[cfg(not(LUA_VERSION = "5.2"))]
     pub fn lua_tointeger(L: *mut lua_State, index: libc::c_int) -> lua_Integer;
 
-    // Introduced in Lua 5.2
-    #[cfg(LUA_VERSION = "5.2")]
+    // Introduced in Lua 5.2, failing on non integral floats as of Lua 5.3
+    #[cfg(LUA_VERSION = "5.3")]
     pub fn lua_tointegerx(
         L: *mut lua_State,
         idx: libc::c_int,
@@ -439,8 +576,25 @@ extern "C" {
     pub fn lua_touserdata(L: *mut lua_State, idx: libc::c_int) -> *mut libc::c_void;
     pub fn lua_type(L: *mut lua_State, idx: libc::c_int) -> libc::c_int;
     pub fn lua_typename(L: *mut lua_State, tp: libc::c_int) -> *const libc::c_char;
+
+    // Introduced in Lua 5.2, returns the number itself as of Lua 5.4
+    #[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.4")))]
     pub fn lua_version(L: *mut lua_State) -> *const lua_Number;
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_version(L: *mut lua_State) -> lua_Number;
+
+    // Introduced in Lua 5.4
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn lua_warning(L: *mut lua_State, msg: *const libc::c_char, tocont: libc::c_int);
+
     pub fn lua_xmove(from: *mut lua_State, to: *mut lua_State, n: libc::c_int);
+
+    // Defined as a macro as of Lua 5.2
+    #[cfg(not(LUA_VERSION = "5.2"))]
+    pub fn lua_yield(L: *mut lua_State, nresults: libc::c_int) -> libc::c_int;
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_yieldk(
         L: *mut lua_State,
         nresults: libc::c_int,
@@ -449,6 +603,258 @@ extern "C" {
     ) -> libc::c_int;
 }
 
+// Functions returning nothing before Lua 5.3, wrapped below to return the type of the value
+#[cfg(not(LUA_VERSION = "5.3"))]
+mod untyped {
+    use crate::*;
+
+    extern "C" {
+        pub fn lua_getfield(L: *mut lua_State, idx: libc::c_int, k: *const libc::c_char);
+        #[cfg(LUA_VERSION = "5.2")]
+        pub fn lua_getglobal(L: *mut lua_State, name: *const libc::c_char);
+        pub fn lua_gettable(L: *mut lua_State, idx: libc::c_int);
+        pub fn lua_rawget(L: *mut lua_State, idx: libc::c_int);
+        pub fn lua_rawgeti(L: *mut lua_State, idx: libc::c_int, n: libc::c_int);
+        #[cfg(LUA_VERSION = "5.2")]
+        pub fn lua_rawgetp(L: *mut lua_State, idx: libc::c_int, p: *const libc::c_void);
+        pub fn lua_rawseti(L: *mut lua_State, idx: libc::c_int, n: libc::c_int);
+    }
+}
+
+// //////////////////////////////////////////// //
+// Compatibility with Lua 5.3                   //
+// //////////////////////////////////////////// //
+
+// Introduced in Lua 5.2
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_absindex(L: *mut lua_State, idx: libc::c_int) -> libc::c_int {
+    if idx > 0 || idx <= LUA_REGISTRYINDEX {
+        idx
+    } else {
+        lua_gettop(L) + idx + 1
+    }
+}
+
+// Introduced in Lua 5.2
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_copy(L: *mut lua_State, fromidx: libc::c_int, toidx: libc::c_int) {
+    let toidx = lua_absindex(L, toidx);
+    lua_pushvalue(L, fromidx);
+    lua_replace(L, toidx);
+}
+
+// Returns the type of the value as of Lua 5.3
+#[inline]
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn lua_getfield(
+    L: *mut lua_State,
+    idx: libc::c_int,
+    k: *const libc::c_char,
+) -> libc::c_int {
+    untyped::lua_getfield(L, idx, k);
+    lua_type(L, -1)
+}
+
+// Returns the type of the value as of Lua 5.3
+#[inline]
+#[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.3")))]
+pub unsafe fn lua_getglobal(L: *mut lua_State, name: *const libc::c_char) -> libc::c_int {
+    untyped::lua_getglobal(L, name);
+    lua_type(L, -1)
+}
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_getglobal(L: *mut lua_State, name: *const libc::c_char) -> libc::c_int {
+    lua_getfield(L, LUA_GLOBALSINDEX, name)
+}
+
+// Returns the type of the value as of Lua 5.3
+#[inline]
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn lua_gettable(L: *mut lua_State, idx: libc::c_int) -> libc::c_int {
+    untyped::lua_gettable(L, idx);
+    lua_type(L, -1)
+}
+
+// Introduced in Lua 5.3, true if the number has an integral value before
+#[inline]
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn lua_isinteger(L: *mut lua_State, idx: libc::c_int) -> libc::c_int {
+    if lua_type(L, idx) == LUA_TNUMBER {
+        let n = lua_tonumber(L, idx);
+        (n == lua_tointeger(L, idx) as lua_Number) as libc::c_int
+    } else {
+        0
+    }
+}
+
+// Introduced in Lua 5.2
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_len(L: *mut lua_State, idx: libc::c_int) {
+    let idx = lua_absindex(L, idx);
+    let name = b"__len\0".as_ptr() as *const libc::c_char;
+    match lua_type(L, idx) {
+        LUA_TSTRING => lua_pushnumber(L, lua_objlen(L, idx) as lua_Number),
+        LUA_TTABLE => {
+            if luaL_callmeta(L, idx, name) == 0 {
+                lua_pushnumber(L, lua_objlen(L, idx) as lua_Number);
+            }
+        }
+        LUA_TUSERDATA if luaL_callmeta(L, idx, name) != 0 => {}
+        tp => {
+            luaL_error(
+                L,
+                b"attempt to get length of a %s value\0".as_ptr() as *const libc::c_char,
+                lua_typename(L, tp),
+            );
+        }
+    }
+}
+
+// Introduced in Lua 5.2
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_pushglobaltable(L: *mut lua_State) {
+    lua_pushvalue(L, LUA_GLOBALSINDEX);
+}
+
+// Returns the type of the value as of Lua 5.3
+#[inline]
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn lua_rawget(L: *mut lua_State, idx: libc::c_int) -> libc::c_int {
+    untyped::lua_rawget(L, idx);
+    lua_type(L, -1)
+}
+
+// Returns the type of the value and takes a lua_Integer as of Lua 5.3
+#[inline]
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn lua_rawgeti(L: *mut lua_State, idx: libc::c_int, n: lua_Integer) -> libc::c_int {
+    if n as libc::c_int as lua_Integer == n {
+        untyped::lua_rawgeti(L, idx, n as libc::c_int);
+        lua_type(L, -1)
+    } else {
+        let idx = lua_absindex(L, idx);
+        lua_pushinteger(L, n);
+        lua_rawget(L, idx)
+    }
+}
+
+// Returns the type of the value as of Lua 5.3
+#[inline]
+#[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.3")))]
+pub unsafe fn lua_rawgetp(L: *mut lua_State, idx: libc::c_int, p: *const libc::c_void) -> libc::c_int {
+    untyped::lua_rawgetp(L, idx, p);
+    lua_type(L, -1)
+}
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_rawgetp(L: *mut lua_State, idx: libc::c_int, p: *const libc::c_void) -> libc::c_int {
+    let idx = lua_absindex(L, idx);
+    lua_pushlightuserdata(L, p as *mut libc::c_void);
+    lua_rawget(L, idx)
+}
+
+// Renamed from lua_objlen in Lua 5.2
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_rawlen(L: *mut lua_State, idx: libc::c_int) -> usize {
+    lua_objlen(L, idx)
+}
+
+// Takes a lua_Integer as of Lua 5.3, keys out of the range of an int need an extra stack slot
+#[inline]
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn lua_rawseti(L: *mut lua_State, idx: libc::c_int, n: lua_Integer) {
+    if n as libc::c_int as lua_Integer == n {
+        untyped::lua_rawseti(L, idx, n as libc::c_int);
+    } else {
+        let idx = lua_absindex(L, idx);
+        lua_pushinteger(L, n);
+        lua_insert(L, -2);
+        lua_rawset(L, idx);
+    }
+}
+
+// Introduced in Lua 5.2
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_rawsetp(L: *mut lua_State, idx: libc::c_int, p: *const libc::c_void) {
+    let idx = lua_absindex(L, idx);
+    lua_pushlightuserdata(L, p as *mut libc::c_void);
+    lua_insert(L, -2);
+    lua_rawset(L, idx);
+}
+
+// Introduced in Lua 5.3
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn lua_rotate(L: *mut lua_State, idx: libc::c_int, n: libc::c_int) {
+    let idx = lua_absindex(L, idx);
+    if n > 0 {
+        for _ in 0..n {
+            lua_insert(L, idx);
+        }
+    } else {
+        for _ in n..0 {
+            lua_pushvalue(L, idx);
+            lua_remove(L, idx);
+        }
+    }
+}
+
+// Defined as a macro in Lua 5.1
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_setglobal(L: *mut lua_State, name: *const libc::c_char) {
+    lua_setfield(L, LUA_GLOBALSINDEX, name);
+}
+
+// Introduced in Lua 5.2, failing on non integral floats as of Lua 5.3
+#[cfg(not(LUA_VERSION = "5.3"))]
+pub unsafe fn lua_tointegerx(
+    L: *mut lua_State,
+    idx: libc::c_int,
+    isnum: *mut libc::c_int,
+) -> lua_Integer {
+    let mut ok = 0;
+    let n = lua_tonumberx(L, idx, &mut ok);
+    let min = lua_Integer::min_value() as lua_Number;
+    let ok = ok != 0 && n >= min && n < -min && n == n as lua_Integer as lua_Number;
+    if !isnum.is_null() {
+        *isnum = ok as libc::c_int;
+    }
+    if ok {
+        n as lua_Integer
+    } else {
+        0
+    }
+}
+
+// Introduced in Lua 5.2
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_tonumberx(
+    L: *mut lua_State,
+    idx: libc::c_int,
+    isnum: *mut libc::c_int,
+) -> lua_Number {
+    let n = lua_tonumber(L, idx);
+    if !isnum.is_null() {
+        *isnum = (n != 0.0 || lua_isnumber(L, idx) != 0) as libc::c_int;
+    }
+    n
+}
+
+// Introduced in Lua 5.2
+#[inline]
+#[cfg(all(LUA_VERSION = "5.1", not(LUA_VERSION = "5.2")))]
+pub unsafe fn lua_version(_L: *mut lua_State) -> *const lua_Number {
+    static VERSION: lua_Number = LUA_VERSION_NUM;
+    &VERSION
+}
+
 // //////////////////////////////////////////// //
 // Lua Macros (represented as inline functions) //
 // //////////////////////////////////////////// //
@@ -483,7 +889,16 @@ pub unsafe fn lua_getextraspace(L: *mut lua_State) -> *mut libc::c_void {
     (L as usize - LUA_EXTRASPACE) as *mut libc::c_void
 }
 
+// Defined as a macro in Lua 5.4
 #[inline]
+#[cfg(LUA_VERSION = "5.4")]
+pub unsafe fn lua_getuservalue(L: *mut lua_State, idx: libc::c_int) -> libc::c_int {
+    lua_getiuservalue(L, idx, 1)
+}
+
+// Defined as a macro as of Lua 5.3
+#[inline]
+#[cfg(LUA_VERSION = "5.3")]
 pub unsafe fn lua_insert(L: *mut lua_State, index: libc::c_int) {
     lua_rotate(L, index, 1);
 }
@@ -539,6 +954,13 @@ pub unsafe fn lua_newtable(L: *mut lua_State) {
     lua_createtable(L, 0, 0);
 }
 
+// Defined as a macro in Lua 5.4
+#[inline]
+#[cfg(LUA_VERSION = "5.4")]
+pub unsafe fn lua_newuserdata(L: *mut lua_State, sz: usize) -> *mut libc::c_void {
+    lua_newuserdatauv(L, sz, 1)
+}
+
 #[inline]
 pub unsafe fn lua_numbertointeger(n: lua_Number, p: *mut lua_Integer) -> libc::c_int {
     if n >= LUA_MININTEGER as lua_Number && n < -(LUA_MININTEGER as lua_Number) {
@@ -595,13 +1017,16 @@ pub unsafe fn lua_register(L: *mut lua_State, name: *const libc::c_char, f: lua_
     lua_setglobal(L, name);
 }
 
+// Defined as macros as of Lua 5.3
 #[inline]
+#[cfg(LUA_VERSION = "5.3")]
 pub unsafe fn lua_remove(L: *mut lua_State, index: libc::c_int) {
     lua_rotate(L, index, -1);
     lua_pop(L, 1);
 }
 
 #[inline]
+#[cfg(LUA_VERSION = "5.3")]
 pub unsafe fn lua_replace(L: *mut lua_State, index: libc::c_int) {
     lua_copy(L, -1, index);
     lua_pop(L, 1);
@@ -617,6 +1042,13 @@ pub unsafe fn lua_strlen(L: *mut lua_State, idx: libc::c_int) -> usize {
     lua_objlen(L, idx)
 }
 
+// Defined as a macro in Lua 5.4
+#[inline]
+#[cfg(LUA_VERSION = "5.4")]
+pub unsafe fn lua_setuservalue(L: *mut lua_State, idx: libc::c_int) -> libc::c_int {
+    lua_setiuservalue(L, idx, 1)
+}
+
 // Defined as a macro in Lua 5.2
 #[cfg_attr(LUA_VERSION = "5.2", inline)]
 #[cfg(LUA_VERSION = "5.2")]
@@ -653,11 +1085,19 @@ pub unsafe fn lua_tounsigned(L: *mut lua_State, index: libc::c_int) -> lua_Unsig
 }
 
 #[inline]
+#[cfg(LUA_VERSION = "5.2")]
 pub const unsafe fn lua_upvalueindex(i: libc::c_int) -> libc::c_int {
     LUA_REGISTRYINDEX - i
 }
+#[inline]
+#[cfg(not(LUA_VERSION = "5.2"))]
+pub const unsafe fn lua_upvalueindex(i: libc::c_int) -> libc::c_int {
+    LUA_GLOBALSINDEX - i
+}
 
+// Defined as a macro as of Lua 5.2
 #[inline]
+#[cfg(LUA_VERSION = "5.2")]
 pub unsafe fn lua_yield(L: *mut lua_State, nresults: libc::c_int) -> libc::c_int {
     lua_yieldk(L, nresults, 0, None)
 }
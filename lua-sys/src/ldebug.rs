@@ -5,12 +5,14 @@ use crate::*;
 // //////////////////////////////////////////// //
 
 #[repr(C)]
+#[cfg(LUA_VERSION = "5.4")]
 pub struct lua_Debug {
     pub event: libc::c_int,
     pub name: *const libc::c_char,
     pub namewhat: *const libc::c_char,
     pub what: *const libc::c_char,
     pub source: *const libc::c_char,
+    pub srclen: usize,
     pub currentline: libc::c_int,
     pub linedefined: libc::c_int,
     pub lastlinedefined: libc::c_int,
@@ -18,6 +20,45 @@ pub struct lua_Debug {
     pub nparams: libc::c_uchar,
     pub isvararg: libc::c_char,
     pub istailcall: libc::c_char,
+    pub ftransfer: libc::c_ushort,
+    pub ntransfer: libc::c_ushort,
+    pub short_src: [libc::c_char; LUA_IDSIZE],
+    /* private part */
+    _private: private::lua_Debug,
+}
+
+#[repr(C)]
+#[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.4")))]
+pub struct lua_Debug {
+    pub event: libc::c_int,
+    pub name: *const libc::c_char,
+    pub namewhat: *const libc::c_char,
+    pub what: *const libc::c_char,
+    pub source: *const libc::c_char,
+    pub currentline: libc::c_int,
+    pub linedefined: libc::c_int,
+    pub lastlinedefined: libc::c_int,
+    pub nups: libc::c_uchar,
+    pub nparams: libc::c_uchar,
+    pub isvararg: libc::c_char,
+    pub istailcall: libc::c_char,
+    pub short_src: [libc::c_char; LUA_IDSIZE],
+    /* private part */
+    _private: private::lua_Debug,
+}
+
+#[repr(C)]
+#[cfg(not(LUA_VERSION = "5.2"))]
+pub struct lua_Debug {
+    pub event: libc::c_int,
+    pub name: *const libc::c_char,
+    pub namewhat: *const libc::c_char,
+    pub what: *const libc::c_char,
+    pub source: *const libc::c_char,
+    pub currentline: libc::c_int,
+    pub nups: libc::c_int,
+    pub linedefined: libc::c_int,
+    pub lastlinedefined: libc::c_int,
     pub short_src: [libc::c_char; LUA_IDSIZE],
     /* private part */
     _private: private::lua_Debug,
@@ -30,10 +71,17 @@ mod private {
     use super::*;
 
     #[repr(C)]
+    #[cfg(LUA_VERSION = "5.2")]
     pub struct lua_Debug {
         i_ci: *mut CallInfo,
     }
 
+    #[repr(C)]
+    #[cfg(not(LUA_VERSION = "5.2"))]
+    pub struct lua_Debug {
+        i_ci: libc::c_int,
+    }
+
     #[repr(C)]
     struct CallInfo {
         _private: [u8; 0],
@@ -75,8 +123,14 @@ extern "C" {
         funcindex: libc::c_int,
         n: libc::c_int,
     ) -> *const libc::c_char;
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_upvalueid(L: *mut lua_State, fidx: libc::c_int, n: libc::c_int)
         -> *mut libc::c_void;
+
+    // Introduced in Lua 5.2
+    #[cfg(LUA_VERSION = "5.2")]
     pub fn lua_upvaluejoin(
         L: *mut lua_State,
         fidx1: libc::c_int,
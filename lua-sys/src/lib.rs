@@ -12,10 +12,14 @@ mod laux;
 mod lconf;
 mod lcore;
 mod ldebug;
+#[cfg(LUAJIT)]
+mod ljit;
 mod llib;
 
 pub use laux::*;
 pub use lconf::*;
 pub use lcore::*;
 pub use ldebug::*;
+#[cfg(LUAJIT)]
+pub use ljit::*;
 pub use llib::*;
@@ -0,0 +1,30 @@
+// LuaJIT extensions
+
+use crate::*;
+
+// //////////////////////////////////////////// //
+// Constants                                    //
+// //////////////////////////////////////////// //
+
+pub const LUAJIT_MODE_ENGINE: libc::c_int = 0;
+pub const LUAJIT_MODE_DEBUG: libc::c_int = 1;
+pub const LUAJIT_MODE_FUNC: libc::c_int = 2;
+pub const LUAJIT_MODE_ALLFUNC: libc::c_int = 3;
+pub const LUAJIT_MODE_ALLSUBFUNC: libc::c_int = 4;
+pub const LUAJIT_MODE_TRACE: libc::c_int = 5;
+pub const LUAJIT_MODE_WRAPCFUNC: libc::c_int = 0x10;
+pub const LUAJIT_MODE_MAX: libc::c_int = 0x11;
+
+pub const LUAJIT_MODE_MASK: libc::c_int = 0x00ff;
+
+pub const LUAJIT_MODE_OFF: libc::c_int = 0x0000;
+pub const LUAJIT_MODE_ON: libc::c_int = 0x0100;
+pub const LUAJIT_MODE_FLUSH: libc::c_int = 0x0200;
+
+// //////////////////////////////////////////// //
+// Functions                                    //
+// //////////////////////////////////////////// //
+
+extern "C" {
+    pub fn luaJIT_setmode(L: *mut lua_State, idx: libc::c_int, mode: libc::c_int) -> libc::c_int;
+}
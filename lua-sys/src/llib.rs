@@ -18,6 +18,14 @@ pub const LUA_UTF8LIBNAME: &str = "utf8";
 #[cfg(LUA_VERSION = "5.2")]
 pub const LUA_BITLIBNAME: &str = "bit32";
 
+// LuaJIT libraries
+#[cfg(LUAJIT)]
+pub const LUA_BITLIBNAME: &str = "bit";
+#[cfg(LUAJIT)]
+pub const LUA_JITLIBNAME: &str = "jit";
+#[cfg(LUAJIT)]
+pub const LUA_FFILIBNAME: &str = "ffi";
+
 pub const LUA_MATHLIBNAME: &str = "math";
 pub const LUA_DBLIBNAME: &str = "db";
 pub const LUA_LOADLIBNAME: &str = "package";
@@ -30,6 +38,9 @@ extern "C" {
     pub fn luaL_openlibs(L: *mut lua_State);
     pub fn luaopen_base(L: *mut lua_State) -> libc::c_int;
 
+    #[cfg(LUAJIT)]
+    pub fn luaopen_bit(L: *mut lua_State) -> libc::c_int;
+
     #[cfg(LUA_VERSION = "5.2")]
     pub fn luaopen_bit32(L: *mut lua_State) -> libc::c_int;
 
@@ -37,7 +48,15 @@ extern "C" {
     pub fn luaopen_coroutine(L: *mut lua_State) -> libc::c_int;
 
     pub fn luaopen_debug(L: *mut lua_State) -> libc::c_int;
+
+    #[cfg(LUAJIT)]
+    pub fn luaopen_ffi(L: *mut lua_State) -> libc::c_int;
+
     pub fn luaopen_io(L: *mut lua_State) -> libc::c_int;
+
+    #[cfg(LUAJIT)]
+    pub fn luaopen_jit(L: *mut lua_State) -> libc::c_int;
+
     pub fn luaopen_math(L: *mut lua_State) -> libc::c_int;
 
     #[cfg(LUA_VERSION = "5.1")]
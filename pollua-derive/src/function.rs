@@ -0,0 +1,297 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    ext::IdentExt, parse::Parser, spanned::Spanned, Error, FnArg, GenericParam, ImplItem, ItemFn,
+    ItemImpl, LitStr, Pat, Result, ReturnType, Signature, Type,
+};
+
+/// How a method borrows the value it is called on.
+#[derive(Clone, Copy, PartialEq)]
+enum Receiver {
+    None,
+    Ref,
+    Mut,
+}
+
+pub fn lua_function(attr: TokenStream, item: ItemFn) -> Result<TokenStream> {
+    let sig = &item.sig;
+    check_signature(sig)?;
+    if let Some(receiver) = sig.receiver() {
+        return Err(Error::new(
+            receiver.span(),
+            "#[lua_function] cannot be used on methods, use #[lua_methods] on the impl block",
+        ));
+    }
+    let ident = &sig.ident;
+    let name = parse_name(attr)?.unwrap_or_else(|| ident.unraw().to_string());
+    let vis = &item.vis;
+    let c_name = format_ident!("lua_{}", ident.unraw());
+    let body = call_body(sig, quote!(#ident), &name, Receiver::None, None)?;
+    let doc = format!(
+        "Calls [`{}`] from Lua, generated by `#[lua_function]`.",
+        ident
+    );
+
+    Ok(quote! {
+        #item
+
+        #[doc = #doc]
+        ///
+        /// # Safety
+        /// Must only be called by Lua.
+        #vis unsafe extern "C" fn #c_name(
+            l: *mut ::pollua::sys::lua_State,
+        ) -> ::std::os::raw::c_int {
+            #body
+        }
+    })
+}
+
+pub fn lua_methods(attr: TokenStream, item: ItemImpl) -> Result<TokenStream> {
+    if let Some((_, path, _)) = &item.trait_ {
+        return Err(Error::new(
+            path.span(),
+            "#[lua_methods] must be used on an inherent impl block",
+        ));
+    }
+    if !item.generics.params.is_empty() {
+        return Err(Error::new(
+            item.generics.span(),
+            "generic types cannot be used as userdata",
+        ));
+    }
+    let self_ty = &item.self_ty;
+    let name = match parse_name(attr)? {
+        Some(name) => name,
+        None => match &**self_ty {
+            Type::Path(path) if path.qself.is_none() => path
+                .path
+                .segments
+                .last()
+                .map(|segment| segment.ident.unraw().to_string())
+                .unwrap_or_default(),
+            _ => {
+                return Err(Error::new(
+                    self_ty.span(),
+                    "cannot name this type, use #[lua_methods(name = \"...\")]",
+                ))
+            }
+        },
+    };
+
+    let mut functions = Vec::new();
+    let mut registrations = Vec::new();
+    for item in &item.items {
+        let method = match item {
+            ImplItem::Fn(method) => method,
+            _ => continue,
+        };
+        let sig = &method.sig;
+        check_signature(sig)?;
+        let receiver = match sig.receiver() {
+            None => Receiver::None,
+            Some(receiver) if receiver.reference.is_none() || receiver.colon_token.is_some() => {
+                return Err(Error::new(
+                    receiver.span(),
+                    "methods called from Lua must take `&self` or `&mut self`",
+                ))
+            }
+            Some(receiver) if receiver.mutability.is_some() => Receiver::Mut,
+            Some(_) => Receiver::Ref,
+        };
+        let ident = &sig.ident;
+        let fn_name = ident.unraw().to_string();
+        let c_name = format_ident!("lua_{}", ident.unraw());
+        let body = call_body(
+            sig,
+            quote!(<#self_ty>::#ident),
+            &fn_name,
+            receiver,
+            Some(&**self_ty),
+        )?;
+        functions.push(quote! {
+            // metamethods are named like `lua___index`
+            #[allow(non_snake_case)]
+            unsafe extern "C" fn #c_name(
+                l: *mut ::pollua::sys::lua_State,
+            ) -> ::std::os::raw::c_int {
+                #body
+            }
+        });
+        registrations.push(match receiver {
+            Receiver::None => quote!(.c_function(#fn_name, Some(#c_name))),
+            _ => quote!(.c_method(#fn_name, Some(#c_name))),
+        });
+    }
+
+    Ok(quote! {
+        #item
+
+        impl ::pollua::userdata::UserData for #self_ty {
+            const NAME: &'static str = #name;
+
+            fn add_methods(
+                methods: ::pollua::userdata::Methods<Self>,
+            ) -> ::pollua::userdata::Methods<Self> {
+                #(#functions)*
+                methods #(#registrations)*
+            }
+        }
+
+        impl ::pollua::value::IntoLuaMulti for #self_ty {
+            fn push_multi(
+                self,
+                pusher: ::pollua::value::Pusher,
+            ) -> ::pollua::LuaResult<::std::os::raw::c_int> {
+                ::pollua::userdata::push(pusher, self).map(|()| 1)
+            }
+        }
+    })
+}
+
+/// Parses the `name = "..."` argument of the attributes.
+fn parse_name(attr: TokenStream) -> Result<Option<String>> {
+    let mut name = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse::<LitStr>()?.value());
+            Ok(())
+        } else {
+            Err(meta.error("unsupported argument, expected `name`"))
+        }
+    });
+    parser.parse2(attr)?;
+    Ok(name)
+}
+
+/// Returns an error for functions that cannot be called from Lua.
+fn check_signature(sig: &Signature) -> Result<()> {
+    if let Some(token) = &sig.asyncness {
+        return Err(Error::new(
+            token.span(),
+            "async functions cannot be called from Lua",
+        ));
+    }
+    if let Some(variadic) = &sig.variadic {
+        return Err(Error::new(
+            variadic.span(),
+            "variadic functions cannot be called from Lua",
+        ));
+    }
+    for param in &sig.generics.params {
+        if !matches!(param, GenericParam::Lifetime(_)) {
+            return Err(Error::new(
+                param.span(),
+                "generic functions cannot be called from Lua",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the function returns a `Result`, whose error is raised instead of being pushed.
+fn returns_result(sig: &Signature) -> bool {
+    match &sig.output {
+        ReturnType::Type(_, ty) => {
+            match &**ty {
+                Type::Path(path) => path.path.segments.last().is_some_and(|segment| {
+                    segment.ident == "Result" || segment.ident == "LuaResult"
+                }),
+                _ => false,
+            }
+        }
+        ReturnType::Default => false,
+    }
+}
+
+/// Whether `ty` is `&LuaStr`, which is borrowed from the stack instead of being converted.
+fn is_lua_str(ty: &Type) -> bool {
+    match ty {
+        Type::Reference(reference) if reference.mutability.is_none() => match &*reference.elem {
+            Type::Path(path) if path.qself.is_none() => path
+                .path
+                .segments
+                .last()
+                .is_some_and(|segment| segment.ident == "LuaStr"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Generates the body of a C function calling `path` with the arguments it was called with.
+fn call_body(
+    sig: &Signature,
+    path: TokenStream,
+    fn_name: &str,
+    receiver: Receiver,
+    self_ty: Option<&Type>,
+) -> Result<TokenStream> {
+    let first = if receiver == Receiver::None { 1 } else { 2 };
+    let mut args = Vec::new();
+    let mut conversions = Vec::new();
+    let mut borrows_str = false;
+    for (i, input) in sig.inputs.iter().enumerate() {
+        let input = match input {
+            FnArg::Typed(input) => input,
+            FnArg::Receiver(_) => continue,
+        };
+        let param = match &*input.pat {
+            Pat::Ident(pat) => pat.ident.unraw().to_string(),
+            _ => format!("arg{}", args.len() + 1),
+        };
+        let arg = format_ident!("arg{}", i);
+        let n = first + args.len() as i32;
+        conversions.push(if is_lua_str(&input.ty) {
+            borrows_str = true;
+            quote! {
+                let #arg = unsafe {
+                    ::pollua::derive::str_arg(&scope, thread, #n, #fn_name, #param)?
+                };
+            }
+        } else {
+            quote! {
+                let #arg = ::pollua::derive::arg(thread, #n, #fn_name, #param)?;
+            }
+        });
+        args.push(arg);
+    }
+
+    let borrow = match (receiver, self_ty) {
+        (Receiver::Ref, Some(self_ty)) => quote! {
+            let this = ::pollua::derive::this::<#self_ty>(thread, #fn_name)?;
+        },
+        (Receiver::Mut, Some(self_ty)) => quote! {
+            let mut this = ::pollua::derive::this_mut::<#self_ty>(thread, #fn_name)?;
+        },
+        _ => quote!(),
+    };
+    let this = match receiver {
+        Receiver::None => quote!(),
+        Receiver::Ref => quote!(&*this,),
+        Receiver::Mut => quote!(&mut *this,),
+    };
+    // bounds the strings borrowed from the stack
+    let scope = if borrows_str {
+        quote!(let scope = ();)
+    } else {
+        quote!()
+    };
+    let question = if returns_result(sig) {
+        quote!(?)
+    } else {
+        quote!()
+    };
+
+    Ok(quote! {
+        ::pollua::derive::call(l, |thread| {
+            #scope
+            #(#conversions)*
+            let results = {
+                #borrow
+                #path(#this #(#args),*)
+            } #question;
+            ::pollua::derive::results(thread, results)
+        })
+    })
+}
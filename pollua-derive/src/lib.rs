@@ -0,0 +1,161 @@
+//! Procedural macros for Pollua.
+//!
+//! They are re-exported by `pollua` when its `derive` feature is enabled,
+//! and the generated code refers to it as `::pollua`.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput, ItemFn, ItemImpl};
+
+mod function;
+mod table;
+
+/// Generates a Lua C function calling the annotated function.
+///
+/// The C function is named after the function with a `lua_` prefix and has the same visibility,
+/// it can be registered with `Module::c_function` or `lua_pushcfunction`.
+/// Each argument is converted with `FromLua`, a missing argument being `nil`,
+/// and a conversion failure raises an error naming the argument and its expected type,
+/// like `luaL_argerror`:
+///
+/// ```text
+/// bad argument #2 'height' to 'area' (expected number, got string)
+/// ```
+///
+/// Arguments of type `&LuaStr` are borrowed from the stack instead, without copying the string.
+/// They only live for the duration of the call and cannot be kept:
+///
+/// ```compile_fail,E0597
+/// use pollua::{lua_function, value::LuaStr};
+///
+/// #[lua_function]
+/// fn keep(s: &'static LuaStr) {}
+/// ```
+///
+/// The return value is pushed with `IntoLuaMulti`, unless the function returns a `Result`,
+/// in which case its error is raised instead.
+///
+/// `#[lua_function(name = "...")]` changes the name of the function in error messages.
+///
+/// # Examples
+/// ```
+/// use pollua::{lua_function, module::Module};
+///
+/// #[lua_function]
+/// fn area(width: f64, height: f64) -> f64 {
+///     width * height
+/// }
+///
+/// let module = Module::new("geometry").c_function("area", Some(lua_area));
+/// ```
+#[proc_macro_attribute]
+pub fn lua_function(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    function::lua_function(attr.into(), item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `UserData` for the type of an inherent impl block,
+/// exposing every function of the block to Lua.
+///
+/// Methods taking `&self` or `&mut self` become methods of the userdata,
+/// called with `value:method(...)`, the value being borrowed for the duration of the call.
+/// Methods whose name starts with `__`, such as `__tostring`, become metamethods.
+/// Associated functions, such as constructors, are added to the table returned by
+/// `userdata::class`. Arguments and return values are handled like with [`lua_function`],
+/// and `IntoLuaMulti` is implemented for the type so that functions can return it.
+///
+/// The name of the type in Lua defaults to the name of the Rust type,
+/// `#[lua_methods(name = "...")]` changes it.
+///
+/// # Examples
+/// ```
+/// use pollua::{lua_methods, thread::{LoadingMode, Thread}, userdata};
+///
+/// struct Counter {
+///     count: u32,
+/// }
+///
+/// #[lua_methods]
+/// impl Counter {
+///     fn new() -> Counter {
+///         Counter { count: 0 }
+///     }
+///
+///     fn incr(&mut self, step: Option<u32>) -> u32 {
+///         self.count += step.unwrap_or(1);
+///         self.count
+///     }
+/// }
+///
+/// Thread::spawn(move |thread| {
+///     thread.register_module(userdata::class::<Counter>())?;
+///     let values = thread
+///         .caller_load("local c = Counter.new() c:incr(2) return c:incr()", None, LoadingMode::Text)?
+///         .call()?;
+///     assert_eq!(values.get_as::<u32>(0)?, 3);
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+///
+/// [`lua_function`]: attr.lua_function.html
+#[proc_macro_attribute]
+pub fn lua_methods(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemImpl);
+    function::lua_methods(attr.into(), item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `FromLua`, converting a table to a struct or an enum.
+///
+/// - structs with named fields are converted from tables with the same fields,
+/// - tuple structs are converted from sequences, except for newtypes which are
+///   converted from their inner value,
+/// - unit variants of enums are converted from their name,
+///   and the other variants from a table with a single field named after the variant,
+///   the value of the field being converted like a struct.
+///
+/// # Examples
+/// ```
+/// use pollua::{thread::{LoadingMode, Thread}, FromLua};
+///
+/// #[derive(Debug, PartialEq, FromLua)]
+/// enum Shape {
+///     Empty,
+///     Circle { radius: f64 },
+/// }
+///
+/// Thread::spawn(move |thread| {
+///     let values = thread
+///         .caller_load("return 'Empty', { Circle = { radius = 2 } }", None, LoadingMode::Text)?
+///         .call()?;
+///     assert_eq!(
+///         values.unpack::<(Shape, Shape)>()?,
+///         (Shape::Empty, Shape::Circle { radius: 2.0 })
+///     );
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+#[proc_macro_derive(FromLua)]
+pub fn derive_from_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    table::derive_from_lua(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `Pushable`, pushing a struct or an enum as a table.
+///
+/// The layout of the tables is the one expected by [`FromLua`].
+///
+/// [`FromLua`]: derive.FromLua.html
+#[proc_macro_derive(IntoLua)]
+pub fn derive_into_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    table::derive_into_lua(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
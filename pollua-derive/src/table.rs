@@ -0,0 +1,252 @@
+use proc_macro2::{Literal, TokenStream};
+use quote::{format_ident, quote};
+use syn::{ext::IdentExt, parse_quote, Data, DeriveInput, Error, Fields, Generics, Index, Result};
+
+pub fn derive_from_lua(input: DeriveInput) -> Result<TokenStream> {
+    let ident = &input.ident;
+    let owner = ident.unraw().to_string();
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let build = from_fields(&data.fields, quote!(Self), &owner);
+            quote!(#build)
+        }
+        Data::Enum(data) => {
+            let mut units = Vec::new();
+            let mut variants = Vec::new();
+            for variant in &data.variants {
+                let variant_ident = &variant.ident;
+                let name = variant_ident.unraw().to_string();
+                if let Fields::Unit = variant.fields {
+                    units.push(quote!(#name => return Ok(Self::#variant_ident),));
+                    continue;
+                }
+                let build = from_fields(
+                    &variant.fields,
+                    quote!(Self::#variant_ident),
+                    &format!("{}::{}", owner, name),
+                );
+                variants.push(quote! {
+                    if let Some(value) = ::pollua::derive::variant(
+                        thread,
+                        index,
+                        #name,
+                        |thread, index| #build,
+                    )? {
+                        return Ok(value);
+                    }
+                });
+            }
+            let units = if units.is_empty() {
+                quote!()
+            } else {
+                quote! {
+                    if let Some(tag) = ::pollua::derive::tag(thread, index) {
+                        match tag.as_str() {
+                            #(#units)*
+                            _ => {}
+                        }
+                    }
+                }
+            };
+            quote! {
+                #units
+                #(#variants)*
+                Err(::pollua::derive::variant_error(thread, index, #owner))
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "unions cannot be converted from Lua values",
+            ))
+        }
+    };
+
+    let generics = add_bounds(input.generics.clone(), quote!(::pollua::value::FromLua));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::pollua::value::FromLua for #ident #ty_generics #where_clause {
+            fn from_lua(
+                thread: &mut ::pollua::Thread,
+                index: ::std::os::raw::c_int,
+            ) -> ::pollua::LuaResult<Self> {
+                #body
+            }
+        }
+    })
+}
+
+pub fn derive_into_lua(input: DeriveInput) -> Result<TokenStream> {
+    let ident = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let values = match &data.fields {
+                Fields::Named(fields) => fields
+                    .named
+                    .iter()
+                    .map(|field| {
+                        let ident = &field.ident;
+                        quote!(&self.#ident)
+                    })
+                    .collect(),
+                fields => (0..fields.len())
+                    .map(|i| {
+                        let index = Index::from(i);
+                        quote!(&self.#index)
+                    })
+                    .collect(),
+            };
+            into_fields(&data.fields, values, None)
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().map(|variant| {
+                let variant_ident = &variant.ident;
+                let name = variant_ident.unraw().to_string();
+                let bindings: Vec<_> = match &variant.fields {
+                    Fields::Named(fields) => fields
+                        .named
+                        .iter()
+                        .map(|field| field.ident.clone().unwrap())
+                        .collect(),
+                    fields => (0..fields.len())
+                        .map(|i| format_ident!("field{}", i))
+                        .collect(),
+                };
+                let pattern = match &variant.fields {
+                    Fields::Named(_) => quote!(Self::#variant_ident { #(#bindings),* }),
+                    Fields::Unnamed(_) => quote!(Self::#variant_ident(#(#bindings),*)),
+                    Fields::Unit => quote!(Self::#variant_ident),
+                };
+                let values = bindings.iter().map(|binding| quote!(#binding)).collect();
+                let push = match &variant.fields {
+                    Fields::Unit => quote!(::pollua::value::Pushable::push(&#name, pusher)),
+                    fields => into_fields(fields, values, Some(&name)),
+                };
+                quote!(#pattern => { #push })
+            });
+            if data.variants.is_empty() {
+                quote!(match *self {})
+            } else {
+                quote! {
+                    match self {
+                        #(#arms)*
+                    }
+                }
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new(
+                data.union_token.span,
+                "unions cannot be converted to Lua values",
+            ))
+        }
+    };
+
+    let generics = add_bounds(input.generics.clone(), quote!(::pollua::value::Pushable));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::pollua::value::Pushable for #ident #ty_generics #where_clause {
+            fn push(&self, pusher: ::pollua::value::Pusher) -> ::pollua::LuaResult<()> {
+                #body
+            }
+        }
+    })
+}
+
+/// Generates the expression converting the value at `index` to `path` with `fields`.
+fn from_fields(fields: &Fields, path: TokenStream, owner: &str) -> TokenStream {
+    match fields {
+        Fields::Named(fields) => {
+            let fields = fields.named.iter().map(|field| {
+                let ident = &field.ident;
+                let key = field.ident.as_ref().unwrap().unraw().to_string();
+                quote!(#ident: ::pollua::derive::field(thread, index, #key, #owner)?)
+            });
+            quote! {{
+                ::pollua::derive::check_table(thread, index, #owner)?;
+                Ok(#path { #(#fields),* })
+            }}
+        }
+        Fields::Unnamed(fields) if fields.unnamed.len() == 1 => quote! {
+            Ok(#path(::pollua::value::FromLua::from_lua(thread, index)?))
+        },
+        Fields::Unnamed(fields) => {
+            // unsuffixed, as lua_Integer is not i64 before Lua 5.3
+            let elements = (1..=fields.unnamed.len())
+                .map(Literal::usize_unsuffixed)
+                .map(|n| quote!(::pollua::derive::element(thread, index, #n, #owner)?));
+            quote! {{
+                ::pollua::derive::check_table(thread, index, #owner)?;
+                Ok(#path(#(#elements),*))
+            }}
+        }
+        Fields::Unit => quote! {{
+            ::pollua::derive::check_table(thread, index, #owner)?;
+            Ok(#path)
+        }},
+    }
+}
+
+/// Generates the statements pushing `values`, the references to `fields`,
+/// wrapped in a table with a single `variant` field if it is set.
+fn into_fields(fields: &Fields, values: Vec<TokenStream>, variant: Option<&str>) -> TokenStream {
+    let len = fields.len() as i32;
+    match fields {
+        Fields::Unnamed(_) if len == 1 => {
+            let value = &values[0];
+            match variant {
+                Some(variant) => quote! {
+                    let mut table = ::pollua::derive::TableBuilder::new(pusher, 0, 1)?;
+                    table.field(#variant, #value)?;
+                    table.finish()
+                },
+                None => quote!(::pollua::value::Pushable::push(#value, pusher)),
+            }
+        }
+        _ => {
+            let (narr, nrec) = match fields {
+                Fields::Named(_) => (0, len),
+                _ => (len, 0),
+            };
+            let builder = match variant {
+                Some(variant) => quote! {
+                    ::pollua::derive::TableBuilder::variant(pusher, #variant, #narr, #nrec)?
+                },
+                None => quote!(::pollua::derive::TableBuilder::new(pusher, #narr, #nrec)?),
+            };
+            let sets =
+                fields
+                    .iter()
+                    .zip(values)
+                    .enumerate()
+                    .map(|(i, (field, value))| match &field.ident {
+                        Some(ident) => {
+                            let key = ident.unraw().to_string();
+                            quote!(table.field(#key, #value)?;)
+                        }
+                        None => {
+                            let n = Literal::usize_unsuffixed(i + 1);
+                            quote!(table.element(#n, #value)?;)
+                        }
+                    });
+            let binding = if len == 0 {
+                quote!(let table)
+            } else {
+                quote!(let mut table)
+            };
+            quote! {
+                #binding = #builder;
+                #(#sets)*
+                table.finish()
+            }
+        }
+    }
+}
+
+/// Adds `bound` to the type parameters of `generics`.
+fn add_bounds(mut generics: Generics, bound: TokenStream) -> Generics {
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#bound));
+    }
+    generics
+}
@@ -0,0 +1,333 @@
+use std::{ffi::CStr, mem::MaybeUninit, ptr};
+
+/// Information about an active function, as displayed in a stack trace.
+pub(super) struct Frame {
+    pub(super) name: String,
+    pub(super) source: String,
+    pub(super) line: libc::c_int,
+}
+
+/// A variable as shown by the client.
+pub(super) struct Variable {
+    pub(super) name: String,
+    pub(super) value: String,
+    pub(super) kind: &'static str,
+    /// Whether the value is a table that can be expanded.
+    pub(super) expandable: bool,
+}
+
+/// Returns the debug record of the function running at the given level.
+pub(super) unsafe fn activation(
+    l: *mut sys::lua_State,
+    level: libc::c_int,
+) -> Option<sys::lua_Debug> {
+    let mut ar = MaybeUninit::<sys::lua_Debug>::zeroed();
+    if sys::lua_getstack(l, level, ar.as_mut_ptr()) == 0 {
+        None
+    } else {
+        Some(ar.assume_init())
+    }
+}
+
+/// Returns the number of active functions.
+pub(super) unsafe fn stack_depth(l: *mut sys::lua_State) -> libc::c_int {
+    let mut ar = MaybeUninit::<sys::lua_Debug>::zeroed();
+    let mut level = 0;
+    while sys::lua_getstack(l, level, ar.as_mut_ptr()) != 0 {
+        level += 1;
+    }
+    level
+}
+
+/// Returns the chunk name of a debug record filled with the `S` option,
+/// stripped of its `@` or `=` prefix.
+pub(super) unsafe fn chunk_name(ar: &sys::lua_Debug) -> String {
+    if ar.source.is_null() {
+        return String::new();
+    }
+    let source = CStr::from_ptr(ar.source).to_string_lossy();
+    match source.as_bytes().first() {
+        Some(b'@') | Some(b'=') => source[1..].to_owned(),
+        _ => source.into_owned(),
+    }
+}
+
+pub(super) unsafe fn frame(l: *mut sys::lua_State, level: libc::c_int) -> Option<Frame> {
+    let mut ar = activation(l, level)?;
+    sys::lua_getinfo(l, b"nSl\0".as_ptr() as *const _, &mut ar);
+    let name = if !ar.name.is_null() {
+        CStr::from_ptr(ar.name).to_string_lossy().into_owned()
+    } else if !ar.what.is_null() && CStr::from_ptr(ar.what).to_bytes() == b"main" {
+        "main chunk".to_owned()
+    } else {
+        "?".to_owned()
+    };
+    Some(Frame {
+        name,
+        source: chunk_name(&ar),
+        line: ar.currentline,
+    })
+}
+
+/// Describes the value at `idx` without calling any metamethod.
+pub(super) unsafe fn describe(l: *mut sys::lua_State, idx: libc::c_int) -> (String, &'static str) {
+    let kind = CStr::from_ptr(sys::luaL_typename(l, idx))
+        .to_str()
+        .unwrap_or("?");
+    let kind = match kind {
+        "nil" => "nil",
+        "boolean" => "boolean",
+        "number" => "number",
+        "string" => "string",
+        "table" => "table",
+        "function" => "function",
+        "userdata" => "userdata",
+        "thread" => "thread",
+        _ => "unknown",
+    };
+    let value = match sys::lua_type(l, idx) {
+        sys::LUA_TNIL => "nil".to_owned(),
+        sys::LUA_TBOOLEAN => (sys::lua_toboolean(l, idx) != 0).to_string(),
+        sys::LUA_TNUMBER if sys::lua_isinteger(l, idx) != 0 => {
+            sys::lua_tointeger(l, idx).to_string()
+        }
+        sys::LUA_TNUMBER => {
+            let n = sys::lua_tonumber(l, idx);
+            if n.fract() == 0.0 && n.is_finite() {
+                format!("{:.1}", n)
+            } else {
+                n.to_string()
+            }
+        }
+        sys::LUA_TSTRING => {
+            let mut len = 0usize;
+            let s = sys::lua_tolstring(l, idx, &mut len);
+            let bytes = std::slice::from_raw_parts(s as *const u8, len);
+            format!("{:?}", String::from_utf8_lossy(bytes))
+        }
+        _ => format!("{}: {:p}", kind, sys::lua_topointer(l, idx)),
+    };
+    (value, kind)
+}
+
+/// Describes the value at the top of the stack and pops it,
+/// `keep` is called beforehand for tables so that they may be expanded later.
+unsafe fn take_variable<F>(l: *mut sys::lua_State, name: String, keep: &mut F) -> Variable
+where
+    F: FnMut(*mut sys::lua_State),
+{
+    let (value, kind) = describe(l, -1);
+    let expandable = sys::lua_type(l, -1) == sys::LUA_TTABLE;
+    if expandable {
+        keep(l);
+    }
+    sys::lua_pop(l, 1);
+    Variable {
+        name,
+        value,
+        kind,
+        expandable,
+    }
+}
+
+/// Lists the local variables of the function running at `level`.
+pub(super) unsafe fn locals<F>(
+    l: *mut sys::lua_State,
+    level: libc::c_int,
+    keep: &mut F,
+) -> Vec<Variable>
+where
+    F: FnMut(*mut sys::lua_State),
+{
+    let mut vars = Vec::new();
+    let ar = match activation(l, level) {
+        Some(ar) => ar,
+        None => return vars,
+    };
+    let mut n = 1;
+    loop {
+        let name = sys::lua_getlocal(l, &ar, n);
+        if name.is_null() {
+            break;
+        }
+        let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+        if name.starts_with('(') {
+            // internal temporaries such as `(for index)`
+            sys::lua_pop(l, 1);
+        } else {
+            vars.push(take_variable(l, name, keep));
+        }
+        n += 1;
+    }
+    vars
+}
+
+/// Lists the upvalues of the function running at `level`.
+pub(super) unsafe fn upvalues<F>(
+    l: *mut sys::lua_State,
+    level: libc::c_int,
+    keep: &mut F,
+) -> Vec<Variable>
+where
+    F: FnMut(*mut sys::lua_State),
+{
+    let mut vars = Vec::new();
+    let mut ar = match activation(l, level) {
+        Some(ar) => ar,
+        None => return vars,
+    };
+    sys::lua_getinfo(l, b"f\0".as_ptr() as *const _, &mut ar);
+    let mut n = 1;
+    loop {
+        let name = sys::lua_getupvalue(l, -1, n);
+        if name.is_null() {
+            break;
+        }
+        let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+        vars.push(take_variable(l, name, keep));
+        n += 1;
+    }
+    sys::lua_pop(l, 1);
+    vars
+}
+
+/// Lists the fields of the table at the top of the stack, without invoking metamethods.
+pub(super) unsafe fn fields<F>(l: *mut sys::lua_State, keep: &mut F) -> Vec<Variable>
+where
+    F: FnMut(*mut sys::lua_State),
+{
+    let mut vars = Vec::new();
+    let table = sys::lua_absindex(l, -1);
+    sys::lua_pushnil(l);
+    while sys::lua_next(l, table) != 0 {
+        let name = match sys::lua_type(l, -2) {
+            sys::LUA_TSTRING => {
+                let mut len = 0usize;
+                let s = sys::lua_tolstring(l, -2, &mut len);
+                String::from_utf8_lossy(std::slice::from_raw_parts(s as *const u8, len))
+                    .into_owned()
+            }
+            _ => format!("[{}]", describe(l, -2).0),
+        };
+        vars.push(take_variable(l, name, keep));
+    }
+    vars
+}
+
+/// Pushes a table whose fields are the locals and upvalues visible at `level`,
+/// falling back to the global environment for other names.
+pub(super) unsafe fn push_frame_env(l: *mut sys::lua_State, level: libc::c_int) -> bool {
+    let mut ar = match activation(l, level) {
+        Some(ar) => ar,
+        None => return false,
+    };
+    sys::lua_newtable(l);
+    let env = sys::lua_gettop(l);
+
+    // upvalues first so that locals shadow them
+    sys::lua_getinfo(l, b"f\0".as_ptr() as *const _, &mut ar);
+    let mut fallback = false;
+    let mut n = 1;
+    loop {
+        let name = sys::lua_getupvalue(l, -1, n);
+        if name.is_null() {
+            break;
+        }
+        if CStr::from_ptr(name).to_bytes() == b"_ENV" {
+            // used as the metatable's `__index`
+            sys::lua_newtable(l);
+            sys::lua_insert(l, -2);
+            sys::lua_setfield(l, -2, b"__index\0".as_ptr() as *const _);
+            sys::lua_setmetatable(l, env);
+            fallback = true;
+        } else {
+            sys::lua_setfield(l, env, name);
+        }
+        n += 1;
+    }
+    sys::lua_pop(l, 1);
+
+    let mut n = 1;
+    loop {
+        let name = sys::lua_getlocal(l, &ar, n);
+        if name.is_null() {
+            break;
+        }
+        if *name == b'(' as libc::c_char {
+            sys::lua_pop(l, 1);
+        } else {
+            sys::lua_setfield(l, env, name);
+        }
+        n += 1;
+    }
+
+    if !fallback {
+        sys::lua_newtable(l);
+        sys::lua_pushglobaltable(l);
+        sys::lua_setfield(l, -2, b"__index\0".as_ptr() as *const _);
+        sys::lua_setmetatable(l, env);
+    }
+    true
+}
+
+/// Evaluates `expression` in the context of the function running at `level`.
+/// On success, the result is left at the top of the stack.
+pub(super) unsafe fn evaluate(
+    l: *mut sys::lua_State,
+    level: libc::c_int,
+    expression: &str,
+) -> Result<(), String> {
+    let top = sys::lua_gettop(l);
+    let name = b"=eval\0".as_ptr() as *const libc::c_char;
+    let source = format!("return {}", expression);
+
+    if sys::luaL_loadbufferx(
+        l,
+        source.as_ptr() as *const _,
+        source.len(),
+        name,
+        ptr::null(),
+    ) != sys::LUA_OK
+    {
+        sys::lua_pop(l, 1);
+        if sys::luaL_loadbufferx(
+            l,
+            expression.as_ptr() as *const _,
+            expression.len(),
+            name,
+            ptr::null(),
+        ) != sys::LUA_OK
+        {
+            return Err(pop_message(l, top));
+        }
+    }
+    if !push_frame_env(l, level) {
+        sys::lua_settop(l, top);
+        return Err("invalid frame".to_owned());
+    }
+    // the environment of a main chunk is its first upvalue
+    #[cfg(LUA_VERSION = "5.2")]
+    {
+        if sys::lua_setupvalue(l, -2, 1).is_null() {
+            sys::lua_pop(l, 1);
+        }
+    }
+    #[cfg(not(LUA_VERSION = "5.2"))]
+    sys::lua_setfenv(l, -2);
+    if sys::lua_pcall(l, 0, 1, 0) != sys::LUA_OK {
+        return Err(pop_message(l, top));
+    }
+    Ok(())
+}
+
+unsafe fn pop_message(l: *mut sys::lua_State, top: libc::c_int) -> String {
+    let message = if sys::lua_type(l, -1) == sys::LUA_TSTRING {
+        let mut len = 0usize;
+        let s = sys::lua_tolstring(l, -1, &mut len);
+        String::from_utf8_lossy(std::slice::from_raw_parts(s as *const u8, len)).into_owned()
+    } else {
+        describe(l, -1).0
+    };
+    sys::lua_settop(l, top);
+    message
+}
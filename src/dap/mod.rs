@@ -0,0 +1,735 @@
+//! A [Debug Adapter Protocol] server for stepping through scripts run by a [`Thread`].
+//!
+//! The server runs on the same OS thread as the Lua code it debugs:
+//! it is driven by a line hook and blocks the interpreter while the client inspects a stopped
+//! program. Requests sent while the program runs are read by a background thread and processed
+//! on the next executed line.
+//!
+//! # Examples
+//! ```no_run
+//! use pollua::{dap::Server, thread::LoadingMode, Thread};
+//!
+//! Thread::spawn(move |thread| {
+//!     let server = Server::listen("127.0.0.1:4711").unwrap().accept().unwrap();
+//!     thread.attach_debugger(server).unwrap();
+//!     thread
+//!         .caller_load("print('hello')", Some("@hello.lua"), LoadingMode::Text)
+//!         .and_then(|c| c.call())
+//!         .unwrap();
+//!     thread.detach_debugger();
+//! })
+//! .unwrap()
+//! ```
+//!
+//! [Debug Adapter Protocol]: https://microsoft.github.io/debug-adapter-protocol/
+//! [`Thread`]: ../thread/struct.Thread.html
+
+use crate::{
+    thread::{StackCheck, Thread},
+    util,
+};
+
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread as os_thread,
+};
+
+mod inspect;
+mod protocol;
+
+pub use protocol::{Listener, Server};
+
+/// DAP thread identifier of the debugged `lua_State`.
+const THREAD_ID: i64 = 1;
+
+/// Registry keys, the address of each static is used as a light userdata.
+static SESSION_KEY: u8 = 0;
+static HANDLES_KEY: u8 = 0;
+
+/// Name of the metatable of the userdata owning the session.
+const SESSION_METATABLE: &[u8] = b"pollua.DebugSession\0";
+
+/// What the debugger should do on the next executed line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StepMode {
+    Run,
+    /// Stop as soon as possible, with the given reason.
+    Pause(&'static str),
+    /// Stop at the next line.
+    In,
+    /// Stop at the next line whose stack depth is at most the given depth.
+    Over(libc::c_int),
+    /// Stop at the next line whose stack depth is below the given depth.
+    Out(libc::c_int),
+}
+
+/// Something the client can expand with a `variables` request.
+#[derive(Debug, Clone, Copy)]
+enum Handle {
+    Locals(libc::c_int),
+    Upvalues(libc::c_int),
+    /// A table stored in the handles table of the registry.
+    Table(sys::lua_Integer),
+}
+
+/// Outcome of a request processed while stopped.
+enum Flow {
+    Stay,
+    Resume,
+}
+
+struct Session {
+    output: protocol::Output,
+    requests: Receiver<Value>,
+    /// Line breakpoints, by source.
+    breakpoints: HashMap<String, Vec<libc::c_int>>,
+    mode: StepMode,
+    handles: Vec<Handle>,
+    /// Set when the client disconnected, the session is freed once the hook returns.
+    disconnected: bool,
+    /// Keeps the socket open until the session ends.
+    _server: Server,
+}
+
+impl Thread {
+    /// Attaches a debugger client to this thread.
+    ///
+    /// The function blocks until the client is done configuring the session (`configurationDone`
+    /// request), after which the code run by this thread can be stepped through.
+    /// Replaces any hook previously set on this thread.
+    pub fn attach_debugger(&mut self, mut server: Server) -> io::Result<()> {
+        let _check = StackCheck::new(self.as_raw(), 0);
+        let (sender, requests) = mpsc::channel();
+        let mut reader = std::mem::replace(&mut server.reader, Box::new(io::empty()));
+        let writer = std::mem::replace(&mut server.writer, Box::new(io::sink()));
+
+        os_thread::Builder::new()
+            .name("pollua-dap".to_owned())
+            .spawn(move || {
+                while let Ok(Some(message)) = protocol::read_message(&mut reader) {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        let mut session = Box::new(Session {
+            output: protocol::Output::new(writer),
+            requests,
+            breakpoints: HashMap::new(),
+            mode: StepMode::Run,
+            handles: Vec::new(),
+            disconnected: false,
+            _server: server,
+        });
+        let l = self.as_raw().as_ptr();
+
+        unsafe {
+            loop {
+                let request = session
+                    .requests
+                    .recv()
+                    .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                if request["command"] == "configurationDone" {
+                    session.output.respond(&request, Value::Null)?;
+                    break;
+                }
+                session.handle(&request)?;
+                if session.disconnected {
+                    return Err(io::ErrorKind::ConnectionAborted.into());
+                }
+            }
+
+            // the session is owned by the registry from now on
+            let slot =
+                sys::lua_newuserdata(l, std::mem::size_of::<*mut Session>()) as *mut *mut Session;
+            slot.write(Box::into_raw(session));
+            util::push_finalized_metatable(l, SESSION_METATABLE, Some(session_gc));
+            sys::lua_setmetatable(l, -2);
+            sys::lua_rawsetp(l, sys::LUA_REGISTRYINDEX, key(&SESSION_KEY));
+            sys::lua_sethook(l, Some(hook), sys::LUA_MASKLINE, 0);
+        }
+        Ok(())
+    }
+
+    /// Ends the debugging session, if any.
+    ///
+    /// The client is told that the program terminated.
+    /// Returns whether a debugger was attached.
+    pub fn detach_debugger(&mut self) -> bool {
+        let _check = StackCheck::new(self.as_raw(), 0);
+        let l = self.as_raw().as_ptr();
+        let attached = unsafe {
+            let attached = match session(l) {
+                Some(session) => {
+                    let _ = session.output.event("terminated", json!({}));
+                    let _ = session.output.event("exited", json!({ "exitCode": 0 }));
+                    true
+                }
+                None => false,
+            };
+            detach(l);
+            attached
+        };
+        // run the finalizer right away to close the connection,
+        // errors raised by the finalizers of other values are ignored
+        let _ = self.gc().collect();
+        attached
+    }
+}
+
+#[inline]
+fn key(k: &'static u8) -> *const libc::c_void {
+    k as *const u8 as *const libc::c_void
+}
+
+/// Returns the session attached to `l`.
+unsafe fn session<'a>(l: *mut sys::lua_State) -> Option<&'a mut Session> {
+    sys::lua_rawgetp(l, sys::LUA_REGISTRYINDEX, key(&SESSION_KEY));
+    let slot = sys::lua_touserdata(l, -1) as *mut *mut Session;
+    sys::lua_pop(l, 1);
+    if slot.is_null() || (*slot).is_null() {
+        None
+    } else {
+        Some(&mut **slot)
+    }
+}
+
+/// Removes the hook and lets the garbage collector free the session.
+///
+/// Any reference to the session must be dropped before calling this function.
+unsafe fn detach(l: *mut sys::lua_State) {
+    sys::lua_sethook(l, None, 0, 0);
+    sys::lua_pushnil(l);
+    sys::lua_rawsetp(l, sys::LUA_REGISTRYINDEX, key(&SESSION_KEY));
+    sys::lua_pushnil(l);
+    sys::lua_rawsetp(l, sys::LUA_REGISTRYINDEX, key(&HANDLES_KEY));
+}
+
+unsafe extern "C" fn session_gc(l: *mut sys::lua_State) -> libc::c_int {
+    let slot =
+        sys::luaL_testudata(l, 1, SESSION_METATABLE.as_ptr() as *const _) as *mut *mut Session;
+    if !slot.is_null() && !(*slot).is_null() {
+        drop(Box::from_raw(*slot));
+        *slot = ptr::null_mut();
+    }
+    0
+}
+
+unsafe extern "C" fn hook(l: *mut sys::lua_State, ar: *mut sys::lua_Debug) {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| match session(l) {
+        Some(session) => session.on_line(l, &mut *ar).map(|()| session.disconnected),
+        None => Ok(false),
+    }));
+    match result {
+        Ok(Ok(false)) => (),
+        // the client is gone or misbehaved, let the program run freely
+        Ok(Ok(true)) | Ok(Err(_)) | Err(_) => detach(l),
+    }
+}
+
+fn integer(request: &Value, pointer: &str) -> Option<i64> {
+    request.pointer(pointer).and_then(Value::as_i64)
+}
+
+impl Session {
+    /// Called by the hook before executing a new line.
+    unsafe fn on_line(
+        &mut self,
+        l: *mut sys::lua_State,
+        ar: &mut sys::lua_Debug,
+    ) -> io::Result<()> {
+        loop {
+            match self.requests.try_recv() {
+                Ok(request) => {
+                    self.handle(&request)?;
+                    if self.disconnected {
+                        return Ok(());
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+
+        let reason = match self.mode {
+            StepMode::Run => None,
+            StepMode::Pause(reason) => Some(reason),
+            StepMode::In => Some("step"),
+            StepMode::Over(depth) if inspect::stack_depth(l) <= depth => Some("step"),
+            StepMode::Out(depth) if inspect::stack_depth(l) < depth => Some("step"),
+            _ => None,
+        };
+        let reason = match reason {
+            Some(reason) => reason,
+            None if self.hits_breakpoint(l, ar) => "breakpoint",
+            None => return Ok(()),
+        };
+        self.stop(l, reason)
+    }
+
+    unsafe fn hits_breakpoint(&self, l: *mut sys::lua_State, ar: &mut sys::lua_Debug) -> bool {
+        let line = ar.currentline;
+        if !self.breakpoints.values().any(|lines| lines.contains(&line)) {
+            return false;
+        }
+        sys::lua_getinfo(l, b"S\0".as_ptr() as *const _, ar);
+        let chunk = inspect::chunk_name(ar);
+        self.breakpoints
+            .iter()
+            .any(|(source, lines)| lines.contains(&line) && source_matches(source, &chunk))
+    }
+
+    /// Blocks the program until the client resumes it.
+    unsafe fn stop(&mut self, l: *mut sys::lua_State, reason: &str) -> io::Result<()> {
+        self.mode = StepMode::Run;
+        self.output.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )?;
+        let depth = inspect::stack_depth(l);
+
+        let result = loop {
+            let request = match self.requests.recv() {
+                Ok(request) => request,
+                Err(_) => break Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+            let mode = match request["command"].as_str() {
+                Some("continue") => StepMode::Run,
+                Some("next") => StepMode::Over(depth),
+                Some("stepIn") => StepMode::In,
+                Some("stepOut") => StepMode::Out(depth),
+                _ => match self.handle_stopped(l, &request) {
+                    Ok(Flow::Stay) => continue,
+                    Ok(Flow::Resume) => break Ok(()),
+                    Err(e) => break Err(e),
+                },
+            };
+            self.mode = mode;
+            let body = if mode == StepMode::Run {
+                json!({ "allThreadsContinued": true })
+            } else {
+                Value::Null
+            };
+            break self.output.respond(&request, body);
+        };
+
+        self.handles.clear();
+        sys::lua_pushnil(l);
+        sys::lua_rawsetp(l, sys::LUA_REGISTRYINDEX, key(&HANDLES_KEY));
+        result
+    }
+
+    /// Handles the requests that are valid whether the program is running or not.
+    fn handle(&mut self, request: &Value) -> io::Result<()> {
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                self.output.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                    }),
+                )?;
+                self.output.event("initialized", json!({}))
+            }
+            "launch" | "attach" => {
+                if request["arguments"]["stopOnEntry"] == true {
+                    self.mode = StepMode::Pause("entry");
+                }
+                self.output.respond(request, Value::Null)
+            }
+            "setBreakpoints" => {
+                let args = &request["arguments"];
+                let source = args["source"]["path"]
+                    .as_str()
+                    .or_else(|| args["source"]["name"].as_str())
+                    .unwrap_or_default()
+                    .to_owned();
+                let lines: Vec<libc::c_int> = args["breakpoints"]
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|bp| bp["line"].as_i64())
+                    .map(|line| line as libc::c_int)
+                    .collect();
+                let body = json!({
+                    "breakpoints": lines
+                        .iter()
+                        .map(|line| json!({ "verified": true, "line": line }))
+                        .collect::<Vec<_>>(),
+                });
+                self.breakpoints.insert(source, lines);
+                self.output.respond(request, body)
+            }
+            "setExceptionBreakpoints" | "configurationDone" => {
+                self.output.respond(request, Value::Null)
+            }
+            "threads" => self.output.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+            ),
+            "pause" => {
+                self.mode = StepMode::Pause("pause");
+                self.output.respond(request, Value::Null)
+            }
+            "disconnect" => {
+                self.disconnected = true;
+                self.mode = StepMode::Run;
+                self.output.respond(request, Value::Null)
+            }
+            "continue" | "next" | "stepIn" | "stepOut" | "stackTrace" | "scopes" | "variables"
+            | "evaluate" => self.output.fail(request, "the program is not stopped"),
+            command => self
+                .output
+                .fail(request, &format!("unsupported request: {}", command)),
+        }
+    }
+
+    /// Handles the inspection requests, only valid while the program is stopped.
+    unsafe fn handle_stopped(
+        &mut self,
+        l: *mut sys::lua_State,
+        request: &Value,
+    ) -> io::Result<Flow> {
+        match request["command"].as_str().unwrap_or_default() {
+            "stackTrace" => {
+                let start = integer(request, "/arguments/startFrame").unwrap_or(0);
+                let levels = integer(request, "/arguments/levels")
+                    .filter(|&levels| levels > 0)
+                    .unwrap_or(i64::MAX);
+                let mut frames = Vec::new();
+                let mut level = start as libc::c_int;
+                while (frames.len() as i64) < levels {
+                    let frame = match inspect::frame(l, level) {
+                        Some(frame) => frame,
+                        None => break,
+                    };
+                    frames.push(json!({
+                        "id": level + 1,
+                        "name": frame.name,
+                        "source": { "name": frame.source, "path": frame.source },
+                        "line": frame.line.max(0),
+                        "column": 1,
+                    }));
+                    level += 1;
+                }
+                let total = inspect::stack_depth(l);
+                self.output.respond(
+                    request,
+                    json!({ "stackFrames": frames, "totalFrames": total }),
+                )?;
+            }
+            "scopes" => {
+                let level = integer(request, "/arguments/frameId").unwrap_or(1) as libc::c_int - 1;
+                let locals = self.add_handle(Handle::Locals(level));
+                let upvalues = self.add_handle(Handle::Upvalues(level));
+                self.output.respond(
+                    request,
+                    json!({ "scopes": [
+                        { "name": "Locals", "variablesReference": locals, "expensive": false },
+                        { "name": "Upvalues", "variablesReference": upvalues, "expensive": false },
+                    ]}),
+                )?;
+            }
+            "variables" => {
+                let reference = integer(request, "/arguments/variablesReference").unwrap_or(0);
+                let handle = match self.handles.get((reference - 1) as usize) {
+                    Some(&handle) if reference > 0 => handle,
+                    _ => {
+                        self.output.fail(request, "invalid variables reference")?;
+                        return Ok(Flow::Stay);
+                    }
+                };
+                let mut kept = Vec::new();
+                let mut keep = |l| kept.push(table_handle(l));
+                let variables = match handle {
+                    Handle::Locals(level) => inspect::locals(l, level, &mut keep),
+                    Handle::Upvalues(level) => inspect::upvalues(l, level, &mut keep),
+                    Handle::Table(slot) => {
+                        push_handles(l);
+                        sys::lua_rawgeti(l, -1, slot);
+                        let fields = inspect::fields(l, &mut keep);
+                        sys::lua_pop(l, 2);
+                        fields
+                    }
+                };
+                let mut kept = kept.into_iter();
+                let variables: Vec<Value> = variables
+                    .into_iter()
+                    .map(|var| {
+                        let reference = if var.expandable {
+                            kept.next().map_or(0, |h| self.add_handle(h))
+                        } else {
+                            0
+                        };
+                        json!({
+                            "name": var.name,
+                            "value": var.value,
+                            "type": var.kind,
+                            "variablesReference": reference,
+                        })
+                    })
+                    .collect();
+                self.output
+                    .respond(request, json!({ "variables": variables }))?;
+            }
+            "evaluate" => {
+                let level = integer(request, "/arguments/frameId").unwrap_or(1) as libc::c_int - 1;
+                let expression = request["arguments"]["expression"]
+                    .as_str()
+                    .unwrap_or_default();
+                match inspect::evaluate(l, level, expression) {
+                    Ok(()) => {
+                        let (value, kind) = inspect::describe(l, -1);
+                        let reference = if sys::lua_type(l, -1) == sys::LUA_TTABLE {
+                            let handle = table_handle(l);
+                            self.add_handle(handle)
+                        } else {
+                            0
+                        };
+                        sys::lua_pop(l, 1);
+                        self.output.respond(
+                            request,
+                            json!({
+                                "result": value,
+                                "type": kind,
+                                "variablesReference": reference,
+                            }),
+                        )?;
+                    }
+                    Err(message) => self.output.fail(request, &message)?,
+                }
+            }
+            "disconnect" => {
+                self.handle(request)?;
+                return Ok(Flow::Resume);
+            }
+            _ => self.handle(request)?,
+        }
+        Ok(Flow::Stay)
+    }
+
+    fn add_handle(&mut self, handle: Handle) -> i64 {
+        self.handles.push(handle);
+        self.handles.len() as i64
+    }
+}
+
+/// Pushes the table holding the values that can be expanded while stopped.
+unsafe fn push_handles(l: *mut sys::lua_State) {
+    if sys::lua_rawgetp(l, sys::LUA_REGISTRYINDEX, key(&HANDLES_KEY)) != sys::LUA_TTABLE {
+        sys::lua_pop(l, 1);
+        sys::lua_newtable(l);
+        sys::lua_pushvalue(l, -1);
+        sys::lua_rawsetp(l, sys::LUA_REGISTRYINDEX, key(&HANDLES_KEY));
+    }
+}
+
+/// Keeps the table at the top of the stack alive until the program resumes.
+unsafe fn table_handle(l: *mut sys::lua_State) -> Handle {
+    push_handles(l);
+    let slot = sys::lua_rawlen(l, -1) as sys::lua_Integer + 1;
+    sys::lua_pushvalue(l, -2);
+    sys::lua_rawseti(l, -2, slot);
+    sys::lua_pop(l, 1);
+    Handle::Table(slot)
+}
+
+/// Whether a breakpoint source designates the given chunk.
+fn source_matches(source: &str, chunk: &str) -> bool {
+    fn normalize(path: &str) -> String {
+        path.replace('\\', "/")
+    }
+    let (source, chunk) = (normalize(source), normalize(chunk));
+    source == chunk
+        || source.ends_with(&format!("/{}", chunk.trim_start_matches("./")))
+        || chunk.ends_with(&format!("/{}", source))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+    use std::{io::BufReader, net::TcpStream};
+
+    const SCRIPT: &str = "\
+local t = { answer = 42 }
+local function add(a, b)
+  local sum = a + b
+  return sum
+end
+local x = add(1, 2)
+local y = x * 2
+result = y + t.answer
+";
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        seq: i64,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let message = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            protocol::write_message(&mut self.writer, &message).unwrap();
+            loop {
+                let message = self.next();
+                if message["type"] == "response" && message["request_seq"] == self.seq {
+                    assert_eq!(message["command"], command);
+                    return message;
+                }
+            }
+        }
+
+        fn next(&mut self) -> Value {
+            protocol::read_message(&mut self.reader).unwrap().unwrap()
+        }
+
+        fn event(&mut self, name: &str) -> Value {
+            loop {
+                let message = self.next();
+                if message["type"] == "event" && message["event"] == name {
+                    return message["body"].clone();
+                }
+            }
+        }
+
+        fn top_frame(&mut self) -> Value {
+            let trace = self.request("stackTrace", json!({ "threadId": THREAD_ID }));
+            trace["body"]["stackFrames"][0].clone()
+        }
+
+        fn variables(&mut self, reference: &Value) -> Vec<Value> {
+            let response = self.request("variables", json!({ "variablesReference": reference }));
+            response["body"]["variables"].as_array().unwrap().clone()
+        }
+    }
+
+    fn find<'a>(variables: &'a [Value], name: &str) -> &'a Value {
+        variables
+            .iter()
+            .find(|v| v["name"] == name)
+            .unwrap_or_else(|| panic!("no variable named {}", name))
+    }
+
+    #[test]
+    fn test_dap_session() {
+        let listener = Server::listen("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = os_thread::spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let mut client = Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+                seq: 0,
+            };
+
+            let init = client.request("initialize", json!({ "adapterID": "pollua" }));
+            assert_eq!(init["success"], true);
+            client.event("initialized");
+            let bps = client.request(
+                "setBreakpoints",
+                json!({ "source": { "path": "/scripts/test.lua" }, "breakpoints": [{ "line": 3 }] }),
+            );
+            assert_eq!(bps["body"]["breakpoints"][0]["verified"], true);
+            client.request("launch", json!({}));
+            client.request("configurationDone", json!({}));
+
+            // breakpoint inside `add`
+            let stopped = client.event("stopped");
+            assert_eq!(stopped["reason"], "breakpoint");
+            let frame = client.top_frame();
+            assert_eq!(frame["line"], 3);
+            assert_eq!(frame["name"], "add");
+            assert_eq!(frame["source"]["name"], "test.lua");
+            let trace = client.request("stackTrace", json!({ "threadId": THREAD_ID }));
+            assert_eq!(trace["body"]["stackFrames"][1]["name"], "main chunk");
+            assert_eq!(trace["body"]["stackFrames"][1]["line"], 6);
+
+            let scopes = client.request("scopes", json!({ "frameId": frame["id"] }));
+            let locals = client.variables(&scopes["body"]["scopes"][0]["variablesReference"]);
+            assert_eq!(find(&locals, "a")["value"], "1");
+            assert_eq!(find(&locals, "b")["value"], "2");
+
+            let eval = client.request(
+                "evaluate",
+                json!({ "expression": "a * 10 + b", "frameId": frame["id"] }),
+            );
+            assert_eq!(eval["body"]["result"], "12");
+            let eval = client.request(
+                "evaluate",
+                json!({ "expression": "nope(", "frameId": frame["id"] }),
+            );
+            assert_eq!(eval["success"], false);
+
+            // step out of `add`, back to the main chunk
+            client.request("next", json!({ "threadId": THREAD_ID }));
+            assert_eq!(client.event("stopped")["reason"], "step");
+            assert_eq!(client.top_frame()["line"], 4);
+            client.request("stepOut", json!({ "threadId": THREAD_ID }));
+            assert_eq!(client.event("stopped")["reason"], "step");
+            let frame = client.top_frame();
+            assert_eq!(frame["name"], "main chunk");
+
+            client.request("next", json!({ "threadId": THREAD_ID }));
+            client.event("stopped");
+            let frame = client.top_frame();
+            assert_eq!(frame["line"], 8);
+            let scopes = client.request("scopes", json!({ "frameId": frame["id"] }));
+            let locals = client.variables(&scopes["body"]["scopes"][0]["variablesReference"]);
+            assert_eq!(find(&locals, "x")["value"], "3");
+            assert_eq!(find(&locals, "y")["value"], "6");
+            let table = find(&locals, "t")["variablesReference"].clone();
+            assert_ne!(table, 0);
+            let fields = client.variables(&table);
+            assert_eq!(find(&fields, "answer")["value"], "42");
+
+            let eval = client.request(
+                "evaluate",
+                json!({ "expression": "y + t.answer", "frameId": frame["id"] }),
+            );
+            assert_eq!(eval["body"]["result"], "48");
+
+            client.request("continue", json!({ "threadId": THREAD_ID }));
+            client.event("terminated");
+        });
+
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let server = listener.accept().unwrap();
+            thread.attach_debugger(server).unwrap();
+            thread
+                .caller_load(SCRIPT, Some("@test.lua"), LoadingMode::Text)
+                .and_then(|c| c.call())
+                .unwrap();
+            assert!(thread.detach_debugger());
+            assert!(!thread.detach_debugger());
+
+            let l = thread.as_raw().as_ptr();
+            unsafe {
+                sys::lua_getglobal(l, b"result\0".as_ptr() as *const _);
+                assert_eq!(sys::lua_tointeger(l, -1), 48);
+                sys::lua_pop(l, 1);
+            }
+        })
+        .unwrap();
+        client.join().unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+use serde_json::{json, Value};
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    str,
+};
+
+/// A bidirectional channel to a DAP client.
+///
+/// Messages are framed with a `Content-Length` header as described in the
+/// [base protocol].
+///
+/// [base protocol]: https://microsoft.github.io/debug-adapter-protocol/overview#base-protocol
+pub struct Server {
+    pub(super) reader: Box<dyn BufRead + Send>,
+    pub(super) writer: Box<dyn Write + Send>,
+    /// Handle used to unblock the reader when the session ends.
+    pub(super) socket: Option<TcpStream>,
+}
+
+impl Server {
+    /// Creates a server communicating through the process' standard input and output.
+    pub fn stdio() -> Server {
+        Server::from_streams(io::stdin(), io::stdout())
+    }
+
+    /// Creates a server from arbitrary streams.
+    pub fn from_streams<R, W>(reader: R, writer: W) -> Server
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        Server {
+            reader: Box::new(BufReader::new(reader)),
+            writer: Box::new(writer),
+            socket: None,
+        }
+    }
+
+    /// Listens for a DAP client on a local TCP socket.
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<Listener> {
+        TcpListener::bind(addr).map(Listener)
+    }
+}
+
+/// A TCP socket waiting for a DAP client, created by [`Server::listen`].
+///
+/// [`Server::listen`]: struct.Server.html#method.listen
+#[derive(Debug)]
+pub struct Listener(TcpListener);
+
+impl Listener {
+    /// Returns the local address this listener is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.0.local_addr()
+    }
+
+    /// Blocks until a client connects.
+    pub fn accept(&self) -> io::Result<Server> {
+        let (stream, _) = self.0.accept()?;
+        Ok(Server {
+            reader: Box::new(BufReader::new(stream.try_clone()?)),
+            writer: Box::new(stream.try_clone()?),
+            socket: Some(stream),
+        })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(socket) = &self.socket {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Reads a single message, returns `None` when the stream is closed.
+pub(crate) fn read_message<R: BufRead + ?Sized>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(invalid_data)?);
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid_data("missing Content-Length header"))?;
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(invalid_data)
+}
+
+/// Writes a single message.
+pub(crate) fn write_message<W: Write + ?Sized>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Outgoing message builder, keeps track of the sequence numbers.
+pub(crate) struct Output {
+    writer: Box<dyn Write + Send>,
+    seq: i64,
+}
+
+impl Output {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Output {
+        Output { writer, seq: 0 }
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &message)
+    }
+
+    pub(crate) fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    pub(crate) fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    pub(crate) fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+}
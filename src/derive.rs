@@ -0,0 +1,267 @@
+use crate::{
+    thread::{self, Thread, ThreadRef},
+    userdata::{self, UserData},
+    value::{self, FromLua, IntoLuaMulti, LuaStr, Pushable, Pusher},
+    Error, ErrorKind, LuaResult,
+};
+
+use std::{
+    cell::{Ref, RefMut},
+    slice,
+};
+
+/// Runs the body of a generated C function, raising errors and panics as Lua errors.
+///
+/// # Safety
+/// `l` must be the state the C function was called with.
+#[inline]
+pub unsafe fn call<F>(l: *mut sys::lua_State, f: F) -> libc::c_int
+where
+    F: FnOnce(&mut Thread) -> LuaResult<libc::c_int>,
+{
+    thread::run_callback(l, f)
+}
+
+/// Converts the argument `n`, named `param`, of the function `func`.
+pub fn arg<T: FromLua>(
+    thread: &mut Thread,
+    n: libc::c_int,
+    func: &str,
+    param: &str,
+) -> LuaResult<T> {
+    let nargs = unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) };
+    value::from_lua_nth(thread, 1, nargs, n - 1).map_err(|e| arg_error(n, func, param, e))
+}
+
+/// Borrows the string argument `n`, named `param`, of the function `func`.
+///
+/// The string is borrowed for as long as `scope`, a local of the generated C function,
+/// so that the called function cannot keep it.
+///
+/// # Safety
+/// `scope` must not outlive the generated C function.
+pub unsafe fn str_arg<'a>(
+    _scope: &'a (),
+    thread: &mut Thread,
+    n: libc::c_int,
+    func: &str,
+    param: &str,
+) -> LuaResult<&'a LuaStr> {
+    let nargs = sys::lua_gettop(thread.as_raw().as_ptr());
+    if n > nargs {
+        let e = Error::new(
+            ErrorKind::Conversion,
+            Some("expected string, got no value".to_owned()),
+        );
+        return Err(arg_error(n, func, param, e));
+    }
+    value::borrow_str(thread, n).map_err(|e| arg_error(n, func, param, e))
+}
+
+/// Borrows the value a method is called on.
+pub fn this<'a, T: UserData>(thread: &'a mut Thread, func: &str) -> LuaResult<Ref<'a, T>> {
+    userdata::borrow(thread, 1).map_err(|e| arg_error(1, func, "self", e))
+}
+
+/// Mutably borrows the value a method is called on.
+pub fn this_mut<'a, T: UserData>(thread: &'a mut Thread, func: &str) -> LuaResult<RefMut<'a, T>> {
+    userdata::borrow_mut(thread, 1).map_err(|e| arg_error(1, func, "self", e))
+}
+
+/// Pushes the results of a generated C function.
+#[inline]
+pub fn results<R: IntoLuaMulti>(thread: &mut Thread, results: R) -> LuaResult<libc::c_int> {
+    results.push_multi(Pusher(ThreadRef::from_ref(thread)))
+}
+
+/// Adds the argument and function names to an error, raised like with `luaL_argerror`.
+fn arg_error(n: libc::c_int, func: &str, param: &str, e: Error) -> Error {
+    Error::new(
+        ErrorKind::Runtime,
+        Some(format!(
+            "bad argument #{} '{}' to '{}' ({})",
+            n,
+            param,
+            func,
+            e.msg().unwrap_or_else(|| e.kind().as_str())
+        )),
+    )
+}
+
+/// Returns an error if the value at `index` is not a table.
+pub fn check_table(thread: &mut Thread, index: libc::c_int, owner: &str) -> LuaResult<()> {
+    if unsafe { sys::lua_type(thread.as_raw().as_ptr(), index) } == sys::LUA_TTABLE {
+        Ok(())
+    } else {
+        Err(value::type_error(thread, index, owner))
+    }
+}
+
+/// Converts the field `key` of the table at the absolute `index`.
+pub fn field<T: FromLua>(
+    thread: &mut Thread,
+    index: libc::c_int,
+    key: &str,
+    owner: &str,
+) -> LuaResult<T> {
+    let raw = thread.as_raw();
+    value::reserve(raw, 1)?;
+    unsafe {
+        sys::lua_pushlstring(raw.as_ptr(), key.as_ptr() as *const libc::c_char, key.len());
+        sys::lua_rawget(raw.as_ptr(), index);
+    }
+    converted(thread).map_err(|e| context(e, format_args!("field '{}' of {}", key, owner)))
+}
+
+/// Converts the element `n` of the table at the absolute `index`.
+pub fn element<T: FromLua>(
+    thread: &mut Thread,
+    index: libc::c_int,
+    n: sys::lua_Integer,
+    owner: &str,
+) -> LuaResult<T> {
+    let raw = thread.as_raw();
+    value::reserve(raw, 1)?;
+    unsafe { sys::lua_rawgeti(raw.as_ptr(), index, n) };
+    converted(thread).map_err(|e| context(e, format_args!("element #{} of {}", n, owner)))
+}
+
+/// Converts the value of the variant `key` with `f`,
+/// if the value at the absolute `index` is a table with this field.
+pub fn variant<T, F>(
+    thread: &mut Thread,
+    index: libc::c_int,
+    key: &str,
+    f: F,
+) -> LuaResult<Option<T>>
+where
+    F: FnOnce(&mut Thread, libc::c_int) -> LuaResult<T>,
+{
+    let raw = thread.as_raw();
+    value::reserve(raw, 1)?;
+    let l = raw.as_ptr();
+    unsafe {
+        if sys::lua_type(l, index) != sys::LUA_TTABLE {
+            return Ok(None);
+        }
+        sys::lua_pushlstring(l, key.as_ptr() as *const libc::c_char, key.len());
+        if sys::lua_rawget(l, index) == sys::LUA_TNIL {
+            sys::lua_pop(l, 1);
+            return Ok(None);
+        }
+        let value = f(thread, sys::lua_gettop(l));
+        sys::lua_pop(l, 1);
+        value.map(Some)
+    }
+}
+
+/// Returns the string at `index`, used as the name of a unit variant.
+pub fn tag(thread: &mut Thread, index: libc::c_int) -> Option<String> {
+    let l = thread.as_raw().as_ptr();
+    unsafe {
+        if sys::lua_type(l, index) != sys::LUA_TSTRING {
+            return None;
+        }
+        let mut len = 0;
+        let ptr = sys::lua_tolstring(l, index, &mut len) as *const u8;
+        Some(String::from_utf8_lossy(slice::from_raw_parts(ptr, len)).into_owned())
+    }
+}
+
+/// Returns the error for a value at `index` that matches no variant of `owner`.
+pub fn variant_error(thread: &mut Thread, index: libc::c_int, owner: &str) -> Error {
+    match tag(thread, index) {
+        Some(tag) => Error::new(
+            ErrorKind::Conversion,
+            Some(format!("unknown variant '{}' of {}", tag, owner)),
+        ),
+        None => value::type_error(thread, index, owner),
+    }
+}
+
+/// Converts and pops the value at the top of the stack.
+fn converted<T: FromLua>(thread: &mut Thread) -> LuaResult<T> {
+    let l = thread.as_raw().as_ptr();
+    let value = T::from_lua(thread, unsafe { sys::lua_gettop(l) });
+    unsafe { sys::lua_pop(l, 1) };
+    value
+}
+
+fn context(e: Error, location: std::fmt::Arguments) -> Error {
+    Error::new(
+        e.kind(),
+        Some(format!(
+            "{}: {}",
+            location,
+            e.msg().unwrap_or_else(|| e.kind().as_str())
+        )),
+    )
+}
+
+/// Pushes a table field by field.
+pub struct TableBuilder<'a> {
+    pusher: Pusher<'a>,
+    /// Whether the table is the value of a variant table, set by `finish`.
+    variant: bool,
+}
+
+impl<'a> TableBuilder<'a> {
+    /// Pushes an empty table.
+    pub fn new(
+        mut pusher: Pusher<'a>,
+        narr: libc::c_int,
+        nrec: libc::c_int,
+    ) -> LuaResult<TableBuilder<'a>> {
+        pusher.reserve(1)?;
+        unsafe { sys::lua_createtable(pusher.0.as_raw().as_ptr(), narr, nrec) };
+        Ok(TableBuilder {
+            pusher,
+            variant: false,
+        })
+    }
+
+    /// Pushes a table with a single `name` field, whose value is the table being built.
+    pub fn variant(
+        mut pusher: Pusher<'a>,
+        name: &str,
+        narr: libc::c_int,
+        nrec: libc::c_int,
+    ) -> LuaResult<TableBuilder<'a>> {
+        pusher.reserve(3)?;
+        unsafe {
+            let l = pusher.0.as_raw().as_ptr();
+            sys::lua_createtable(l, 0, 1);
+            sys::lua_pushlstring(l, name.as_ptr() as *const libc::c_char, name.len());
+            sys::lua_createtable(l, narr, nrec);
+        }
+        Ok(TableBuilder {
+            pusher,
+            variant: true,
+        })
+    }
+
+    /// Sets the field `key` to `value`.
+    pub fn field<V: Pushable>(&mut self, key: &str, value: &V) -> LuaResult<()> {
+        self.pusher.reserve(1)?;
+        let l = self.pusher.0.as_raw().as_ptr();
+        unsafe { sys::lua_pushlstring(l, key.as_ptr() as *const libc::c_char, key.len()) };
+        value.push(self.pusher.reborrow())?;
+        unsafe { sys::lua_rawset(l, -3) };
+        Ok(())
+    }
+
+    /// Sets the element `n` to `value`.
+    pub fn element<V: Pushable>(&mut self, n: sys::lua_Integer, value: &V) -> LuaResult<()> {
+        value.push(self.pusher.reborrow())?;
+        unsafe { sys::lua_rawseti(self.pusher.0.as_raw().as_ptr(), -2, n) };
+        Ok(())
+    }
+
+    /// Leaves the table on the stack.
+    pub fn finish(mut self) -> LuaResult<()> {
+        if self.variant {
+            unsafe { sys::lua_rawset(self.pusher.0.as_raw().as_ptr(), -3) };
+        }
+        Ok(())
+    }
+}
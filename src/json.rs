@@ -0,0 +1,513 @@
+use crate::{
+    thread::{StackCheck, Thread},
+    value, LuaResult,
+};
+
+use serde_json::{Map, Number, Value};
+use std::{collections::HashSet, ffi::CStr, ptr, slice, str};
+
+/// Maximum nesting depth of encoded tables.
+const MAX_DEPTH: usize = 1000;
+
+/// Opens the `json` library into `thread`,
+/// setting the `json` global and `package.loaded.json`.
+///
+/// # Examples
+/// ```
+/// use pollua::thread::{LoadingMode, Thread};
+///
+/// Thread::spawn(move |thread| {
+///     pollua::json::open(thread)?;
+///     let values = thread
+///         .caller_load("return json.encode({ 1, 2, json.null })", None, LoadingMode::Text)?
+///         .call()?;
+///     assert_eq!(values.get_as::<String>(0)?, "[1,2,null]");
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+pub fn open(thread: &mut Thread) -> LuaResult<()> {
+    let raw = thread.as_raw();
+    let _check = StackCheck::new(raw, 0);
+    value::reserve(raw, 2)?;
+    unsafe {
+        sys::luaL_requiref(
+            raw.as_ptr(),
+            b"json\0".as_ptr() as *const _,
+            Some(luaopen_json),
+            1,
+        );
+        sys::lua_pop(raw.as_ptr(), 1);
+    }
+    Ok(())
+}
+
+/// Creates the `json` library table, for use with `luaL_requiref` or `package.preload`.
+///
+/// The library contains:
+/// - `json.encode(value [, options])`: returns the JSON text of `value`.
+///   Tables whose keys are exactly `1..n` are encoded as arrays, other tables as objects,
+///   with integer keys converted to strings.
+///   `options` is an optional table with the fields
+///   `pretty` (boolean), `indent` (string or number of spaces, defaults to 2),
+///   `sort_keys` (boolean) and `empty_table` (`"object"` or `"array"`, the default is `"object"`).
+/// - `json.decode(text)`: returns the value of the JSON `text`,
+///   integers are decoded as Lua integers and other numbers as floats.
+/// - `json.null`: the value of JSON `null` inside arrays and objects,
+///   the same light userdata as [`serde::NULL`].
+///
+/// [`serde::NULL`]: ../serde/constant.NULL.html
+///
+/// # Safety
+/// `l` must be a valid Lua state.
+pub unsafe extern "C" fn luaopen_json(l: *mut sys::lua_State) -> libc::c_int {
+    sys::lua_createtable(l, 0, 3);
+    sys::lua_pushcfunction(l, Some(json_encode));
+    sys::lua_setfield(l, -2, b"encode\0".as_ptr() as *const _);
+    sys::lua_pushcfunction(l, Some(json_decode));
+    sys::lua_setfield(l, -2, b"decode\0".as_ptr() as *const _);
+    sys::lua_pushlightuserdata(l, ptr::null_mut());
+    sys::lua_setfield(l, -2, b"null\0".as_ptr() as *const _);
+    1
+}
+
+/// Raises `msg` as a Lua error, all Rust values must have been dropped beforehand.
+unsafe fn raise(l: *mut sys::lua_State, msg: String) -> ! {
+    sys::lua_settop(l, 0);
+    sys::lua_pushlstring(l, msg.as_ptr() as *const _, msg.len());
+    drop(msg);
+    sys::lua_error(l)
+}
+
+unsafe extern "C" fn json_encode(l: *mut sys::lua_State) -> libc::c_int {
+    sys::luaL_checkany(l, 1);
+    let result = Encoder::from_options(l, 2).and_then(|mut encoder| {
+        encoder.value(l, 1, 0)?;
+        Ok(encoder.out)
+    });
+    match result {
+        Ok(out) => {
+            sys::lua_pushlstring(l, out.as_ptr() as *const _, out.len());
+            1
+        }
+        Err(mut msg) => {
+            // moved into raise, which drops it before the error is raised
+            msg.insert_str(0, "json.encode: ");
+            raise(l, msg)
+        }
+    }
+}
+
+unsafe extern "C" fn json_decode(l: *mut sys::lua_State) -> libc::c_int {
+    let mut len = 0usize;
+    let text = sys::luaL_checklstring(l, 1, &mut len);
+    let text = slice::from_raw_parts(text as *const u8, len);
+    let result = serde_json::from_slice::<Value>(text)
+        .map_err(|e| e.to_string())
+        .and_then(|value| push_value(l, &value));
+    match result {
+        Ok(()) => 1,
+        Err(mut msg) => {
+            msg.insert_str(0, "json.decode: ");
+            raise(l, msg)
+        }
+    }
+}
+
+/// A key of an encoded object.
+enum Key {
+    String(Vec<u8>),
+    Integer(sys::lua_Integer),
+}
+
+impl Key {
+    fn text(&self) -> String {
+        match self {
+            Key::String(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+            Key::Integer(n) => n.to_string(),
+        }
+    }
+
+    unsafe fn push(&self, l: *mut sys::lua_State) {
+        match self {
+            Key::String(bytes) => {
+                sys::lua_pushlstring(l, bytes.as_ptr() as *const _, bytes.len());
+            }
+            Key::Integer(n) => sys::lua_pushinteger(l, *n),
+        }
+    }
+}
+
+struct Encoder {
+    out: String,
+    pretty: bool,
+    indent: String,
+    sort_keys: bool,
+    empty_as_array: bool,
+    /// Tables being encoded, used to detect cycles.
+    ancestors: Vec<*const libc::c_void>,
+}
+
+impl Encoder {
+    /// Reads the options table at `index`, which may be absent.
+    unsafe fn from_options(l: *mut sys::lua_State, index: libc::c_int) -> Result<Encoder, String> {
+        let mut encoder = Encoder {
+            out: String::new(),
+            pretty: false,
+            indent: "  ".to_owned(),
+            sort_keys: false,
+            empty_as_array: false,
+            ancestors: Vec::new(),
+        };
+        match sys::lua_type(l, index) {
+            sys::LUA_TNONE | sys::LUA_TNIL => return Ok(encoder),
+            sys::LUA_TTABLE => {}
+            _ => return Err("options must be a table".to_owned()),
+        }
+        if sys::lua_checkstack(l, 1) == 0 {
+            return Err("stack overflow".to_owned());
+        }
+
+        sys::lua_getfield(l, index, b"pretty\0".as_ptr() as *const _);
+        encoder.pretty = sys::lua_toboolean(l, -1) != 0;
+        sys::lua_getfield(l, index, b"sort_keys\0".as_ptr() as *const _);
+        encoder.sort_keys = sys::lua_toboolean(l, -1) != 0;
+        sys::lua_pop(l, 2);
+
+        match sys::lua_getfield(l, index, b"indent\0".as_ptr() as *const _) {
+            sys::LUA_TNIL => {}
+            sys::LUA_TNUMBER => {
+                encoder.indent = " ".repeat(sys::lua_tointeger(l, -1).max(0) as usize)
+            }
+            sys::LUA_TSTRING => encoder.indent = string_at(l, -1)?.to_owned(),
+            _ => return Err("'indent' must be a string or a number".to_owned()),
+        }
+        sys::lua_pop(l, 1);
+
+        match sys::lua_getfield(l, index, b"empty_table\0".as_ptr() as *const _) {
+            sys::LUA_TNIL => {}
+            sys::LUA_TSTRING => match string_at(l, -1)? {
+                "object" => encoder.empty_as_array = false,
+                "array" => encoder.empty_as_array = true,
+                other => return Err(format!("invalid 'empty_table' option '{}'", other)),
+            },
+            _ => return Err("'empty_table' must be a string".to_owned()),
+        }
+        sys::lua_pop(l, 1);
+        Ok(encoder)
+    }
+
+    fn newline(&mut self, depth: usize) {
+        if self.pretty {
+            self.out.push('\n');
+            for _ in 0..depth {
+                self.out.push_str(&self.indent);
+            }
+        }
+    }
+
+    /// Encodes the value at the absolute stack `index`.
+    unsafe fn value(
+        &mut self,
+        l: *mut sys::lua_State,
+        index: libc::c_int,
+        depth: usize,
+    ) -> Result<(), String> {
+        match sys::lua_type(l, index) {
+            sys::LUA_TNIL => self.out.push_str("null"),
+            sys::LUA_TBOOLEAN => self.out.push_str(if sys::lua_toboolean(l, index) != 0 {
+                "true"
+            } else {
+                "false"
+            }),
+            sys::LUA_TNUMBER if sys::lua_isinteger(l, index) != 0 => {
+                self.out.push_str(&sys::lua_tointeger(l, index).to_string())
+            }
+            sys::LUA_TNUMBER => {
+                let n = sys::lua_tonumber(l, index) as f64;
+                if !n.is_finite() {
+                    return Err(format!("cannot encode {}", n));
+                }
+                // keeps a fractional part so that floats are decoded as floats
+                self.out.push_str(&format!("{:?}", n));
+            }
+            sys::LUA_TSTRING => {
+                let s = string_at(l, index)?;
+                self.string(s)
+            }
+            sys::LUA_TLIGHTUSERDATA if sys::lua_touserdata(l, index).is_null() => {
+                self.out.push_str("null")
+            }
+            sys::LUA_TTABLE => self.table(l, index, depth)?,
+            _ => {
+                let name = CStr::from_ptr(sys::luaL_typename(l, index));
+                return Err(format!("cannot encode a {}", name.to_string_lossy()));
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, s: &str) {
+        self.out.reserve(s.len() + 2);
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                '\u{8}' => self.out.push_str("\\b"),
+                '\u{c}' => self.out.push_str("\\f"),
+                c if (c as u32) < 0x20 => self.out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+
+    unsafe fn table(
+        &mut self,
+        l: *mut sys::lua_State,
+        index: libc::c_int,
+        depth: usize,
+    ) -> Result<(), String> {
+        let table = sys::lua_topointer(l, index);
+        if self.ancestors.contains(&table) {
+            return Err("cannot encode a table that contains itself".to_owned());
+        }
+        if depth >= MAX_DEPTH {
+            return Err("tables are nested too deeply".to_owned());
+        }
+        if sys::lua_checkstack(l, 3) == 0 {
+            return Err("stack overflow".to_owned());
+        }
+        self.ancestors.push(table);
+
+        let len = sys::lua_rawlen(l, index);
+        let mut keys = Vec::new();
+        let mut sequence = true;
+        sys::lua_pushnil(l);
+        while sys::lua_next(l, index) != 0 {
+            sys::lua_pop(l, 1);
+            let key = match sys::lua_type(l, -1) {
+                sys::LUA_TNUMBER if sys::lua_isinteger(l, -1) != 0 => {
+                    let n = sys::lua_tointeger(l, -1);
+                    sequence &= n >= 1 && n as usize <= len;
+                    Key::Integer(n)
+                }
+                sys::LUA_TSTRING => {
+                    sequence = false;
+                    let mut len = 0usize;
+                    let s = sys::lua_tolstring(l, -1, &mut len);
+                    Key::String(slice::from_raw_parts(s as *const u8, len).to_vec())
+                }
+                _ => {
+                    let name = CStr::from_ptr(sys::luaL_typename(l, -1));
+                    sys::lua_pop(l, 1);
+                    return Err(format!(
+                        "cannot encode a table with {} keys",
+                        name.to_string_lossy()
+                    ));
+                }
+            };
+            keys.push(key);
+        }
+
+        if keys.is_empty() {
+            self.out
+                .push_str(if self.empty_as_array { "[]" } else { "{}" });
+        } else if sequence && keys.len() == len {
+            self.out.push('[');
+            for i in 1..=len {
+                if i > 1 {
+                    self.out.push(',');
+                }
+                self.newline(depth + 1);
+                sys::lua_rawgeti(l, index, i as sys::lua_Integer);
+                let result = self.value(l, sys::lua_gettop(l), depth + 1);
+                sys::lua_pop(l, 1);
+                result?;
+            }
+            self.newline(depth);
+            self.out.push(']');
+        } else {
+            let mut keys: Vec<(String, Key)> = keys.into_iter().map(|k| (k.text(), k)).collect();
+            // 1 and '1' would both become the name "1"
+            let mut names = HashSet::with_capacity(keys.len());
+            if let Some((text, _)) = keys.iter().find(|(text, _)| !names.insert(text)) {
+                return Err(format!(
+                    "cannot encode a table with the key \"{}\" twice",
+                    text
+                ));
+            }
+            if self.sort_keys {
+                keys.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+            self.out.push('{');
+            for (i, (text, key)) in keys.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                self.newline(depth + 1);
+                self.string(text);
+                self.out.push(':');
+                if self.pretty {
+                    self.out.push(' ');
+                }
+                key.push(l);
+                sys::lua_rawget(l, index);
+                let result = self.value(l, sys::lua_gettop(l), depth + 1);
+                sys::lua_pop(l, 1);
+                result?;
+            }
+            self.newline(depth);
+            self.out.push('}');
+        }
+
+        self.ancestors.pop();
+        Ok(())
+    }
+}
+
+/// Returns the UTF-8 string at `index`.
+unsafe fn string_at<'a>(l: *mut sys::lua_State, index: libc::c_int) -> Result<&'a str, String> {
+    let mut len = 0usize;
+    let s = sys::lua_tolstring(l, index, &mut len);
+    str::from_utf8(slice::from_raw_parts(s as *const u8, len))
+        .map_err(|_| "cannot encode a string that is not valid UTF-8".to_owned())
+}
+
+/// Pushes a decoded JSON value.
+unsafe fn push_value(l: *mut sys::lua_State, value: &Value) -> Result<(), String> {
+    if sys::lua_checkstack(l, 3) == 0 {
+        return Err("stack overflow".to_owned());
+    }
+    match value {
+        Value::Null => sys::lua_pushlightuserdata(l, ptr::null_mut()),
+        Value::Bool(b) => sys::lua_pushboolean(l, *b as libc::c_int),
+        Value::Number(n) => push_number(l, n),
+        Value::String(s) => {
+            sys::lua_pushlstring(l, s.as_ptr() as *const _, s.len());
+        }
+        Value::Array(values) => {
+            sys::lua_createtable(l, values.len().min(libc::c_int::MAX as usize) as _, 0);
+            for (i, value) in values.iter().enumerate() {
+                push_value(l, value)?;
+                sys::lua_rawseti(l, -2, i as sys::lua_Integer + 1);
+            }
+        }
+        Value::Object(fields) => push_object(l, fields)?,
+    }
+    Ok(())
+}
+
+unsafe fn push_number(l: *mut sys::lua_State, n: &Number) {
+    match n.as_i64() {
+        Some(i) => sys::lua_pushinteger(l, i as sys::lua_Integer),
+        None => sys::lua_pushnumber(l, n.as_f64().unwrap_or(f64::NAN) as sys::lua_Number),
+    }
+}
+
+unsafe fn push_object(l: *mut sys::lua_State, fields: &Map<String, Value>) -> Result<(), String> {
+    sys::lua_createtable(l, 0, fields.len().min(libc::c_int::MAX as usize) as _);
+    for (key, value) in fields {
+        sys::lua_pushlstring(l, key.as_ptr() as *const _, key.len());
+        push_value(l, value)?;
+        sys::lua_rawset(l, -3);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{thread::LoadingMode, ErrorKind};
+
+    fn eval(thread: &mut Thread, code: &str) -> LuaResult<String> {
+        let values = thread.caller_load(code, None, LoadingMode::Text)?.call()?;
+        values.get_as::<String>(0)
+    }
+
+    #[test]
+    fn test_json_encode() {
+        Thread::spawn(move |thread| {
+            open(thread).unwrap();
+            assert_eq!(
+                eval(
+                    thread,
+                    "return json.encode({ 1, 2.5, 'a\\n', true, json.null })"
+                )
+                .unwrap(),
+                "[1,2.5,\"a\\n\",true,null]"
+            );
+            let sorted = eval(
+                thread,
+                "return json.encode({ a = { 3.0 }, [2] = {} }, { sort_keys = true })",
+            );
+            // floats are only told apart from integers since Lua 5.3
+            #[cfg(LUA_VERSION = "5.3")]
+            assert_eq!(sorted.unwrap(), r#"{"2":{},"a":[3.0]}"#);
+            #[cfg(not(LUA_VERSION = "5.3"))]
+            assert_eq!(sorted.unwrap(), r#"{"2":{},"a":[3]}"#);
+            assert_eq!(
+                eval(
+                    thread,
+                    "return json.encode({ a = {}, b = { 1 } }, \
+                     { pretty = true, sort_keys = true, empty_table = 'array' })"
+                )
+                .unwrap(),
+                "{\n  \"a\": [],\n  \"b\": [\n    1\n  ]\n}"
+            );
+            let err = eval(thread, "local t = {} t.t = t return json.encode(t)").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Runtime);
+            assert!(err.msg().unwrap().contains("contains itself"));
+            let err = eval(thread, "return json.encode({ function() end })").unwrap_err();
+            assert!(err.msg().unwrap().contains("cannot encode a function"));
+            let err = eval(thread, "return json.encode({ [true] = 1 })").unwrap_err();
+            assert!(err
+                .msg()
+                .unwrap()
+                .contains("cannot encode a table with boolean keys"));
+            let err = eval(thread, "return json.encode({ [1] = 'a', ['1'] = 'b' })").unwrap_err();
+            assert!(err.msg().unwrap().contains("the key \"1\" twice"));
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_decode() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            open(thread).unwrap();
+            #[cfg(LUA_VERSION = "5.3")]
+            assert_eq!(
+                eval(
+                    thread,
+                    r#"local v = json.decode('{"a": [1, 2.0, null, "x"], "b": {"c": false}}')
+                    return table.concat({
+                        math.type(v.a[1]), math.type(v.a[2]), tostring(v.a[3] == json.null),
+                        #v.a, v.a[4], tostring(v.b.c),
+                    }, ' ')"#
+                )
+                .unwrap(),
+                "integer float true 4 x false"
+            );
+            assert_eq!(
+                eval(
+                    thread,
+                    "return json.encode(json.decode('{\"a\":[1,2.5,null],\"b\":\"\\\\u00e9\"}'), \
+                     { sort_keys = true })"
+                )
+                .unwrap(),
+                "{\"a\":[1,2.5,null],\"b\":\"é\"}"
+            );
+            let err = eval(thread, "return json.decode('[1,')").unwrap_err();
+            assert!(err.msg().unwrap().starts_with("json.decode:"));
+            assert_eq!(
+                eval(thread, "return package.loaded.json == json and 'ok'").unwrap(),
+                "ok"
+            );
+        })
+        .unwrap()
+    }
+}
@@ -1,14 +1,32 @@
-//! High level bindings to Lua 5.3
-
-#![cfg_attr(rust_nightly, feature(non_exhaustive))]
+//! High level bindings to Lua 5.1, 5.2, 5.3, 5.4 and LuaJIT
 
 extern crate libc;
 pub extern crate lua_sys as sys;
 
 use std::{error, fmt, ptr};
 
+/// Debug Adapter Protocol server.
+#[cfg(feature = "dap")]
+pub mod dap;
+/// Support code for the procedural macros of `pollua-derive`, not a public API.
+#[doc(hidden)]
+pub mod derive;
+/// A JSON library for Lua scripts.
+#[cfg(feature = "json")]
+pub mod json;
+/// Native modules built from Rust functions.
+pub mod module;
+/// Lua patterns matched from Rust.
+pub mod pattern;
+/// Pools of reusable, pre-initialized threads.
+pub mod pool;
+/// Conversions between Rust and Lua values using serde.
+#[cfg(feature = "serde")]
+pub mod serde;
 /// Lua thread API.
 pub mod thread;
+/// Rust types exposed to Lua as userdata.
+pub mod userdata;
 /// Useful functions.
 pub(crate) mod util;
 /// WIP
@@ -16,6 +34,9 @@ pub mod value;
 
 pub use thread::Thread;
 
+#[cfg(feature = "derive")]
+pub use pollua_derive::{lua_function, lua_methods, FromLua, IntoLua};
+
 /// Returns the version number stored in the Lua core.
 ///
 /// # Examples
@@ -23,10 +44,18 @@ pub use thread::Thread;
 /// println!("Lua version number: {}", pollua::lua_version());
 /// ```
 #[inline]
+#[cfg(not(LUA_VERSION = "5.4"))]
 pub fn lua_version() -> sys::lua_Number {
     unsafe { *sys::lua_version(ptr::null_mut()) }
 }
 
+/// Returns the version number stored in the Lua core.
+#[inline]
+#[cfg(LUA_VERSION = "5.4")]
+pub fn lua_version() -> sys::lua_Number {
+    unsafe { sys::lua_version(ptr::null_mut()) }
+}
+
 /// The Lua error type.
 #[derive(Debug, Clone)]
 pub struct Error {
@@ -39,7 +68,7 @@ pub struct Error {
 ///
 /// [`Error`]: struct.Error.html
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-#[cfg_attr(rust_nightly, non_exhaustive)]
+#[non_exhaustive]
 pub enum ErrorKind {
     Runtime,
     Syntax,
@@ -47,14 +76,30 @@ pub enum ErrorKind {
     MessageHandler,
     GarbageCollection,
     Io,
-    #[doc(hidden)]
-    #[cfg(not(rust_nightly))]
-    _NonExhaustive,
+    StackOverflow,
+    Conversion,
+}
+
+impl ErrorKind {
+    /// Returns a short description of this error kind.
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Runtime => "runtime error",
+            ErrorKind::Syntax => "syntax error",
+            ErrorKind::OutOfMemory => "out of memory",
+            ErrorKind::MessageHandler => "error while running the message handler",
+            ErrorKind::GarbageCollection => "error while running a __gc metamethod",
+            ErrorKind::Io => "IO error",
+            ErrorKind::StackOverflow => "stack overflow",
+            ErrorKind::Conversion => "conversion error",
+        }
+    }
 }
 
 impl Error {
+    /// Creates an error from its kind and an optional message.
     #[inline]
-    fn new(kind: ErrorKind, msg: Option<String>) -> Error {
+    pub fn new(kind: ErrorKind, msg: Option<String>) -> Error {
         Error { kind, msg }
     }
 
@@ -67,22 +112,13 @@ impl Error {
     /// Returns the message associated with this error.
     #[inline]
     pub fn msg(&self) -> Option<&str> {
-        self.msg.as_ref().map(|m| &**m)
+        self.msg.as_deref()
     }
 }
 
 impl error::Error for Error {
     fn description(&self) -> &str {
-        #[allow(unreachable_patterns)]
-        match self.kind {
-            ErrorKind::Runtime => "runtime error",
-            ErrorKind::Syntax => "syntax error",
-            ErrorKind::OutOfMemory => "out of memory",
-            ErrorKind::MessageHandler => "error while running the message handler",
-            ErrorKind::GarbageCollection => "error while running a __gc metamethod",
-            ErrorKind::Io => "IO error",
-            _ => "unknown error",
-        }
+        self.kind.as_str()
     }
     fn cause(&self) -> Option<&dyn error::Error> {
         None
@@ -91,7 +127,7 @@ impl error::Error for Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(error::Error::description(self))?;
+        f.write_str(self.kind.as_str())?;
         match &self.msg {
             Some(msg) => write!(f, ": {}", msg),
             None => Ok(()),
@@ -0,0 +1,366 @@
+use crate::{
+    thread::{StackCheck, StackGuard, Thread, ThreadRef},
+    userdata::{self, UserData},
+    util,
+    value::{self, FromLuaMulti, IntoLuaMulti, Pushable, Pusher},
+    LuaResult,
+};
+
+use std::{fmt, ptr::NonNull};
+
+/// Pushes the value of a module field.
+pub(crate) type Field = Box<dyn FnOnce(&mut Thread) -> LuaResult<()> + Send>;
+
+/// Registry key of the module being opened by `luaL_requiref`.
+static REQUIRED_KEY: u8 = 0;
+
+/// A table of Rust functions, constants and submodules, built declaratively.
+///
+/// A module can be registered as a global with [`Thread::register_module`],
+/// loaded into `package.loaded` with [`Thread::require_module`],
+/// or exported as a `luaopen_*` function with the [`luaopen!`] macro.
+///
+/// # Examples
+/// ```
+/// use pollua::{module::Module, thread::{LoadingMode, Thread}};
+///
+/// Thread::spawn(move |thread| {
+///     let module = Module::new("geometry")
+///         .constant("origin", 0.0)
+///         .function("area", |(w, h): (f64, f64)| Ok(w * h))
+///         .submodule(Module::new("units").constant("default", "cm"));
+///     thread.register_module(module)?;
+///
+///     let values = thread
+///         .caller_load("return geometry.area(2, 3), geometry.units.default", None, LoadingMode::Text)?
+///         .call()?;
+///     assert_eq!(values.unpack::<(f64, String)>()?, (6.0, "cm".to_owned()));
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+///
+/// [`Thread::register_module`]: ../thread/struct.Thread.html#method.register_module
+/// [`Thread::require_module`]: ../thread/struct.Thread.html#method.require_module
+/// [`luaopen!`]: ../macro.luaopen.html
+pub struct Module {
+    name: String,
+    fields: Vec<(String, Field)>,
+}
+
+impl Module {
+    /// Creates an empty module, `name` is used when it is registered or used as a submodule.
+    pub fn new<S: Into<String>>(name: S) -> Module {
+        Module {
+            name: name.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Returns the name of this module.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds a Rust function, see [`Thread::register_fn`] for how it is called.
+    ///
+    /// [`Thread::register_fn`]: ../thread/struct.Thread.html#method.register_fn
+    pub fn function<A, R, F>(mut self, name: &str, f: F) -> Module
+    where
+        A: FromLuaMulti + 'static,
+        R: IntoLuaMulti + 'static,
+        F: Fn(A) -> LuaResult<R> + Send + 'static,
+    {
+        self.fields
+            .push((name.to_owned(), Box::new(move |thread| thread.push_fn(f))));
+        self
+    }
+
+    /// Adds a C function.
+    pub fn c_function(mut self, name: &str, f: sys::lua_CFunction) -> Module {
+        self.fields.push((
+            name.to_owned(),
+            Box::new(move |thread| {
+                let raw = thread.as_raw();
+                value::reserve(raw, 1)?;
+                unsafe { sys::lua_pushcfunction(raw.as_ptr(), f) };
+                Ok(())
+            }),
+        ));
+        self
+    }
+
+    /// Adds a constant value.
+    pub fn constant<V>(mut self, name: &str, value: V) -> Module
+    where
+        V: Pushable + Send + 'static,
+    {
+        self.fields.push((
+            name.to_owned(),
+            Box::new(move |thread| value.push(Pusher(ThreadRef::from_ref(thread)))),
+        ));
+        self
+    }
+
+    /// Adds `module` as a table, under its own name.
+    pub fn submodule(mut self, module: Module) -> Module {
+        self.fields.push((
+            module.name.clone(),
+            Box::new(move |thread| module.push(thread)),
+        ));
+        self
+    }
+
+    /// Adds the table of the associated functions of `T`, under the name of the type.
+    ///
+    /// See [`userdata::class`].
+    ///
+    /// [`userdata::class`]: ../userdata/fn.class.html
+    pub fn userdata<T: UserData>(self) -> Module {
+        self.submodule(userdata::class::<T>())
+    }
+
+    /// Adds a field whose value is pushed by `push`.
+    pub(crate) fn field(mut self, name: &str, push: Field) -> Module {
+        self.fields.push((name.to_owned(), push));
+        self
+    }
+
+    /// Pushes the table of this module.
+    pub(crate) fn push(self, thread: &mut Thread) -> LuaResult<()> {
+        let raw = thread.as_raw();
+        value::reserve(raw, 2)?;
+        unsafe {
+            sys::lua_createtable(
+                raw.as_ptr(),
+                0,
+                self.fields.len().min(libc::c_int::MAX as usize) as libc::c_int,
+            )
+        };
+        for (name, push) in self.fields {
+            value::reserve(raw, 1)?;
+            unsafe { sys::lua_pushlstring(raw.as_ptr(), name.as_ptr() as *const _, name.len()) };
+            push(thread)?;
+            unsafe { sys::lua_rawset(raw.as_ptr(), -3) };
+        }
+        Ok(())
+    }
+
+    /// Pushes the table of this module onto the stack of `l`,
+    /// to implement a `luaopen_*` function. Errors are raised as Lua errors.
+    ///
+    /// Prefer the [`luaopen!`] macro.
+    ///
+    /// # Safety
+    /// `l` must be a valid Lua state, and this must be called from a C function.
+    ///
+    /// [`luaopen!`]: ../macro.luaopen.html
+    pub unsafe fn open(self, l: *mut sys::lua_State) -> libc::c_int {
+        let result = {
+            let mut thread = ThreadRef::from_raw(NonNull::new_unchecked(l));
+            let top = sys::lua_gettop(l);
+            self.push(&mut thread).map_err(|e| {
+                sys::lua_settop(l, top);
+                e.to_string()
+            })
+        };
+        match result {
+            Ok(()) => 1,
+            Err(msg) => {
+                sys::lua_pushlstring(l, msg.as_ptr() as *const _, msg.len());
+                drop(msg);
+                sys::lua_error(l)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Module")
+            .field("name", &self.name)
+            .field(
+                "fields",
+                &self.fields.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Thread {
+    /// Sets the global named after `module` to its table.
+    pub fn register_module(&mut self, module: Module) -> LuaResult<()> {
+        let _check = StackCheck::new(self.as_raw(), 0);
+        let mut thread = StackGuard::new(self);
+        let raw = thread.as_raw();
+        value::reserve(raw, 2)?;
+        let name = module.name.clone();
+        unsafe {
+            sys::lua_pushglobaltable(raw.as_ptr());
+            sys::lua_pushlstring(raw.as_ptr(), name.as_ptr() as *const _, name.len());
+        }
+        module.push(&mut thread)?;
+        unsafe { sys::lua_rawset(raw.as_ptr(), -3) };
+        Ok(())
+    }
+
+    /// Loads `module` like `require` would, using `luaL_requiref`:
+    /// its table is stored in `package.loaded` unless a module with the same name
+    /// was already loaded, in which case `module` is dropped.
+    /// The table is also set as a global if `global` is `true`.
+    pub fn require_module(&mut self, module: Module, global: bool) -> LuaResult<()> {
+        unsafe extern "C" fn open_required(l: *mut sys::lua_State) -> libc::c_int {
+            // luaL_requiref of Lua 5.2 opens the module even if it is already loaded
+            #[cfg(not(LUA_VERSION = "5.3"))]
+            {
+                sys::lua_getfield(l, sys::LUA_REGISTRYINDEX, b"_LOADED\0".as_ptr() as _);
+                if sys::lua_getfield(l, -1, sys::lua_tostring(l, 1)) != sys::LUA_TNIL {
+                    return 1;
+                }
+            }
+            sys::lua_rawgetp(l, sys::LUA_REGISTRYINDEX, key());
+            1
+        }
+
+        #[inline]
+        fn key() -> *const libc::c_void {
+            &REQUIRED_KEY as *const u8 as *const libc::c_void
+        }
+
+        let _check = StackCheck::new(self.as_raw(), 0);
+        let mut thread = StackGuard::new(self);
+        let raw = thread.as_raw();
+        value::reserve(raw, 3)?;
+        let mut name_buf = Vec::new();
+        let name = module.name.clone();
+        // the table is built beforehand, so that opening it cannot fail
+        module.push(&mut thread)?;
+        unsafe {
+            let l = raw.as_ptr();
+            sys::lua_rawsetp(l, sys::LUA_REGISTRYINDEX, key());
+            sys::luaL_requiref(
+                l,
+                util::cstr_buf(Some(&name), &mut name_buf),
+                Some(open_required),
+                global as libc::c_int,
+            );
+            sys::lua_pushnil(l);
+            sys::lua_rawsetp(l, sys::LUA_REGISTRYINDEX, key());
+        }
+        Ok(())
+    }
+}
+
+/// Defines a `luaopen_*` function returning the table of a [`Module`],
+/// so that a `cdylib` crate can be loaded by `require` from a stock `lua` interpreter.
+///
+/// The library must then use the interpreter's Lua, see the `system-lua` feature.
+///
+/// # Examples
+/// ```
+/// use pollua::{luaopen, module::Module};
+///
+/// luaopen!(luaopen_greeter, || {
+///     Module::new("greeter").function("greet", |name: String| Ok(format!("Hello, {}!", name)))
+/// });
+/// ```
+///
+/// [`Module`]: module/struct.Module.html
+#[macro_export]
+macro_rules! luaopen {
+    ($name:ident, $build:expr) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn $name(l: *mut $crate::sys::lua_State) -> ::std::os::raw::c_int {
+            let build: fn() -> $crate::module::Module = $build;
+            build().open(l)
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{thread::LoadingMode, ErrorKind};
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    fn eval(thread: &mut Thread, code: &str) -> LuaResult<String> {
+        thread
+            .caller_load(code, None, LoadingMode::Text)?
+            .call()?
+            .get_as::<String>(0)
+    }
+
+    fn test_module() -> Module {
+        unsafe extern "C" fn version(l: *mut sys::lua_State) -> libc::c_int {
+            sys::lua_pushinteger(l, 3);
+            1
+        }
+
+        Module::new("test")
+            .constant("name", "test module")
+            .c_function("version", Some(version))
+            .function("join", |(a, b): (String, String)| Ok(a + &b))
+            .submodule(Module::new("inner").constant("enabled", true))
+    }
+
+    luaopen!(luaopen_test, test_module);
+
+    #[test]
+    fn test_module_register() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            thread.register_module(test_module()).unwrap();
+            assert_eq!(stack_top(thread), top);
+            assert_eq!(
+                eval(
+                    thread,
+                    "return test.join(test.name, test.version() .. \
+                     (test.inner.enabled and 'true' or 'false'))"
+                )
+                .unwrap(),
+                "test module3true"
+            );
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_module_require() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let top = stack_top(thread);
+            thread.require_module(test_module(), false).unwrap();
+            thread
+                .require_module(Module::new("test").constant("name", "replaced"), true)
+                .unwrap();
+            assert_eq!(stack_top(thread), top);
+            assert_eq!(
+                eval(thread, "return require('test').name .. test.name").unwrap(),
+                "test moduletest module"
+            );
+
+            unsafe {
+                sys::luaL_requiref(
+                    thread.as_raw().as_ptr(),
+                    b"opened\0".as_ptr() as *const _,
+                    Some(luaopen_test),
+                    1,
+                );
+                sys::lua_pop(thread.as_raw().as_ptr(), 1);
+            }
+            assert_eq!(
+                eval(thread, "return opened.inner.enabled and opened.name").unwrap(),
+                "test module"
+            );
+
+            let module = Module::new("bad").constant("big", u64::MAX);
+            let err = thread.register_module(module).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,1196 @@
+//! Lua patterns, matched in Rust with the semantics of the `string` library of the linked Lua.
+//!
+//! [`find`], [`captures`], [`gmatch`] and [`gsub`] work like `string.find`, `string.match`,
+//! `string.gmatch` and `string.gsub`.
+//! The matcher is a port of the one of `lstrlib.c`, so that strings validated by scripts
+//! and by Rust code give the same results, errors included.
+//! Positions are 0-based byte offsets, where Lua uses 1-based positions.
+//!
+//! # Examples
+//! ```
+//! use pollua::pattern;
+//!
+//! let captures = pattern::find("key = value", "(%w+)%s*=%s*(%w+)")?.unwrap();
+//! assert_eq!(captures.range(), 0..11);
+//! assert_eq!(captures.get(1).unwrap().as_bytes(), Some(&b"value"[..]));
+//! # Ok::<_, pattern::PatternError>(())
+//! ```
+//!
+//! [`find`]: fn.find.html
+//! [`captures`]: fn.captures.html
+//! [`gmatch`]: fn.gmatch.html
+//! [`gsub`]: fn.gsub.html
+
+use crate::{value::LuaStr, Error, ErrorKind};
+
+use std::{error, fmt, iter::FusedIterator, ops::Range};
+
+/// Maximum recursion depth of the matcher.
+const MAXCCALLS: usize = 200;
+
+/// Maximum number of captures in a pattern.
+const MAXCAPTURES: usize = 32;
+
+/// Characters that make a pattern different from a plain string.
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+/// An error in a pattern, with the message of the `string` library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError {
+    msg: String,
+}
+
+impl PatternError {
+    fn new<S: Into<String>>(msg: S) -> PatternError {
+        PatternError { msg: msg.into() }
+    }
+}
+
+impl fmt::Display for PatternError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl error::Error for PatternError {}
+
+impl From<PatternError> for Error {
+    #[inline]
+    fn from(e: PatternError) -> Error {
+        Error::new(ErrorKind::Runtime, Some(e.msg))
+    }
+}
+
+/// A value captured by a pattern.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Capture<'s> {
+    /// A substring of the subject.
+    Str(&'s LuaStr),
+    /// A position capture `()`, the offset of the position in the subject.
+    ///
+    /// Lua returns this offset plus one.
+    Position(usize),
+}
+
+impl<'s> Capture<'s> {
+    /// Returns the substring, or `None` for a position capture.
+    #[inline]
+    pub fn as_bytes(&self) -> Option<&'s [u8]> {
+        match self {
+            Capture::Str(s) => Some(s.as_bytes()),
+            Capture::Position(_) => None,
+        }
+    }
+}
+
+/// A match of a pattern and its captures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'s> {
+    subject: &'s [u8],
+    range: Range<usize>,
+    captures: Vec<Capture<'s>>,
+}
+
+impl<'s> Captures<'s> {
+    /// Returns the range of the match in the subject.
+    #[inline]
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Returns the whole match.
+    #[inline]
+    pub fn whole(&self) -> &'s LuaStr {
+        LuaStr::from_bytes(&self.subject[self.range()])
+    }
+
+    /// Returns the number of captures of the pattern.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.captures.len()
+    }
+
+    /// Returns true if the pattern has no captures.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.captures.is_empty()
+    }
+
+    /// Returns the capture at `index`, starting from 0 for the capture Lua names `%1`.
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<Capture<'s>> {
+        self.captures.get(index).copied()
+    }
+
+    /// Returns the captures, or the whole match if the pattern has no captures,
+    /// which are the values returned by `string.match` and `string.gmatch`.
+    pub fn values(&self) -> Vec<Capture<'s>> {
+        if self.captures.is_empty() {
+            vec![Capture::Str(self.whole())]
+        } else {
+            self.captures.clone()
+        }
+    }
+
+    /// Returns an iterator over the captures.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = Capture<'s>> + '_ {
+        self.captures.iter().copied()
+    }
+}
+
+/// Length of a capture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CaptureLen {
+    Unfinished,
+    Position,
+    Len(usize),
+}
+
+/// State of the matcher, `src` and `pat` are indexed with byte offsets.
+struct MatchState<'s, 'p> {
+    src: &'s [u8],
+    pat: &'p [u8],
+    depth: usize,
+    level: usize,
+    capture: [(usize, CaptureLen); MAXCAPTURES],
+}
+
+type MatchResult = Result<Option<usize>, PatternError>;
+
+impl<'s, 'p> MatchState<'s, 'p> {
+    fn new(src: &'s [u8], pat: &'p [u8]) -> MatchState<'s, 'p> {
+        MatchState {
+            src,
+            pat,
+            depth: MAXCCALLS,
+            level: 0,
+            capture: [(0, CaptureLen::Unfinished); MAXCAPTURES],
+        }
+    }
+
+    /// Resets the captures before matching at another position.
+    #[inline]
+    fn reset(&mut self) {
+        self.level = 0;
+    }
+
+    /// Returns the byte of the pattern at `p`, or 0 like the terminator of a C string.
+    #[inline]
+    fn pat_at(&self, p: usize) -> u8 {
+        self.pat.get(p).copied().unwrap_or(0)
+    }
+
+    /// Returns the byte of the subject at `s`, or 0 like the terminator of a C string.
+    #[inline]
+    fn src_at(&self, s: usize) -> u8 {
+        self.src.get(s).copied().unwrap_or(0)
+    }
+
+    fn check_capture(&self, l: u8) -> Result<usize, PatternError> {
+        let l = i32::from(l) - i32::from(b'1');
+        if l < 0 || l as usize >= self.level || self.capture[l as usize].1 == CaptureLen::Unfinished
+        {
+            // Lua 5.1 does not tell the index
+            return Err(invalid_capture(l + 1, cfg!(LUA_VERSION = "5.2")));
+        }
+        Ok(l as usize)
+    }
+
+    fn capture_to_close(&self) -> Result<usize, PatternError> {
+        (0..self.level)
+            .rev()
+            .find(|&level| self.capture[level].1 == CaptureLen::Unfinished)
+            .ok_or_else(|| PatternError::new("invalid pattern capture"))
+    }
+
+    /// Returns the end of the single character class starting at `p`.
+    fn class_end(&self, p: usize) -> Result<usize, PatternError> {
+        let c = self.pat_at(p);
+        let mut p = p + 1;
+        match c {
+            b'%' => {
+                if p == self.pat.len() {
+                    return Err(PatternError::new("malformed pattern (ends with '%')"));
+                }
+                Ok(p + 1)
+            }
+            b'[' => {
+                if self.pat_at(p) == b'^' {
+                    p += 1;
+                }
+                // look for a ']'
+                loop {
+                    if p == self.pat.len() {
+                        return Err(PatternError::new("malformed pattern (missing ']')"));
+                    }
+                    let c = self.pat[p];
+                    p += 1;
+                    if c == b'%' && p < self.pat.len() {
+                        // skip escapes, such as '%]'
+                        p += 1;
+                    }
+                    if self.pat_at(p) == b']' {
+                        break;
+                    }
+                }
+                Ok(p + 1)
+            }
+            _ => Ok(p),
+        }
+    }
+
+    /// Matches `c` against the set between `p` and `ec`, which is at the closing `]`.
+    fn match_bracket_class(&self, c: u8, p: usize, ec: usize) -> bool {
+        let mut p = p;
+        let mut sig = true;
+        if self.pat_at(p + 1) == b'^' {
+            sig = false;
+            p += 1;
+        }
+        loop {
+            p += 1;
+            if p >= ec {
+                return !sig;
+            }
+            if self.pat[p] == b'%' {
+                p += 1;
+                if match_class(c, self.pat_at(p)) {
+                    return sig;
+                }
+            } else if self.pat_at(p + 1) == b'-' && p + 2 < ec {
+                p += 2;
+                if self.pat[p - 2] <= c && c <= self.pat[p] {
+                    return sig;
+                }
+            } else if self.pat[p] == c {
+                return sig;
+            }
+        }
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        if s >= self.src.len() {
+            return false;
+        }
+        let c = self.src[s];
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pat_at(p + 1)),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> MatchResult {
+        if p + 1 >= self.pat.len() {
+            return Err(PatternError::new(if cfg!(LUA_VERSION = "5.2") {
+                "malformed pattern (missing arguments to '%b')"
+            } else {
+                "unbalanced pattern"
+            }));
+        }
+        if self.src_at(s) != self.pat[p] {
+            return Ok(None);
+        }
+        let (b, e) = (self.pat[p], self.pat[p + 1]);
+        let mut cont = 1;
+        for s in s + 1..self.src.len() {
+            if self.src[s] == e {
+                cont -= 1;
+                if cont == 0 {
+                    return Ok(Some(s + 1));
+                }
+            } else if self.src[s] == b {
+                cont += 1;
+            }
+        }
+        // the subject ends out of balance
+        Ok(None)
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> MatchResult {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        // tries to match with the maximum repetitions, then with less
+        loop {
+            if let Some(res) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(res));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> MatchResult {
+        loop {
+            if let Some(res) = self.do_match(s, ep + 1)? {
+                return Ok(Some(res));
+            } else if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: CaptureLen) -> MatchResult {
+        let level = self.level;
+        if level >= MAXCAPTURES {
+            return Err(PatternError::new("too many captures"));
+        }
+        self.capture[level] = (s, what);
+        self.level = level + 1;
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.level -= 1;
+        }
+        Ok(res)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> MatchResult {
+        let l = self.capture_to_close()?;
+        self.capture[l].1 = CaptureLen::Len(s - self.capture[l].0);
+        let res = self.do_match(s, p)?;
+        if res.is_none() {
+            self.capture[l].1 = CaptureLen::Unfinished;
+        }
+        Ok(res)
+    }
+
+    fn match_capture(&self, s: usize, l: u8) -> MatchResult {
+        let l = self.check_capture(l)?;
+        let (init, len) = match self.capture[l] {
+            (init, CaptureLen::Len(len)) => (init, len),
+            // the length of a position capture never fits
+            _ => return Ok(None),
+        };
+        if self.src.len() - s >= len && self.src[init..init + len] == self.src[s..s + len] {
+            Ok(Some(s + len))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Matches the pattern from `p` against the subject from `s`,
+    /// returns the end of the match.
+    fn do_match(&mut self, s: usize, p: usize) -> MatchResult {
+        if self.depth == 0 {
+            return Err(PatternError::new("pattern too complex"));
+        }
+        self.depth -= 1;
+        let res = self.match_loop(s, p);
+        self.depth += 1;
+        res
+    }
+
+    fn match_loop(&mut self, mut s: usize, mut p: usize) -> MatchResult {
+        // `continue` replaces the tail calls to `do_match`
+        loop {
+            if p == self.pat.len() {
+                return Ok(Some(s));
+            }
+            match (self.pat[p], self.pat_at(p + 1)) {
+                (b'(', b')') => return self.start_capture(s, p + 2, CaptureLen::Position),
+                (b'(', _) => return self.start_capture(s, p + 1, CaptureLen::Unfinished),
+                (b')', _) => return self.end_capture(s, p + 1),
+                (b'$', _) if p + 1 == self.pat.len() => {
+                    return Ok(if s == self.src.len() { Some(s) } else { None });
+                }
+                (b'%', b'b') => match self.match_balance(s, p + 2)? {
+                    Some(e) => {
+                        s = e;
+                        p += 4;
+                    }
+                    None => return Ok(None),
+                },
+                (b'%', b'f') => {
+                    p += 2;
+                    if self.pat_at(p) != b'[' {
+                        return Err(PatternError::new("missing '[' after '%f' in pattern"));
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    if !self.match_bracket_class(previous, p, ep - 1)
+                        && self.match_bracket_class(self.src_at(s), p, ep - 1)
+                    {
+                        p = ep;
+                    } else {
+                        return Ok(None);
+                    }
+                }
+                (b'%', l) if l.is_ascii_digit() => match self.match_capture(s, l)? {
+                    Some(e) => {
+                        s = e;
+                        p += 2;
+                    }
+                    None => return Ok(None),
+                },
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let suffix = self.pat_at(ep);
+                    if !self.single_match(s, p, ep) {
+                        if suffix == b'*' || suffix == b'?' || suffix == b'-' {
+                            // accepts an empty match
+                            p = ep + 1;
+                            continue;
+                        }
+                        return Ok(None);
+                    }
+                    match suffix {
+                        b'?' => {
+                            if let Some(res) = self.do_match(s + 1, ep + 1)? {
+                                return Ok(Some(res));
+                            }
+                            p = ep + 1;
+                        }
+                        b'+' => return self.max_expand(s + 1, p, ep),
+                        b'*' => return self.max_expand(s, p, ep),
+                        b'-' => return self.min_expand(s, p, ep),
+                        _ => {
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_capture(&self, i: usize, s: usize, e: usize) -> Result<Capture<'s>, PatternError> {
+        if i >= self.level {
+            if i == 0 {
+                Ok(Capture::Str(LuaStr::from_bytes(&self.src[s..e])))
+            } else {
+                // Lua 5.2 only tells the index of invalid patterns
+                Err(invalid_capture(i as i32 + 1, cfg!(LUA_VERSION = "5.3")))
+            }
+        } else {
+            match self.capture[i] {
+                (_, CaptureLen::Unfinished) => Err(PatternError::new("unfinished capture")),
+                (init, CaptureLen::Position) => Ok(Capture::Position(init)),
+                (init, CaptureLen::Len(len)) => Ok(Capture::Str(LuaStr::from_bytes(
+                    &self.src[init..init + len],
+                ))),
+            }
+        }
+    }
+
+    /// Returns the match from `s` to `e` with its explicit captures.
+    fn captures(&self, s: usize, e: usize) -> Result<Captures<'s>, PatternError> {
+        let captures = (0..self.level)
+            .map(|i| self.get_capture(i, s, e))
+            .collect::<Result<_, _>>()?;
+        Ok(Captures {
+            subject: self.src,
+            range: s..e,
+            captures,
+        })
+    }
+
+    /// Appends the replacement of the match from `s` to `e` like `add_value`.
+    fn add_value(
+        &self,
+        out: &mut Vec<u8>,
+        s: usize,
+        e: usize,
+        replacement: &mut Replacement,
+    ) -> Result<(), PatternError> {
+        match replacement {
+            Replacement::Str(r) => self.add_str(out, s, e, r),
+            Replacement::Fn(f) => {
+                match f(&self.captures(s, e)?) {
+                    Some(value) => out.extend_from_slice(&value),
+                    // keeps the original text
+                    None => out.extend_from_slice(&self.src[s..e]),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Appends the replacement string `r`, where captures are only checked when used.
+    fn add_str(&self, out: &mut Vec<u8>, s: usize, e: usize, r: &[u8]) -> Result<(), PatternError> {
+        let mut i = 0;
+        while i < r.len() {
+            let c = r[i];
+            i += 1;
+            if c != b'%' {
+                out.push(c);
+                continue;
+            }
+            let c = r.get(i).copied().unwrap_or(0);
+            i += 1;
+            match c {
+                b'%' => out.push(b'%'),
+                b'0' => out.extend_from_slice(&self.src[s..e]),
+                b'1'..=b'9' => match self.get_capture(usize::from(c - b'1'), s, e)? {
+                    Capture::Str(capture) => out.extend_from_slice(capture.as_bytes()),
+                    Capture::Position(p) => out.extend_from_slice((p + 1).to_string().as_bytes()),
+                },
+                // Lua 5.1 copies the character, or a NUL at the end of the string
+                _ if !cfg!(LUA_VERSION = "5.2") => out.push(c),
+                _ => {
+                    return Err(PatternError::new(
+                        "invalid use of '%' in replacement string",
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The error of a reference to the capture `%index` that does not exist.
+fn invalid_capture(index: i32, with_index: bool) -> PatternError {
+    if with_index {
+        PatternError::new(format!("invalid capture index %{}", index))
+    } else {
+        PatternError::new("invalid capture index")
+    }
+}
+
+fn match_class(c: u8, cl: u8) -> bool {
+    let res = match cl.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        // introduced in Lua 5.2
+        b'g' if cfg!(LUA_VERSION = "5.2") => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        // like isspace, which also includes '\v'
+        b's' => matches!(c, b' ' | b'\t'..=b'\r'),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return cl == c,
+    };
+    if cl.is_ascii_lowercase() {
+        res
+    } else {
+        !res
+    }
+}
+
+/// Finds `needle` in `haystack` like `lmemfind`, an empty needle is found at 0.
+pub(crate) fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Finds the first match of `pattern` in `subject`, like `string.find`.
+///
+/// Unlike `string.find`, the whole match is always available with [`Captures::whole`].
+/// Patterns without special characters are searched as plain strings.
+///
+/// [`Captures::whole`]: struct.Captures.html#method.whole
+#[inline]
+pub fn find<'s, S, P>(subject: &'s S, pattern: &P) -> Result<Option<Captures<'s>>, PatternError>
+where
+    S: AsRef<[u8]> + ?Sized,
+    P: AsRef<[u8]> + ?Sized,
+{
+    find_at(subject, pattern, 0)
+}
+
+/// Finds the first match of `pattern` in `subject` starting at the offset `init`,
+/// like `string.find` with its `init` argument.
+///
+/// Returns `None` if `init` is after the end of the subject,
+/// Lua 5.1 starts at the end of the subject instead.
+pub fn find_at<'s, S, P>(
+    subject: &'s S,
+    pattern: &P,
+    init: usize,
+) -> Result<Option<Captures<'s>>, PatternError>
+where
+    S: AsRef<[u8]> + ?Sized,
+    P: AsRef<[u8]> + ?Sized,
+{
+    let (src, pat) = (subject.as_ref(), pattern.as_ref());
+    if init > src.len() {
+        if cfg!(LUA_VERSION = "5.2") {
+            return Ok(None);
+        }
+        return find_at(src, pat, src.len());
+    }
+    if !pat.iter().any(|c| SPECIALS.contains(c)) {
+        return Ok(find_bytes(&src[init..], pat).map(|start| Captures {
+            subject: src,
+            range: init + start..init + start + pat.len(),
+            captures: Vec::new(),
+        }));
+    }
+    first_match(src, pat, init)
+}
+
+/// Returns the first match of `pattern` in `subject`, like `string.match`.
+///
+/// The values returned by `string.match` are given by [`Captures::values`].
+/// Unlike [`find`], the pattern is always interpreted, even without special characters,
+/// so that errors such as an unbalanced `)` are reported as in Lua.
+///
+/// # Examples
+/// ```
+/// use pollua::pattern::{self, Capture};
+///
+/// let captures = pattern::captures("2024-01-31", "^(%d+)-(%d+)-(%d+)$")?.unwrap();
+/// assert_eq!(captures.len(), 3);
+/// assert_eq!(captures.get(1).unwrap().as_bytes(), Some(&b"01"[..]));
+/// let position = pattern::captures("hello", "()ll")?.unwrap();
+/// assert_eq!(position.values(), [Capture::Position(2)]);
+/// # Ok::<_, pattern::PatternError>(())
+/// ```
+///
+/// [`Captures::values`]: struct.Captures.html#method.values
+/// [`find`]: fn.find.html
+#[inline]
+pub fn captures<'s, S, P>(subject: &'s S, pattern: &P) -> Result<Option<Captures<'s>>, PatternError>
+where
+    S: AsRef<[u8]> + ?Sized,
+    P: AsRef<[u8]> + ?Sized,
+{
+    captures_at(subject, pattern, 0)
+}
+
+/// Returns the first match of `pattern` in `subject` starting at the offset `init`,
+/// like `string.match` with its `init` argument.
+///
+/// Returns `None` if `init` is after the end of the subject.
+pub fn captures_at<'s, S, P>(
+    subject: &'s S,
+    pattern: &P,
+    init: usize,
+) -> Result<Option<Captures<'s>>, PatternError>
+where
+    S: AsRef<[u8]> + ?Sized,
+    P: AsRef<[u8]> + ?Sized,
+{
+    let src = subject.as_ref();
+    if init > src.len() {
+        return Ok(None);
+    }
+    first_match(src, pattern.as_ref(), init)
+}
+
+/// Splits the `^` anchor from the pattern.
+#[inline]
+fn split_anchor(pat: &[u8]) -> (bool, &[u8]) {
+    match pat.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, pat),
+    }
+}
+
+/// Finds the first match from `init`, handling the `^` anchor.
+fn first_match<'s>(
+    src: &'s [u8],
+    pat: &[u8],
+    init: usize,
+) -> Result<Option<Captures<'s>>, PatternError> {
+    let (anchor, pat) = split_anchor(pat);
+    let mut ms = MatchState::new(src, pat);
+    let mut s = init;
+    loop {
+        ms.reset();
+        if let Some(e) = ms.do_match(s, 0)? {
+            return ms.captures(s, e).map(Some);
+        }
+        s += 1;
+        if s > src.len() || anchor {
+            return Ok(None);
+        }
+    }
+}
+
+/// Returns an iterator over the successive matches of `pattern` in `subject`,
+/// like `string.gmatch`.
+///
+/// As with `string.gmatch`, a `^` at the start of the pattern is not an anchor,
+/// and [`Captures::values`] gives the values Lua would return.
+/// An error ends the iteration.
+///
+/// # Examples
+/// ```
+/// use pollua::pattern;
+///
+/// let words = pattern::gmatch("one two  three", "%a+")
+///     .map(|m| m.map(|m| m.whole().to_string_lossy().into_owned()))
+///     .collect::<Result<Vec<_>, _>>()?;
+/// assert_eq!(words, ["one", "two", "three"]);
+/// # Ok::<_, pattern::PatternError>(())
+/// ```
+///
+/// [`Captures::values`]: struct.Captures.html#method.values
+#[inline]
+pub fn gmatch<'s, 'p, S, P>(subject: &'s S, pattern: &'p P) -> GMatch<'s, 'p>
+where
+    S: AsRef<[u8]> + ?Sized,
+    P: AsRef<[u8]> + ?Sized,
+{
+    GMatch {
+        src: subject.as_ref(),
+        pat: pattern.as_ref(),
+        pos: 0,
+        last_match: None,
+        done: false,
+    }
+}
+
+/// An iterator over the matches of a pattern.
+///
+/// This struct is created by [`gmatch`].
+///
+/// [`gmatch`]: fn.gmatch.html
+#[derive(Debug, Clone)]
+pub struct GMatch<'s, 'p> {
+    src: &'s [u8],
+    pat: &'p [u8],
+    pos: usize,
+    last_match: Option<usize>,
+    done: bool,
+}
+
+impl<'s> Iterator for GMatch<'s, '_> {
+    type Item = Result<Captures<'s>, PatternError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut ms = MatchState::new(self.src, self.pat);
+        for s in self.pos..=self.src.len() {
+            ms.reset();
+            match ms.do_match(s, 0) {
+                Ok(Some(e)) if Some(e) != self.last_match => {
+                    self.pos = e;
+                    self.last_match = last_match_end(s, e);
+                    let captures = ms.captures(s, e);
+                    self.done = captures.is_err();
+                    return Some(captures);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+impl FusedIterator for GMatch<'_, '_> {}
+
+/// The replacement of the matches of [`gsub`].
+///
+/// [`gsub`]: fn.gsub.html
+pub enum Replacement<'r> {
+    /// A string where `%0` stands for the whole match, `%1` to `%9` for the captures
+    /// and `%%` for `%`, like a replacement string in Lua.
+    Str(&'r [u8]),
+    /// A function of the match, like a replacement function in Lua,
+    /// returning `None` keeps the match unchanged.
+    Fn(&'r mut dyn FnMut(&Captures<'_>) -> Option<Vec<u8>>),
+}
+
+impl fmt::Debug for Replacement<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Replacement::Str(r) => f.debug_tuple("Str").field(&LuaStr::from_bytes(r)).finish(),
+            Replacement::Fn(_) => f.debug_tuple("Fn").finish(),
+        }
+    }
+}
+
+impl<'r> From<&'r str> for Replacement<'r> {
+    #[inline]
+    fn from(r: &'r str) -> Replacement<'r> {
+        Replacement::Str(r.as_bytes())
+    }
+}
+
+impl<'r> From<&'r [u8]> for Replacement<'r> {
+    #[inline]
+    fn from(r: &'r [u8]) -> Replacement<'r> {
+        Replacement::Str(r)
+    }
+}
+
+impl<'r> From<&'r LuaStr> for Replacement<'r> {
+    #[inline]
+    fn from(r: &'r LuaStr) -> Replacement<'r> {
+        Replacement::Str(r.as_bytes())
+    }
+}
+
+/// Replaces the matches of `pattern` in `subject`, at most `max` of them if given,
+/// like `string.gsub`.
+///
+/// Returns the new string and the number of matches replaced.
+///
+/// # Examples
+/// ```
+/// use pollua::pattern::{self, Replacement};
+///
+/// let (swapped, n) = pattern::gsub("hello world", "(%w+) (%w+)", "%2 %1", None)?;
+/// assert_eq!((&swapped[..], n), (&b"world hello"[..], 1));
+///
+/// let mut upper = |c: &pattern::Captures| Some(c.whole().as_bytes().to_ascii_uppercase());
+/// let (shout, n) = pattern::gsub("a b c", "%a", Replacement::Fn(&mut upper), Some(2))?;
+/// assert_eq!((&shout[..], n), (&b"A B c"[..], 2));
+/// # Ok::<_, pattern::PatternError>(())
+/// ```
+pub fn gsub<'r, S, P, R>(
+    subject: &S,
+    pattern: &P,
+    replacement: R,
+    max: Option<usize>,
+) -> Result<(Vec<u8>, usize), PatternError>
+where
+    S: AsRef<[u8]> + ?Sized,
+    P: AsRef<[u8]> + ?Sized,
+    R: Into<Replacement<'r>>,
+{
+    let src = subject.as_ref();
+    let (anchor, pat) = split_anchor(pattern.as_ref());
+    let max = max.unwrap_or(src.len() + 1);
+    let mut replacement = replacement.into();
+    let mut ms = MatchState::new(src, pat);
+    let mut out = Vec::with_capacity(src.len());
+    let (mut s, mut n, mut last_match) = (0, 0, None);
+    while n < max {
+        ms.reset();
+        match ms.do_match(s, 0)? {
+            Some(e) if Some(e) != last_match => {
+                n += 1;
+                ms.add_value(&mut out, s, e, &mut replacement)?;
+                last_match = last_match_end(s, e);
+                s = e;
+            }
+            _ if s < src.len() => {
+                out.push(src[s]);
+                s += 1;
+            }
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
+    }
+    out.extend_from_slice(&src[s..]);
+    Ok((out, n))
+}
+
+/// The end of a match from `s` to `e` that the next match must not end at.
+///
+/// An empty match is never repeated; since Lua 5.3, an empty match cannot follow any match.
+#[inline]
+fn last_match_end(s: usize, e: usize) -> Option<usize> {
+    if cfg!(LUA_VERSION = "5.3") || s == e {
+        Some(e)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::{LoadingMode, Thread};
+
+    fn find_str(subject: &str, pattern: &str) -> Option<(Range<usize>, Vec<String>)> {
+        find(subject, pattern).unwrap().map(|m| {
+            let captures = m
+                .iter()
+                .map(|c| match c {
+                    Capture::Str(s) => s.to_string_lossy().into_owned(),
+                    Capture::Position(p) => format!("@{}", p),
+                })
+                .collect();
+            (m.range(), captures)
+        })
+    }
+
+    #[test]
+    fn test_find() {
+        assert_eq!(find_str("hello world", "o w"), Some((4..7, vec![])));
+        assert_eq!(find_str("hello world", "^world"), None);
+        assert_eq!(find_str("hello world", "world$"), Some((6..11, vec![])));
+        assert_eq!(
+            find_str("  x = 10", "(%w+)%s*=%s*(%d+)"),
+            Some((2..8, vec!["x".to_owned(), "10".to_owned()]))
+        );
+        assert_eq!(find_str("f(a(b)c) d", "%b()"), Some((1..8, vec![])));
+        assert_eq!(
+            find_str("THE (quick) fox", "%f[%a]%a+%f[%A]"),
+            Some((0..3, vec![]))
+        );
+        assert_eq!(
+            find_str("abcabc", "()(b)()"),
+            Some((1..2, vec!["@1".to_owned(), "b".to_owned(), "@2".to_owned()]))
+        );
+        assert_eq!(
+            find_str("say 'hi' now", "(['\"])(.-)%1"),
+            Some((4..8, vec!["'".to_owned(), "hi".to_owned()]))
+        );
+        assert_eq!(find_str("a.b", "[.]"), Some((1..2, vec![])));
+        assert_eq!(find_str("x]y", "[]]"), Some((1..2, vec![])));
+        assert_eq!(find_str("a-z", "[a%-]+"), Some((0..2, vec![])));
+        assert_eq!(find_str("", ""), Some((0..0, vec![])));
+        assert_eq!(find_at("abc", "", 3).unwrap().unwrap().range(), 3..3);
+        #[cfg(LUA_VERSION = "5.2")]
+        assert!(find_at("abc", "", 4).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pattern_errors() {
+        let error = |p: &str| find("subject", p).expect_err(p).to_string();
+        assert_eq!(error("%"), "malformed pattern (ends with '%')");
+        assert_eq!(error("[a"), "malformed pattern (missing ']')");
+        assert_eq!(error("(s"), "unfinished capture");
+        assert_eq!(error("s.)"), "invalid pattern capture");
+        #[cfg(LUA_VERSION = "5.2")]
+        assert_eq!(error("%1"), "invalid capture index %1");
+        #[cfg(not(LUA_VERSION = "5.2"))]
+        assert_eq!(error("%1"), "invalid capture index");
+        #[cfg(LUA_VERSION = "5.2")]
+        assert_eq!(error("%b"), "malformed pattern (missing arguments to '%b')");
+        #[cfg(not(LUA_VERSION = "5.2"))]
+        assert_eq!(error("%b"), "unbalanced pattern");
+        assert_eq!(error("%fa"), "missing '[' after '%f' in pattern");
+        assert_eq!(error(&"(".repeat(33)), "too many captures");
+        assert_eq!(
+            find(&"a".repeat(300), &"a?".repeat(300))
+                .unwrap_err()
+                .to_string(),
+            "pattern too complex"
+        );
+    }
+
+    #[test]
+    fn test_gmatch() {
+        let values: Vec<_> = gmatch("k1=v1, k2=v2", "(%w+)=(%w+)")
+            .map(|m| {
+                let m = m.unwrap();
+                (m.get(0).unwrap(), m.get(1).unwrap())
+            })
+            .map(|(k, v)| (k.as_bytes().unwrap(), v.as_bytes().unwrap()))
+            .collect();
+        assert_eq!(values, [(&b"k1"[..], &b"v1"[..]), (b"k2", b"v2")]);
+
+        // empty matches advance, and since Lua 5.3 never follow the previous match
+        let ranges: Vec<_> = gmatch("abc", "%a*").map(|m| m.unwrap().range()).collect();
+        #[cfg(LUA_VERSION = "5.3")]
+        assert_eq!(ranges, [Range { start: 0, end: 3 }]);
+        #[cfg(not(LUA_VERSION = "5.3"))]
+        assert_eq!(ranges, [0..3, 3..3]);
+        let ranges: Vec<_> = gmatch("a,b", "[^,]*").map(|m| m.unwrap().range()).collect();
+        #[cfg(LUA_VERSION = "5.3")]
+        assert_eq!(ranges, [0..1, 2..3]);
+        #[cfg(not(LUA_VERSION = "5.3"))]
+        assert_eq!(ranges, [0..1, 1..1, 2..3, 3..3]);
+
+        let mut errors = gmatch("abc", "(");
+        assert!(errors.next().unwrap().is_err());
+        assert!(errors.next().is_none());
+    }
+
+    /// Runs `string.find`, `match`, `gmatch` and `gsub` and joins their results.
+    const LUA_RESULTS: &str = r#"
+        local s, p, r = ...
+        local unpack = table.unpack or unpack
+        local function pack(...) return { n = select('#', ...), ... } end
+        local function join(ok, ...)
+            if not ok then return "error: " .. tostring((...)) end
+            local values = pack(...)
+            for i = 1, values.n do values[i] = tostring(values[i]) end
+            return table.concat(values, "|", 1, values.n)
+        end
+        local function gmatch()
+            local matches = {}
+            local next = string.gmatch(s, p)
+            while true do
+                -- called from pcall, errors have no position like the other functions
+                local values = pack(pcall(next))
+                if not values[1] then error(values[2], 0) end
+                if values[2] == nil then break end
+                matches[#matches + 1] = join(unpack(values, 1, values.n))
+            end
+            return table.concat(matches, ";")
+        end
+        local function replace(...)
+            if (...) == "b" then return nil end
+            return "<" .. join(true, ...) .. ">"
+        end
+        return table.concat({
+            join(pcall(string.find, s, p)),
+            join(pcall(string.find, s, p, 3)),
+            join(pcall(string.match, s, p)),
+            join(pcall(gmatch)),
+            join(pcall(string.gsub, s, p, r)),
+            join(pcall(string.gsub, s, p, r, 1)),
+            join(pcall(string.gsub, s, p, replace)),
+        }, "\n")
+    "#;
+
+    fn join<'s>(values: impl IntoIterator<Item = Capture<'s>>) -> String {
+        values
+            .into_iter()
+            .map(|c| match c {
+                Capture::Str(s) => s.to_string_lossy().into_owned(),
+                Capture::Position(p) => (p + 1).to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+
+    fn join_result<T>(result: Result<T, PatternError>, f: impl FnOnce(T) -> String) -> String {
+        match result {
+            Ok(value) => f(value),
+            Err(e) => format!("error: {}", e),
+        }
+    }
+
+    fn join_find(result: Result<Option<Captures<'_>>, PatternError>) -> String {
+        join_result(result, |m| match m {
+            Some(m) => {
+                let range = format!("{}|{}", m.range().start + 1, m.range().end);
+                if m.is_empty() {
+                    range
+                } else {
+                    format!("{}|{}", range, join(m.iter()))
+                }
+            }
+            None => "nil".to_owned(),
+        })
+    }
+
+    fn join_gsub(result: Result<(Vec<u8>, usize), PatternError>) -> String {
+        join_result(result, |(s, n)| {
+            format!("{}|{}", String::from_utf8_lossy(&s), n)
+        })
+    }
+
+    fn rust_results(s: &str, p: &str, r: &str) -> String {
+        let mut replace = |c: &Captures| {
+            if c.values()[0].as_bytes() == Some(b"b") {
+                None
+            } else {
+                Some(format!("<{}>", join(c.values())).into_bytes())
+            }
+        };
+        [
+            join_find(find(s, p)),
+            join_find(find_at(s, p, 2)),
+            join_result(captures(s, p), |m| {
+                m.map_or_else(|| "nil".to_owned(), |m| join(m.values()))
+            }),
+            join_result(gmatch(s, p).collect::<Result<Vec<_>, _>>(), |matches| {
+                let matches: Vec<_> = matches.iter().map(|m| join(m.values())).collect();
+                matches.join(";")
+            }),
+            join_gsub(gsub(s, p, r, None)),
+            join_gsub(gsub(s, p, r, Some(1))),
+            join_gsub(gsub(s, p, Replacement::Fn(&mut replace), None)),
+        ]
+        .join("\n")
+    }
+
+    #[test]
+    fn test_string_library_conformance() {
+        const CASES: &[(&str, &str, &str)] = &[
+            ("hello world", "o", "0"),
+            ("hello world", "l+", "[%0]"),
+            ("hello world", "^h", "H"),
+            ("hello world", "^w", "W"),
+            ("hello world", "d$", "D"),
+            ("hello world", "o$w", "x"),
+            ("hello world", "(o)(.)", "%2%1"),
+            ("hello world", "()o()", "%1"),
+            ("hello world", "%a+", "<%0>"),
+            ("hello world", "%A", "_"),
+            ("a,b,,c", "[^,]*", "x"),
+            ("a,b,,c", "([^,]*)", "(%1)"),
+            ("abc", "%w*", "-"),
+            ("abc", "", "-"),
+            ("abc", "b*", "-"),
+            ("aaab", "a-b", "x"),
+            ("aaab", "a-", "x"),
+            ("aaab", "a?a?b", "x"),
+            ("f(a(b)c) (d", "%b()", "[]"),
+            ("if x then y end", "%f[%w]%w+", "%0."),
+            ("THE (quick) fox", "%f[%a]%a+%f[%A]", "w"),
+            ("say 'hi' and \"bye\"", "(['\"])(.-)%1", "%2"),
+            ("a.b-c+d", "[%.%-+]", "%%"),
+            ("x]y[z", "[]]", "!"),
+            ("x]y[z", "[^]]", "!"),
+            ("a-z", "[a-]", "1"),
+            ("0x1F, 0xg", "0x(%x+)", "%1"),
+            ("tab\there\nnew\x0bline", "%s", " "),
+            ("MiXeD CaSe", "%u%l", "%0"),
+            ("a\0b\0c", "%z", "0"),
+            ("a\0b\0c", "[%z]b", "0"),
+            ("é café", "[^%w%s]+", "?"),
+            ("key = value", "(%w+)%s*=%s*(%w+)", "%2=%1"),
+            ("abc", "(a)(b)(c)", "%3%2%1%0"),
+            ("abc", "b", "%1"),
+            ("abc", "(b)", "%2"),
+            ("abc", "b", "%"),
+            ("abc", "b", "%x"),
+            ("abc", "%", "x"),
+            ("abc", "[a", "x"),
+            ("abc", "(a", "x"),
+            ("abc", "a)", "x"),
+            ("abc", "b)", "x"),
+            ("abc", "%1", "x"),
+            ("abc", "(a)%2", "x"),
+            ("abc", "%b", "x"),
+            ("abc", "%fa", "x"),
+            ("abc", "%g+", "x"),
+            ("a.b", "a.b", "x"),
+            ("a+b", "a+b", "x"),
+            ("", "", "x"),
+            ("", ".*", "x"),
+        ];
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            for &(s, p, r) in CASES {
+                let values = thread
+                    .caller_load(LUA_RESULTS, None, LoadingMode::Text)
+                    .unwrap()
+                    .args((s, p, r))
+                    .call()
+                    .unwrap();
+                let expected = values.get_str(0).unwrap().to_string_lossy().into_owned();
+                assert_eq!(rust_results(s, p, r), expected, "{:?} {:?} {:?}", s, p, r);
+            }
+            // Lua 5.1 only has the limit of the C stack
+            #[cfg(LUA_VERSION = "5.2")]
+            {
+                let many = "a".repeat(300);
+                let values = thread
+                    .caller_load(LUA_RESULTS, None, LoadingMode::Text)
+                    .unwrap()
+                    .args((many.as_str(), "a?".repeat(250).as_str(), "x"))
+                    .call()
+                    .unwrap();
+                let expected = values.get_str(0).unwrap().to_string_lossy().into_owned();
+                assert_eq!(rust_results(&many, &"a?".repeat(250), "x"), expected);
+            }
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,381 @@
+use crate::{
+    thread::{GlobalsSnapshot, Thread},
+    LuaResult,
+};
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Mutex, MutexGuard},
+    thread as os_thread,
+};
+
+type Init = Box<dyn Fn(&mut Thread) -> LuaResult<()> + Send + Sync>;
+
+/// A pool of initialized [`Thread`]s, shared between OS threads.
+///
+/// Threads are created lazily by running the init function on a new thread,
+/// and are returned to the pool when the [`PooledThread`] handing them out is dropped.
+///
+/// # Examples
+/// ```
+/// use pollua::{pool::StatePool, thread::LoadingMode};
+/// use std::{sync::Arc, thread};
+///
+/// let pool = Arc::new(
+///     StatePool::new(|thread| {
+///         thread.register_fn("greet", |name: String| Ok(format!("Hello, {}!", name)))
+///     })
+///     .reset_globals(true),
+/// );
+///
+/// let workers: Vec<_> = (0..4).map(|i| {
+///     let pool = pool.clone();
+///     thread::spawn(move || {
+///         pool.run(|thread| {
+///             let values = thread
+///                 .caller_load("return greet(...)", None, LoadingMode::Text)?
+///                 .arg(format!("worker {}", i))
+///                 .call()?;
+///             values.get_as::<String>(0)
+///         })
+///     })
+/// }).collect();
+///
+/// for (i, worker) in workers.into_iter().enumerate() {
+///     assert_eq!(worker.join().unwrap().unwrap(), format!("Hello, worker {}!", i));
+/// }
+/// ```
+///
+/// [`Thread`]: ../thread/struct.Thread.html
+/// [`PooledThread`]: struct.PooledThread.html
+pub struct StatePool {
+    init: Init,
+    idle: Mutex<Vec<Entry>>,
+    max_idle: usize,
+    reset_globals: bool,
+    memory_limit: Option<usize>,
+}
+
+impl StatePool {
+    /// Creates an empty pool whose threads are initialized by `init`.
+    ///
+    /// `init` may open libraries, register functions or run scripts,
+    /// threads for which it fails are discarded.
+    pub fn new<F>(init: F) -> StatePool
+    where
+        F: Fn(&mut Thread) -> LuaResult<()> + Send + Sync + 'static,
+    {
+        StatePool {
+            init: Box::new(init),
+            idle: Mutex::new(Vec::new()),
+            max_idle: usize::MAX,
+            reset_globals: false,
+            memory_limit: None,
+        }
+    }
+
+    /// Sets the maximum number of idle threads kept by the pool, unlimited by default.
+    #[inline]
+    pub fn max_idle(mut self, max_idle: usize) -> StatePool {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Sets whether the globals and `package.loaded` are reset to their values after
+    /// initialization when a thread is returned to the pool, `false` by default.
+    ///
+    /// See [`Thread::restore_globals`], changes made to the tables stored in globals,
+    /// such as libraries, are kept.
+    ///
+    /// [`Thread::restore_globals`]: ../thread/struct.Thread.html#method.restore_globals
+    #[inline]
+    pub fn reset_globals(mut self, reset_globals: bool) -> StatePool {
+        self.reset_globals = reset_globals;
+        self
+    }
+
+    /// Sets the amount of memory, in bytes, above which threads are discarded
+    /// instead of being returned to the pool. There is no limit by default.
+    ///
+    /// Threads are measured after a full garbage collection,
+    /// and discarded if a finalizer raises an error during that collection.
+    #[inline]
+    pub fn memory_limit(mut self, bytes: usize) -> StatePool {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Takes an idle thread from the pool, or creates a new one.
+    ///
+    /// Returns the error raised by the init function if a new thread could not be initialized.
+    pub fn get(&self) -> LuaResult<PooledThread<'_>> {
+        let entry = match self.lock().pop() {
+            Some(entry) => entry,
+            None => self.create()?,
+        };
+        Ok(PooledThread {
+            pool: self,
+            entry: Some(entry),
+        })
+    }
+
+    /// Runs `f` with a thread of the pool.
+    ///
+    /// The thread is discarded if `f` returns an error, as it may have been left in any state.
+    pub fn run<F, T>(&self, f: F) -> LuaResult<T>
+    where
+        F: FnOnce(&mut Thread) -> LuaResult<T>,
+    {
+        let mut thread = self.get()?;
+        let result = f(&mut thread);
+        if result.is_err() {
+            thread.discard();
+        }
+        result
+    }
+
+    /// Returns the number of idle threads in the pool.
+    #[inline]
+    pub fn idle_count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Removes every idle thread from the pool.
+    #[inline]
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, Vec<Entry>> {
+        // the idle list stays consistent even if a thread panicked while holding the lock
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn create(&self) -> LuaResult<Entry> {
+        let mut thread = Thread::new()?;
+        (self.init)(&mut thread)?;
+        let snapshot = if self.reset_globals {
+            Some(thread.snapshot_globals()?)
+        } else {
+            None
+        };
+        Ok(Entry { thread, snapshot })
+    }
+
+    /// Returns `entry` to the pool, unless it should be discarded.
+    fn recycle(&self, mut entry: Entry) {
+        if let Some(snapshot) = &entry.snapshot {
+            if entry.thread.restore_globals(snapshot).is_err() {
+                return;
+            }
+        }
+        if let Some(limit) = self.memory_limit {
+            if entry.thread.gc().collect().is_err() || entry.thread.gc().used_bytes() > limit {
+                return;
+            }
+        }
+        let mut idle = self.lock();
+        if idle.len() < self.max_idle {
+            idle.push(entry);
+        }
+    }
+}
+
+impl fmt::Debug for StatePool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StatePool")
+            .field("idle", &self.idle_count())
+            .field("max_idle", &self.max_idle)
+            .field("reset_globals", &self.reset_globals)
+            .field("memory_limit", &self.memory_limit)
+            .finish()
+    }
+}
+
+/// A [`Thread`] taken from a [`StatePool`], returned to the pool when dropped.
+///
+/// The thread is discarded instead if [`discard`] was called, or if it is dropped while panicking.
+///
+/// [`Thread`]: ../thread/struct.Thread.html
+/// [`StatePool`]: struct.StatePool.html
+/// [`discard`]: #method.discard
+#[derive(Debug)]
+pub struct PooledThread<'a> {
+    pool: &'a StatePool,
+    entry: Option<Entry>,
+}
+
+/// A thread of the pool, along with the globals to restore.
+#[derive(Debug)]
+struct Entry {
+    thread: Thread,
+    snapshot: Option<GlobalsSnapshot>,
+}
+
+impl PooledThread<'_> {
+    /// Closes the thread instead of returning it to the pool,
+    /// used when it may have been left in an inconsistent state.
+    #[inline]
+    pub fn discard(mut self) {
+        self.entry = None;
+    }
+}
+
+impl Deref for PooledThread<'_> {
+    type Target = Thread;
+
+    #[inline]
+    fn deref(&self) -> &Thread {
+        &self
+            .entry
+            .as_ref()
+            .expect("thread already discarded")
+            .thread
+    }
+}
+
+impl DerefMut for PooledThread<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Thread {
+        &mut self
+            .entry
+            .as_mut()
+            .expect("thread already discarded")
+            .thread
+    }
+}
+
+impl Drop for PooledThread<'_> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            if !os_thread::panicking() {
+                self.pool.recycle(entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{thread::LoadingMode, Error, ErrorKind};
+    use std::sync::Arc;
+
+    fn eval(thread: &mut Thread, code: &str) -> LuaResult<Option<String>> {
+        let values = thread.caller_load(code, None, LoadingMode::Text)?.call()?;
+        values.get_as::<Option<String>>(0)
+    }
+
+    #[test]
+    fn test_pool_reuse() {
+        let pool = StatePool::new(|thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            eval(thread, "name = 'init' count = 0").map(|_| ())
+        })
+        .reset_globals(true)
+        .max_idle(1);
+
+        {
+            let mut thread = pool.get().unwrap();
+            let top = unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) };
+            eval(&mut thread, "name = 'changed' count = nil added = true").unwrap();
+            assert_eq!(
+                eval(&mut thread, "return name .. tostring(added)").unwrap(),
+                Some("changedtrue".to_owned())
+            );
+            assert_eq!(unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }, top);
+        }
+        assert_eq!(pool.idle_count(), 1);
+
+        {
+            let mut first = pool.get().unwrap();
+            let mut second = pool.get().unwrap();
+            for thread in [&mut first, &mut second].iter_mut() {
+                assert_eq!(
+                    eval(thread, "return name .. count .. tostring(added)").unwrap(),
+                    Some("init0nil".to_owned())
+                );
+            }
+        }
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[test]
+    fn test_pool_discard() {
+        let pool = StatePool::new(|_| Ok(()));
+        let err = pool
+            .run(|thread| eval(thread, "error('failed')"))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Runtime);
+        assert_eq!(pool.idle_count(), 0);
+
+        pool.run(|thread| eval(thread, "return nil")).unwrap();
+        assert_eq!(pool.idle_count(), 1);
+        pool.get().unwrap().discard();
+        assert_eq!(pool.idle_count(), 0);
+
+        let pool = StatePool::new(|thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            Ok(())
+        })
+        .memory_limit(256 * 1024);
+        pool.run(|thread| eval(thread, "small = string.rep('x', 16)"))
+            .unwrap();
+        assert_eq!(pool.idle_count(), 1);
+        pool.run(|thread| eval(thread, "big = string.rep('x', 1024 * 1024)"))
+            .unwrap();
+        assert_eq!(pool.idle_count(), 0);
+        // Lua 5.1 does not finalize tables, and Lua 5.4 turns errors in finalizers into warnings
+        #[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.4")))]
+        {
+            pool.run(|thread| {
+                eval(
+                    thread,
+                    "setmetatable({}, { __gc = function() error('gc') end })",
+                )
+            })
+            .unwrap();
+            assert_eq!(pool.idle_count(), 0);
+        }
+
+        let pool = StatePool::new(|_| Err(Error::new(ErrorKind::Runtime, Some("init".to_owned()))));
+        assert_eq!(pool.get().unwrap_err().msg(), Some("init"));
+    }
+
+    #[test]
+    fn test_pool_threads() {
+        let pool = Arc::new(StatePool::new(|thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            Ok(())
+        }));
+        let workers: Vec<_> = (0..8)
+            .map(|i| {
+                let pool = pool.clone();
+                os_thread::spawn(move || {
+                    for _ in 0..10 {
+                        let result = pool
+                            .run(|thread| {
+                                thread
+                                    .caller_load(
+                                        "return string.rep('a', ...)",
+                                        None,
+                                        LoadingMode::Text,
+                                    )?
+                                    .arg(i)
+                                    .call()?
+                                    .get_as::<String>(0)
+                            })
+                            .unwrap();
+                        assert_eq!(result.len(), i);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(pool.idle_count() <= 8);
+    }
+}
@@ -0,0 +1,1282 @@
+use crate::{
+    thread::{StackGuard, Thread, ThreadRef},
+    value::{self, FromLua, LightUserdata, Pushable, Pusher},
+    Error, ErrorKind, LuaResult,
+};
+
+use ::serde::{
+    de::{self, DeserializeOwned, IntoDeserializer},
+    ser::{self, Serialize},
+};
+use std::{fmt, mem, slice};
+
+/// Light userdata used to represent `null` where `nil` cannot be used,
+/// such as inside sequences.
+///
+/// It is deserialized as a unit or `None`, and `None` and unit values are serialized
+/// to it when [`Options::serialize_none_as_null`] is set.
+///
+/// [`Options::serialize_none_as_null`]: struct.Options.html#method.serialize_none_as_null
+pub const NULL: LightUserdata<libc::c_void> = LightUserdata::null();
+
+/// How an empty table is deserialized when the target type accepts anything,
+/// such as `serde_json::Value`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EmptyTable {
+    Sequence,
+    Map,
+}
+
+/// Options of the [`Serializer`] and [`Deserializer`].
+///
+/// [`Serializer`]: struct.Serializer.html
+/// [`Deserializer`]: struct.Deserializer.html
+#[derive(Debug, Copy, Clone)]
+pub struct Options {
+    empty_table: EmptyTable,
+    detect_arrays: bool,
+    none_as_null: bool,
+}
+
+impl Default for Options {
+    #[inline]
+    fn default() -> Options {
+        Options {
+            empty_table: EmptyTable::Map,
+            detect_arrays: true,
+            none_as_null: false,
+        }
+    }
+}
+
+impl Options {
+    /// Sets how empty tables are deserialized, defaults to [`EmptyTable::Map`].
+    ///
+    /// [`EmptyTable::Map`]: enum.EmptyTable.html#variant.Map
+    #[inline]
+    pub fn empty_table(mut self, empty_table: EmptyTable) -> Options {
+        self.empty_table = empty_table;
+        self
+    }
+
+    /// Sets whether tables whose keys are exactly `1..=n` are deserialized as sequences,
+    /// when the target type accepts anything. Defaults to `true`.
+    #[inline]
+    pub fn detect_arrays(mut self, detect_arrays: bool) -> Options {
+        self.detect_arrays = detect_arrays;
+        self
+    }
+
+    /// Sets whether `None` and unit values are serialized to [`NULL`] instead of `nil`.
+    /// Defaults to `false`.
+    ///
+    /// [`NULL`]: constant.NULL.html
+    #[inline]
+    pub fn serialize_none_as_null(mut self, none_as_null: bool) -> Options {
+        self.none_as_null = none_as_null;
+        self
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::new(ErrorKind::Conversion, Some(msg.to_string()))
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Error {
+        Error::new(ErrorKind::Conversion, Some(msg.to_string()))
+    }
+}
+
+/// Pushes `value` onto the stack of `thread`.
+///
+/// # Examples
+/// ```
+/// use pollua::{serde::Options, thread::{LoadingMode, Thread}};
+/// use std::collections::HashMap;
+///
+/// Thread::spawn(move |thread| {
+///     let mut config = HashMap::new();
+///     config.insert("width", 640);
+///     pollua::serde::to_lua(thread, &config, Options::default())?;
+///     # unsafe { pollua::sys::lua_pop(thread.as_raw().as_ptr(), 1) };
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+pub fn to_lua<T>(thread: &mut Thread, value: &T, options: Options) -> LuaResult<()>
+where
+    T: Serialize + ?Sized,
+{
+    let mut guard = StackGuard::new(thread);
+    value.serialize(Serializer::new(&mut guard, options))?;
+    // keep the serialized value, partially built values are popped on errors
+    mem::forget(guard);
+    Ok(())
+}
+
+/// Deserializes the value at `index` without popping it.
+pub fn from_lua<T>(thread: &mut Thread, index: libc::c_int, options: Options) -> LuaResult<T>
+where
+    T: DeserializeOwned,
+{
+    let index = unsafe { sys::lua_absindex(thread.as_raw().as_ptr(), index) };
+    let mut guard = StackGuard::new(thread);
+    let mut ancestors = Vec::new();
+    T::deserialize(Deserializer::new(
+        &mut guard,
+        index,
+        options,
+        &mut ancestors,
+    ))
+}
+
+/// Wrapper that pushes any [`Serialize`] value, using the default [`Options`].
+///
+/// [`Serialize`]: https://docs.rs/serde/1/serde/trait.Serialize.html
+/// [`Options`]: struct.Options.html
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Serialized<T>(pub T);
+
+impl<T: Serialize> Pushable for Serialized<T> {
+    #[inline]
+    fn push(&self, mut pusher: Pusher) -> LuaResult<()> {
+        to_lua(&mut pusher.0, &self.0, Options::default())
+    }
+}
+
+/// Wrapper that converts a Lua value to any [`Deserialize`] value,
+/// using the default [`Options`].
+///
+/// # Examples
+/// ```
+/// use pollua::{serde::Deserialized, thread::{LoadingMode, Thread}};
+/// use std::collections::BTreeMap;
+///
+/// Thread::spawn(move |thread| {
+///     let values = thread
+///         .caller_load("return { width = 640, height = 480 }", None, LoadingMode::Text)?
+///         .call()?;
+///     let Deserialized(config) = values.get_as::<Deserialized<BTreeMap<String, u32>>>(0)?;
+///     assert_eq!(config["width"], 640);
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+///
+/// [`Deserialize`]: https://docs.rs/serde/1/serde/trait.Deserialize.html
+/// [`Options`]: struct.Options.html
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Deserialized<T>(pub T);
+
+impl<T: DeserializeOwned> FromLua for Deserialized<T> {
+    #[inline]
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<Deserialized<T>> {
+        from_lua(thread, index, Options::default()).map(Deserialized)
+    }
+}
+
+/// A serializer that pushes a single Lua value onto the stack.
+pub struct Serializer<'a> {
+    thread: ThreadRef<'a>,
+    options: Options,
+}
+
+impl<'a> Serializer<'a> {
+    /// Creates a serializer for `thread`.
+    ///
+    /// Values pushed before an error are not popped, prefer using [`to_lua`].
+    ///
+    /// [`to_lua`]: fn.to_lua.html
+    #[inline]
+    pub fn new(thread: &'a mut Thread, options: Options) -> Serializer<'a> {
+        Serializer {
+            thread: ThreadRef::from_ref(thread),
+            options,
+        }
+    }
+
+    #[inline]
+    fn raw(&mut self) -> *mut sys::lua_State {
+        self.thread.as_raw().as_ptr()
+    }
+
+    #[inline]
+    fn reserve(&mut self, n: usize) -> LuaResult<*mut sys::lua_State> {
+        value::reserve(self.thread.as_raw(), n)?;
+        Ok(self.raw())
+    }
+
+    fn push_bytes(mut self, bytes: &[u8]) -> LuaResult<()> {
+        let l = self.reserve(1)?;
+        unsafe { sys::lua_pushlstring(l, bytes.as_ptr() as *const _, bytes.len()) };
+        Ok(())
+    }
+
+    fn push_table(&mut self, narr: usize, nrec: usize) -> LuaResult<()> {
+        let l = self.reserve(3)?;
+        let clamp = |n: usize| n.min(libc::c_int::MAX as usize) as libc::c_int;
+        unsafe { sys::lua_createtable(l, clamp(narr), clamp(nrec)) };
+        Ok(())
+    }
+
+    /// Pushes a table with a single `variant` field, and an inner table to be filled.
+    fn push_variant(&mut self, variant: &str, len: usize, sequence: bool) -> LuaResult<()> {
+        self.push_table(0, 1)?;
+        self.reborrow().push_bytes(variant.as_bytes())?;
+        if sequence {
+            self.push_table(len, 0)
+        } else {
+            self.push_table(0, len)
+        }
+    }
+
+    #[inline]
+    fn reborrow(&mut self) -> Serializer<'_> {
+        Serializer {
+            thread: ThreadRef::from_ref(&mut self.thread),
+            options: self.options,
+        }
+    }
+}
+
+/// Serializer for sequences, maps and structs, the table is at the top of the stack.
+pub struct SerializeTable<'a> {
+    serializer: Serializer<'a>,
+    /// Number of elements for sequences.
+    len: sys::lua_Integer,
+    /// Whether the table is wrapped in a variant table.
+    variant: bool,
+}
+
+impl<'a> SerializeTable<'a> {
+    #[inline]
+    fn new(serializer: Serializer<'a>, variant: bool) -> SerializeTable<'a> {
+        SerializeTable {
+            serializer,
+            len: 0,
+            variant,
+        }
+    }
+
+    fn push_element<T: Serialize + ?Sized>(&mut self, value: &T) -> LuaResult<()> {
+        value.serialize(self.serializer.reborrow())?;
+        self.len += 1;
+        unsafe { sys::lua_rawseti(self.serializer.raw(), -2, self.len) };
+        Ok(())
+    }
+
+    fn push_field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> LuaResult<()> {
+        self.serializer.reborrow().push_bytes(key.as_bytes())?;
+        value.serialize(self.serializer.reborrow())?;
+        unsafe { sys::lua_rawset(self.serializer.raw(), -3) };
+        Ok(())
+    }
+
+    fn finish(mut self) -> LuaResult<()> {
+        if self.variant {
+            // set the inner table as the variant's value
+            unsafe { sys::lua_rawset(self.serializer.raw(), -3) };
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::Serializer for Serializer<'a> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = SerializeTable<'a>;
+    type SerializeTuple = SerializeTable<'a>;
+    type SerializeTupleStruct = SerializeTable<'a>;
+    type SerializeTupleVariant = SerializeTable<'a>;
+    type SerializeMap = SerializeTable<'a>;
+    type SerializeStruct = SerializeTable<'a>;
+    type SerializeStructVariant = SerializeTable<'a>;
+
+    fn serialize_bool(mut self, v: bool) -> LuaResult<()> {
+        let l = self.reserve(1)?;
+        unsafe { sys::lua_pushboolean(l, v as libc::c_int) };
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> LuaResult<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> LuaResult<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> LuaResult<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_i64(mut self, v: i64) -> LuaResult<()> {
+        let l = self.reserve(1)?;
+        unsafe { sys::lua_pushinteger(l, v as sys::lua_Integer) };
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> LuaResult<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> LuaResult<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> LuaResult<()> {
+        self.serialize_i64(i64::from(v))
+    }
+
+    /// Integers that do not fit in a Lua integer are serialized as floats.
+    fn serialize_u64(self, v: u64) -> LuaResult<()> {
+        if v <= i64::MAX as u64 {
+            self.serialize_i64(v as i64)
+        } else {
+            self.serialize_f64(v as f64)
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> LuaResult<()> {
+        self.serialize_f64(f64::from(v))
+    }
+
+    fn serialize_f64(mut self, v: f64) -> LuaResult<()> {
+        let l = self.reserve(1)?;
+        unsafe { sys::lua_pushnumber(l, v as sys::lua_Number) };
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> LuaResult<()> {
+        self.push_bytes(v.encode_utf8(&mut [0; 4]).as_bytes())
+    }
+
+    fn serialize_str(self, v: &str) -> LuaResult<()> {
+        self.push_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> LuaResult<()> {
+        self.push_bytes(v)
+    }
+
+    fn serialize_none(self) -> LuaResult<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> LuaResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(mut self) -> LuaResult<()> {
+        let l = self.reserve(1)?;
+        if self.options.none_as_null {
+            unsafe { sys::lua_pushlightuserdata(l, NULL.as_ptr()) };
+        } else {
+            unsafe { sys::lua_pushnil(l) };
+        }
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> LuaResult<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> LuaResult<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> LuaResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        mut self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> LuaResult<()> {
+        self.push_table(0, 1)?;
+        let mut table = SerializeTable::new(self, false);
+        table.push_field(variant, value)
+    }
+
+    fn serialize_seq(mut self, len: Option<usize>) -> LuaResult<SerializeTable<'a>> {
+        self.push_table(len.unwrap_or(0), 0)?;
+        Ok(SerializeTable::new(self, false))
+    }
+
+    fn serialize_tuple(self, len: usize) -> LuaResult<SerializeTable<'a>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> LuaResult<SerializeTable<'a>> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        mut self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> LuaResult<SerializeTable<'a>> {
+        self.push_variant(variant, len, true)?;
+        Ok(SerializeTable::new(self, true))
+    }
+
+    fn serialize_map(mut self, len: Option<usize>) -> LuaResult<SerializeTable<'a>> {
+        self.push_table(0, len.unwrap_or(0))?;
+        Ok(SerializeTable::new(self, false))
+    }
+
+    fn serialize_struct(
+        mut self,
+        _name: &'static str,
+        len: usize,
+    ) -> LuaResult<SerializeTable<'a>> {
+        self.push_table(0, len)?;
+        Ok(SerializeTable::new(self, false))
+    }
+
+    fn serialize_struct_variant(
+        mut self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> LuaResult<SerializeTable<'a>> {
+        self.push_variant(variant, len, false)?;
+        Ok(SerializeTable::new(self, true))
+    }
+}
+
+impl ser::SerializeSeq for SerializeTable<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> LuaResult<()> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> LuaResult<()> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeTable<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> LuaResult<()> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> LuaResult<()> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeTable<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> LuaResult<()> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> LuaResult<()> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeTable<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> LuaResult<()> {
+        self.push_element(value)
+    }
+
+    fn end(self) -> LuaResult<()> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for SerializeTable<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> LuaResult<()> {
+        key.serialize(self.serializer.reborrow())?;
+        let l = self.serializer.raw();
+        // lua_rawset raises an error outside of protected mode on these keys
+        let valid = unsafe {
+            match sys::lua_type(l, -1) {
+                sys::LUA_TNIL => false,
+                sys::LUA_TNUMBER => !sys::lua_tonumber(l, -1).is_nan(),
+                _ => true,
+            }
+        };
+        if !valid {
+            return Err(Error::new(
+                ErrorKind::Conversion,
+                Some("map keys cannot be nil or NaN".to_owned()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> LuaResult<()> {
+        value.serialize(self.serializer.reborrow())?;
+        unsafe { sys::lua_rawset(self.serializer.raw(), -3) };
+        Ok(())
+    }
+
+    fn end(self) -> LuaResult<()> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeTable<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> LuaResult<()> {
+        self.push_field(key, value)
+    }
+
+    fn end(self) -> LuaResult<()> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeTable<'_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> LuaResult<()> {
+        self.push_field(key, value)
+    }
+
+    fn end(self) -> LuaResult<()> {
+        self.finish()
+    }
+}
+
+/// A deserializer that reads a Lua value from a stack slot.
+pub struct Deserializer<'a> {
+    thread: ThreadRef<'a>,
+    index: libc::c_int,
+    options: Options,
+    /// Tables being deserialized, used to detect cycles.
+    ancestors: &'a mut Vec<*const libc::c_void>,
+}
+
+impl<'a> Deserializer<'a> {
+    /// Creates a deserializer for the value at the absolute stack `index`.
+    ///
+    /// `ancestors` is used to detect cycles and should be empty,
+    /// values pushed before an error are not popped, prefer using [`from_lua`].
+    ///
+    /// [`from_lua`]: fn.from_lua.html
+    #[inline]
+    pub fn new(
+        thread: &'a mut Thread,
+        index: libc::c_int,
+        options: Options,
+        ancestors: &'a mut Vec<*const libc::c_void>,
+    ) -> Deserializer<'a> {
+        Deserializer {
+            thread: ThreadRef::from_ref(thread),
+            index,
+            options,
+            ancestors,
+        }
+    }
+
+    #[inline]
+    fn raw(&mut self) -> *mut sys::lua_State {
+        self.thread.as_raw().as_ptr()
+    }
+
+    #[inline]
+    fn value_type(&mut self) -> libc::c_int {
+        unsafe { sys::lua_type(self.raw(), self.index) }
+    }
+
+    fn is_null(&mut self) -> bool {
+        match self.value_type() {
+            sys::LUA_TNIL | sys::LUA_TNONE => true,
+            sys::LUA_TLIGHTUSERDATA => {
+                unsafe { sys::lua_touserdata(self.raw(), self.index) }.is_null()
+            }
+            _ => false,
+        }
+    }
+
+    /// Creates a deserializer for the value at `index`, sharing the ancestors.
+    #[inline]
+    fn child(&mut self, index: libc::c_int) -> Deserializer<'_> {
+        Deserializer {
+            thread: ThreadRef::from_ref(&mut self.thread),
+            index,
+            options: self.options,
+            ancestors: &mut *self.ancestors,
+        }
+    }
+
+    fn bytes(&mut self) -> LuaResult<&[u8]> {
+        if self.value_type() != sys::LUA_TSTRING {
+            return Err(self.type_error("string"));
+        }
+        let mut len = 0usize;
+        let s = unsafe { sys::lua_tolstring(self.raw(), self.index, &mut len) };
+        Ok(unsafe { slice::from_raw_parts(s as *const u8, len) })
+    }
+
+    fn type_error(&mut self, expected: &str) -> Error {
+        let index = self.index;
+        value::type_error(&mut self.thread, index, expected)
+    }
+
+    /// Returns true if the keys of the table are exactly `1..=n`, with `n > 0`.
+    fn is_sequence(&mut self) -> LuaResult<bool> {
+        let l = self.raw();
+        value::reserve(self.thread.as_raw(), 2)?;
+        let len = unsafe { sys::lua_rawlen(l, self.index) };
+        if len == 0 {
+            return Ok(false);
+        }
+        let mut count = 0usize;
+        unsafe {
+            sys::lua_pushnil(l);
+            while sys::lua_next(l, self.index) != 0 {
+                sys::lua_pop(l, 1);
+                if sys::lua_isinteger(l, -1) == 0 {
+                    sys::lua_pop(l, 1);
+                    return Ok(false);
+                }
+                let key = sys::lua_tointeger(l, -1);
+                if key < 1 || key as usize > len {
+                    sys::lua_pop(l, 1);
+                    return Ok(false);
+                }
+                count += 1;
+            }
+        }
+        Ok(count == len)
+    }
+
+    /// Visits the table with `f`, detecting cycles and restoring the stack top.
+    fn visit_table<T, F>(mut self, f: F) -> LuaResult<T>
+    where
+        F: FnOnce(&mut Deserializer<'a>) -> LuaResult<T>,
+    {
+        if self.value_type() != sys::LUA_TTABLE {
+            return Err(self.type_error("table"));
+        }
+        let table = unsafe { sys::lua_topointer(self.raw(), self.index) };
+        if self.ancestors.contains(&table) {
+            return Err(Error::new(
+                ErrorKind::Conversion,
+                Some("cannot deserialize a table that contains itself".to_owned()),
+            ));
+        }
+        self.ancestors.push(table);
+        let top = unsafe { sys::lua_gettop(self.raw()) };
+        let result = f(&mut self);
+        unsafe { sys::lua_settop(self.raw(), top) };
+        self.ancestors.pop();
+        result
+    }
+
+    fn visit_seq<'de, V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.visit_table(|de| {
+            let len = unsafe { sys::lua_rawlen(de.raw(), de.index) };
+            let value = visitor.visit_seq(SeqAccess {
+                de: de.child(de.index),
+                next: 1,
+                len,
+            })?;
+            Ok(value)
+        })
+    }
+
+    fn visit_map<'de, V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.visit_table(|de| {
+            value::reserve(de.thread.as_raw(), 3)?;
+            unsafe { sys::lua_pushnil(de.raw()) };
+            visitor.visit_map(MapAccess {
+                de: de.child(de.index),
+                done: false,
+            })
+        })
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: de::Visitor<'de>>(mut self, visitor: V) -> LuaResult<V::Value> {
+        let l = self.raw();
+        match self.value_type() {
+            sys::LUA_TNIL | sys::LUA_TNONE => visitor.visit_unit(),
+            sys::LUA_TBOOLEAN => {
+                visitor.visit_bool(unsafe { sys::lua_toboolean(l, self.index) } != 0)
+            }
+            sys::LUA_TNUMBER if unsafe { sys::lua_isinteger(l, self.index) } != 0 => {
+                visitor.visit_i64(unsafe { sys::lua_tointeger(l, self.index) } as i64)
+            }
+            sys::LUA_TNUMBER => {
+                visitor.visit_f64(unsafe { sys::lua_tonumber(l, self.index) } as f64)
+            }
+            sys::LUA_TSTRING => {
+                let bytes = self.bytes()?;
+                match std::str::from_utf8(bytes) {
+                    Ok(s) => visitor.visit_str(s),
+                    Err(_) => visitor.visit_bytes(bytes),
+                }
+            }
+            sys::LUA_TTABLE => {
+                value::reserve(self.thread.as_raw(), 2)?;
+                let sequence = if unsafe { sys::lua_rawlen(l, self.index) } == 0
+                    && table_is_empty(l, self.index)
+                {
+                    self.options.empty_table == EmptyTable::Sequence
+                } else {
+                    self.options.detect_arrays && self.is_sequence()?
+                };
+                if sequence {
+                    self.visit_seq(visitor)
+                } else {
+                    self.visit_map(visitor)
+                }
+            }
+            sys::LUA_TLIGHTUSERDATA if self.is_null() => visitor.visit_unit(),
+            _ => Err(self.type_error("a serializable value")),
+        }
+    }
+
+    fn deserialize_bool<V: de::Visitor<'de>>(mut self, visitor: V) -> LuaResult<V::Value> {
+        match self.value_type() {
+            sys::LUA_TBOOLEAN => self.deserialize_any(visitor),
+            _ => Err(self.type_error("boolean")),
+        }
+    }
+
+    fn deserialize_i64<V: de::Visitor<'de>>(mut self, visitor: V) -> LuaResult<V::Value> {
+        // floats with an exact integer representation are accepted
+        if self.value_type() == sys::LUA_TNUMBER {
+            let mut isnum = 0;
+            let n = unsafe { sys::lua_tointegerx(self.raw(), self.index, &mut isnum) };
+            if isnum != 0 {
+                return visitor.visit_i64(n as i64);
+            }
+        }
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_i8<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u8<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u16<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u32<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_u64<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_f32<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: de::Visitor<'de>>(mut self, visitor: V) -> LuaResult<V::Value> {
+        match self.value_type() {
+            sys::LUA_TNUMBER => {
+                visitor.visit_f64(unsafe { sys::lua_tonumber(self.raw(), self.index) } as f64)
+            }
+            _ => Err(self.type_error("number")),
+        }
+    }
+
+    fn deserialize_char<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_str<V: de::Visitor<'de>>(mut self, visitor: V) -> LuaResult<V::Value> {
+        match std::str::from_utf8(self.bytes()?) {
+            Ok(s) => visitor.visit_str(s),
+            Err(e) => Err(Error::new(ErrorKind::Conversion, Some(e.to_string()))),
+        }
+    }
+
+    fn deserialize_string<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: de::Visitor<'de>>(mut self, visitor: V) -> LuaResult<V::Value> {
+        match self.value_type() {
+            sys::LUA_TSTRING => visitor.visit_bytes(self.bytes()?),
+            _ => self.visit_seq(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: de::Visitor<'de>>(mut self, visitor: V) -> LuaResult<V::Value> {
+        if self.is_null() {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: de::Visitor<'de>>(mut self, visitor: V) -> LuaResult<V::Value> {
+        if self.is_null() {
+            visitor.visit_unit()
+        } else {
+            Err(self.type_error("nil"))
+        }
+    }
+
+    fn deserialize_unit_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: de::Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        self.visit_seq(visitor)
+    }
+
+    fn deserialize_map<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.visit_map(visitor)
+    }
+
+    fn deserialize_struct<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        self.visit_map(visitor)
+    }
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        mut self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        match self.value_type() {
+            sys::LUA_TSTRING => {
+                let variant = std::str::from_utf8(self.bytes()?)
+                    .map_err(|e| Error::new(ErrorKind::Conversion, Some(e.to_string())))?
+                    .to_owned();
+                visitor.visit_enum(variant.into_deserializer())
+            }
+            sys::LUA_TTABLE => self.visit_table(|de| {
+                let l = de.raw();
+                value::reserve(de.thread.as_raw(), 4)?;
+                unsafe { sys::lua_pushnil(l) };
+                if unsafe { sys::lua_next(l, de.index) } == 0 {
+                    return Err(Error::new(
+                        ErrorKind::Conversion,
+                        Some("expected a table with a single key, got an empty table".to_owned()),
+                    ));
+                }
+                let top = unsafe { sys::lua_gettop(l) };
+                unsafe { sys::lua_pushvalue(l, -2) };
+                if unsafe { sys::lua_next(l, de.index) } != 0 {
+                    // there is another key-value pair
+                    return Err(Error::new(
+                        ErrorKind::Conversion,
+                        Some("expected a table with a single key".to_owned()),
+                    ));
+                }
+                unsafe { sys::lua_settop(l, top) };
+                visitor.visit_enum(EnumAccess {
+                    de: de.child(top - 1),
+                })
+            }),
+            _ => Err(self.type_error("string or table")),
+        }
+    }
+
+    fn deserialize_identifier<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_ignored_any<V: de::Visitor<'de>>(self, visitor: V) -> LuaResult<V::Value> {
+        visitor.visit_unit()
+    }
+}
+
+fn table_is_empty(l: *mut sys::lua_State, index: libc::c_int) -> bool {
+    unsafe {
+        sys::lua_pushnil(l);
+        if sys::lua_next(l, index) != 0 {
+            sys::lua_pop(l, 2);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+struct SeqAccess<'a> {
+    de: Deserializer<'a>,
+    next: usize,
+    len: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> LuaResult<Option<T::Value>> {
+        if self.next > self.len {
+            return Ok(None);
+        }
+        let l = self.de.raw();
+        value::reserve(self.de.thread.as_raw(), 1)?;
+        unsafe { sys::lua_rawgeti(l, self.de.index, self.next as sys::lua_Integer) };
+        let top = unsafe { sys::lua_gettop(l) };
+        let value = seed.deserialize(self.de.child(top));
+        unsafe { sys::lua_settop(l, top - 1) };
+        self.next += 1;
+        value.map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len + 1 - self.next)
+    }
+}
+
+/// Iterates over a table with `lua_next`, the current key is at the top of the stack.
+struct MapAccess<'a> {
+    de: Deserializer<'a>,
+    done: bool,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'_> {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> LuaResult<Option<K::Value>> {
+        if self.done {
+            return Ok(None);
+        }
+        let l = self.de.raw();
+        value::reserve(self.de.thread.as_raw(), 2)?;
+        if unsafe { sys::lua_next(l, self.de.index) } == 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        let top = unsafe { sys::lua_gettop(l) };
+        seed.deserialize(self.de.child(top - 1)).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> LuaResult<V::Value> {
+        let l = self.de.raw();
+        let top = unsafe { sys::lua_gettop(l) };
+        let value = seed.deserialize(self.de.child(top));
+        // keep the key for the next iteration
+        unsafe { sys::lua_settop(l, top - 1) };
+        value
+    }
+}
+
+/// Accesses the variant of a single key table, the key is below the value.
+struct EnumAccess<'a> {
+    de: Deserializer<'a>,
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for EnumAccess<'a> {
+    type Error = Error;
+    type Variant = Deserializer<'a>;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        mut self,
+        seed: V,
+    ) -> LuaResult<(V::Value, Deserializer<'a>)> {
+        let variant = seed.deserialize(self.de.child(self.de.index))?;
+        let value_index = self.de.index + 1;
+        self.de.index = value_index;
+        Ok((variant, self.de))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> LuaResult<()> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> LuaResult<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(self, _len: usize, visitor: V) -> LuaResult<V::Value> {
+        self.visit_seq(visitor)
+    }
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> LuaResult<V::Value> {
+        self.visit_map(visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+    use ::serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+        Line(i32, i32),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Config {
+        name: String,
+        size: Option<u32>,
+        tags: Vec<String>,
+        shapes: Vec<Shape>,
+        extra: BTreeMap<String, f64>,
+        enabled: bool,
+    }
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    #[test]
+    fn test_serde_roundtrip() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            let config = Config {
+                name: "test".to_owned(),
+                size: None,
+                tags: vec!["a".to_owned(), "b".to_owned()],
+                shapes: vec![
+                    Shape::Empty,
+                    Shape::Circle(1.5),
+                    Shape::Rect { w: 2, h: 3 },
+                    Shape::Line(-1, 1),
+                ],
+                extra: vec![("x".to_owned(), 0.5)].into_iter().collect(),
+                enabled: true,
+            };
+            to_lua(thread, &config, Options::default()).unwrap();
+            assert_eq!(stack_top(thread), top + 1);
+            let back: Config = from_lua(thread, -1, Options::default()).unwrap();
+            assert_eq!(back, config);
+            unsafe { sys::lua_pop(thread.as_raw().as_ptr(), 1) };
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_serde_script_table() {
+        Thread::spawn(move |thread| {
+            let values = thread
+                .caller_load(
+                    "return {
+                        name = 'script', size = 10 / 2, tags = {}, enabled = true,
+                        shapes = { 'Empty', { Rect = { w = 1, h = 2 } } },
+                        extra = { y = 2 },
+                    }",
+                    None,
+                    LoadingMode::Text,
+                )
+                .and_then(|c| c.call())
+                .unwrap();
+            let Deserialized(config) = values.get_as::<Deserialized<Config>>(0).unwrap();
+            assert_eq!(config.size, Some(5));
+            assert!(config.tags.is_empty());
+            assert_eq!(config.shapes[1], Shape::Rect { w: 1, h: 2 });
+            assert_eq!(config.extra["y"], 2.0);
+        })
+        .unwrap()
+    }
+
+    /// Any value, deserialized with `deserialize_any`.
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(untagged)]
+    enum Any {
+        Null,
+        Int(i64),
+        Str(String),
+        Seq(Vec<Any>),
+        Map(BTreeMap<i64, Any>),
+    }
+
+    #[test]
+    fn test_serde_policies() {
+        Thread::spawn(move |thread| {
+            unsafe {
+                sys::lua_pushlightuserdata(thread.as_raw().as_ptr(), NULL.as_ptr());
+                sys::lua_setglobal(thread.as_raw().as_ptr(), b"null\0".as_ptr() as *const _);
+            }
+            thread
+                .caller_load(
+                    "local cycle = {} cycle[1] = cycle
+                    return {}, { 1, 2, null }, { 1, nil, 3 }, cycle",
+                    None,
+                    LoadingMode::Text,
+                )
+                .and_then(|c| c.calln(4))
+                .map(std::mem::forget)
+                .unwrap();
+            let top = stack_top(thread);
+            let sequences = Options::default().empty_table(EmptyTable::Sequence);
+            let no_arrays = Options::default().detect_arrays(false);
+
+            let any = |thread: &mut Thread, index, options| from_lua::<Any>(thread, index, options);
+            assert_eq!(any(thread, top - 3, sequences).unwrap(), Any::Seq(vec![]));
+            assert_eq!(
+                any(thread, top - 3, no_arrays).unwrap(),
+                Any::Map(BTreeMap::new())
+            );
+            assert_eq!(
+                any(thread, top - 2, Options::default()).unwrap(),
+                Any::Seq(vec![Any::Int(1), Any::Int(2), Any::Null])
+            );
+            match any(thread, top - 2, no_arrays).unwrap() {
+                Any::Map(map) => assert_eq!(map.len(), 3),
+                other => panic!("{:?}", other),
+            }
+            match any(thread, top - 1, Options::default()).unwrap() {
+                Any::Map(map) => assert_eq!(map.keys().collect::<Vec<_>>(), [&1, &3]),
+                other => panic!("{:?}", other),
+            }
+            let err = from_lua::<Vec<Vec<()>>>(thread, top, Options::default()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+            assert_eq!(stack_top(thread), top);
+
+            let nulls = Options::default().serialize_none_as_null(true);
+            to_lua(thread, &[Some(1), None, Some(3)], nulls).unwrap();
+            assert_eq!(unsafe { sys::lua_rawlen(thread.as_raw().as_ptr(), -1) }, 3);
+            let back: Vec<Option<i32>> = from_lua(thread, -1, Options::default()).unwrap();
+            assert_eq!(back, [Some(1), None, Some(3)]);
+        })
+        .unwrap()
+    }
+
+    /// A map with a single float key.
+    struct FloatKey(f64);
+
+    impl Serialize for FloatKey {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_map(std::iter::once((self.0, 1)))
+        }
+    }
+
+    #[test]
+    fn test_serde_invalid_keys() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            let err = to_lua(thread, &FloatKey(f64::NAN), Options::default()).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+            let err = to_lua(
+                thread,
+                &[(None::<i32>, 1)]
+                    .iter()
+                    .cloned()
+                    .collect::<BTreeMap<_, _>>(),
+                Options::default(),
+            )
+            .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+            assert_eq!(stack_top(thread), top);
+
+            to_lua(thread, &FloatKey(0.5), Options::default()).unwrap();
+            assert_eq!(stack_top(thread), top + 1);
+        })
+        .unwrap()
+    }
+}
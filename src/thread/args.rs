@@ -0,0 +1,156 @@
+use crate::{
+    thread::{Thread, ThreadRef},
+    value::{self, FromLua, LuaStr, ValueType},
+    Error, ErrorKind, LuaResult,
+};
+
+use std::{cell::UnsafeCell, convert::TryFrom};
+
+/// The arguments of a C function, which are the values on its stack.
+///
+/// Created by the [`arguments`] method on [`Thread`].
+/// Unlike [`FromLua`] conversions, strings can be borrowed without being copied,
+/// for as long as the `Arguments` is alive.
+///
+/// # Examples
+/// ```
+/// use pollua::{
+///     module::Module,
+///     thread::{LoadingMode, Thread, ThreadRef},
+/// };
+/// use std::ptr::NonNull;
+///
+/// /// Counts the lines of its argument without copying it.
+/// unsafe extern "C" fn count_lines(l: *mut pollua::sys::lua_State) -> std::os::raw::c_int {
+///     let mut thread = ThreadRef::from_raw(NonNull::new_unchecked(l));
+///     let lines = match thread.arguments().get_str(0) {
+///         Ok(text) => text.as_bytes().split(|&b| b == b'\n').count(),
+///         Err(_) => 0,
+///     };
+///     pollua::sys::lua_pushinteger(l, lines as pollua::sys::lua_Integer);
+///     1
+/// }
+///
+/// Thread::spawn(move |thread| {
+///     thread.register_module(Module::new("text").c_function("count_lines", Some(count_lines)))?;
+///     let values = thread
+///         .caller_load("return text.count_lines('a\\nb\\nc')", None, LoadingMode::Text)?
+///         .call()?;
+///     assert_eq!(values.get_as::<u32>(0)?, 3);
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+///
+/// [`arguments`]: struct.Thread.html#method.arguments
+/// [`Thread`]: struct.Thread.html
+/// [`FromLua`]: ../value/trait.FromLua.html
+#[derive(Debug)]
+pub struct Arguments<'a> {
+    thread: UnsafeCell<ThreadRef<'a>>,
+    count: libc::c_int,
+}
+
+impl Arguments<'_> {
+    #[inline]
+    fn thread(&self) -> ThreadRef<'_> {
+        unsafe { ThreadRef::from_raw((*self.thread.get()).as_raw()) }
+    }
+
+    /// Returns the number of arguments.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Returns true if there are no arguments.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the type of the argument at the given position or `None` if out of bounds.
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<ValueType> {
+        if index < self.len() {
+            ValueType::from_code(unsafe {
+                sys::lua_type(self.thread().as_raw().as_ptr(), index as libc::c_int + 1)
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Converts the argument at the given position,
+    /// out of bounds arguments are converted from `nil`.
+    pub fn get_as<T: FromLua>(&self, index: usize) -> LuaResult<T> {
+        let n = libc::c_int::try_from(index).unwrap_or(libc::c_int::MAX);
+        value::from_lua_nth(&mut self.thread(), 1, self.count, n)
+    }
+
+    /// Borrows the string argument at the given position without copying it.
+    ///
+    /// Returns an error of kind [`ErrorKind::Conversion`] if the argument is not a string,
+    /// numbers are not converted, or is out of bounds.
+    ///
+    /// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+    pub fn get_str(&self, index: usize) -> LuaResult<&LuaStr> {
+        if index >= self.len() {
+            return Err(Error::new(
+                ErrorKind::Conversion,
+                Some("expected string, got no value".to_owned()),
+            ));
+        }
+        unsafe { value::borrow_str(&mut self.thread(), index as libc::c_int + 1) }
+    }
+}
+
+impl Thread {
+    /// Returns the values on the stack, which are the arguments in a C function.
+    #[inline]
+    pub fn arguments(&mut self) -> Arguments<'_> {
+        let count = unsafe { sys::lua_gettop(self.as_raw().as_ptr()) };
+        Arguments {
+            thread: UnsafeCell::new(ThreadRef::from_ref(self)),
+            count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::LuaNil;
+
+    #[test]
+    fn test_arguments() {
+        Thread::spawn(move |thread| {
+            let raw = thread.as_raw().as_ptr();
+            unsafe {
+                sys::lua_pushstring(raw, b"first\0".as_ptr() as *const _);
+                sys::lua_pushinteger(raw, 42);
+                sys::lua_pushlstring(raw, b"a\0b".as_ptr() as *const _, 3);
+            }
+            {
+                let args = thread.arguments();
+                assert_eq!(args.len(), 3);
+                assert_eq!(args.get(1), Some(ValueType::Number));
+                assert_eq!(args.get(3), None);
+                let (first, last) = (args.get_str(0).unwrap(), args.get_str(2).unwrap());
+                assert_eq!(first.as_bytes(), b"first");
+                assert_eq!(last.as_bytes(), b"a\0b");
+                assert_eq!(args.get_as::<i64>(1).unwrap(), 42);
+                assert_eq!(args.get_as::<LuaNil>(5).unwrap(), LuaNil);
+                // numbers are not converted in place
+                let err = args.get_str(1).unwrap_err();
+                assert_eq!(err.msg(), Some("expected string, got number"));
+                assert_eq!(args.get(1), Some(ValueType::Number));
+                assert_eq!(
+                    args.get_str(3).unwrap_err().msg(),
+                    Some("expected string, got no value")
+                );
+            }
+            unsafe { sys::lua_settop(raw, 0) };
+        })
+        .unwrap()
+    }
+}
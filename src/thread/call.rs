@@ -1,10 +1,14 @@
 use crate::{
-    thread::{Thread, ThreadRef},
-    value::{Pushable, Pusher, ValueType},
-    LuaResult,
+    thread::{StackGuard, ThreadRef},
+    value::{
+        self, FromLua, FromLuaMulti, IntoLuaMulti, LuaStr, OwnedValue, Pushable, Pusher, Table,
+        TransferOptions, ValueType,
+    },
+    Error, ErrorKind, LuaResult,
 };
 use std::{
     cell::UnsafeCell,
+    convert::TryFrom,
     iter::{DoubleEndedIterator, FusedIterator},
     ops::Index,
 };
@@ -16,19 +20,26 @@ use std::{
 /// [`Thread`]: struct.Thread.html
 #[derive(Debug)]
 pub struct Caller<'a> {
-    thread: ThreadRef<'a>,
+    /// Restores the stack top from before the function was pushed.
+    thread: StackGuard<'a>,
     /// Number of arguments pushed to the stack.
     nargs: libc::c_int,
+    /// First error raised while pushing the arguments, returned when calling.
+    error: Option<Error>,
 }
 
 impl<'a> Caller<'a> {
-    pub(super) fn from_global(mut thread: ThreadRef<'a>, name: &[u8]) -> Option<Caller<'a>> {
-        // check if _G[name] is a function
+    pub(super) fn from_global(thread: ThreadRef<'a>, name: &[u8]) -> Option<Caller<'a>> {
+        let mut thread = StackGuard::from_ref(thread);
+        // check if _G[name] is a function, the guard pops it otherwise
         if thread.push_global(name) != sys::LUA_TFUNCTION {
-            unsafe { sys::lua_pop(thread.as_raw().as_ptr(), 1) };
             None
         } else {
-            Some(Caller { thread, nargs: 0 })
+            Some(Caller {
+                thread,
+                nargs: 0,
+                error: None,
+            })
         }
     }
 
@@ -38,37 +49,73 @@ impl<'a> Caller<'a> {
     /// # Safety
     /// Behavior is undefined if the value at the top of the stack is not a function.
     #[inline]
-    pub(crate) unsafe fn from_stack_unchecked(mut thread: ThreadRef) -> Caller {
+    pub(crate) unsafe fn from_stack_unchecked(mut thread: ThreadRef<'_>) -> Caller<'_> {
+        let top = sys::lua_gettop(thread.as_raw().as_ptr());
         debug_assert_eq!(
             sys::lua_type(thread.as_raw().as_ptr(), -1),
             sys::LUA_TFUNCTION
         );
-        Caller { thread, nargs: 0 }
+        Caller {
+            thread: StackGuard::with_top(thread, top - 1),
+            nargs: 0,
+            error: None,
+        }
     }
 
+    /// Pushes an argument for the call.
+    ///
+    /// If the argument cannot be pushed, e.g. because the stack cannot grow anymore,
+    /// the remaining arguments are ignored and the error is returned by the call.
     #[inline]
-    pub fn arg<A: Pushable>(mut self, arg: A) -> Caller<'a> {
-        unsafe { arg.push(Pusher(ThreadRef::from_raw(self.thread.as_raw()))) }
-        self.nargs += 1;
+    pub fn arg<A: Pushable>(self, arg: A) -> Caller<'a> {
+        self.args(arg)
+    }
+
+    /// Pushes any number of arguments for the call,
+    /// such as a tuple or a [`Variadic`].
+    ///
+    /// Errors are handled the same way as in [`arg`].
+    ///
+    /// [`Variadic`]: ../value/struct.Variadic.html
+    /// [`arg`]: #method.arg
+    pub fn args<A: IntoLuaMulti>(mut self, args: A) -> Caller<'a> {
+        if self.error.is_none() {
+            let raw = self.thread.as_raw();
+            match args.push_multi(Pusher(unsafe { ThreadRef::from_raw(raw) })) {
+                Ok(n) => self.nargs += n,
+                Err(e) => {
+                    // remove what may have been pushed before the error
+                    let top = self.thread.top() + 1 + self.nargs;
+                    unsafe { sys::lua_settop(raw.as_ptr(), top) };
+                    self.error = Some(e);
+                }
+            }
+        }
         self
     }
 
+    /// Returns the error raised while pushing the arguments, if any.
+    #[inline]
+    fn check_args(&mut self) -> LuaResult<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
     /// Executes the call, consuming the `Caller`.
     pub fn call(mut self) -> LuaResult<ReturnValues<'a>> {
+        self.check_args()?;
         unsafe {
-            // stack top before function and args were pushed
-            let top = sys::lua_gettop(self.thread.as_raw().as_ptr()) - self.nargs - 1;
             let status = sys::lua_pcall(
                 self.thread.as_raw().as_ptr(),
                 self.nargs,
                 sys::LUA_MULTRET,
                 0,
             );
-            self.nargs = -1;
-            let nresults = sys::lua_gettop(self.thread.as_raw().as_ptr()) - top;
-            self.thread
-                .get_error(status)
-                .map(|_| ReturnValues::new(self, nresults))
+            self.thread.get_error(status)?;
+            let nresults = sys::lua_gettop(self.thread.as_raw().as_ptr()) - self.thread.top();
+            Ok(ReturnValues::new(self, nresults))
         }
     }
 
@@ -80,25 +127,31 @@ impl<'a> Caller<'a> {
     /// Prefer using [`call`] if you are not sure whether the function will throw errors.
     /// See [Lua error handling] for more details.
     ///
+    /// # Panics
+    /// This panics if one of the arguments could not be pushed.
+    ///
     /// [`call`]: #method.call
     /// [Lua error handling]: https://www.lua.org/manual/5.3/manual.html#4.6
     pub unsafe fn call_unprotected(mut self) -> ReturnValues<'a> {
-        // stack top before function and args were pushed
-        let top = sys::lua_gettop(self.thread.as_raw().as_ptr()) - self.nargs - 1;
+        self.check_args()
+            .expect("failed to push the call arguments");
         sys::lua_pcall(
             self.thread.as_raw().as_ptr(),
             self.nargs,
             sys::LUA_MULTRET,
             0,
         );
-        self.nargs = -1;
-        let nresults = sys::lua_gettop(self.thread.as_raw().as_ptr()) - top;
+        let nresults = sys::lua_gettop(self.thread.as_raw().as_ptr()) - self.thread.top();
         ReturnValues::new(self, nresults)
     }
 
     /// Executes the call, consuming the `Caller`.
     /// The number of results is adjusted to `nresults`.
     pub fn calln(mut self, nresults: u32) -> LuaResult<ReturnValues<'a>> {
+        self.check_args()?;
+        // the results replace the function and its arguments
+        let extra = (nresults as usize).saturating_sub(self.nargs as usize + 1);
+        value::reserve(self.thread.as_raw(), extra)?;
         unsafe {
             let status = sys::lua_pcall(
                 self.thread.as_raw().as_ptr(),
@@ -106,10 +159,8 @@ impl<'a> Caller<'a> {
                 nresults as libc::c_int,
                 0,
             );
-            self.nargs = -1;
-            self.thread
-                .get_error(status)
-                .map(|_| ReturnValues::new(self, nresults as libc::c_int))
+            self.thread.get_error(status)?;
+            Ok(ReturnValues::new(self, nresults as libc::c_int))
         }
     }
 
@@ -122,58 +173,55 @@ impl<'a> Caller<'a> {
     /// Prefer using [`call`] if you are not sure whether the function will throw errors.
     /// See [Lua error handling] for more details.
     ///
+    /// # Panics
+    /// This panics if one of the arguments could not be pushed.
+    ///
     /// [`call`]: #method.call
     /// [Lua error handling]: https://www.lua.org/manual/5.3/manual.html#4.6
     pub unsafe fn calln_unprotected(mut self, nresults: u32) -> ReturnValues<'a> {
+        self.check_args()
+            .expect("failed to push the call arguments");
+        let extra = (nresults as usize).saturating_sub(self.nargs as usize + 1);
+        value::reserve(self.thread.as_raw(), extra).expect("failed to reserve the call results");
         sys::lua_call(
             self.thread.as_raw().as_ptr(),
             self.nargs,
             nresults as libc::c_int,
         );
-        self.nargs = -1;
         ReturnValues::new(self, nresults as libc::c_int)
     }
 }
 
-impl<'a> Drop for Caller<'a> {
-    fn drop(&mut self) {
-        // Pops all remaining pushed elements from the stack
-        unsafe {
-            sys::lua_pop(
-                self.thread.as_raw().as_ptr(),
-                (self.nargs + 1) as libc::c_int,
-            )
-        };
-    }
-}
-
 /// Holds the values produced by the [`call*`] methods on [`Caller`].
 ///
+/// The values are popped from the stack when `ReturnValues` is dropped.
+///
 /// [`call*`]: struct.Caller.html#method.call
 /// [`Caller`]: struct.Caller.html
 #[derive(Debug)]
 pub struct ReturnValues<'a> {
-    thread: UnsafeCell<ThreadRef<'a>>,
+    thread: UnsafeCell<StackGuard<'a>>,
     nresults: libc::c_int,
 }
 
 impl<'a> ReturnValues<'a> {
     #[inline]
-    fn new(mut caller: Caller, nresults: libc::c_int) -> ReturnValues {
+    fn new(caller: Caller<'a>, nresults: libc::c_int) -> ReturnValues<'a> {
         ReturnValues {
-            thread: unsafe { UnsafeCell::new(ThreadRef::from_raw(caller.thread.as_raw())) },
+            thread: UnsafeCell::new(caller.thread),
             nresults,
         }
     }
 
     #[inline]
-    fn thread(&mut self) -> &mut Thread {
-        unsafe { &mut *self.thread.get() }
+    fn thread_ptr(&self) -> *mut sys::lua_State {
+        unsafe { &mut *self.thread.get() }.as_raw().as_ptr()
     }
 
+    /// Returns the absolute stack index of the return value at `index`.
     #[inline]
-    fn thread_ptr(&self) -> *mut sys::lua_State {
-        unsafe { &mut *self.thread.get() }.as_raw().as_ptr()
+    fn stack_index(&self, index: usize) -> libc::c_int {
+        unsafe { &*self.thread.get() }.top() + 1 + index as libc::c_int
     }
 
     /// Returns the number of values returned by the call.
@@ -193,18 +241,140 @@ impl<'a> ReturnValues<'a> {
     pub fn get(&self, index: usize) -> Option<ValueType> {
         if index < self.nresults as usize {
             ValueType::from_code(unsafe {
-                sys::lua_type(self.thread_ptr(), -self.nresults + (index as libc::c_int))
+                sys::lua_type(self.thread_ptr(), self.stack_index(index))
             })
         } else {
             None
         }
     }
 
+    /// Converts the return value at the given position,
+    /// out of bounds values are converted from `nil`.
+    pub fn get_as<T: FromLua>(&self, index: usize) -> LuaResult<T> {
+        let mut thread = unsafe { ThreadRef::from_raw((*self.thread.get()).as_raw()) };
+        let start = self.stack_index(0);
+        let n = libc::c_int::try_from(index).unwrap_or(libc::c_int::MAX);
+        value::from_lua_nth(&mut thread, start, self.nresults, n)
+    }
+
+    /// Borrows the string at the given position without copying it.
+    ///
+    /// The string stays on the stack as long as the `ReturnValues`.
+    /// Returns an error of kind [`ErrorKind::Conversion`] if the value is not a string,
+    /// numbers are not converted, or is out of bounds.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::{LoadingMode, Thread};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     let values = thread
+    ///         .caller_load("return 'some text', 42", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     assert_eq!(values.get_str(0)?.as_bytes(), b"some text");
+    ///     assert!(values.get_str(1).is_err());
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    ///
+    /// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+    pub fn get_str(&self, index: usize) -> LuaResult<&LuaStr> {
+        if index >= self.nresults as usize {
+            return Err(Error::new(
+                ErrorKind::Conversion,
+                Some("expected string, got no value".to_owned()),
+            ));
+        }
+        let mut thread = unsafe { ThreadRef::from_raw((*self.thread.get()).as_raw()) };
+        unsafe { value::borrow_str(&mut thread, self.stack_index(index)) }
+    }
+
+    /// Returns the table at the given position.
+    ///
+    /// Returns an error of kind [`ErrorKind::Conversion`] if the value is not a table
+    /// or is out of bounds.
+    ///
+    /// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+    pub fn get_table(&mut self, index: usize) -> LuaResult<Table<'_>> {
+        if index >= self.nresults as usize {
+            return Err(Error::new(
+                ErrorKind::Conversion,
+                Some("expected table, got no value".to_owned()),
+            ));
+        }
+        let stack_index = self.stack_index(index);
+        Table::from_stack(self.thread.get_mut(), stack_index)
+    }
+
+    /// Copies the return value at the given position so that it can be pushed into another
+    /// thread, out of bounds values are copied as `nil`.
+    ///
+    /// See [`OwnedValue::transferable`] for the values that cannot be copied.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::{thread::{LoadingMode, Thread}, value::TransferOptions};
+    /// use std::thread;
+    ///
+    /// let options = TransferOptions::new().dump_functions(true);
+    /// let value = Thread::spawn(move |thread| {
+    ///     let values = thread
+    ///         .caller_load("return { 2, 3, 7 }, function(t) return t[1] * t[2] * t[3] end", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     Ok::<_, pollua::Error>((values.get_transferable(0, &options)?, values.get_transferable(1, &options)?))
+    /// }).unwrap().unwrap();
+    ///
+    /// thread::spawn(move || Thread::spawn(move |thread| {
+    ///     let (t, f) = value;
+    ///     let values = thread
+    ///         .caller_load("local t, f = ... return f(t)", None, LoadingMode::Text)?
+    ///         .args((t, f))
+    ///         .call()?;
+    ///     assert_eq!(values.get_as::<i64>(0)?, 42);
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()).join().unwrap()
+    /// ```
+    ///
+    /// [`OwnedValue::transferable`]: ../value/enum.OwnedValue.html#method.transferable
+    pub fn get_transferable(
+        &self,
+        index: usize,
+        options: &TransferOptions,
+    ) -> LuaResult<OwnedValue> {
+        if index >= self.nresults as usize {
+            return Ok(OwnedValue::Nil);
+        }
+        let mut thread = unsafe { ThreadRef::from_raw((*self.thread.get()).as_raw()) };
+        OwnedValue::transferable(&mut thread, self.stack_index(index), options)
+    }
+
+    /// Converts all return values, usually to a tuple or a [`Variadic`].
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::{LoadingMode, Thread};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     let values = thread
+    ///         .caller_load("return 42, 'hello'", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     let (n, s, missing): (i32, String, Option<f64>) = values.unpack()?;
+    ///     assert_eq!((n, s.as_str(), missing), (42, "hello", None));
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    ///
+    /// [`Variadic`]: ../value/struct.Variadic.html
+    pub fn unpack<T: FromLuaMulti>(&self) -> LuaResult<T> {
+        let mut thread = unsafe { ThreadRef::from_raw((*self.thread.get()).as_raw()) };
+        T::from_lua_multi(&mut thread, self.stack_index(0), self.nresults)
+    }
+
     /// Returns an iterator over the return values.
     #[inline]
     pub fn iter<'b>(&'b self) -> Iter<'a, 'b> {
         Iter {
-            values: &self,
+            values: self,
             start: 0,
             end: self.nresults,
         }
@@ -225,7 +395,7 @@ impl Index<usize> for ReturnValues<'_> {
     fn index(&self, index: usize) -> &Self::Output {
         if index < self.nresults as usize {
             ValueType::from_code_ref(unsafe {
-                sys::lua_type(self.thread_ptr(), -self.nresults + (index as libc::c_int))
+                sys::lua_type(self.thread_ptr(), self.stack_index(index))
             })
             .expect("no return value found in stack.\nTHIS IS A BUG, please report.")
         } else {
@@ -238,12 +408,6 @@ impl Index<usize> for ReturnValues<'_> {
     }
 }
 
-impl Drop for ReturnValues<'_> {
-    fn drop(&mut self) {
-        unsafe { sys::lua_pop(self.thread().as_raw().as_ptr(), self.nresults) }
-    }
-}
-
 /// Immutable return values iterator.
 /// This struct is created by [`iter`] method on [`ReturnValues`].
 ///
@@ -299,7 +463,7 @@ impl FusedIterator for Iter<'_, '_> {}
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{value::LuaNil, ErrorKind};
+    use crate::{thread::Thread, value::LuaNil, ErrorKind};
     use std::mem;
 
     fn stack_top(thread: &mut Thread) -> libc::c_int {
@@ -456,7 +620,7 @@ mod test {
         unsafe extern "C" fn test_sum(l: *mut sys::lua_State) -> libc::c_int {
             let mut sum = 0.0;
             let nargs = match sys::lua_gettop(l) {
-                n if n == 0 => sys::lua_error(l),
+                0 => sys::lua_error(l),
                 n => n,
             };
             for i in 1..=nargs {
@@ -526,4 +690,75 @@ mod test {
         })
         .unwrap()
     }
+
+    #[test]
+    fn test_call_many_args() {
+        unsafe extern "C" fn echo(l: *mut sys::lua_State) -> libc::c_int {
+            sys::lua_gettop(l)
+        }
+
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            unsafe {
+                sys::lua_register(
+                    thread.as_raw().as_ptr(),
+                    b"echo\0".as_ptr() as *const _,
+                    Some(echo),
+                );
+            }
+
+            let caller = (0..5000).fold(thread.caller_global("echo").unwrap(), |c, i| {
+                c.arg(if i % 2 == 0 { "even" } else { "odd" })
+            });
+            {
+                let return_values = caller.call().unwrap();
+                assert_eq!(return_values.len(), 5000);
+                assert!(return_values.iter().all(|t| t == ValueType::String));
+            }
+            assert_eq!(stack_top(thread), top);
+
+            {
+                let return_values = thread
+                    .caller_global("echo")
+                    .unwrap()
+                    .arg(1.0)
+                    .calln(5000)
+                    .unwrap();
+                assert_eq!(return_values.len(), 5000);
+                assert_eq!(return_values[0], ValueType::Number);
+                assert_eq!(return_values[4999], ValueType::Nil);
+            }
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_call_stack_overflow() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let top = stack_top(thread);
+
+            let caller = (0..sys::LUAI_MAXSTACK + 1)
+                .fold(thread.caller_global("print").unwrap(), |c, _| c.arg(LuaNil));
+            let err = caller.call().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::StackOverflow);
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap();
+
+        // Lua 5.4 keeps the stack at its error size after an overflow, use a new state
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let top = stack_top(thread);
+            let err = thread
+                .caller_global("print")
+                .unwrap()
+                .calln(sys::LUAI_MAXSTACK as u32 + 1)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::StackOverflow);
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
 }
@@ -0,0 +1,331 @@
+use crate::{
+    thread::{StackCheck, StackGuard, Thread, ThreadRef},
+    util,
+    value::{self, FromLuaMulti, IntoLuaMulti, Pusher},
+    Error, ErrorKind, LuaResult,
+};
+
+use std::{
+    any::Any,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    ptr::{self, NonNull},
+    rc::Rc,
+};
+
+/// A Rust function callable from Lua, returns the number of pushed results.
+pub(crate) type Callback = Box<dyn Fn(&mut Thread) -> LuaResult<libc::c_int> + Send>;
+
+/// Content of a callback userdata, `None` once finalized.
+///
+/// Running calls hold a clone, so that finalizing the userdata during a call is harmless.
+type CallbackSlot = Option<Rc<Callback>>;
+
+/// Registry name of the metatable shared by all callback userdata.
+const CALLBACK_METATABLE: &[u8] = b"pollua.Callback\0";
+
+impl Thread {
+    /// Pushes a Lua function that calls `f` onto the stack.
+    ///
+    /// The arguments are converted with [`FromLuaMulti`] and the results with [`IntoLuaMulti`].
+    /// Errors returned by `f`, as well as panics, are raised as Lua errors.
+    ///
+    /// [`FromLuaMulti`]: ../value/trait.FromLuaMulti.html
+    /// [`IntoLuaMulti`]: ../value/trait.IntoLuaMulti.html
+    pub(crate) fn push_fn<A, R, F>(&mut self, f: F) -> LuaResult<()>
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(A) -> LuaResult<R> + Send + 'static,
+    {
+        let callback: Callback = Box::new(move |thread| {
+            let nargs = unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) };
+            let args = A::from_lua_multi(thread, 1, nargs).map_err(bad_argument)?;
+            f(args)?.push_multi(Pusher(ThreadRef::from_ref(thread)))
+        });
+        self.push_callback(callback)
+    }
+
+    /// Pushes a Lua function that calls `callback` with the thread it is called from.
+    pub(crate) fn push_callback(&mut self, callback: Callback) -> LuaResult<()> {
+        let raw = self.as_raw();
+        value::reserve(raw, 3)?;
+        unsafe {
+            let l = raw.as_ptr();
+            let ud = sys::lua_newuserdata(l, mem::size_of::<CallbackSlot>()) as *mut CallbackSlot;
+            ptr::write(ud, Some(Rc::new(callback)));
+            util::push_finalized_metatable(l, CALLBACK_METATABLE, Some(drop_callback));
+            sys::lua_setmetatable(l, -2);
+            sys::lua_pushcclosure(l, Some(call_callback), 1);
+        }
+        Ok(())
+    }
+
+    /// Sets the global `name` to a Lua function that calls `f`.
+    ///
+    /// The arguments are converted with [`FromLuaMulti`], missing arguments being `nil`,
+    /// and the results with [`IntoLuaMulti`].
+    /// Errors returned by `f`, as well as panics, are raised as Lua errors.
+    /// `f` must be `Send` since the thread can be moved to another OS thread.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::{thread::{LoadingMode, Thread}, value::Variadic};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     thread.register_fn("sum", |numbers: Variadic<f64>| Ok(numbers.iter().sum::<f64>()))?;
+    ///     let values = thread
+    ///         .caller_load("return sum(1, 2, 3)", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     assert_eq!(values.get_as::<f64>(0)?, 6.0);
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    ///
+    /// [`FromLuaMulti`]: ../value/trait.FromLuaMulti.html
+    /// [`IntoLuaMulti`]: ../value/trait.IntoLuaMulti.html
+    pub fn register_fn<S, A, R, F>(&mut self, name: &S, f: F) -> LuaResult<()>
+    where
+        S: AsRef<[u8]> + ?Sized,
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(A) -> LuaResult<R> + Send + 'static,
+    {
+        let _check = StackCheck::new(self.as_raw(), 0);
+        let mut thread = StackGuard::new(self);
+        let name = name.as_ref();
+        let raw = thread.as_raw();
+        value::reserve(raw, 2)?;
+        unsafe {
+            sys::lua_pushglobaltable(raw.as_ptr());
+            sys::lua_pushlstring(raw.as_ptr(), name.as_ptr() as *const _, name.len());
+        }
+        thread.push_fn(f)?;
+        unsafe { sys::lua_rawset(raw.as_ptr(), -3) };
+        Ok(())
+    }
+}
+
+/// Adds context to the conversion errors of arguments.
+pub(crate) fn bad_argument(e: Error) -> Error {
+    match e.kind() {
+        ErrorKind::Conversion => Error::new(
+            ErrorKind::Conversion,
+            Some(format!("bad argument: {}", e.msg().unwrap_or_default())),
+        ),
+        _ => e,
+    }
+}
+
+unsafe extern "C" fn drop_callback(l: *mut sys::lua_State) -> libc::c_int {
+    // the finalizer can still be called on other values through `debug.getmetatable`
+    let ud =
+        sys::luaL_testudata(l, 1, CALLBACK_METATABLE.as_ptr() as *const _) as *mut CallbackSlot;
+    if !ud.is_null() {
+        drop((*ud).take());
+    }
+    0
+}
+
+unsafe extern "C" fn call_callback(l: *mut sys::lua_State) -> libc::c_int {
+    let ud = sys::lua_touserdata(l, sys::lua_upvalueindex(1)) as *const CallbackSlot;
+    match &*ud {
+        // the clone is dropped by run_callback before raising an error
+        Some(callback) => {
+            let callback = Rc::clone(callback);
+            run_callback(l, move |thread| callback(thread))
+        }
+        None => sys::luaL_error(l, b"callback already finalized\0".as_ptr() as *const _),
+    }
+}
+
+/// Runs `f` as the body of a C function, returns the number of results.
+///
+/// Errors returned by `f`, as well as panics, are raised as Lua errors.
+pub(crate) unsafe fn run_callback<F>(l: *mut sys::lua_State, f: F) -> libc::c_int
+where
+    F: FnOnce(&mut Thread) -> LuaResult<libc::c_int>,
+{
+    match invoke(l, f) {
+        Some(nresults) => nresults,
+        // the error message is on the stack and every Rust value has been dropped
+        None => sys::lua_error(l),
+    }
+}
+
+/// Runs `f`, returns `None` after pushing an error message if it failed.
+unsafe fn invoke<F>(l: *mut sys::lua_State, f: F) -> Option<libc::c_int>
+where
+    F: FnOnce(&mut Thread) -> LuaResult<libc::c_int>,
+{
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        f(&mut ThreadRef::from_raw(NonNull::new_unchecked(l)))
+    }));
+    let msg = match result {
+        Ok(Ok(nresults)) => return Some(nresults),
+        Ok(Err(e)) => match (e.kind(), e.msg()) {
+            (ErrorKind::Runtime, Some(msg)) => msg.to_owned(),
+            _ => e.to_string(),
+        },
+        Err(panic) => format!("Rust callback panicked: {}", panic_message(&*panic)),
+    };
+    // the arguments and partial results are discarded, leaving room for the message
+    sys::lua_settop(l, 0);
+    sys::lua_pushlstring(l, msg.as_ptr() as *const _, msg.len());
+    None
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg
+    } else {
+        "<no message>"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{thread::LoadingMode, value::Variadic};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    #[test]
+    fn test_register_fn() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            thread
+                .register_fn("swap", |(a, b): (f64, String)| Ok((b, a)))
+                .unwrap();
+            thread
+                .register_fn("count", |args: Variadic<Option<f64>>| Ok(args.len()))
+                .unwrap();
+            assert_eq!(stack_top(thread), top);
+
+            {
+                let values = thread
+                    .caller_global("swap")
+                    .unwrap()
+                    .args((1.0, "a"))
+                    .call()
+                    .unwrap();
+                assert_eq!(
+                    values.unpack::<(String, f64)>().unwrap(),
+                    ("a".to_owned(), 1.0)
+                );
+            }
+            {
+                let values = thread
+                    .caller_load("return count(nil, 2, nil)", None, LoadingMode::Text)
+                    .and_then(|c| c.call())
+                    .unwrap();
+                assert_eq!(values.get_as::<usize>(0).unwrap(), 3);
+            }
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_register_fn_errors() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            thread
+                .register_fn("fail", |msg: String| -> LuaResult<()> {
+                    Err(Error::new(ErrorKind::Runtime, Some(msg)))
+                })
+                .unwrap();
+            thread
+                .register_fn("panic", |()| -> LuaResult<()> { panic!("oops") })
+                .unwrap();
+            thread.register_fn("number", |n: f64| Ok(n)).unwrap();
+
+            let err = thread
+                .caller_global("fail")
+                .unwrap()
+                .arg("message")
+                .call()
+                .unwrap_err();
+            assert_eq!(
+                (err.kind(), err.msg()),
+                (ErrorKind::Runtime, Some("message"))
+            );
+
+            let err = thread.caller_global("panic").unwrap().call().unwrap_err();
+            assert!(err.msg().unwrap().contains("oops"));
+
+            let err = thread
+                .caller_global("number")
+                .unwrap()
+                .arg("one")
+                .call()
+                .unwrap_err();
+            assert!(err.msg().unwrap().contains("bad argument"));
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    // Lua 5.1 does not give the upvalues of C functions to Lua code
+    #[cfg(LUA_VERSION = "5.2")]
+    fn test_callback_finalizer() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            thread.register_fn("f", |()| Ok(42)).unwrap();
+            let values = thread
+                .caller_load(
+                    "local _, ud = debug.getupvalue(f, 1) \
+                     local gc = debug.getmetatable(ud).__gc \
+                     gc(io.stdout) gc(42) \
+                     local before = f() \
+                     gc(ud) gc(ud) \
+                     return getmetatable(ud), before, select(2, pcall(f))",
+                    None,
+                    LoadingMode::Text,
+                )
+                .unwrap()
+                .call()
+                .unwrap();
+            assert_eq!(values.get_as::<String>(0).unwrap(), "pollua.Callback");
+            assert_eq!(values.get_as::<i64>(1).unwrap(), 42);
+            assert_eq!(
+                values.get_as::<String>(2).unwrap(),
+                "callback already finalized"
+            );
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_callback_drop() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = DropFlag(dropped.clone());
+        Thread::spawn(move |thread| {
+            thread
+                .register_fn("f", move |()| {
+                    let _ = &guard;
+                    Ok(())
+                })
+                .unwrap();
+        })
+        .unwrap();
+        assert!(dropped.load(Ordering::SeqCst));
+
+        struct DropFlag(Arc<AtomicBool>);
+
+        impl Drop for DropFlag {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
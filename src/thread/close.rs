@@ -0,0 +1,224 @@
+use crate::{
+    thread::{StackGuard, Thread, ThreadRef},
+    value::{self, Pushable, Pusher},
+    LuaResult,
+};
+
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    ptr::NonNull,
+};
+
+/// State of [`Thread::with_closing`] shared with `call_closing`.
+struct Closing<F, R> {
+    f: Option<F>,
+    result: Option<LuaResult<R>>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+/// Marks its first argument as to-be-closed and runs the function of the `Closing`
+/// passed as second argument, whose error is raised again so that `__close` receives it.
+unsafe extern "C" fn call_closing<F, R>(l: *mut sys::lua_State) -> libc::c_int
+where
+    F: FnOnce(&mut Thread) -> LuaResult<R>,
+{
+    let closing = &mut *(sys::lua_touserdata(l, 2) as *mut Closing<F, R>);
+    sys::lua_toclose(l, 1);
+    let f = match closing.f.take() {
+        Some(f) => f,
+        None => return 0,
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        f(&mut ThreadRef::from_raw(NonNull::new_unchecked(l)))
+    }));
+    let msg = match result {
+        Ok(Ok(value)) => {
+            closing.result = Some(Ok(value));
+            return 0;
+        }
+        Ok(Err(e)) => {
+            let msg = e.to_string();
+            closing.result = Some(Err(e));
+            msg
+        }
+        Err(panic) => {
+            closing.panic = Some(panic);
+            "Rust function panicked".to_owned()
+        }
+    };
+    sys::lua_pushlstring(l, msg.as_ptr() as *const _, msg.len());
+    // lua_error does not return, free the message first
+    drop(msg);
+    sys::lua_error(l)
+}
+
+impl Thread {
+    /// Runs `f` with `value` held in a to-be-closed variable, like `local value <close>` in Lua.
+    ///
+    /// The `__close` metamethod of `value` is called when `f` returns, with the error
+    /// returned by `f`, if any, as second argument. `value` must have a `__close` metamethod
+    /// or be `nil` or `false`. If `f` fails, its error is returned even if `__close` fails too.
+    ///
+    /// Requires Lua 5.4.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::{
+    ///     thread::Thread,
+    ///     userdata::{self, Methods, UserData},
+    ///     value::{Pushable, Pusher},
+    ///     LuaResult,
+    /// };
+    /// use std::sync::{
+    ///     atomic::{AtomicBool, Ordering},
+    ///     Arc,
+    /// };
+    ///
+    /// #[derive(Clone)]
+    /// struct Lock(Arc<AtomicBool>);
+    ///
+    /// impl UserData for Lock {
+    ///     const NAME: &'static str = "Lock";
+    ///
+    ///     fn add_methods(methods: Methods<Lock>) -> Methods<Lock> {
+    ///         methods.method("__close", |lock: &Lock, ()| {
+    ///             lock.0.store(false, Ordering::SeqCst);
+    ///             Ok(())
+    ///         })
+    ///     }
+    /// }
+    ///
+    /// impl Pushable for Lock {
+    ///     fn push(&self, pusher: Pusher) -> LuaResult<()> {
+    ///         userdata::push(pusher, self.clone())
+    ///     }
+    /// }
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     let locked = Arc::new(AtomicBool::new(true));
+    ///     thread.with_closing(Lock(locked.clone()), |_| Ok(()))?;
+    ///     assert!(!locked.load(Ordering::SeqCst));
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    pub fn with_closing<V, F, R>(&mut self, value: V, f: F) -> LuaResult<R>
+    where
+        V: Pushable,
+        F: FnOnce(&mut Thread) -> LuaResult<R>,
+    {
+        let mut thread = StackGuard::new(self);
+        let raw = thread.as_raw();
+        value::reserve(raw, 3)?;
+        let mut closing = Closing {
+            f: Some(f),
+            result: None,
+            panic: None,
+        };
+        unsafe { sys::lua_pushcfunction(raw.as_ptr(), Some(call_closing::<F, R>)) };
+        value.push(Pusher(ThreadRef::from_ref(&mut thread)))?;
+        let status = unsafe {
+            sys::lua_pushlightuserdata(raw.as_ptr(), &mut closing as *mut Closing<F, R> as *mut _);
+            sys::lua_pcall(raw.as_ptr(), 2, 0, 0)
+        };
+        if let Some(panic) = closing.panic {
+            panic::resume_unwind(panic);
+        }
+        match (closing.result, thread.get_error(status)) {
+            (Some(Err(e)), _) | (_, Err(e)) => Err(e),
+            (Some(Ok(value)), Ok(())) => Ok(value),
+            (None, Ok(())) => unreachable!("pcall succeeded without running the function"),
+        }
+    }
+
+    /// Closes the pending to-be-closed variables of this thread and empties its stack,
+    /// so that it can be reused.
+    ///
+    /// Returns the error that stopped the thread or the error raised by a `__close` metamethod,
+    /// if any.
+    ///
+    /// Requires Lua 5.4.
+    #[inline]
+    pub fn reset(&mut self) -> LuaResult<()> {
+        let status = unsafe { sys::lua_resetthread(self.as_raw().as_ptr()) };
+        self.get_error(status)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        userdata::{self, Methods, UserData},
+        value::LuaNil,
+        Error, ErrorKind,
+    };
+    use std::sync::{Arc, Mutex};
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    #[derive(Clone)]
+    struct Closable(Arc<Mutex<Vec<Option<String>>>>);
+
+    impl UserData for Closable {
+        const NAME: &'static str = "Closable";
+
+        fn add_methods(methods: Methods<Self>) -> Methods<Self> {
+            methods.method("__close", |c: &Closable, err: Option<String>| {
+                c.0.lock().unwrap().push(err);
+                Ok(())
+            })
+        }
+    }
+
+    impl Pushable for Closable {
+        fn push(&self, pusher: Pusher) -> LuaResult<()> {
+            userdata::push(pusher, self.clone())
+        }
+    }
+
+    #[test]
+    fn test_with_closing() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            let closed = Arc::new(Mutex::new(Vec::new()));
+            let closable = Closable(closed.clone());
+
+            let value = thread
+                .with_closing(closable.clone(), |_| {
+                    assert!(closed.lock().unwrap().is_empty());
+                    Ok(42)
+                })
+                .unwrap();
+            assert_eq!(value, 42);
+            let err = thread
+                .with_closing(closable, |_| -> LuaResult<()> {
+                    Err(Error::new(ErrorKind::Conversion, Some("boom".to_owned())))
+                })
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+            assert_eq!(
+                *closed.lock().unwrap(),
+                [None, Some("conversion error: boom".to_owned())]
+            );
+
+            assert!(thread.with_closing(LuaNil, |_| Ok(())).is_ok());
+            let err = thread.with_closing(1, |_| Ok(())).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Runtime);
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_reset() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::lua_pushinteger(thread.as_raw().as_ptr(), 1) };
+            thread.reset().unwrap();
+            assert_eq!(stack_top(thread), 0);
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{
+    thread::{StackCheck, Thread, ThreadRef},
+    value, LuaResult,
+};
+
+/// Handle to the garbage collector of a [`Thread`].
+/// Created by the [`gc`] method on [`Thread`].
+///
+/// [`gc`]: struct.Thread.html#method.gc
+/// [`Thread`]: struct.Thread.html
+#[derive(Debug)]
+pub struct Gc<'a> {
+    thread: ThreadRef<'a>,
+}
+
+/// Garbage collection modes, introduced in Lua 5.4.
+#[cfg(LUA_VERSION = "5.4")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GcMode {
+    Incremental,
+    Generational,
+}
+
+/// Runs `lua_gc` with the integer arguments at indices 1 and 2, and pushes its result.
+///
+/// Called in protected mode, since finalizers may raise errors during a collection.
+unsafe extern "C" fn protected_gc(l: *mut sys::lua_State) -> libc::c_int {
+    let what = sys::lua_tointeger(l, 1) as libc::c_int;
+    let data = sys::lua_tointeger(l, 2) as libc::c_int;
+    let result = sys::lua_gc(l, what, data);
+    sys::lua_pushinteger(l, result as sys::lua_Integer);
+    1
+}
+
+impl<'a> Gc<'a> {
+    #[inline]
+    pub(super) fn new(thread: ThreadRef<'a>) -> Gc<'a> {
+        Gc { thread }
+    }
+
+    #[inline]
+    fn control(&mut self, what: libc::c_int, data: libc::c_int) -> libc::c_int {
+        unsafe { sys::lua_gc(self.thread.as_raw().as_ptr(), what, data) }
+    }
+
+    /// Like `control`, for the operations that may run finalizers.
+    fn protected_control(
+        &mut self,
+        what: libc::c_int,
+        data: libc::c_int,
+    ) -> LuaResult<libc::c_int> {
+        let raw = self.thread.as_raw();
+        let _check = StackCheck::new(raw, 0);
+        value::reserve(raw, 3)?;
+        let l = raw.as_ptr();
+        unsafe {
+            sys::lua_pushcfunction(l, Some(protected_gc));
+            sys::lua_pushinteger(l, what as sys::lua_Integer);
+            sys::lua_pushinteger(l, data as sys::lua_Integer);
+            self.thread.get_error(sys::lua_pcall(l, 2, 1, 0))?;
+            let result = sys::lua_tointeger(l, -1) as libc::c_int;
+            sys::lua_pop(l, 1);
+            Ok(result)
+        }
+    }
+
+    /// Performs a full garbage-collection cycle.
+    ///
+    /// Returns the error raised by a finalizer, if any.
+    /// Since Lua 5.4, such errors are emitted as warnings instead.
+    #[inline]
+    pub fn collect(&mut self) -> LuaResult<()> {
+        self.protected_control(sys::LUA_GCCOLLECT, 0).map(|_| ())
+    }
+
+    /// Performs an incremental step of garbage collection,
+    /// as if `kb` kilobytes had been allocated.
+    ///
+    /// Returns `true` if the step finished a collection cycle,
+    /// or the error raised by a finalizer, as with [`collect`].
+    ///
+    /// [`collect`]: #method.collect
+    #[inline]
+    pub fn step(&mut self, kb: u32) -> LuaResult<bool> {
+        self.protected_control(sys::LUA_GCSTEP, kb as libc::c_int)
+            .map(|done| done != 0)
+    }
+
+    /// Stops the garbage collector.
+    /// Memory will only be reclaimed by explicit calls to [`collect`] and [`step`].
+    ///
+    /// [`collect`]: #method.collect
+    /// [`step`]: #method.step
+    #[inline]
+    pub fn stop(&mut self) {
+        self.control(sys::LUA_GCSTOP, 0);
+    }
+
+    /// Restarts the garbage collector.
+    #[inline]
+    pub fn restart(&mut self) {
+        self.control(sys::LUA_GCRESTART, 0);
+    }
+
+    /// Returns `true` if the collector is running (i.e. not stopped).
+    #[inline]
+    #[cfg(LUA_VERSION = "5.2")]
+    pub fn is_running(&mut self) -> bool {
+        self.control(sys::LUA_GCISRUNNING, 0) != 0
+    }
+
+    /// Returns the total amount of memory in use by the Lua state, in bytes.
+    #[inline]
+    pub fn used_bytes(&mut self) -> usize {
+        let kb = self.control(sys::LUA_GCCOUNT, 0) as usize;
+        let bytes = self.control(sys::LUA_GCCOUNTB, 0) as usize;
+        kb * 1024 + bytes
+    }
+
+    /// Sets the collector pause, in percent, and returns the previous value.
+    ///
+    /// The pause controls how long the collector waits before starting a new cycle:
+    /// a value of `200` waits for the total memory in use to double.
+    /// Lua 5.4 stores both the pause and the step multiplier rounded down to a multiple of 4.
+    #[inline]
+    pub fn set_pause(&mut self, pause: u32) -> u32 {
+        self.control(sys::LUA_GCSETPAUSE, pause as libc::c_int) as u32
+    }
+
+    /// Sets the collector step multiplier, in percent, and returns the previous value.
+    ///
+    /// The multiplier controls the speed of the collector relative to memory allocation.
+    #[inline]
+    pub fn set_step_multiplier(&mut self, multiplier: u32) -> u32 {
+        self.control(sys::LUA_GCSETSTEPMUL, multiplier as libc::c_int) as u32
+    }
+
+    /// Switches the collector to generational mode and returns the previous mode.
+    ///
+    /// A parameter of `0` keeps its current value.
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn generational(&mut self, minor_multiplier: u32, major_multiplier: u32) -> GcMode {
+        let previous = unsafe {
+            sys::lua_gc(
+                self.thread.as_raw().as_ptr(),
+                sys::LUA_GCGEN,
+                minor_multiplier as libc::c_int,
+                major_multiplier as libc::c_int,
+            )
+        };
+        GcMode::from_code(previous)
+    }
+
+    /// Switches the collector to incremental mode and returns the previous mode.
+    ///
+    /// A parameter of `0` keeps its current value.
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn incremental(&mut self, pause: u32, step_multiplier: u32, step_size: u32) -> GcMode {
+        let previous = unsafe {
+            sys::lua_gc(
+                self.thread.as_raw().as_ptr(),
+                sys::LUA_GCINC,
+                pause as libc::c_int,
+                step_multiplier as libc::c_int,
+                step_size as libc::c_int,
+            )
+        };
+        GcMode::from_code(previous)
+    }
+}
+
+#[cfg(LUA_VERSION = "5.4")]
+impl GcMode {
+    fn from_code(code: libc::c_int) -> GcMode {
+        if code == sys::LUA_GCGEN {
+            GcMode::Generational
+        } else {
+            GcMode::Incremental
+        }
+    }
+}
+
+impl Thread {
+    /// Returns a handle to the garbage collector of this thread.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::Thread;
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     let mut gc = thread.gc();
+    ///     gc.stop();
+    ///     // ... run a frame ...
+    ///     gc.step(64)?;
+    ///     gc.restart();
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    #[inline]
+    pub fn gc(&mut self) -> Gc<'_> {
+        Gc::new(ThreadRef::from_ref(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+
+    #[test]
+    fn test_gc_collect() {
+        Thread::spawn(move |thread| {
+            thread.gc().stop();
+            #[cfg(LUA_VERSION = "5.2")]
+            assert!(!thread.gc().is_running());
+
+            let before = thread.gc().used_bytes();
+            thread
+                .caller_load(
+                    "local t = {} for i = 1, 10000 do t[i] = { i } end",
+                    None,
+                    LoadingMode::Text,
+                )
+                .and_then(|c| c.call())
+                .unwrap();
+            let garbage = thread.gc().used_bytes();
+            assert!(garbage > before);
+
+            thread.gc().collect().unwrap();
+            assert!(thread.gc().used_bytes() < garbage);
+            #[cfg(LUA_VERSION = "5.2")]
+            assert!(!thread.gc().is_running());
+
+            thread.gc().restart();
+            #[cfg(LUA_VERSION = "5.2")]
+            assert!(thread.gc().is_running());
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_gc_tuning() {
+        Thread::spawn(move |thread| {
+            let mut gc = thread.gc();
+            let pause = gc.set_pause(160);
+            assert_eq!(gc.set_pause(pause), 160);
+            let multiplier = gc.set_step_multiplier(400);
+            assert_eq!(gc.set_step_multiplier(multiplier), 400);
+
+            // steps until the end of a cycle
+            while !gc.step(0).unwrap() {}
+        })
+        .unwrap()
+    }
+
+    #[test]
+    #[cfg(LUA_VERSION = "5.4")]
+    fn test_gc_modes() {
+        Thread::spawn(move |thread| {
+            let mut gc = thread.gc();
+            assert_eq!(gc.generational(20, 100), GcMode::Incremental);
+            assert_eq!(gc.generational(0, 0), GcMode::Generational);
+            gc.collect().unwrap();
+            assert_eq!(gc.incremental(200, 100, 13), GcMode::Generational);
+            assert_eq!(gc.incremental(0, 0, 0), GcMode::Incremental);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_gc_finalizer_error() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            #[cfg(LUA_VERSION = "5.2")]
+            let chunk = "setmetatable({}, { __gc = function() error('boom') end })";
+            // Lua 5.1 only runs the finalizers of userdata
+            #[cfg(not(LUA_VERSION = "5.2"))]
+            let chunk = "getmetatable(newproxy(true)).__gc = function() error('boom') end";
+            thread
+                .caller_load(chunk, None, LoadingMode::Text)
+                .and_then(|c| c.call())
+                .unwrap();
+            let result = thread.gc().collect();
+            #[cfg(not(LUA_VERSION = "5.4"))]
+            assert!(result.unwrap_err().msg().unwrap().contains("boom"));
+            #[cfg(LUA_VERSION = "5.4")]
+            result.unwrap();
+            // the object is gone and the state is still usable
+            thread.gc().collect().unwrap();
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    thread::{StackCheck, Thread},
+    value, Error, ErrorKind, LuaResult,
+};
+
+/// A copy of the globals and of the loaded modules of a [`Thread`],
+/// created by [`Thread::snapshot_globals`].
+///
+/// The copied values are kept in the registry of the thread
+/// until [`Thread::discard_snapshot`] is called or the thread is closed.
+///
+/// [`Thread`]: struct.Thread.html
+/// [`Thread::snapshot_globals`]: struct.Thread.html#method.snapshot_globals
+/// [`Thread::discard_snapshot`]: struct.Thread.html#method.discard_snapshot
+#[derive(Debug, PartialEq, Eq)]
+pub struct GlobalsSnapshot {
+    /// Address of the main thread of the state the snapshot belongs to.
+    state: usize,
+    /// Registry reference to the `{ globals, loaded }` copies.
+    reference: libc::c_int,
+}
+
+impl Thread {
+    /// Captures the keys and values of the global table (`_G`) and of `package.loaded`,
+    /// so that they can be restored with [`restore_globals`].
+    ///
+    /// The copy is shallow: the tables stored in globals, such as libraries, are not copied.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::{LoadingMode, Thread};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     thread.caller_load("answer = 42", None, LoadingMode::Text)?.call()?;
+    ///     let snapshot = thread.snapshot_globals()?;
+    ///
+    ///     thread.caller_load("answer = nil leaked = true", None, LoadingMode::Text)?.call()?;
+    ///     thread.restore_globals(&snapshot)?;
+    ///     let values = thread
+    ///         .caller_load("return answer, leaked", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     assert_eq!(values.unpack::<(i64, Option<bool>)>()?, (42, None));
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    ///
+    /// [`restore_globals`]: #method.restore_globals
+    pub fn snapshot_globals(&mut self) -> LuaResult<GlobalsSnapshot> {
+        let raw = self.as_raw();
+        let _check = StackCheck::new(raw, 0);
+        value::reserve(raw, 6)?;
+        unsafe {
+            let l = raw.as_ptr();
+            sys::lua_createtable(l, 2, 0);
+            sys::lua_pushglobaltable(l);
+            copy_table(l);
+            sys::lua_rawseti(l, -2, 1);
+            if push_loaded(l) == sys::LUA_TTABLE {
+                copy_table(l);
+                sys::lua_rawseti(l, -2, 2);
+            } else {
+                sys::lua_pop(l, 1);
+            }
+            Ok(GlobalsSnapshot {
+                state: main_thread(l),
+                reference: sys::luaL_ref(l, sys::LUA_REGISTRYINDEX),
+            })
+        }
+    }
+
+    /// Restores the globals and `package.loaded` to their state when `snapshot` was taken.
+    ///
+    /// Keys added since then are removed, so that modules are loaded again by `require`,
+    /// and the values of the others are restored.
+    /// Returns an error of kind [`ErrorKind::Runtime`]
+    /// if the snapshot was taken from another thread.
+    ///
+    /// [`ErrorKind::Runtime`]: ../enum.ErrorKind.html#variant.Runtime
+    pub fn restore_globals(&mut self, snapshot: &GlobalsSnapshot) -> LuaResult<()> {
+        let raw = self.as_raw();
+        let _check = StackCheck::new(raw, 0);
+        value::reserve(raw, 7)?;
+        unsafe {
+            let l = raw.as_ptr();
+            check_state(l, snapshot)?;
+            sys::lua_rawgeti(
+                l,
+                sys::LUA_REGISTRYINDEX,
+                snapshot.reference as sys::lua_Integer,
+            );
+            sys::lua_pushglobaltable(l);
+            sys::lua_rawgeti(l, -2, 1);
+            restore_table(l);
+            if sys::lua_rawgeti(l, -1, 2) == sys::LUA_TTABLE {
+                if push_loaded(l) == sys::LUA_TTABLE {
+                    sys::lua_insert(l, -2);
+                    restore_table(l);
+                } else {
+                    sys::lua_pop(l, 2);
+                }
+            } else {
+                sys::lua_pop(l, 1);
+            }
+            sys::lua_pop(l, 1);
+        }
+        Ok(())
+    }
+
+    /// Releases the values kept by `snapshot`.
+    ///
+    /// Returns an error of kind [`ErrorKind::Runtime`]
+    /// if the snapshot was taken from another thread.
+    ///
+    /// [`ErrorKind::Runtime`]: ../enum.ErrorKind.html#variant.Runtime
+    pub fn discard_snapshot(&mut self, snapshot: GlobalsSnapshot) -> LuaResult<()> {
+        let raw = self.as_raw();
+        let _check = StackCheck::new(raw, 0);
+        value::reserve(raw, 1)?;
+        unsafe {
+            check_state(raw.as_ptr(), &snapshot)?;
+            sys::luaL_unref(raw.as_ptr(), sys::LUA_REGISTRYINDEX, snapshot.reference);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the address of the main thread of `l`, which identifies the state.
+#[cfg(LUA_VERSION = "5.2")]
+unsafe fn main_thread(l: *mut sys::lua_State) -> usize {
+    sys::lua_rawgeti(l, sys::LUA_REGISTRYINDEX, sys::LUA_RIDX_MAINTHREAD);
+    let main = sys::lua_topointer(l, -1) as usize;
+    sys::lua_pop(l, 1);
+    main
+}
+
+/// Returns the address of the registry of `l`, which identifies the state
+/// since Lua 5.1 does not store the main thread.
+#[cfg(not(LUA_VERSION = "5.2"))]
+unsafe fn main_thread(l: *mut sys::lua_State) -> usize {
+    sys::lua_topointer(l, sys::LUA_REGISTRYINDEX) as usize
+}
+
+unsafe fn check_state(l: *mut sys::lua_State, snapshot: &GlobalsSnapshot) -> LuaResult<()> {
+    if main_thread(l) == snapshot.state {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Runtime,
+            Some("the snapshot was taken from another thread".to_owned()),
+        ))
+    }
+}
+
+/// Pushes the `package.loaded` table of the registry, returns its type.
+unsafe fn push_loaded(l: *mut sys::lua_State) -> libc::c_int {
+    let name = sys::LUA_LOADED_TABLE;
+    sys::lua_pushlstring(l, name.as_ptr() as *const libc::c_char, name.len());
+    sys::lua_rawget(l, sys::LUA_REGISTRYINDEX)
+}
+
+/// Replaces the table at the top of the stack with a shallow copy of it.
+unsafe fn copy_table(l: *mut sys::lua_State) {
+    sys::lua_newtable(l);
+    sys::lua_pushnil(l);
+    while sys::lua_next(l, -3) != 0 {
+        sys::lua_pushvalue(l, -2);
+        sys::lua_insert(l, -2);
+        sys::lua_rawset(l, -4);
+    }
+    sys::lua_replace(l, -2);
+}
+
+/// Resets the table below the top of the stack to the copy at the top, and pops both.
+unsafe fn restore_table(l: *mut sys::lua_State) {
+    let (table, saved) = (sys::lua_absindex(l, -2), sys::lua_absindex(l, -1));
+
+    // existing fields may be modified or cleared during the traversal
+    sys::lua_pushnil(l);
+    while sys::lua_next(l, table) != 0 {
+        sys::lua_pushvalue(l, -2);
+        sys::lua_rawget(l, saved);
+        if sys::lua_rawequal(l, -1, -2) == 0 {
+            sys::lua_pushvalue(l, -3);
+            sys::lua_insert(l, -2);
+            sys::lua_rawset(l, table);
+        } else {
+            sys::lua_pop(l, 1);
+        }
+        sys::lua_pop(l, 1);
+    }
+
+    // restores the removed fields
+    sys::lua_pushnil(l);
+    while sys::lua_next(l, saved) != 0 {
+        sys::lua_pushvalue(l, -2);
+        if sys::lua_rawget(l, table) == sys::LUA_TNIL {
+            sys::lua_pop(l, 1);
+            sys::lua_pushvalue(l, -2);
+            sys::lua_insert(l, -2);
+            sys::lua_rawset(l, table);
+        } else {
+            sys::lua_pop(l, 2);
+        }
+    }
+    sys::lua_pop(l, 2);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    fn eval(thread: &mut Thread, code: &str) -> Option<String> {
+        thread
+            .caller_load(code, None, LoadingMode::Text)
+            .and_then(|c| c.call())
+            .and_then(|values| values.get_as::<Option<String>>(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_globals_snapshot() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            eval(
+                thread,
+                "package.preload.counter = function() count = (count or 0) + 1 return count end",
+            );
+            let top = stack_top(thread);
+            let snapshot = thread.snapshot_globals().unwrap();
+            assert_eq!(stack_top(thread), top);
+
+            for _ in 0..2 {
+                assert_eq!(
+                    eval(
+                        thread,
+                        "print = nil string = {} x = 1 return tostring(require('counter'))"
+                    ),
+                    Some("1".to_owned())
+                );
+                thread.restore_globals(&snapshot).unwrap();
+                assert_eq!(stack_top(thread), top);
+                assert_eq!(
+                    eval(
+                        thread,
+                        "return type(print) .. type(string.rep) .. tostring(x)"
+                    ),
+                    Some("functionfunctionnil".to_owned())
+                );
+            }
+
+            let other = Thread::spawn(move |other| other.snapshot_globals().unwrap()).unwrap();
+            let err = thread.restore_globals(&other).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Runtime);
+            thread.discard_snapshot(snapshot).unwrap();
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+}
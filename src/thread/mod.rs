@@ -1,7 +1,8 @@
 use crate::{util, Error, ErrorKind, LuaResult};
 
+#[cfg(not(LUAJIT))]
+use std::alloc::{self, Layout};
 use std::{
-    alloc::{self, Layout},
     any::Any,
     error, fmt,
     marker::PhantomData,
@@ -11,9 +12,29 @@ use std::{
     slice,
 };
 
+mod args;
 mod call;
-
+mod callback;
+#[cfg(LUA_VERSION = "5.4")]
+mod close;
+mod gc;
+mod globals;
+mod ops;
+mod require;
+mod stack;
+mod transfer;
+#[cfg(LUA_VERSION = "5.4")]
+mod warn;
+
+pub use args::*;
 pub use call::*;
+pub(crate) use callback::{bad_argument, run_callback};
+pub use gc::*;
+pub use globals::*;
+#[cfg(LUA_VERSION = "5.2")]
+pub use ops::*;
+pub use require::*;
+pub use stack::*;
 
 #[derive(Debug)]
 pub enum ThreadError {
@@ -21,13 +42,19 @@ pub enum ThreadError {
     Lua(Error),
 }
 
-impl error::Error for ThreadError {
-    fn description(&self) -> &str {
+impl ThreadError {
+    fn as_str(&self) -> &'static str {
         match self {
             ThreadError::Panic(_) => "panicked while running thread",
             ThreadError::Lua(_) => "lua error",
         }
     }
+}
+
+impl error::Error for ThreadError {
+    fn description(&self) -> &str {
+        self.as_str()
+    }
 
     fn cause(&self) -> Option<&dyn error::Error> {
         None
@@ -36,7 +63,7 @@ impl error::Error for ThreadError {
 
 impl fmt::Display for ThreadError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}: ", error::Error::description(self))?;
+        write!(f, "{}: ", self.as_str())?;
         match self {
             ThreadError::Panic(panic) => fmt::Debug::fmt(panic, f),
             ThreadError::Lua(error) => fmt::Display::fmt(error, f),
@@ -51,12 +78,49 @@ impl From<Error> for ThreadError {
 }
 
 /// Lua thread (state) wrapper.
+///
+/// A `Thread` owns its whole Lua state and can be moved to another OS thread,
+/// every Rust value stored in the state, such as the functions registered with
+/// [`register_fn`], must therefore be `Send`.
+///
+/// [`register_fn`]: struct.Thread.html#method.register_fn
 #[derive(Debug)]
 pub struct Thread {
     raw: NonNull<sys::lua_State>,
 }
 
+// The state is only reachable through this `Thread` (or references borrowed from it),
+// and the Rust values it stores are bound by `Send` when they are registered.
+unsafe impl Send for Thread {}
+
 impl Thread {
+    /// Creates a new Lua thread using the default allocator.
+    ///
+    /// Unlike [`Thread::spawn`], the thread is owned by the caller
+    /// and can be initialized then moved to another OS thread.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::{LoadingMode, Thread};
+    ///
+    /// let mut thread = Thread::new().unwrap();
+    /// thread.register_fn("double", |n: i64| Ok(n * 2)).unwrap();
+    ///
+    /// std::thread::spawn(move || {
+    ///     let values = thread
+    ///         .caller_load("return double(21)", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     assert_eq!(values.get_as::<i64>(0)?, 42);
+    ///     Ok::<_, pollua::Error>(())
+    /// }).join().unwrap().unwrap()
+    /// ```
+    ///
+    /// [`Thread::spawn`]: struct.Thread.html#method.spawn
+    pub fn new() -> LuaResult<Thread> {
+        // Safe because allocator is set to `None`.
+        unsafe { Thread::with_allocator(None, ptr::null_mut()) }
+    }
+
     /// Spawns a new Lua thread and runs `f` with the new thread as a parameter.
     ///
     /// # Examples
@@ -77,10 +141,15 @@ impl Thread {
 
     /// A variant of [`Thread::spawn`] that takes an optional allocator function.alloc
     ///
+    /// 64-bit builds of LuaJIT without GC64 do not accept custom allocators,
+    /// an out of memory error is then returned.
+    ///
     /// # Safety
     /// If present, the allocator function must behave exactly as defined in [`the Lua manual`],
     /// behavior is undefined if the function pointer is invalid, returns invalid allocations,
     /// or frees memory incorrectly.
+    /// As a `Thread` can be sent to another OS thread, the allocator must also accept being
+    /// called with `userdata` from any OS thread.
     ///
     /// [`Thread::spawn`]: struct.Thread.html#method.spawn
     /// [`the Lua manual`]: https://www.lua.org/manual/5.3/manual.html#lua_Alloc
@@ -92,7 +161,7 @@ impl Thread {
     where
         F: FnOnce(&mut Thread) -> T,
     {
-        Thread::new(allocator, userdata as *mut libc::c_void)
+        Thread::with_allocator(allocator, userdata as *mut libc::c_void)
             .map(|mut t| f(&mut t))
             .map_err(ThreadError::from)
     }
@@ -108,10 +177,17 @@ impl Thread {
     /// or frees memory incorrectly.
     ///
     /// [`the Lua manual`]: https://www.lua.org/manual/5.3/manual.html#lua_Alloc
-    unsafe fn new(allocator: sys::lua_Alloc, userdata: *mut libc::c_void) -> LuaResult<Thread> {
+    unsafe fn with_allocator(
+        allocator: sys::lua_Alloc,
+        userdata: *mut libc::c_void,
+    ) -> LuaResult<Thread> {
         let mut thread = Thread {
             raw: NonNull::new(match allocator {
                 Some(_) => sys::lua_newstate(allocator, userdata),
+                // 64-bit builds of LuaJIT without GC64 only accept their own allocator
+                #[cfg(LUAJIT)]
+                None => sys::luaL_newstate(),
+                #[cfg(not(LUAJIT))]
                 None => sys::lua_newstate(Some(alloc_default), ptr::null_mut()),
             })
             .ok_or_else(|| Error::new(ErrorKind::OutOfMemory, None))?,
@@ -132,47 +208,54 @@ impl Thread {
             0
         }
 
+        let _check = StackCheck::new(self.raw, 0);
         // If luaL_checkversion failed, pcall will return an error
         sys::lua_pushcfunction(self.raw.as_ptr(), Some(check));
         self.get_error(sys::lua_pcall(self.raw.as_ptr(), 0, 0, 0))
     }
 
     /// Returns the error for the given `code`.
-    /// If `code` is not `LUA_OK` then the object at stack index -1 is used as the error message.
+    /// If `code` is not `LUA_OK` then the object at stack index -1 is used as the error message,
+    /// and is popped from the stack.
     pub fn get_error(&mut self, code: libc::c_int) -> LuaResult<()> {
         if code == sys::LUA_OK {
-            Ok(())
-        } else {
-            Err(Error {
-                kind: match code {
-                    sys::LUA_ERRRUN => ErrorKind::Runtime,
-                    sys::LUA_ERRSYNTAX => ErrorKind::Syntax,
-                    sys::LUA_ERRMEM => ErrorKind::OutOfMemory,
-                    sys::LUA_ERRERR => ErrorKind::MessageHandler,
-                    sys::LUA_ERRGCMM => ErrorKind::GarbageCollection,
-                    sys::LUA_ERRFILE | _ => ErrorKind::Io,
-                },
-                msg: unsafe {
-                    // check if there is a value at stack index -1
-                    if sys::lua_isnone(self.as_raw().as_ptr(), -1) == 0 {
-                        let mut len = 0usize;
-                        // get the error object as a c string
-                        let s = sys::luaL_tolstring(self.as_raw().as_ptr(), -1, &mut len as *mut _);
-                        // luaL_tolstring also pushes its result to the stack, so we have to pop it.
-                        sys::lua_pop(self.as_raw().as_ptr(), -1);
-                        if s.is_null() {
-                            None
-                        } else {
-                            // s is garanteed to be a valid c string at this point.
-                            let buf = slice::from_raw_parts(s as *const u8, len);
-                            Some(String::from_utf8_lossy(buf).into_owned())
-                        }
-                    } else {
-                        None
-                    }
-                },
-            })
+            return Ok(());
         }
+        let raw = self.as_raw();
+        // the error object, if any, is popped along with the string pushed by luaL_tolstring
+        let top = unsafe { sys::lua_gettop(raw.as_ptr()) };
+        let _check = StackCheck::new(raw, if top > 0 { -1 } else { 0 });
+        let guard = StackGuard::with_top(ThreadRef::from_ref(self), (top - 1).max(0));
+        Err(Error {
+            kind: match code {
+                sys::LUA_ERRRUN => ErrorKind::Runtime,
+                sys::LUA_ERRSYNTAX => ErrorKind::Syntax,
+                sys::LUA_ERRMEM => ErrorKind::OutOfMemory,
+                sys::LUA_ERRERR => ErrorKind::MessageHandler,
+                #[cfg(all(LUA_VERSION = "5.2", not(LUA_VERSION = "5.4")))]
+                sys::LUA_ERRGCMM => ErrorKind::GarbageCollection,
+                _ => ErrorKind::Io,
+            },
+            msg: unsafe {
+                // check if there is a value at stack index -1
+                if top > 0 {
+                    let mut len = 0usize;
+                    // get the error object as a c string, it stays valid until the guard is dropped
+                    let s = sys::luaL_tolstring(raw.as_ptr(), -1, &mut len as *mut _);
+                    let msg = if s.is_null() {
+                        None
+                    } else {
+                        // s is garanteed to be a valid c string at this point.
+                        let buf = slice::from_raw_parts(s as *const u8, len);
+                        Some(String::from_utf8_lossy(buf).into_owned())
+                    };
+                    drop(guard);
+                    msg
+                } else {
+                    None
+                }
+            },
+        })
     }
 
     /// Returns the Lua version number.
@@ -188,10 +271,18 @@ impl Thread {
     /// assert_eq!(thread_version, pollua::lua_version());
     /// ```
     #[inline]
+    #[cfg(not(LUA_VERSION = "5.4"))]
     pub fn version(&self) -> sys::lua_Number {
         unsafe { *sys::lua_version(self.raw.as_ptr()) }
     }
 
+    /// Returns the Lua version number.
+    #[inline]
+    #[cfg(LUA_VERSION = "5.4")]
+    pub fn version(&self) -> sys::lua_Number {
+        unsafe { sys::lua_version(self.raw.as_ptr()) }
+    }
+
     /// Returns a raw pointer the wrapped `lua_State`.
     ///
     /// It is up to the caller to ensure that the object is still alive when accessing it through
@@ -222,8 +313,8 @@ impl Thread {
     ///
     /// [`ThreadRef`]: struct.ThreadRef.html
     #[inline]
-    pub unsafe fn ref_from_raw<'a>(raw: NonNull<sys::lua_State>) -> &'a mut Thread {
-        &mut *raw.cast::<Thread>().as_ptr()
+    pub unsafe fn ref_from_raw<'a>(raw: NonNull<sys::lua_State>) -> ThreadRef<'a> {
+        ThreadRef::from_raw(raw)
     }
 
     /// Loads a Lua chunk and creates a [`Caller`] for it if there were no errors.
@@ -245,7 +336,7 @@ impl Thread {
     ///
     /// [`Caller`]: struct.Caller.html
     #[inline(always)]
-    pub fn caller_global<S: AsRef<[u8]> + ?Sized>(&mut self, name: &S) -> Option<Caller> {
+    pub fn caller_global<S: AsRef<[u8]> + ?Sized>(&mut self, name: &S) -> Option<Caller<'_>> {
         Caller::from_global(ThreadRef::from_ref(self), name.as_ref())
     }
 
@@ -256,7 +347,7 @@ impl Thread {
     ///
     /// [`Caller`]: struct.Caller.html
     #[inline(always)]
-    pub(crate) unsafe fn caller_stack_unchecked(&mut self) -> Caller {
+    pub(crate) unsafe fn caller_stack_unchecked(&mut self) -> Caller<'_> {
         Caller::from_stack_unchecked(ThreadRef::from_ref(self))
     }
 
@@ -276,6 +367,7 @@ impl Thread {
         mode: LoadingMode,
     ) -> LuaResult<Caller<'a>> {
         let mut name_buf = Vec::new();
+        let mut check = StackCheck::new(self.as_raw(), 0);
         unsafe {
             let code = sys::luaL_loadbufferx(
                 self.as_raw().as_ptr(),
@@ -289,17 +381,21 @@ impl Thread {
                 })),
             );
             match self.get_error(code) {
-                Ok(()) => Ok(self.caller_stack_unchecked()),
+                Ok(()) => {
+                    check.adjust(1);
+                    Ok(self.caller_stack_unchecked())
+                }
                 Err(e) => Err(e),
             }
         }
     }
 
     fn push_global_impl(&mut self, name: &[u8]) -> libc::c_int {
+        let _check = StackCheck::new(self.raw, 1);
         unsafe {
             let ptr = self.raw.as_ptr();
             // push the global env onto the stack
-            sys::lua_rawgeti(ptr, sys::LUA_REGISTRYINDEX, sys::LUA_RIDX_GLOBALS);
+            sys::lua_pushglobaltable(ptr);
             // push the global variable name onto the stack
             sys::lua_pushlstring(ptr, name.as_ptr() as *const libc::c_char, name.len());
             // fetch _G[name]
@@ -385,7 +481,7 @@ impl<'a> DerefMut for ThreadRef<'a> {
 
 /// Default panic handler function.
 unsafe extern "C" fn at_panic(thread: *mut sys::lua_State) -> libc::c_int {
-    match Thread::ref_from_raw(NonNull::new_unchecked(thread)).get_error(sys::LUA_ERRRUN) {
+    match ThreadRef::from_raw(NonNull::new_unchecked(thread)).get_error(sys::LUA_ERRRUN) {
         Ok(()) => 0,
         Err(Error { msg: None, .. }) => panic!("Lua panic: <no error message>"),
         Err(Error { msg: Some(m), .. }) => panic!("Lua panic: {}", m),
@@ -394,6 +490,7 @@ unsafe extern "C" fn at_panic(thread: *mut sys::lua_State) -> libc::c_int {
 
 /// Default allocation function.
 /// Uses the liballoc functions instead of the one from libc.
+#[cfg(not(LUAJIT))]
 unsafe extern "C" fn alloc_default(
     _ud: *mut libc::c_void,
     ptr: *mut libc::c_void,
@@ -451,4 +548,34 @@ mod test {
         })
         .unwrap()
     }
+
+    #[test]
+    fn test_thread_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Thread>();
+
+        let mut thread = Thread::new().unwrap();
+        let counter = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let calls = counter.clone();
+        thread
+            .register_fn("count", move |()| {
+                Ok(calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1)
+            })
+            .unwrap();
+
+        let mut thread = std::thread::spawn(move || {
+            thread
+                .caller_global("count")
+                .unwrap()
+                .call()
+                .and_then(|values| values.get_as::<usize>(0))
+                .unwrap();
+            thread
+        })
+        .join()
+        .unwrap();
+        let values = thread.caller_global("count").unwrap().call().unwrap();
+        assert_eq!(values.get_as::<usize>(0).unwrap(), 2);
+        assert_eq!(counter.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
 }
@@ -0,0 +1,396 @@
+use crate::{
+    thread::{StackGuard, Thread, ThreadRef},
+    value::{self, FromLua, IntoLuaMulti, Pushable, Pusher},
+    LuaResult,
+};
+
+/// Arithmetic and bitwise operators of [`Thread::arith`].
+///
+/// Floor division and the bitwise operators were introduced in Lua 5.3.
+///
+/// [`Thread::arith`]: struct.Thread.html#method.arith
+#[cfg(LUA_VERSION = "5.2")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArithOp {
+    /// Addition (`+`).
+    Add,
+    /// Subtraction (`-`).
+    Sub,
+    /// Multiplication (`*`).
+    Mul,
+    /// Float division (`/`).
+    Div,
+    /// Floor division (`//`).
+    #[cfg(LUA_VERSION = "5.3")]
+    IDiv,
+    /// Modulo (`%`).
+    Mod,
+    /// Exponentiation (`^`).
+    Pow,
+    /// Negation (unary `-`).
+    Unm,
+    /// Bitwise and (`&`).
+    #[cfg(LUA_VERSION = "5.3")]
+    BAnd,
+    /// Bitwise or (`|`).
+    #[cfg(LUA_VERSION = "5.3")]
+    BOr,
+    /// Bitwise exclusive or (binary `~`).
+    #[cfg(LUA_VERSION = "5.3")]
+    BXor,
+    /// Left shift (`<<`).
+    #[cfg(LUA_VERSION = "5.3")]
+    Shl,
+    /// Right shift (`>>`).
+    #[cfg(LUA_VERSION = "5.3")]
+    Shr,
+    /// Bitwise not (unary `~`).
+    #[cfg(LUA_VERSION = "5.3")]
+    BNot,
+}
+
+#[cfg(LUA_VERSION = "5.2")]
+impl ArithOp {
+    #[inline]
+    fn code(self) -> libc::c_int {
+        match self {
+            ArithOp::Add => sys::LUA_OPADD,
+            ArithOp::Sub => sys::LUA_OPSUB,
+            ArithOp::Mul => sys::LUA_OPMUL,
+            ArithOp::Div => sys::LUA_OPDIV,
+            #[cfg(LUA_VERSION = "5.3")]
+            ArithOp::IDiv => sys::LUA_OPIDIV,
+            ArithOp::Mod => sys::LUA_OPMOD,
+            ArithOp::Pow => sys::LUA_OPPOW,
+            ArithOp::Unm => sys::LUA_OPUNM,
+            #[cfg(LUA_VERSION = "5.3")]
+            ArithOp::BAnd => sys::LUA_OPBAND,
+            #[cfg(LUA_VERSION = "5.3")]
+            ArithOp::BOr => sys::LUA_OPBOR,
+            #[cfg(LUA_VERSION = "5.3")]
+            ArithOp::BXor => sys::LUA_OPBXOR,
+            #[cfg(LUA_VERSION = "5.3")]
+            ArithOp::Shl => sys::LUA_OPSHL,
+            #[cfg(LUA_VERSION = "5.3")]
+            ArithOp::Shr => sys::LUA_OPSHR,
+            #[cfg(LUA_VERSION = "5.3")]
+            ArithOp::BNot => sys::LUA_OPBNOT,
+        }
+    }
+
+    /// Returns `true` for the operators taking a single operand.
+    #[inline]
+    pub fn is_unary(self) -> bool {
+        match self {
+            ArithOp::Unm => true,
+            #[cfg(LUA_VERSION = "5.3")]
+            ArithOp::BNot => true,
+            _ => false,
+        }
+    }
+}
+
+/// Comparison operators of [`Thread::compare`].
+///
+/// [`Thread::compare`]: struct.Thread.html#method.compare
+#[cfg(LUA_VERSION = "5.2")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CompareOp {
+    /// Equality (`==`).
+    Eq,
+    /// Less than (`<`).
+    Lt,
+    /// Less or equal (`<=`).
+    Le,
+}
+
+#[cfg(LUA_VERSION = "5.2")]
+impl CompareOp {
+    #[inline]
+    fn code(self) -> libc::c_int {
+        match self {
+            CompareOp::Eq => sys::LUA_OPEQ,
+            CompareOp::Lt => sys::LUA_OPLT,
+            CompareOp::Le => sys::LUA_OPLE,
+        }
+    }
+}
+
+#[cfg(LUA_VERSION = "5.2")]
+unsafe extern "C" fn arith(l: *mut sys::lua_State) -> libc::c_int {
+    let op = sys::lua_tointeger(l, 3) as libc::c_int;
+    let unary = op == sys::LUA_OPUNM;
+    #[cfg(LUA_VERSION = "5.3")]
+    let unary = unary || op == sys::LUA_OPBNOT;
+    sys::lua_settop(l, if unary { 1 } else { 2 });
+    sys::lua_arith(l, op);
+    1
+}
+
+#[cfg(LUA_VERSION = "5.2")]
+unsafe extern "C" fn compare(l: *mut sys::lua_State) -> libc::c_int {
+    let op = sys::lua_tointeger(l, 3) as libc::c_int;
+    let result = sys::lua_compare(l, 1, 2, op);
+    sys::lua_pushboolean(l, result);
+    1
+}
+
+unsafe extern "C" fn concat(l: *mut sys::lua_State) -> libc::c_int {
+    sys::lua_concat(l, sys::lua_gettop(l));
+    1
+}
+
+unsafe extern "C" fn len(l: *mut sys::lua_State) -> libc::c_int {
+    sys::lua_len(l, 1);
+    1
+}
+
+impl Thread {
+    /// Calls `f` in protected mode with the values pushed by `args`,
+    /// and converts its single result.
+    fn call_op<A, R>(&mut self, f: sys::lua_CFunction, args: A) -> LuaResult<R>
+    where
+        A: IntoLuaMulti,
+        R: FromLua,
+    {
+        let mut thread = StackGuard::new(self);
+        let raw = thread.as_raw();
+        value::reserve(raw, 1)?;
+        unsafe { sys::lua_pushcfunction(raw.as_ptr(), f) };
+        let nargs = args.push_multi(Pusher(ThreadRef::from_ref(&mut thread)))?;
+        unsafe {
+            let status = sys::lua_pcall(raw.as_ptr(), nargs, 1, 0);
+            thread.get_error(status)?;
+        }
+        let index = thread.top() + 1;
+        R::from_lua(&mut thread, index)
+    }
+
+    /// Applies the operator `op` to `a` and `b` with the semantics of Lua,
+    /// including string coercion, integer and float subtypes and metamethods.
+    ///
+    /// Unary operators ignore `b`, errors such as an arithmetic on a table
+    /// without metamethods are returned as errors of kind [`ErrorKind::Runtime`].
+    /// Since Lua 5.4, strings are only coerced once the string library is open.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::{thread::{ArithOp, Thread}, value::LuaNil};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     unsafe { pollua::sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+    ///     assert_eq!(thread.arith::<_, _, i64>(ArithOp::Mod, 7, 2)?, 1);
+    ///     assert_eq!(thread.arith::<_, _, f64>(ArithOp::Div, 7, 2)?, 3.5);
+    ///     assert_eq!(thread.arith::<_, _, i64>(ArithOp::Add, "10", 5)?, 15);
+    ///     assert_eq!(thread.arith::<_, _, i64>(ArithOp::Unm, 1, LuaNil)?, -1);
+    ///     assert!(thread.arith::<_, _, i64>(ArithOp::Add, "x", 1).is_err());
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    ///
+    /// Requires Lua 5.2 or later.
+    ///
+    /// [`ErrorKind::Runtime`]: ../enum.ErrorKind.html#variant.Runtime
+    #[cfg(LUA_VERSION = "5.2")]
+    pub fn arith<A, B, R>(&mut self, op: ArithOp, a: A, b: B) -> LuaResult<R>
+    where
+        A: Pushable,
+        B: Pushable,
+        R: FromLua,
+    {
+        self.call_op(Some(arith), (a, b, op.code() as sys::lua_Integer))
+    }
+
+    /// Compares `a` and `b` with the operator `op` and the semantics of Lua, including metamethods.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::{CompareOp, Thread};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     assert!(thread.compare(1, 1.0, CompareOp::Eq)?);
+    ///     assert!(thread.compare("a", "b", CompareOp::Lt)?);
+    ///     assert!(thread.compare(1, "2", CompareOp::Lt).is_err());
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    ///
+    /// Requires Lua 5.2 or later.
+    #[cfg(LUA_VERSION = "5.2")]
+    pub fn compare<A, B>(&mut self, a: A, b: B, op: CompareOp) -> LuaResult<bool>
+    where
+        A: Pushable,
+        B: Pushable,
+    {
+        self.call_op(Some(compare), (a, b, op.code() as sys::lua_Integer))
+    }
+
+    /// Returns `true` if `a` and `b` are primitively equal, without invoking the `__eq` metamethod.
+    pub fn raw_equal<A, B>(&mut self, a: A, b: B) -> LuaResult<bool>
+    where
+        A: Pushable,
+        B: Pushable,
+    {
+        let mut thread = StackGuard::new(self);
+        a.push(Pusher(ThreadRef::from_ref(&mut thread)))?;
+        b.push(Pusher(ThreadRef::from_ref(&mut thread)))?;
+        Ok(unsafe { sys::lua_rawequal(thread.as_raw().as_ptr(), -2, -1) } != 0)
+    }
+
+    /// Concatenates `values` with the semantics of the `..` operator of Lua,
+    /// including number coercion and metamethods.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::Thread;
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     assert_eq!(thread.concat::<_, String>(("x = ", 1, ".5"))?, "x = 1.5");
+    ///     assert_eq!(thread.concat::<_, String>(())?, "");
+    ///     assert!(thread.concat::<_, String>(("x", true)).is_err());
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    pub fn concat<V, R>(&mut self, values: V) -> LuaResult<R>
+    where
+        V: IntoLuaMulti,
+        R: FromLua,
+    {
+        self.call_op(Some(concat), values)
+    }
+
+    /// Returns the length of `value` with the semantics of the `#` operator of Lua,
+    /// including the `__len` metamethod.
+    pub fn len<V, R>(&mut self, value: V) -> LuaResult<R>
+    where
+        V: Pushable,
+        R: FromLua,
+    {
+        self.call_op(Some(len), value)
+    }
+}
+
+// the tests rely on the integer subtype and the bitwise operators of Lua 5.3
+#[cfg(all(test, LUA_VERSION = "5.3"))]
+mod test {
+    use super::*;
+    use crate::{
+        userdata::{self, Methods, UserData},
+        value::{LuaNil, LuaNumber, OwnedValue},
+        Error, ErrorKind,
+    };
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    #[test]
+    fn test_arith() {
+        Thread::spawn(move |thread| {
+            // strings are coerced by the metamethods of the string library since Lua 5.4
+            #[cfg(LUA_VERSION = "5.4")]
+            unsafe {
+                sys::luaL_openlibs(thread.as_raw().as_ptr())
+            };
+            let top = stack_top(thread);
+            assert_eq!(
+                thread
+                    .arith::<_, _, OwnedValue>(ArithOp::Add, 1, 2)
+                    .unwrap(),
+                OwnedValue::Integer(3)
+            );
+            assert_eq!(
+                thread
+                    .arith::<_, _, OwnedValue>(ArithOp::Add, 1, 2.0)
+                    .unwrap(),
+                OwnedValue::Number(LuaNumber::from(3.0))
+            );
+            assert_eq!(thread.arith::<_, _, i64>(ArithOp::Mod, -7, 3).unwrap(), 2);
+            assert_eq!(
+                thread
+                    .arith::<_, _, i64>(ArithOp::Add, i64::MAX, 1)
+                    .unwrap(),
+                i64::MIN
+            );
+            assert_eq!(
+                thread.arith::<_, _, i64>(ArithOp::BNot, 0, LuaNil).unwrap(),
+                -1
+            );
+            assert_eq!(
+                thread
+                    .arith::<_, _, f64>(ArithOp::Pow, "2", "0x10")
+                    .unwrap(),
+                65536.0
+            );
+            let err = thread.arith::<_, _, i64>(ArithOp::IDiv, 1, 0).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Runtime);
+            let err = thread.arith::<_, _, i64>(ArithOp::Sub, "x", 1).unwrap_err();
+            assert!(err.msg().unwrap().contains("string"));
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[derive(Clone)]
+    struct Meters(f64);
+
+    impl UserData for Meters {
+        const NAME: &'static str = "Meters";
+
+        fn add_methods(methods: Methods<Self>) -> Methods<Self> {
+            methods
+                .method("__add", |m: &Meters, other: f64| Ok(Meters(m.0 + other)))
+                .method("__len", |m: &Meters, ()| Ok(m.0.round() as i64))
+                .method("__lt", |_: &Meters, ()| {
+                    Err::<bool, _>(Error::new(ErrorKind::Runtime, Some("no order".to_owned())))
+                })
+                .method("__concat", |m: &Meters, suffix: String| {
+                    Ok(format!("{}m{}", m.0, suffix))
+                })
+        }
+    }
+
+    impl Pushable for Meters {
+        fn push(&self, pusher: Pusher) -> LuaResult<()> {
+            userdata::push(pusher, self.clone())
+        }
+    }
+
+    #[test]
+    fn test_compare_concat_len() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            assert!(!thread.compare(2, 1, CompareOp::Le).unwrap());
+            assert!(!thread.compare("1", 1, CompareOp::Eq).unwrap());
+            assert!(thread.raw_equal(1, 1.0).unwrap());
+            assert!(!thread.raw_equal(1, "1").unwrap());
+
+            assert_eq!(thread.concat::<_, String>((1, 2, 3)).unwrap(), "123");
+            assert_eq!(thread.len::<_, i64>("four").unwrap(), 4);
+            assert_eq!(
+                thread.len::<_, i64>(1).unwrap_err().kind(),
+                ErrorKind::Runtime
+            );
+            assert_eq!(stack_top(thread), top);
+
+            // metamethods are used, and their errors are caught
+            let m = Meters(2.5);
+            let sum = thread
+                .arith::<_, _, Meters>(ArithOp::Add, m.clone(), 1)
+                .unwrap();
+            assert_eq!(sum.0, 3.5);
+            assert_eq!(thread.len::<_, i64>(m.clone()).unwrap(), 3);
+            assert_eq!(
+                thread.concat::<_, String>((m.clone(), "!")).unwrap(),
+                "2.5m!"
+            );
+            let err = thread
+                .compare(m.clone(), m.clone(), CompareOp::Lt)
+                .unwrap_err();
+            assert_eq!(err.msg(), Some("no order"));
+            assert!(!thread.raw_equal(m.clone(), m).unwrap());
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+}
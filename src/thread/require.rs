@@ -0,0 +1,323 @@
+use crate::{
+    thread::{StackCheck, StackGuard, Thread, ThreadRef},
+    util,
+    value::{self, IntoLuaMulti, OwnedValue, Pusher},
+    Error, ErrorKind, LuaResult,
+};
+
+use std::{cell::RefCell, fmt};
+
+/// The source of a module found by a Rust searcher or preloaded with
+/// [`Thread::preload_module`].
+///
+/// [`Thread::preload_module`]: struct.Thread.html#method.preload_module
+#[derive(Clone, PartialEq)]
+pub enum ModuleSource {
+    /// Lua source code, run to get the module.
+    Text(Vec<u8>),
+    /// A binary chunk, as produced by `string.dump`, run to get the module.
+    Bytecode(Vec<u8>),
+    /// The value of the module itself, usually a table.
+    Value(OwnedValue),
+}
+
+impl fmt::Debug for ModuleSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleSource::Text(code) => f
+                .debug_tuple("Text")
+                .field(&String::from_utf8_lossy(code))
+                .finish(),
+            ModuleSource::Bytecode(code) => write!(f, "Bytecode({} bytes)", code.len()),
+            ModuleSource::Value(value) => f.debug_tuple("Value").field(value).finish(),
+        }
+    }
+}
+
+impl ModuleSource {
+    /// Pushes the loader function of the module `name`.
+    fn push_loader(&self, name: &str, mut pusher: Pusher) -> LuaResult<()> {
+        let (code, mode) = match self {
+            ModuleSource::Text(code) => (code, "t\0"),
+            ModuleSource::Bytecode(code) => (code, "b\0"),
+            ModuleSource::Value(value) => {
+                let value = value.clone();
+                return pusher.0.push_fn(move |()| Ok(value.clone()));
+            }
+        };
+        pusher.reserve(1)?;
+        let mut name_buf = Vec::new();
+        let chunk_name = format!("={}", name);
+        let status = unsafe {
+            sys::luaL_loadbufferx(
+                pusher.0.as_raw().as_ptr(),
+                code.as_ptr() as *const libc::c_char,
+                code.len(),
+                util::cstr_buf(Some(&chunk_name), &mut name_buf),
+                mode.as_ptr() as *const libc::c_char,
+            )
+        };
+        pusher.0.get_error(status).map_err(|e| {
+            Error::new(
+                e.kind(),
+                Some(format!(
+                    "error loading module '{}':\n\t{}",
+                    name,
+                    e.msg().unwrap_or_default()
+                )),
+            )
+        })
+    }
+}
+
+/// The field of the package library holding the searchers, `loaders` in Lua 5.1.
+#[cfg(LUA_VERSION = "5.2")]
+const SEARCHERS: &[u8] = b"searchers\0";
+#[cfg(not(LUA_VERSION = "5.2"))]
+const SEARCHERS: &[u8] = b"loaders\0";
+
+/// Pushes the loaded package library and its table `field`,
+/// or returns an error if the library is not open.
+unsafe fn push_package_field(l: *mut sys::lua_State, field: &[u8]) -> LuaResult<()> {
+    let name = sys::LUA_LOADED_TABLE;
+    sys::lua_pushlstring(l, name.as_ptr() as *const libc::c_char, name.len());
+    let found = sys::lua_rawget(l, sys::LUA_REGISTRYINDEX) == sys::LUA_TTABLE
+        && sys::lua_getfield(l, -1, b"package\0".as_ptr() as *const _) == sys::LUA_TTABLE
+        && sys::lua_getfield(l, -1, field.as_ptr() as *const _) == sys::LUA_TTABLE;
+    if found {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::Runtime,
+            Some("the package library is not open".to_owned()),
+        ))
+    }
+}
+
+/// Results of a Rust searcher: a loader, or the reason why the module was not found.
+enum Search {
+    Found(String, ModuleSource),
+    NotFound(String),
+}
+
+impl IntoLuaMulti for Search {
+    fn push_multi(self, mut pusher: Pusher) -> LuaResult<libc::c_int> {
+        match self {
+            Search::Found(name, source) => {
+                source.push_loader(&name, pusher.reborrow())?;
+                Ok(1)
+            }
+            Search::NotFound(msg) => msg.push_multi(pusher),
+        }
+    }
+}
+
+impl Thread {
+    /// Adds a Rust function to `package.searchers`, after the default searchers,
+    /// so that `require` can load modules that are not files.
+    /// With Lua 5.1, the function is added to `package.loaders`.
+    ///
+    /// `searcher` is called with the name of the required module
+    /// and returns its source, or `None` if it does not know the module.
+    /// Returns an error of kind [`ErrorKind::Runtime`] if the package library is not open.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::{LoadingMode, ModuleSource, Thread};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut files = HashMap::new();
+    /// files.insert("greeting", "return { text = 'hello' }");
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     unsafe { pollua::sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+    ///     thread.add_module_searcher(move |name| {
+    ///         files.get(name).map(|code| ModuleSource::Text(code.as_bytes().to_vec()))
+    ///     })?;
+    ///     let values = thread
+    ///         .caller_load("return require('greeting').text", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     assert_eq!(values.get_as::<String>(0)?, "hello");
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    ///
+    /// [`ErrorKind::Runtime`]: ../enum.ErrorKind.html#variant.Runtime
+    pub fn add_module_searcher<F>(&mut self, searcher: F) -> LuaResult<()>
+    where
+        F: FnMut(&str) -> Option<ModuleSource> + Send + 'static,
+    {
+        let _check = StackCheck::new(self.as_raw(), 0);
+        let mut thread = StackGuard::new(self);
+        let raw = thread.as_raw();
+        value::reserve(raw, 3)?;
+        let l = raw.as_ptr();
+        unsafe { push_package_field(l, SEARCHERS)? };
+
+        let searcher = RefCell::new(searcher);
+        thread.push_fn(move |name: String| {
+            let source = (*searcher.borrow_mut())(&name);
+            Ok(match source {
+                Some(source) => Search::Found(name, source),
+                None => Search::NotFound(format!("\n\tno module '{}' in Rust searchers", name)),
+            })
+        })?;
+        unsafe {
+            let n = sys::lua_rawlen(l, -2) as sys::lua_Integer;
+            sys::lua_rawseti(l, -2, n + 1);
+        }
+        Ok(())
+    }
+
+    /// Sets the loader of the module `name` in `package.preload`,
+    /// so that `require` loads it from `source`.
+    ///
+    /// The package library does not need to be open, except with Lua 5.1
+    /// where the preloaded modules are only stored in the library.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::{thread::{LoadingMode, ModuleSource, Thread}, value::OwnedValue};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     unsafe { pollua::sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+    ///     let config = OwnedValue::Table(vec![(
+    ///         OwnedValue::String(b"debug".to_vec()),
+    ///         OwnedValue::Boolean(true),
+    ///     )]);
+    ///     thread.preload_module("config", ModuleSource::Value(config))?;
+    ///     let values = thread
+    ///         .caller_load("return require('config').debug", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     assert!(values.get_as::<bool>(0)?);
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    pub fn preload_module(&mut self, name: &str, source: ModuleSource) -> LuaResult<()> {
+        let _check = StackCheck::new(self.as_raw(), 0);
+        let mut thread = StackGuard::new(self);
+        let raw = thread.as_raw();
+        value::reserve(raw, 4)?;
+        #[cfg(LUA_VERSION = "5.2")]
+        unsafe {
+            let mut table_buf = Vec::new();
+            sys::luaL_getsubtable(
+                raw.as_ptr(),
+                sys::LUA_REGISTRYINDEX,
+                util::cstr_buf(Some(sys::LUA_PRELOAD_TABLE), &mut table_buf),
+            );
+        }
+        #[cfg(not(LUA_VERSION = "5.2"))]
+        unsafe {
+            push_package_field(raw.as_ptr(), b"preload\0")?
+        };
+        unsafe { sys::lua_pushlstring(raw.as_ptr(), name.as_ptr() as *const _, name.len()) };
+        source.push_loader(name, Pusher(ThreadRef::from_ref(&mut thread)))?;
+        unsafe { sys::lua_rawset(raw.as_ptr(), -3) };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+    use std::collections::HashMap;
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    fn eval(thread: &mut Thread, code: &str) -> LuaResult<String> {
+        thread
+            .caller_load(code, None, LoadingMode::Text)?
+            .call()?
+            .get_as::<String>(0)
+    }
+
+    #[test]
+    fn test_module_searcher() {
+        Thread::spawn(move |thread| {
+            let err = thread.add_module_searcher(|_| None).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Runtime);
+
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let bytecode = thread
+                .caller_load(
+                    "return string.dump(function() return 'dumped' end)",
+                    None,
+                    LoadingMode::Text,
+                )
+                .and_then(|c| c.call()?.get_as::<Vec<u8>>(0))
+                .unwrap();
+            let mut files = HashMap::new();
+            files.insert("text", ModuleSource::Text(b"return ... .. '!'".to_vec()));
+            files.insert("bytecode", ModuleSource::Bytecode(bytecode));
+            files.insert("broken", ModuleSource::Text(b"return (".to_vec()));
+            files.insert(
+                "value",
+                ModuleSource::Value(OwnedValue::String(b"value".to_vec())),
+            );
+
+            let top = stack_top(thread);
+            let mut searched = 0;
+            thread
+                .add_module_searcher(move |name| {
+                    searched += 1;
+                    assert!(searched <= 5);
+                    files.get(name).cloned()
+                })
+                .unwrap();
+            assert_eq!(stack_top(thread), top);
+
+            assert_eq!(
+                eval(
+                    thread,
+                    "return require('text') .. require('bytecode') .. require('value') \
+                     .. require('text')"
+                )
+                .unwrap(),
+                "text!dumpedvaluetext!"
+            );
+            let err = eval(thread, "return require('broken')").unwrap_err();
+            assert!(err.msg().unwrap().contains("error loading module 'broken'"));
+            let err = eval(thread, "return require('missing')").unwrap_err();
+            assert!(err
+                .msg()
+                .unwrap()
+                .contains("no module 'missing' in Rust searchers"));
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_preload_module() {
+        Thread::spawn(move |thread| {
+            // Lua 5.1 only stores the preloaded modules in the package library
+            #[cfg(not(LUA_VERSION = "5.2"))]
+            unsafe {
+                sys::luaL_openlibs(thread.as_raw().as_ptr())
+            };
+            let top = stack_top(thread);
+            thread
+                .preload_module("answer", ModuleSource::Text(b"return 42".to_vec()))
+                .unwrap();
+            let err = thread
+                .preload_module("broken", ModuleSource::Bytecode(b"return 42".to_vec()))
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Syntax);
+            assert_eq!(stack_top(thread), top);
+
+            #[cfg(LUA_VERSION = "5.2")]
+            unsafe {
+                sys::luaL_openlibs(thread.as_raw().as_ptr())
+            };
+            assert_eq!(
+                eval(thread, "return tostring(require('answer'))").unwrap(),
+                "42"
+            );
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,227 @@
+use crate::thread::{Thread, ThreadRef};
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+/// Restores the stack top of a [`Thread`] when dropped.
+///
+/// The guard dereferences to the guarded thread, any value pushed through it
+/// is popped once the guard goes out of scope.
+///
+/// # Examples
+/// ```
+/// use pollua::thread::{StackGuard, Thread};
+///
+/// Thread::spawn(move |thread| {
+///     let top = StackGuard::new(thread).top();
+///     {
+///         let mut guard = StackGuard::new(thread);
+///         unsafe { pollua::sys::lua_pushinteger(guard.as_raw().as_ptr(), 42) };
+///     }
+///     assert_eq!(StackGuard::new(thread).top(), top);
+/// }).unwrap()
+/// ```
+///
+/// [`Thread`]: struct.Thread.html
+pub struct StackGuard<'a> {
+    thread: ThreadRef<'a>,
+    top: libc::c_int,
+}
+
+impl<'a> StackGuard<'a> {
+    /// Creates a guard that will restore the current stack top of `thread`.
+    #[inline]
+    pub fn new(thread: &'a mut Thread) -> StackGuard<'a> {
+        StackGuard::from_ref(ThreadRef::from_ref(thread))
+    }
+
+    #[inline]
+    pub(crate) fn from_ref(mut thread: ThreadRef<'a>) -> StackGuard<'a> {
+        let top = unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) };
+        StackGuard { thread, top }
+    }
+
+    /// Creates a guard that will restore the stack top to `top`.
+    #[inline]
+    pub(crate) fn with_top(thread: ThreadRef<'a>, top: libc::c_int) -> StackGuard<'a> {
+        StackGuard { thread, top }
+    }
+
+    /// Returns the stack top that will be restored.
+    #[inline]
+    pub fn top(&self) -> libc::c_int {
+        self.top
+    }
+}
+
+impl fmt::Debug for StackGuard<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StackGuard")
+            .field("thread", &self.thread)
+            .field("top", &self.top)
+            .finish()
+    }
+}
+
+impl Deref for StackGuard<'_> {
+    type Target = Thread;
+
+    #[inline]
+    fn deref(&self) -> &Thread {
+        &self.thread
+    }
+}
+
+impl DerefMut for StackGuard<'_> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Thread {
+        &mut self.thread
+    }
+}
+
+impl Drop for StackGuard<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { sys::lua_settop(self.thread.as_raw().as_ptr(), self.top) }
+    }
+}
+
+/// Checks that the stack top moved by exactly `delta` slots when dropped.
+///
+/// The check is only performed in debug builds,
+/// it is used to make sure that public functions leave the stack balanced.
+pub(crate) struct StackCheck {
+    #[cfg(debug_assertions)]
+    raw: NonNull<sys::lua_State>,
+    #[cfg(debug_assertions)]
+    expected: libc::c_int,
+}
+
+impl StackCheck {
+    #[inline]
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(crate) fn new(raw: NonNull<sys::lua_State>, delta: libc::c_int) -> StackCheck {
+        #[cfg(debug_assertions)]
+        {
+            let top = unsafe { sys::lua_gettop(raw.as_ptr()) };
+            StackCheck {
+                raw,
+                expected: top + delta,
+            }
+        }
+        #[cfg(not(debug_assertions))]
+        StackCheck {}
+    }
+
+    /// Changes the expected difference,
+    /// for functions whose effect on the stack depends on their outcome.
+    #[inline]
+    #[cfg_attr(not(debug_assertions), allow(unused_variables))]
+    pub(crate) fn adjust(&mut self, delta: libc::c_int) {
+        #[cfg(debug_assertions)]
+        {
+            self.expected += delta;
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for StackCheck {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            let top = unsafe { sys::lua_gettop(self.raw.as_ptr()) };
+            assert_eq!(
+                top, self.expected,
+                "unbalanced Lua stack: the top is {} instead of {}",
+                top, self.expected
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{thread::LoadingMode, value::LuaNil, ErrorKind};
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    #[test]
+    fn test_stack_guard() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            {
+                let mut guard = StackGuard::new(thread);
+                assert_eq!(guard.top(), top);
+                unsafe {
+                    sys::lua_pushinteger(guard.as_raw().as_ptr(), 1);
+                    sys::lua_pushinteger(guard.as_raw().as_ptr(), 2);
+                }
+                assert_eq!(stack_top(&mut guard), top + 2);
+            }
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_stack_balance() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            thread
+                .caller_load(
+                    "function echo(...) return ... end function fail() error('fail') end",
+                    None,
+                    LoadingMode::Text,
+                )
+                .and_then(|c| c.call())
+                .unwrap();
+            let top = stack_top(thread);
+
+            for _ in 0..1000 {
+                thread.version();
+                thread.gc().step(0).unwrap();
+                thread.gc().used_bytes();
+                assert!(thread.caller_global("undefined").is_none());
+                assert!(thread.caller_global("echo").is_some());
+                let err = thread
+                    .caller_load("syntax error", None, LoadingMode::Text)
+                    .unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::Syntax);
+                thread
+                    .caller_load("return 1, 2, 3", None, LoadingMode::Text)
+                    .and_then(|c| c.call())
+                    .map(|values| assert_eq!(values.len(), 3))
+                    .unwrap();
+                thread
+                    .caller_global("echo")
+                    .unwrap()
+                    .arg(1.0)
+                    .arg(LuaNil)
+                    .calln(5)
+                    .map(|values| assert_eq!(values.len(), 5))
+                    .unwrap();
+                let err = thread.caller_global("fail").unwrap().call().unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::Runtime);
+                let err = thread
+                    .caller_global("fail")
+                    .unwrap()
+                    .arg("ignored")
+                    .calln(2)
+                    .unwrap_err();
+                assert!(err.msg().unwrap().ends_with("fail"));
+                unsafe {
+                    sys::lua_pushinteger(thread.as_raw().as_ptr(), 42);
+                    assert!(thread.get_error(sys::LUA_ERRRUN).is_err());
+                }
+                assert_eq!(stack_top(thread), top);
+            }
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    thread::{StackCheck, Thread, ThreadRef},
+    value::{OwnedValue, Pushable, Pusher, TransferOptions},
+    LuaResult,
+};
+
+impl Thread {
+    /// Copies the value at `index` of the thread `from` onto the stack of this thread,
+    /// nothing is pushed if the value cannot be copied.
+    ///
+    /// Tables and strings are deep-copied, see [`OwnedValue::transferable`] for the values that
+    /// cannot be copied and for how `options` applies to functions.
+    /// To transfer a value to a thread running on another OS thread,
+    /// send the [`OwnedValue`] returned by [`ReturnValues::get_transferable`]
+    /// and push it there instead.
+    ///
+    /// [`ReturnValues::get_transferable`]: struct.ReturnValues.html#method.get_transferable
+    /// [`OwnedValue`]: ../value/enum.OwnedValue.html
+    /// [`OwnedValue::transferable`]: ../value/enum.OwnedValue.html#method.transferable
+    pub fn transfer(
+        &mut self,
+        from: &mut Thread,
+        index: libc::c_int,
+        options: &TransferOptions,
+    ) -> LuaResult<()> {
+        let value = OwnedValue::transferable(from, index, options)?;
+        let raw = self.as_raw();
+        let mut check = StackCheck::new(raw, 0);
+        let top = unsafe { sys::lua_gettop(raw.as_ptr()) };
+        match value.push(Pusher(ThreadRef::from_ref(self))) {
+            Ok(()) => {
+                check.adjust(1);
+                Ok(())
+            }
+            Err(e) => {
+                unsafe { sys::lua_settop(raw.as_ptr(), top) };
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{thread::LoadingMode, ErrorKind};
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    fn load(thread: &mut Thread, code: &str) {
+        // the return values are forgotten to leave the result on the stack
+        thread
+            .caller_load(code, None, LoadingMode::Text)
+            .and_then(|c| c.calln(1))
+            .map(std::mem::forget)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_transfer_values() {
+        Thread::spawn(move |from| {
+            Thread::spawn(move |to| {
+                let options = TransferOptions::new();
+                load(from, "return { list = { 1, 2.5, 'x' }, flag = true }");
+                let top = stack_top(to);
+                to.transfer(from, -1, &options).unwrap();
+                assert_eq!(stack_top(to), top + 1);
+                let from_value = OwnedValue::transferable(from, -1, &options).unwrap();
+                let to_value = OwnedValue::transferable(to, -1, &options).unwrap();
+                assert_eq!(from_value, to_value);
+
+                // values can be sent to other OS threads
+                let value = std::thread::spawn(move || from_value).join().unwrap();
+                assert_eq!(value, to_value);
+
+                load(from, "return { f = function() end }");
+                let err = to.transfer(from, -1, &options).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::Conversion);
+                assert_eq!(stack_top(to), top + 1);
+
+                let options = options.dump_functions(true);
+                load(from, "local n = 1 return function() return n end");
+                let err = to.transfer(from, -1, &options).unwrap_err();
+                assert!(err.msg().unwrap().contains("upvalues"));
+                assert_eq!(stack_top(to), top + 1);
+            })
+            .unwrap()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_transfer_function() {
+        Thread::spawn(move |from| {
+            Thread::spawn(move |to| {
+                load(
+                    from,
+                    "name = 'from' return function(s) return s .. name end",
+                );
+                load(to, "name = 'to'");
+                let options = TransferOptions::new()
+                    .dump_functions(true)
+                    .strip_debug_info(true);
+                to.transfer(from, -1, &options).unwrap();
+                let values = unsafe { to.caller_stack_unchecked() }
+                    .arg("from ")
+                    .call()
+                    .unwrap();
+                assert_eq!(values.get_as::<String>(0).unwrap(), "from to");
+            })
+            .unwrap()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_transfer_userdata() {
+        Thread::spawn(move |from| {
+            Thread::spawn(move |to| {
+                let options = TransferOptions::new().dump_functions(true);
+                let top = stack_top(to);
+                unsafe { sys::lua_newuserdata(from.as_raw().as_ptr(), 8) };
+                let err = to.transfer(from, -1, &options).unwrap_err();
+                assert_eq!(
+                    err.msg(),
+                    Some("cannot transfer a userdata without a __transfer metamethod")
+                );
+
+                load(
+                    from,
+                    "return { __transfer = function(u) return { 'copied' } end }",
+                );
+                unsafe { sys::lua_setmetatable(from.as_raw().as_ptr(), -2) };
+                to.transfer(from, -1, &options).unwrap();
+                assert_eq!(
+                    OwnedValue::transferable(to, -1, &options).unwrap(),
+                    OwnedValue::Table(vec![(
+                        OwnedValue::Integer(1),
+                        OwnedValue::String(b"copied".to_vec())
+                    )])
+                );
+
+                load(from, "return { __transfer = function(u) return u end }");
+                unsafe { sys::lua_setmetatable(from.as_raw().as_ptr(), -2) };
+                assert!(to.transfer(from, -1, &options).is_err());
+                load(from, "return { __transfer = function(u) error('no') end }");
+                unsafe { sys::lua_setmetatable(from.as_raw().as_ptr(), -2) };
+                let err = to.transfer(from, -1, &options).unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::Runtime);
+                assert_eq!(stack_top(to), top + 1);
+            })
+            .unwrap()
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{
+    thread::{StackCheck, Thread},
+    util,
+    value::{self, LuaStr},
+    LuaResult,
+};
+
+use std::{
+    ffi::CStr,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+/// A warning handler and the pieces of the message being emitted.
+struct Warnings {
+    handler: Box<dyn FnMut(&LuaStr) + Send>,
+    message: Vec<u8>,
+}
+
+/// Registry key of the userdata owning the `Warnings` of a state.
+static WARNINGS_KEY: u8 = 0;
+
+/// Registry name of the metatable of the userdata owning `Warnings`.
+const WARNINGS_METATABLE: &[u8] = b"pollua.Warnings\0";
+
+#[inline]
+fn key() -> *const libc::c_void {
+    &WARNINGS_KEY as *const u8 as *const libc::c_void
+}
+
+unsafe extern "C" fn warn(ud: *mut libc::c_void, msg: *const libc::c_char, tocont: libc::c_int) {
+    let warnings = &mut *(ud as *mut Warnings);
+    warnings
+        .message
+        .extend_from_slice(CStr::from_ptr(msg).to_bytes());
+    if tocont == 0 {
+        let message = mem::take(&mut warnings.message);
+        let handler = &mut warnings.handler;
+        // warnings cannot fail, a panicking handler only loses its message
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| handler(LuaStr::from_bytes(&message))));
+    }
+}
+
+/// Returns the slot of the userdata owning `Warnings` at `index`,
+/// or null if the value is not such a userdata.
+unsafe fn warnings_slot(l: *mut sys::lua_State, index: libc::c_int) -> *mut *mut Warnings {
+    sys::luaL_testudata(l, index, WARNINGS_METATABLE.as_ptr() as *const _) as *mut *mut Warnings
+}
+
+/// Frees the `Warnings` owned by the userdata at `index`, if any.
+unsafe fn free_warnings(l: *mut sys::lua_State, index: libc::c_int) {
+    let slot = warnings_slot(l, index);
+    if !slot.is_null() && !(*slot).is_null() {
+        drop(Box::from_raw(*slot));
+        *slot = ptr::null_mut();
+    }
+}
+
+unsafe extern "C" fn warnings_gc(l: *mut sys::lua_State) -> libc::c_int {
+    // the finalizer can still be called on other values through `debug.getmetatable`
+    let slot = warnings_slot(l, 1);
+    if slot.is_null() || (*slot).is_null() {
+        return 0;
+    }
+    // only the handler of the registered userdata is installed
+    sys::lua_rawgetp(l, sys::LUA_REGISTRYINDEX, key());
+    if sys::lua_rawequal(l, 1, -1) != 0 {
+        sys::lua_setwarnf(l, None, ptr::null_mut());
+    }
+    sys::lua_pop(l, 1);
+    free_warnings(l, 1);
+    0
+}
+
+impl Thread {
+    /// Sets the function receiving the warnings emitted with the `warn` function of Lua
+    /// or with [`warn`], replacing the previous one.
+    ///
+    /// Messages emitted in several pieces are concatenated, so that `handler` receives
+    /// each complete message. Control messages such as `"@on"` are passed as is,
+    /// the handler decides whether to follow them. Warnings are discarded until a handler is set.
+    ///
+    /// Requires Lua 5.4.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::{LoadingMode, Thread};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     unsafe { pollua::sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+    ///     let warnings = Arc::new(Mutex::new(Vec::new()));
+    ///     let sink = warnings.clone();
+    ///     thread.set_warning_handler(move |msg| {
+    ///         sink.lock().unwrap().push(msg.to_string_lossy().into_owned())
+    ///     })?;
+    ///     thread
+    ///         .caller_load("warn('disk ', 'almost full')", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     assert_eq!(*warnings.lock().unwrap(), ["disk almost full"]);
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    ///
+    /// [`warn`]: #method.warn
+    pub fn set_warning_handler<F>(&mut self, handler: F) -> LuaResult<()>
+    where
+        F: FnMut(&LuaStr) + Send + 'static,
+    {
+        let raw = self.as_raw();
+        let _check = StackCheck::new(raw, 0);
+        value::reserve(raw, 3)?;
+        let warnings = Box::into_raw(Box::new(Warnings {
+            handler: Box::new(handler),
+            message: Vec::new(),
+        }));
+        unsafe {
+            let l = raw.as_ptr();
+            sys::lua_setwarnf(l, Some(warn), warnings as *mut libc::c_void);
+            // the previous handler is freed now, its finalizer would uninstall the new one
+            sys::lua_rawgetp(l, sys::LUA_REGISTRYINDEX, key());
+            free_warnings(l, -1);
+            sys::lua_pop(l, 1);
+
+            // the handler is owned by the registry
+            let slot =
+                sys::lua_newuserdata(l, mem::size_of::<*mut Warnings>()) as *mut *mut Warnings;
+            slot.write(warnings);
+            util::push_finalized_metatable(l, WARNINGS_METATABLE, Some(warnings_gc));
+            sys::lua_setmetatable(l, -2);
+            sys::lua_rawsetp(l, sys::LUA_REGISTRYINDEX, key());
+        }
+        Ok(())
+    }
+
+    /// Emits the warning `msg` to the handler set with [`set_warning_handler`].
+    ///
+    /// `msg` is truncated at its first nul byte.
+    ///
+    /// Requires Lua 5.4.
+    ///
+    /// [`set_warning_handler`]: #method.set_warning_handler
+    pub fn warn<S: AsRef<[u8]> + ?Sized>(&mut self, msg: &S) {
+        let mut buf = Vec::new();
+        let msg = util::cstr_buf(Some(msg), &mut buf);
+        unsafe { sys::lua_warning(self.as_raw().as_ptr(), msg, 0) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_warnings() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            thread.warn("discarded");
+
+            let first = Arc::new(Mutex::new(Vec::new()));
+            let sink = first.clone();
+            thread
+                .set_warning_handler(move |msg| sink.lock().unwrap().push(msg.as_bytes().to_vec()))
+                .unwrap();
+            thread.warn("from Rust");
+            thread
+                .caller_load("warn('@on') warn('a', 'b', 'c')", None, LoadingMode::Text)
+                .and_then(|c| c.call())
+                .unwrap();
+            assert_eq!(
+                *first.lock().unwrap(),
+                [&b"from Rust"[..], &b"@on"[..], &b"abc"[..]]
+            );
+
+            // the previous handler is replaced and freed
+            let second = Arc::new(Mutex::new(0));
+            let count = second.clone();
+            thread
+                .set_warning_handler(move |_| *count.lock().unwrap() += 1)
+                .unwrap();
+            thread.warn("second");
+            assert_eq!(first.lock().unwrap().len(), 3);
+            assert_eq!(Arc::strong_count(&first), 1);
+            assert_eq!(*second.lock().unwrap(), 1);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_warnings_finalizer() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let count = Arc::new(Mutex::new(0));
+            let sink = count.clone();
+            thread
+                .set_warning_handler(move |_| *sink.lock().unwrap() += 1)
+                .unwrap();
+            thread
+                .caller_load(
+                    "local ud \
+                     for _, v in pairs(debug.getregistry()) do \
+                         if getmetatable(v) == 'pollua.Warnings' then ud = v end \
+                     end \
+                     local gc = debug.getmetatable(ud).__gc \
+                     gc(42) gc(io.stdout) \
+                     warn('kept') \
+                     gc(ud) gc(ud) \
+                     warn('discarded')",
+                    None,
+                    LoadingMode::Text,
+                )
+                .and_then(|c| c.call())
+                .unwrap();
+            assert_eq!(*count.lock().unwrap(), 1);
+            assert_eq!(Arc::strong_count(&count), 1);
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,517 @@
+use crate::{
+    module::Module,
+    thread::{bad_argument, Thread, ThreadRef},
+    value::{self, FromLua, FromLuaMulti, IntoLuaMulti, Pusher},
+    Error, ErrorKind, LuaResult,
+};
+
+use std::{
+    any::TypeId,
+    cell::{Ref, RefCell, RefMut},
+    fmt,
+    marker::PhantomData,
+    mem, ptr,
+};
+
+/// The content of a userdata block, `None` once it has been finalized.
+type Cell<T> = Option<Box<RefCell<T>>>;
+
+/// A Rust type that can be moved into Lua as a full userdata.
+///
+/// Values are pushed with [`push`], the type must then implement [`IntoLuaMulti`] using it
+/// to be returned from functions, which `#[lua_methods]` does.
+/// Values of types that are also `Clone` can be converted back with [`FromLua`],
+/// and methods can borrow them with [`borrow`] and [`borrow_mut`].
+///
+/// # Examples
+/// ```
+/// use pollua::{
+///     thread::{LoadingMode, Thread},
+///     userdata::{self, Methods, UserData},
+///     value::{IntoLuaMulti, Pusher},
+///     LuaResult,
+/// };
+///
+/// struct Counter(u32);
+///
+/// impl UserData for Counter {
+///     const NAME: &'static str = "Counter";
+///
+///     fn add_methods(methods: Methods<Counter>) -> Methods<Counter> {
+///         methods
+///             .function("new", |()| Ok(Counter(0)))
+///             .method_mut("incr", |counter, ()| {
+///                 counter.0 += 1;
+///                 Ok(counter.0)
+///             })
+///     }
+/// }
+///
+/// impl IntoLuaMulti for Counter {
+///     fn push_multi(self, pusher: Pusher) -> LuaResult<libc::c_int> {
+///         userdata::push(pusher, self).map(|()| 1)
+///     }
+/// }
+///
+/// Thread::spawn(move |thread| {
+///     thread.register_module(userdata::class::<Counter>())?;
+///     let values = thread
+///         .caller_load("local c = Counter.new() c:incr() return c:incr()", None, LoadingMode::Text)?
+///         .call()?;
+///     assert_eq!(values.get_as::<u32>(0)?, 2);
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+///
+/// [`push`]: fn.push.html
+/// [`borrow`]: fn.borrow.html
+/// [`borrow_mut`]: fn.borrow_mut.html
+/// [`IntoLuaMulti`]: ../value/trait.IntoLuaMulti.html
+/// [`FromLua`]: ../value/trait.FromLua.html
+pub trait UserData: Send + Sized + 'static {
+    /// The name of the type in Lua, used by error messages and as the `__name` metafield.
+    const NAME: &'static str;
+
+    /// Adds the methods, metamethods and associated functions of the type.
+    #[inline]
+    fn add_methods(methods: Methods<Self>) -> Methods<Self> {
+        methods
+    }
+}
+
+/// The methods of a [`UserData`] type, set in its metatable the first time
+/// a value of the type is pushed.
+///
+/// Methods whose name starts with `__`, such as `__tostring`, are set as metamethods.
+/// The other methods are found through the `__index` metamethod,
+/// unless the type defines its own.
+///
+/// [`UserData`]: trait.UserData.html
+pub struct Methods<T> {
+    methods: Module,
+    metamethods: Module,
+    functions: Module,
+    marker: PhantomData<fn(&T)>,
+}
+
+impl<T: UserData> Methods<T> {
+    fn new() -> Methods<T> {
+        Methods {
+            methods: Module::new("__index"),
+            metamethods: Module::new(T::NAME),
+            functions: Module::new(T::NAME),
+            marker: PhantomData,
+        }
+    }
+
+    /// Adds a method borrowing the value it is called on.
+    ///
+    /// The other arguments are converted with [`FromLuaMulti`] and the results with
+    /// [`IntoLuaMulti`], like in [`Thread::register_fn`].
+    ///
+    /// [`FromLuaMulti`]: ../value/trait.FromLuaMulti.html
+    /// [`IntoLuaMulti`]: ../value/trait.IntoLuaMulti.html
+    /// [`Thread::register_fn`]: ../thread/struct.Thread.html#method.register_fn
+    pub fn method<A, R, F>(self, name: &str, f: F) -> Methods<T>
+    where
+        A: FromLuaMulti + 'static,
+        R: IntoLuaMulti + 'static,
+        F: Fn(&T, A) -> LuaResult<R> + Send + 'static,
+    {
+        self.add_method(name, move |thread| {
+            let args = method_args(thread)?;
+            let results = f(&*borrow(thread, 1)?, args)?;
+            results.push_multi(Pusher(ThreadRef::from_ref(thread)))
+        })
+    }
+
+    /// Adds a method mutably borrowing the value it is called on.
+    ///
+    /// See [`method`].
+    ///
+    /// [`method`]: #method.method
+    pub fn method_mut<A, R, F>(self, name: &str, f: F) -> Methods<T>
+    where
+        A: FromLuaMulti + 'static,
+        R: IntoLuaMulti + 'static,
+        F: Fn(&mut T, A) -> LuaResult<R> + Send + 'static,
+    {
+        self.add_method(name, move |thread| {
+            let args = method_args(thread)?;
+            let results = f(&mut *borrow_mut(thread, 1)?, args)?;
+            results.push_multi(Pusher(ThreadRef::from_ref(thread)))
+        })
+    }
+
+    /// Adds a C function as a method, the value it is called on being its first argument.
+    pub fn c_method(mut self, name: &str, f: sys::lua_CFunction) -> Methods<T> {
+        if name.starts_with("__") {
+            self.metamethods = self.metamethods.c_function(name, f);
+        } else {
+            self.methods = self.methods.c_function(name, f);
+        }
+        self
+    }
+
+    /// Adds an associated function, such as a constructor, to the table returned by [`class`].
+    ///
+    /// [`class`]: fn.class.html
+    pub fn function<A, R, F>(mut self, name: &str, f: F) -> Methods<T>
+    where
+        A: FromLuaMulti + 'static,
+        R: IntoLuaMulti + 'static,
+        F: Fn(A) -> LuaResult<R> + Send + 'static,
+    {
+        self.functions = self.functions.function(name, f);
+        self
+    }
+
+    /// Adds a C function to the table returned by [`class`].
+    ///
+    /// [`class`]: fn.class.html
+    pub fn c_function(mut self, name: &str, f: sys::lua_CFunction) -> Methods<T> {
+        self.functions = self.functions.c_function(name, f);
+        self
+    }
+
+    fn add_method<F>(mut self, name: &str, callback: F) -> Methods<T>
+    where
+        F: Fn(&mut Thread) -> LuaResult<libc::c_int> + Send + 'static,
+    {
+        let push = Box::new(move |thread: &mut Thread| thread.push_callback(Box::new(callback)));
+        if name.starts_with("__") {
+            self.metamethods = self.metamethods.field(name, push);
+        } else {
+            self.methods = self.methods.field(name, push);
+        }
+        self
+    }
+}
+
+impl<T> fmt::Debug for Methods<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Methods")
+            .field("methods", &self.methods)
+            .field("metamethods", &self.metamethods)
+            .field("functions", &self.functions)
+            .finish()
+    }
+}
+
+/// Converts the arguments of a method, after the value it is called on.
+fn method_args<A: FromLuaMulti>(thread: &mut Thread) -> LuaResult<A> {
+    let nargs = unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) };
+    A::from_lua_multi(thread, 2, (nargs - 1).max(0)).map_err(bad_argument)
+}
+
+/// Returns the table of the associated functions of `T`, named after the type.
+///
+/// It can be set as a global with [`Thread::register_module`],
+/// or added to a module with [`Module::userdata`].
+///
+/// [`Thread::register_module`]: ../thread/struct.Thread.html#method.register_module
+/// [`Module::userdata`]: ../module/struct.Module.html#method.userdata
+pub fn class<T: UserData>() -> Module {
+    T::add_methods(Methods::new()).functions
+}
+
+/// Moves `value` into a new userdata, pushed onto the stack.
+pub fn push<T: UserData>(mut pusher: Pusher, value: T) -> LuaResult<()> {
+    pusher.reserve(2)?;
+    push_metatable::<T>(&mut pusher.0)?;
+    unsafe {
+        let l = pusher.0.as_raw().as_ptr();
+        let ud = sys::lua_newuserdata(l, mem::size_of::<Cell<T>>()) as *mut Cell<T>;
+        ptr::write(ud, Some(Box::new(RefCell::new(value))));
+        sys::lua_insert(l, -2);
+        sys::lua_setmetatable(l, -2);
+    }
+    Ok(())
+}
+
+/// Borrows the value of the userdata at `index`.
+///
+/// Returns an error of kind [`ErrorKind::Conversion`] if the value at `index`
+/// is not a userdata of type `T`, or of kind [`ErrorKind::Runtime`] if it is
+/// already mutably borrowed.
+///
+/// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+/// [`ErrorKind::Runtime`]: ../enum.ErrorKind.html#variant.Runtime
+pub fn borrow<T: UserData>(thread: &mut Thread, index: libc::c_int) -> LuaResult<Ref<'_, T>> {
+    cell::<T>(thread, index)?.try_borrow().map_err(|_| {
+        Error::new(
+            ErrorKind::Runtime,
+            Some(format!("{} is already mutably borrowed", T::NAME)),
+        )
+    })
+}
+
+/// Mutably borrows the value of the userdata at `index`.
+///
+/// Returns an error of kind [`ErrorKind::Conversion`] if the value at `index`
+/// is not a userdata of type `T`, or of kind [`ErrorKind::Runtime`] if it is
+/// already borrowed.
+///
+/// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+/// [`ErrorKind::Runtime`]: ../enum.ErrorKind.html#variant.Runtime
+pub fn borrow_mut<T: UserData>(
+    thread: &mut Thread,
+    index: libc::c_int,
+) -> LuaResult<RefMut<'_, T>> {
+    cell::<T>(thread, index)?.try_borrow_mut().map_err(|_| {
+        Error::new(
+            ErrorKind::Runtime,
+            Some(format!("{} is already borrowed", T::NAME)),
+        )
+    })
+}
+
+/// The value is cloned out of the userdata.
+impl<T: UserData + Clone> FromLua for T {
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<T> {
+        borrow::<T>(thread, index).map(|value| value.clone())
+    }
+}
+
+/// Returns the cell of the userdata of type `T` at `index`.
+fn cell<T: UserData>(thread: &mut Thread, index: libc::c_int) -> LuaResult<&RefCell<T>> {
+    let raw = thread.as_raw();
+    value::reserve(raw, 2)?;
+    let l = raw.as_ptr();
+    if !unsafe { is_userdata::<T>(l, index) } {
+        return Err(value::type_error(thread, index, T::NAME));
+    }
+    let cell = unsafe { &*(sys::lua_touserdata(l, index) as *const Cell<T>) };
+    cell.as_deref().ok_or_else(|| {
+        Error::new(
+            ErrorKind::Runtime,
+            Some(format!("attempt to use a finalized {}", T::NAME)),
+        )
+    })
+}
+
+/// Returns `true` if the value at `index` is a userdata of type `T`.
+///
+/// The stack must have room for 2 more values.
+unsafe fn is_userdata<T: 'static>(l: *mut sys::lua_State, index: libc::c_int) -> bool {
+    let index = sys::lua_absindex(l, index);
+    if sys::lua_type(l, index) == sys::LUA_TUSERDATA && sys::lua_getmetatable(l, index) != 0 {
+        let key = metatable_key::<T>();
+        sys::lua_pushlstring(l, key.as_ptr() as *const libc::c_char, key.len());
+        sys::lua_rawget(l, sys::LUA_REGISTRYINDEX);
+        let matches = sys::lua_rawequal(l, -1, -2) != 0;
+        sys::lua_pop(l, 2);
+        matches
+    } else {
+        false
+    }
+}
+
+/// Returns the registry key of the metatable of `T`.
+fn metatable_key<T: 'static>() -> String {
+    format!("pollua.UserData({:?})", TypeId::of::<T>())
+}
+
+/// Pushes the metatable of `T`, creating it if this is the first value of the type.
+fn push_metatable<T: UserData>(thread: &mut Thread) -> LuaResult<()> {
+    let raw = thread.as_raw();
+    value::reserve(raw, 3)?;
+    let l = raw.as_ptr();
+    let key = metatable_key::<T>();
+    unsafe {
+        sys::lua_pushlstring(l, key.as_ptr() as *const libc::c_char, key.len());
+        if sys::lua_rawget(l, sys::LUA_REGISTRYINDEX) == sys::LUA_TTABLE {
+            return Ok(());
+        }
+        sys::lua_pop(l, 1);
+    }
+
+    let methods = T::add_methods(Methods::new());
+    methods.metamethods.push(thread)?;
+    unsafe {
+        sys::lua_pushstring(l, b"__index\0".as_ptr() as *const _);
+        if sys::lua_rawget(l, -2) == sys::LUA_TNIL {
+            sys::lua_pop(l, 1);
+            methods.methods.push(thread)?;
+            sys::lua_setfield(l, -2, b"__index\0".as_ptr() as *const _);
+        } else {
+            sys::lua_pop(l, 1);
+        }
+        sys::lua_pushlstring(l, T::NAME.as_ptr() as *const libc::c_char, T::NAME.len());
+        sys::lua_setfield(l, -2, b"__name\0".as_ptr() as *const _);
+        sys::lua_pushcfunction(l, Some(drop_userdata::<T>));
+        sys::lua_setfield(l, -2, b"__gc\0".as_ptr() as *const _);
+        // hides the metamethods, which could otherwise be called on other values
+        sys::lua_pushstring(l, b"__metatable\0".as_ptr() as *const _);
+        if sys::lua_rawget(l, -2) == sys::LUA_TNIL {
+            sys::lua_pop(l, 1);
+            sys::lua_pushlstring(l, T::NAME.as_ptr() as *const libc::c_char, T::NAME.len());
+            sys::lua_setfield(l, -2, b"__metatable\0".as_ptr() as *const _);
+        } else {
+            sys::lua_pop(l, 1);
+        }
+
+        sys::lua_pushlstring(l, key.as_ptr() as *const libc::c_char, key.len());
+        sys::lua_pushvalue(l, -2);
+        sys::lua_rawset(l, sys::LUA_REGISTRYINDEX);
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn drop_userdata<T: 'static>(l: *mut sys::lua_State) -> libc::c_int {
+    // the finalizer can still be called on other values through `debug.getmetatable`
+    if is_userdata::<T>(l, 1) {
+        let ud = sys::lua_touserdata(l, 1) as *mut Cell<T>;
+        // the userdata may still be reachable from other finalizers
+        drop((*ud).take());
+    }
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    fn eval(thread: &mut Thread, code: &str) -> LuaResult<String> {
+        thread
+            .caller_load(code, None, LoadingMode::Text)?
+            .call()?
+            .get_as::<String>(0)
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    impl UserData for Point {
+        const NAME: &'static str = "Point";
+
+        fn add_methods(methods: Methods<Point>) -> Methods<Point> {
+            methods
+                .function("new", |(x, y)| Ok(Point { x, y }))
+                .method("norm", |p, ()| Ok(p.x.hypot(p.y)))
+                .method_mut("scale", |p, k: f64| {
+                    p.x *= k;
+                    p.y *= k;
+                    Ok(())
+                })
+                .method("__tostring", |p, ()| Ok(format!("({}, {})", p.x, p.y)))
+        }
+    }
+
+    impl IntoLuaMulti for Point {
+        fn push_multi(self, pusher: Pusher) -> LuaResult<libc::c_int> {
+            push(pusher, self).map(|()| 1)
+        }
+    }
+
+    #[test]
+    fn test_userdata_methods() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            thread.register_module(class::<Point>()).unwrap();
+            let top = stack_top(thread);
+            assert_eq!(
+                eval(
+                    thread,
+                    "local p = Point.new(3, 4) p:scale(2) return ('%.1f %s'):format(p:norm(), tostring(p))"
+                )
+                .unwrap(),
+                "10.0 (6, 8)"
+            );
+
+            let err = eval(thread, "return Point.new(1, 2).scale(42, 2)").unwrap_err();
+            assert!(err.msg().unwrap().contains("expected Point, got number"));
+            let err = eval(thread, "return Point.new(1, 2):scale('x')").unwrap_err();
+            assert!(err.msg().unwrap().contains("bad argument"));
+            assert_eq!(stack_top(thread), top);
+
+            let point = thread
+                .caller_load("return Point.new(1, 2)", None, LoadingMode::Text)
+                .and_then(|c| c.call()?.get_as::<Point>(0))
+                .unwrap();
+            assert_eq!(point, Point { x: 1.0, y: 2.0 });
+        })
+        .unwrap()
+    }
+
+    #[derive(Debug)]
+    struct Name(String);
+
+    impl UserData for Name {
+        const NAME: &'static str = "Name";
+
+        fn add_methods(methods: Methods<Name>) -> Methods<Name> {
+            methods
+                .function("new", |name: String| Ok(Name(name)))
+                .method("get", |n, ()| Ok(n.0.clone()))
+        }
+    }
+
+    impl IntoLuaMulti for Name {
+        fn push_multi(self, pusher: Pusher) -> LuaResult<libc::c_int> {
+            push(pusher, self).map(|()| 1)
+        }
+    }
+
+    #[test]
+    fn test_userdata_metatable_protected() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            thread.register_module(class::<Point>()).unwrap();
+            thread.register_module(class::<Name>()).unwrap();
+            assert_eq!(
+                eval(thread, "return getmetatable(Point.new(1, 2))").unwrap(),
+                "Point"
+            );
+
+            // the finalizer of Point ignores a Name
+            assert_eq!(
+                eval(
+                    thread,
+                    "local p, n = Point.new(1, 2), Name.new('x') \
+                     debug.getmetatable(p).__gc(n) \
+                     debug.getmetatable(p).__gc(42) \
+                     return n:get() .. tostring(p)"
+                )
+                .unwrap(),
+                "x(1, 2)"
+            );
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_userdata_borrow() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            push(
+                Pusher(ThreadRef::from_ref(thread)),
+                Point { x: 1.0, y: 0.0 },
+            )
+            .unwrap();
+            unsafe { sys::lua_pushinteger(thread.as_raw().as_ptr(), 1) };
+            assert_eq!(stack_top(thread), top + 2);
+
+            let err = borrow::<Point>(thread, -1).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+            borrow_mut::<Point>(thread, -2).unwrap().x = 5.0;
+            assert_eq!(borrow::<Point>(thread, -2).unwrap().x, 5.0);
+
+            let index = top + 1;
+            let cell = cell::<Point>(thread, index).unwrap() as *const RefCell<Point>;
+            let _first = unsafe { &*cell }.borrow();
+            let err = borrow_mut::<Point>(thread, index).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Runtime);
+            unsafe { sys::lua_settop(thread.as_raw().as_ptr(), top) };
+        })
+        .unwrap()
+    }
+}
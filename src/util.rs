@@ -36,3 +36,22 @@ pub unsafe fn cstr_unchecked<S: AsRef<[u8]> + ?Sized>(s: Option<&S>) -> *const l
         None => ptr::null(),
     }
 }
+
+/// Pushes the metatable registered as `name`, a nul-terminated string,
+/// creating it with the finalizer `gc` if needed.
+///
+/// Its `__metatable` field hides it from `getmetatable`, but `debug.getmetatable` can still
+/// call `gc` on any value, which must then be checked with `luaL_testudata`.
+/// The stack must have room for 2 more values.
+pub(crate) unsafe fn push_finalized_metatable(
+    l: *mut sys::lua_State,
+    name: &[u8],
+    gc: sys::lua_CFunction,
+) {
+    if sys::luaL_newmetatable(l, name.as_ptr() as *const libc::c_char) != 0 {
+        sys::lua_pushcfunction(l, gc);
+        sys::lua_setfield(l, -2, b"__gc\0".as_ptr() as *const libc::c_char);
+        sys::lua_pushlstring(l, name.as_ptr() as *const libc::c_char, name.len() - 1);
+        sys::lua_setfield(l, -2, b"__metatable\0".as_ptr() as *const libc::c_char);
+    }
+}
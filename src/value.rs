@@ -1,4 +1,8 @@
-use crate::thread::{Thread, ThreadRef};
+use crate::{
+    pattern,
+    thread::{Thread, ThreadRef},
+    Error, ErrorKind, LuaResult,
+};
 
 use std::{
     ascii,
@@ -14,6 +18,20 @@ use std::{
     str::{self, FromStr, Utf8Error},
 };
 
+mod builder;
+mod convert;
+mod metamethod;
+mod owned;
+mod table;
+mod utf8;
+
+pub use builder::*;
+pub use convert::*;
+pub use metamethod::*;
+pub use owned::*;
+pub use table::*;
+pub use utf8::*;
+
 /// Lua value type.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ValueType {
@@ -65,6 +83,20 @@ impl ValueType {
             ValueType::Table => sys::LUA_TTABLE,
         }
     }
+
+    /// Returns the name of this type, as given by the Lua `type` function.
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueType::Nil => "nil",
+            ValueType::Boolean => "boolean",
+            ValueType::Number => "number",
+            ValueType::String => "string",
+            ValueType::Function => "function",
+            ValueType::LightUserdata | ValueType::Userdata => "userdata",
+            ValueType::Thread => "thread",
+            ValueType::Table => "table",
+        }
+    }
 }
 
 /// A type that can be pushed onto the stack.
@@ -97,17 +129,55 @@ pub struct Pusher<'a>(pub(crate) ThreadRef<'a>);
 
 impl Pusher<'_> {
     #[inline]
-    pub fn push<V: Pushable>(self, value: &V) {
-        value.push(self);
+    pub fn push<V: Pushable>(self, value: &V) -> LuaResult<()> {
+        value.push(self)
+    }
+
+    /// Creates a `Pusher` for the same thread, used to push several values.
+    #[inline]
+    pub fn reborrow(&mut self) -> Pusher<'_> {
+        Pusher(ThreadRef::from_ref(&mut self.0))
+    }
+
+    /// Ensures that the stack has room for at least `n` more values.
+    ///
+    /// Returns an error of kind [`ErrorKind::StackOverflow`] if the stack cannot grow.
+    ///
+    /// [`ErrorKind::StackOverflow`]: ../enum.ErrorKind.html#variant.StackOverflow
+    #[inline]
+    pub fn reserve(&mut self, n: usize) -> LuaResult<()> {
+        reserve(self.0.as_raw(), n)
+    }
+}
+
+/// Ensures that the stack of `raw` has room for at least `n` more values.
+pub(crate) fn reserve(raw: NonNull<sys::lua_State>, n: usize) -> LuaResult<()> {
+    if n <= libc::c_int::MAX as usize
+        && unsafe { sys::lua_checkstack(raw.as_ptr(), n as libc::c_int) } != 0
+    {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::StackOverflow,
+            Some(format!("cannot grow the stack by {} values", n)),
+        ))
     }
 }
 
 /// A trait for values that can be pushed onto the stack.
+///
+/// Implementations must [`reserve`] the stack slots they use before pushing.
+///
+/// [`reserve`]: struct.Pusher.html#method.reserve
 pub trait Pushable {
-    fn push(&self, pusher: Pusher);
+    fn push(&self, pusher: Pusher) -> LuaResult<()>;
 }
 
 /// A Lua floating-point number.
+///
+/// Its operators are the ones of `f64`, [`Thread::arith`] applies the operators of Lua.
+///
+/// [`Thread::arith`]: ../thread/struct.Thread.html#method.arith
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub struct LuaNumber {
     value: sys::lua_Number,
@@ -178,20 +248,21 @@ impl From<f64> for LuaNumber {
     }
 }
 
-impl Into<f32> for LuaNumber {
+impl From<LuaNumber> for f32 {
     /// Converts `LuaNumber` to `f64`,
     /// truncation may happen depending on the size of [`lua_sys::lua_Number`].
     #[inline]
-    fn into(self) -> f32 {
-        self.value as f32
+    fn from(n: LuaNumber) -> f32 {
+        n.value as f32
     }
 }
 
-impl Into<f64> for LuaNumber {
+impl From<LuaNumber> for f64 {
     /// Converts `LuaNumber` to `f64` losslessly.
     #[inline]
-    fn into(self) -> f64 {
-        self.value as f64
+    #[allow(clippy::useless_conversion)]
+    fn from(n: LuaNumber) -> f64 {
+        f64::from(n.value)
     }
 }
 
@@ -296,8 +367,10 @@ number_sum!(LuaNumber, Sum, sum, sys::lua_Number);
 
 impl Pushable for LuaNumber {
     #[inline]
-    fn push(&self, mut pusher: Pusher) {
-        unsafe { sys::lua_pushnumber(pusher.0.as_raw().as_mut(), self.value) }
+    fn push(&self, mut pusher: Pusher) -> LuaResult<()> {
+        pusher.reserve(1)?;
+        unsafe { sys::lua_pushnumber(pusher.0.as_raw().as_mut(), self.value) };
+        Ok(())
     }
 }
 
@@ -305,7 +378,7 @@ macro_rules! lua_number_pushable_impl {
     ($type:ty) => {
         impl Pushable for $type {
             #[inline]
-            fn push(&self, pusher: Pusher) {
+            fn push(&self, pusher: Pusher) -> LuaResult<()> {
                 LuaNumber::from(*self).push(pusher)
             }
         }
@@ -339,8 +412,10 @@ impl Value for LuaNil {
 
 impl Pushable for LuaNil {
     #[inline]
-    fn push(&self, mut pusher: Pusher) {
-        unsafe { sys::lua_pushnil(pusher.0.as_raw().as_ptr()) }
+    fn push(&self, mut pusher: Pusher) -> LuaResult<()> {
+        pusher.reserve(1)?;
+        unsafe { sys::lua_pushnil(pusher.0.as_raw().as_ptr()) };
+        Ok(())
     }
 }
 
@@ -397,10 +472,10 @@ impl LuaStr {
     /// function will return the corresponding &str slice. Otherwise,
     /// it will return an error with details of where UTF-8 validation failed.
     ///
-    /// > **Note**: This method is currently implemented to check for validity
-    /// > after a constant-time cast, but it is planned to alter its definition
-    /// > in the future to perform the length calculation in addition to the
-    /// > UTF-8 check whenever this method is called.
+    /// This follows Rust's rules, which unlike Lua's `utf8` library reject surrogates,
+    /// see [`code_points`] for Lua's rules.
+    ///
+    /// [`code_points`]: #method.code_points
     #[inline]
     pub fn to_str(&self) -> Result<&str, Utf8Error> {
         str::from_utf8(self.as_bytes())
@@ -421,9 +496,125 @@ impl LuaStr {
     /// [`String`]: std::string::String
     /// [U+FFFD]: std::char::REPLACEMENT_CHARACTER
     #[inline]
-    pub fn to_string_lossy(&self) -> Cow<str> {
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
         String::from_utf8_lossy(self.as_bytes())
     }
+
+    /// Converts this Lua string to an `OsStr` without checking its encoding.
+    #[cfg(unix)]
+    #[inline]
+    pub fn to_os_str(&self) -> &std::ffi::OsStr {
+        std::os::unix::ffi::OsStrExt::from_bytes(self.as_bytes())
+    }
+
+    /// Returns the offset of the first occurrence of `needle`, which is found at 0 if empty.
+    ///
+    /// The needle is a plain string, see [`find_pattern`] for Lua patterns.
+    ///
+    /// [`find_pattern`]: #method.find_pattern
+    #[inline]
+    pub fn find<B: AsRef<[u8]> + ?Sized>(&self, needle: &B) -> Option<usize> {
+        pattern::find_bytes(self.as_bytes(), needle.as_ref())
+    }
+
+    /// Returns an iterator over the substrings separated by `separator`.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::value::LuaStr;
+    ///
+    /// let fields: Vec<_> = LuaStr::from_bytes("a,b,,c")
+    ///     .split(b',')
+    ///     .map(|field| field.as_bytes())
+    ///     .collect();
+    /// assert_eq!(fields, [&b"a"[..], b"b", b"", b"c"]);
+    /// ```
+    #[inline]
+    pub fn split(&self, separator: u8) -> Split<'_> {
+        Split {
+            rest: Some(self.as_bytes()),
+            separator,
+        }
+    }
+
+    /// Finds the first match of a Lua pattern, see [`pattern::find`].
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::value::LuaStr;
+    ///
+    /// let line = LuaStr::from_bytes("version = 5.3");
+    /// let captures = line.find_pattern("(%d+)%.(%d+)")?.unwrap();
+    /// assert_eq!(captures.range(), 10..13);
+    /// assert_eq!(captures.get(0).unwrap().as_bytes(), Some(&b"5"[..]));
+    /// # Ok::<_, pollua::pattern::PatternError>(())
+    /// ```
+    ///
+    /// [`pattern::find`]: ../pattern/fn.find.html
+    #[inline]
+    pub fn find_pattern<P: AsRef<[u8]> + ?Sized>(
+        &self,
+        pattern: &P,
+    ) -> Result<Option<pattern::Captures<'_>>, pattern::PatternError> {
+        pattern::find(self, pattern)
+    }
+
+    /// Returns an iterator over the matches of a Lua pattern, see [`pattern::gmatch`].
+    ///
+    /// [`pattern::gmatch`]: ../pattern/fn.gmatch.html
+    #[inline]
+    pub fn gmatch<'p, P: AsRef<[u8]> + ?Sized>(&self, pattern: &'p P) -> pattern::GMatch<'_, 'p> {
+        pattern::gmatch(self, pattern)
+    }
+}
+
+/// An iterator over the substrings of a [`LuaStr`] separated by a byte.
+///
+/// This struct is created by the [`split`] method on [`LuaStr`].
+///
+/// [`LuaStr`]: struct.LuaStr.html
+/// [`split`]: struct.LuaStr.html#method.split
+#[derive(Debug, Clone)]
+pub struct Split<'a> {
+    rest: Option<&'a [u8]>,
+    separator: u8,
+}
+
+impl<'a> Iterator for Split<'a> {
+    type Item = &'a LuaStr;
+
+    fn next(&mut self) -> Option<&'a LuaStr> {
+        let rest = self.rest?;
+        match rest.iter().position(|&b| b == self.separator) {
+            Some(i) => {
+                self.rest = Some(&rest[i + 1..]);
+                Some(LuaStr::from_bytes(&rest[..i]))
+            }
+            None => {
+                self.rest = None;
+                Some(LuaStr::from_bytes(rest))
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for Split<'_> {}
+
+/// Borrows the string at `index` without copying it, numbers are not converted.
+///
+/// # Safety
+/// The string must stay anchored, on the stack or in a table, for `'a`.
+pub(crate) unsafe fn borrow_str<'a>(
+    thread: &mut Thread,
+    index: libc::c_int,
+) -> LuaResult<&'a LuaStr> {
+    let l = thread.as_raw().as_ptr();
+    if sys::lua_type(l, index) != sys::LUA_TSTRING {
+        return Err(type_error(thread, index, "string"));
+    }
+    let mut len = 0;
+    let ptr = sys::lua_tolstring(l, index, &mut len);
+    Ok(LuaStr::from_ptr(ptr as *const u8, len))
 }
 
 impl fmt::Debug for LuaStr {
@@ -473,20 +664,21 @@ impl Eq for LuaStr {}
 impl PartialOrd for LuaStr {
     #[inline]
     fn partial_cmp(&self, other: &LuaStr) -> Option<Ordering> {
-        self.as_bytes().partial_cmp(&other.as_bytes())
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for LuaStr {
     #[inline]
     fn cmp(&self, other: &LuaStr) -> Ordering {
-        self.as_bytes().cmp(&other.as_bytes())
+        self.as_bytes().cmp(other.as_bytes())
     }
 }
 
-impl<'a> Pushable for &'a LuaStr {
+impl Pushable for &LuaStr {
     #[inline]
-    fn push(&self, mut pusher: Pusher) {
+    fn push(&self, mut pusher: Pusher) -> LuaResult<()> {
+        pusher.reserve(1)?;
         unsafe {
             sys::lua_pushlstring(
                 pusher.0.as_raw().as_ptr(),
@@ -494,6 +686,7 @@ impl<'a> Pushable for &'a LuaStr {
                 self.repr.0.len(),
             );
         }
+        Ok(())
     }
 }
 
@@ -501,7 +694,7 @@ macro_rules! luastr_push_impl {
     ($type:ty) => {
         impl Pushable for $type {
             #[inline]
-            fn push(&self, pusher: Pusher) {
+            fn push(&self, pusher: Pusher) -> LuaResult<()> {
                 LuaStr::from_bytes(self).push(pusher)
             }
         }
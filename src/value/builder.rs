@@ -0,0 +1,250 @@
+use crate::{
+    thread::{StackGuard, Thread, ThreadRef},
+    value::{reserve, Pusher},
+    LuaResult,
+};
+
+use std::{fmt, io, mem};
+
+/// Stack slots used by `luaL_Buffer` when it grows.
+#[cfg(LUA_VERSION = "5.2")]
+const BUFFER_SLOTS: usize = 4;
+
+/// Stack slots used by `luaL_Buffer` when it grows.
+///
+/// Lua 5.1 keeps up to `LUA_MINSTACK / 2` pieces of the string on the stack before merging them.
+#[cfg(not(LUA_VERSION = "5.2"))]
+const BUFFER_SLOTS: usize = sys::LUA_MINSTACK as usize / 2 + 1;
+
+/// Builds a Lua string in place with a `luaL_Buffer`, without copying it into a Rust `Vec` first.
+///
+/// The buffer uses the top of the stack, which is why the builder borrows the thread mutably.
+/// [`finish`] pushes the string, dropping the builder instead discards it.
+///
+/// # Examples
+/// ```
+/// use pollua::{
+///     thread::{LoadingMode, Thread},
+///     value::{Pushable, Pusher},
+///     LuaResult,
+/// };
+/// use std::fmt::Write;
+///
+/// /// A list rendered directly into a Lua string.
+/// struct Report(Vec<(&'static str, u32)>);
+///
+/// impl Pushable for Report {
+///     fn push(&self, pusher: Pusher) -> LuaResult<()> {
+///         let mut builder = pusher.string_builder()?;
+///         for (name, score) in &self.0 {
+///             writeln!(builder, "{}: {}", name, score).unwrap();
+///         }
+///         builder.finish();
+///         Ok(())
+///     }
+/// }
+///
+/// Thread::spawn(move |thread| {
+///     thread.register_fn("report", |()| Ok(Report(vec![("ana", 3), ("bo", 5)])))?;
+///     let values = thread
+///         .caller_load("return report()", None, LoadingMode::Text)?
+///         .call()?;
+///     assert_eq!(values.get_as::<String>(0)?, "ana: 3\nbo: 5\n");
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+///
+/// [`finish`]: #method.finish
+pub struct LuaStringBuilder<'a> {
+    thread: StackGuard<'a>,
+    // boxed since the buffer may point to itself
+    buffer: Box<sys::luaL_Buffer>,
+    len: usize,
+}
+
+impl<'a> LuaStringBuilder<'a> {
+    /// Creates an empty builder using the stack of `thread`.
+    #[inline]
+    pub fn new(thread: &'a mut Thread) -> LuaResult<LuaStringBuilder<'a>> {
+        LuaStringBuilder::from_ref(ThreadRef::from_ref(thread))
+    }
+
+    fn from_ref(thread: ThreadRef<'a>) -> LuaResult<LuaStringBuilder<'a>> {
+        let mut thread = StackGuard::from_ref(thread);
+        let raw = thread.as_raw();
+        reserve(raw, BUFFER_SLOTS)?;
+        let mut buffer: Box<sys::luaL_Buffer> = Box::new(unsafe { mem::zeroed() });
+        unsafe { sys::luaL_buffinit(raw.as_ptr(), &mut *buffer) };
+        Ok(LuaStringBuilder {
+            thread,
+            buffer,
+            len: 0,
+        })
+    }
+
+    /// Creates an empty builder with room for at least `capacity` bytes.
+    pub fn with_capacity(
+        thread: &'a mut Thread,
+        capacity: usize,
+    ) -> LuaResult<LuaStringBuilder<'a>> {
+        let mut builder = LuaStringBuilder::new(thread)?;
+        builder.reserve(capacity);
+        Ok(builder)
+    }
+
+    /// Returns the number of bytes written so far.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if nothing was written yet.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Ensures that at least `additional` more bytes can be written without growing the buffer.
+    ///
+    /// Does nothing with Lua 5.1, whose buffers grow in fixed steps.
+    #[inline]
+    #[cfg(LUA_VERSION = "5.2")]
+    pub fn reserve(&mut self, additional: usize) {
+        unsafe { sys::luaL_prepbuffsize(&mut *self.buffer, additional) };
+    }
+
+    /// Ensures that at least `additional` more bytes can be written without growing the buffer.
+    ///
+    /// Does nothing with Lua 5.1, whose buffers grow in fixed steps.
+    #[inline]
+    #[cfg(not(LUA_VERSION = "5.2"))]
+    pub fn reserve(&mut self, _additional: usize) {}
+
+    /// Appends `bytes` to the string.
+    #[inline]
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        unsafe {
+            sys::luaL_addlstring(
+                &mut *self.buffer,
+                bytes.as_ptr() as *const libc::c_char,
+                bytes.len(),
+            )
+        };
+        self.len += bytes.len();
+    }
+
+    /// Appends `s` to the string.
+    #[inline]
+    pub fn push_str(&mut self, s: &str) {
+        self.push_bytes(s.as_bytes());
+    }
+
+    /// Pushes the string onto the stack, where it stays after the builder is consumed.
+    pub fn finish(self) {
+        let LuaStringBuilder {
+            thread, mut buffer, ..
+        } = self;
+        unsafe { sys::luaL_pushresult(&mut *buffer) };
+        // the string replaces the values used by the buffer
+        mem::forget(thread);
+    }
+}
+
+impl io::Write for LuaStringBuilder<'_> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push_bytes(buf);
+        Ok(buf.len())
+    }
+
+    #[inline]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.push_bytes(buf);
+        Ok(())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Write for LuaStringBuilder<'_> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LuaStringBuilder<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LuaStringBuilder")
+            .field("thread", &self.thread)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<'a> Pusher<'a> {
+    /// Creates a [`LuaStringBuilder`] whose [`finish`] method pushes the string,
+    /// to push generated text without copying it.
+    ///
+    /// [`LuaStringBuilder`]: struct.LuaStringBuilder.html
+    /// [`finish`]: struct.LuaStringBuilder.html#method.finish
+    #[inline]
+    pub fn string_builder(self) -> LuaResult<LuaStringBuilder<'a>> {
+        LuaStringBuilder::from_ref(self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write as _;
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    #[test]
+    fn test_string_builder() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            let line = "0123456789abcdef".repeat(64);
+            {
+                let mut builder = LuaStringBuilder::with_capacity(thread, 16).unwrap();
+                for i in 0..1024 {
+                    writeln!(builder, "{} {}", i, line).unwrap();
+                }
+                builder.push_bytes(b"\0end");
+                assert!(builder.len() > 1024 * 1024);
+                // dropping discards the buffer
+            }
+            assert_eq!(stack_top(thread), top);
+
+            let mut builder = LuaStringBuilder::new(thread).unwrap();
+            assert!(builder.is_empty());
+            for i in 0..1024 {
+                builder.push_str(&i.to_string());
+                builder.push_str(" ");
+                builder.push_str(&line);
+                builder.push_str("\n");
+            }
+            builder.push_bytes(b"\0end");
+            let len = builder.len();
+            builder.finish();
+            assert_eq!(stack_top(thread), top + 1);
+
+            let raw = thread.as_raw().as_ptr();
+            let mut actual = 0;
+            let ptr = unsafe { sys::lua_tolstring(raw, -1, &mut actual) };
+            assert_eq!(actual, len);
+            let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, actual) };
+            assert!(bytes.starts_with(format!("0 {}\n1 ", line).as_bytes()));
+            assert!(bytes.ends_with(format!("1023 {}\n\0end", line).as_bytes()));
+            unsafe { sys::lua_pop(raw, 1) };
+        })
+        .unwrap()
+    }
+}
@@ -0,0 +1,489 @@
+use crate::{
+    thread::Thread,
+    value::{reserve, LightUserdata, LuaNil, LuaNumber, Pushable, Pusher},
+    Error, ErrorKind, LuaResult,
+};
+
+use std::{
+    convert::TryFrom,
+    ffi::CStr,
+    iter::FromIterator,
+    ops::{Deref, DerefMut},
+    slice,
+};
+
+/// A trait for values that can be created from a Lua value.
+pub trait FromLua: Sized {
+    /// Converts the value at the absolute stack `index` without popping it.
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<Self>;
+}
+
+/// A trait for values that push any number of Lua values onto the stack,
+/// such as the arguments of a function call.
+///
+/// It is implemented for every [`Pushable`] value, for [`Variadic`],
+/// and for tuples of up to 16 elements.
+///
+/// [`Pushable`]: trait.Pushable.html
+/// [`Variadic`]: struct.Variadic.html
+pub trait IntoLuaMulti {
+    /// Pushes the values and returns how many of them were pushed.
+    fn push_multi(self, pusher: Pusher) -> LuaResult<libc::c_int>;
+}
+
+/// A trait for values that can be created from a sequence of Lua values,
+/// such as the results of a function call.
+///
+/// It is implemented for every [`FromLua`] value, for [`Variadic`],
+/// and for tuples of up to 16 elements.
+/// Missing values are converted from `nil` and extra values are ignored.
+///
+/// [`FromLua`]: trait.FromLua.html
+/// [`Variadic`]: struct.Variadic.html
+pub trait FromLuaMulti: Sized {
+    /// Converts the `count` values starting at the absolute stack index `start`.
+    fn from_lua_multi(
+        thread: &mut Thread,
+        start: libc::c_int,
+        count: libc::c_int,
+    ) -> LuaResult<Self>;
+}
+
+/// Any number of values of the same type.
+///
+/// Used to pass or receive a variable number of arguments and results,
+/// like `...` in Lua.
+///
+/// # Examples
+/// ```
+/// use pollua::{thread::{LoadingMode, Thread}, value::Variadic};
+///
+/// Thread::spawn(move |thread| {
+///     let values = thread
+///         .caller_load("return ...", None, LoadingMode::Text)?
+///         .args(Variadic(vec![1.0, 2.0, 3.0]))
+///         .call()?;
+///     let Variadic(numbers) = values.unpack::<Variadic<f64>>()?;
+///     assert_eq!(numbers, [1.0, 2.0, 3.0]);
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Variadic<T>(pub Vec<T>);
+
+impl<T> Variadic<T> {
+    /// Creates an empty `Variadic`.
+    #[inline]
+    pub fn new() -> Variadic<T> {
+        Variadic(Vec::new())
+    }
+}
+
+impl<T> Deref for Variadic<T> {
+    type Target = Vec<T>;
+
+    #[inline]
+    fn deref(&self) -> &Vec<T> {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Variadic<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.0
+    }
+}
+
+impl<T> From<Vec<T>> for Variadic<T> {
+    #[inline]
+    fn from(values: Vec<T>) -> Variadic<T> {
+        Variadic(values)
+    }
+}
+
+impl<T> FromIterator<T> for Variadic<T> {
+    #[inline]
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Variadic<T> {
+        Variadic(Vec::from_iter(iter))
+    }
+}
+
+impl<T> IntoIterator for Variadic<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Returns the error for a value of the wrong type at `index`.
+pub(crate) fn type_error(thread: &mut Thread, index: libc::c_int, expected: &str) -> Error {
+    let got = unsafe { CStr::from_ptr(sys::luaL_typename(thread.as_raw().as_ptr(), index)) };
+    Error::new(
+        ErrorKind::Conversion,
+        Some(format!(
+            "expected {}, got {}",
+            expected,
+            got.to_string_lossy()
+        )),
+    )
+}
+
+/// Converts the `n`th value of a sequence, or `nil` if the sequence is too short.
+pub(crate) fn from_lua_nth<T: FromLua>(
+    thread: &mut Thread,
+    start: libc::c_int,
+    count: libc::c_int,
+    n: libc::c_int,
+) -> LuaResult<T> {
+    if n < count {
+        T::from_lua(thread, start + n)
+    } else {
+        let raw = thread.as_raw();
+        reserve(raw, 1)?;
+        unsafe { sys::lua_pushnil(raw.as_ptr()) };
+        let index = unsafe { sys::lua_gettop(raw.as_ptr()) };
+        let value = T::from_lua(thread, index);
+        unsafe { sys::lua_pop(raw.as_ptr(), 1) };
+        value
+    }
+}
+
+impl FromLua for LuaNil {
+    #[inline]
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<LuaNil> {
+        if unsafe { sys::lua_isnil(thread.as_raw().as_ptr(), index) } != 0 {
+            Ok(LuaNil)
+        } else {
+            Err(type_error(thread, index, "nil"))
+        }
+    }
+}
+
+impl Pushable for bool {
+    #[inline]
+    fn push(&self, mut pusher: Pusher) -> LuaResult<()> {
+        pusher.reserve(1)?;
+        unsafe { sys::lua_pushboolean(pusher.0.as_raw().as_ptr(), *self as libc::c_int) };
+        Ok(())
+    }
+}
+
+/// Uses the truthiness of the value: only `nil` and `false` are converted to `false`.
+impl FromLua for bool {
+    #[inline]
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<bool> {
+        Ok(unsafe { sys::lua_toboolean(thread.as_raw().as_ptr(), index) } != 0)
+    }
+}
+
+/// Numbers and strings convertible to numbers are accepted.
+impl FromLua for LuaNumber {
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<LuaNumber> {
+        let mut isnum = 0;
+        let n = unsafe { sys::lua_tonumberx(thread.as_raw().as_ptr(), index, &mut isnum) };
+        if isnum != 0 {
+            Ok(LuaNumber::from(n))
+        } else {
+            Err(type_error(thread, index, "number"))
+        }
+    }
+}
+
+macro_rules! lua_number_from_lua_impl {
+    ($type:ty) => {
+        impl FromLua for $type {
+            #[inline]
+            fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<$type> {
+                LuaNumber::from_lua(thread, index).map(<$type>::from)
+            }
+        }
+    };
+}
+
+lua_number_from_lua_impl!(f32);
+lua_number_from_lua_impl!(f64);
+
+macro_rules! lua_integer_impl {
+    ($($type:ty),*) => {$(
+        impl Pushable for $type {
+            fn push(&self, mut pusher: Pusher) -> LuaResult<()> {
+                let n = sys::lua_Integer::try_from(*self).map_err(|_| {
+                    Error::new(
+                        ErrorKind::Conversion,
+                        Some(format!("{} does not fit in a Lua integer", self)),
+                    )
+                })?;
+                pusher.reserve(1)?;
+                unsafe { sys::lua_pushinteger(pusher.0.as_raw().as_ptr(), n) };
+                Ok(())
+            }
+        }
+
+        /// Numbers and strings with an exact integer representation are accepted.
+        impl FromLua for $type {
+            fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<$type> {
+                let mut isnum = 0;
+                let n =
+                    unsafe { sys::lua_tointegerx(thread.as_raw().as_ptr(), index, &mut isnum) };
+                if isnum == 0 {
+                    return Err(type_error(thread, index, "integer"));
+                }
+                <$type>::try_from(n).map_err(|_| {
+                    Error::new(
+                        ErrorKind::Conversion,
+                        Some(format!(
+                            "{} is out of range for {}",
+                            n,
+                            stringify!($type)
+                        )),
+                    )
+                })
+            }
+        }
+    )*};
+}
+
+lua_integer_impl!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+/// Returns the bytes of the string at `index`, numbers are converted without
+/// modifying the stack value.
+fn string_bytes(thread: &mut Thread, index: libc::c_int) -> LuaResult<Vec<u8>> {
+    unsafe fn to_bytes(raw: *mut sys::lua_State, index: libc::c_int) -> Vec<u8> {
+        let mut len = 0usize;
+        let s = sys::lua_tolstring(raw, index, &mut len);
+        slice::from_raw_parts(s as *const u8, len).to_vec()
+    }
+
+    let raw = thread.as_raw();
+    unsafe {
+        match sys::lua_type(raw.as_ptr(), index) {
+            sys::LUA_TSTRING => Ok(to_bytes(raw.as_ptr(), index)),
+            sys::LUA_TNUMBER => {
+                // lua_tolstring would change the stack value to a string, convert a copy instead
+                reserve(raw, 1)?;
+                sys::lua_pushvalue(raw.as_ptr(), index);
+                let bytes = to_bytes(raw.as_ptr(), -1);
+                sys::lua_pop(raw.as_ptr(), 1);
+                Ok(bytes)
+            }
+            _ => Err(type_error(thread, index, "string")),
+        }
+    }
+}
+
+impl FromLua for Vec<u8> {
+    #[inline]
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<Vec<u8>> {
+        string_bytes(thread, index)
+    }
+}
+
+impl FromLua for String {
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<String> {
+        String::from_utf8(string_bytes(thread, index)?)
+            .map_err(|e| Error::new(ErrorKind::Conversion, Some(e.to_string())))
+    }
+}
+
+impl<T> Pushable for LightUserdata<T> {
+    #[inline]
+    fn push(&self, mut pusher: Pusher) -> LuaResult<()> {
+        pusher.reserve(1)?;
+        unsafe { sys::lua_pushlightuserdata(pusher.0.as_raw().as_ptr(), self.as_ptr() as *mut _) };
+        Ok(())
+    }
+}
+
+impl<T> FromLua for LightUserdata<T> {
+    #[inline]
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<LightUserdata<T>> {
+        let raw = thread.as_raw().as_ptr();
+        if unsafe { sys::lua_type(raw, index) } == sys::LUA_TLIGHTUSERDATA {
+            Ok(LightUserdata::new(
+                unsafe { sys::lua_touserdata(raw, index) } as *mut T,
+            ))
+        } else {
+            Err(type_error(thread, index, "light userdata"))
+        }
+    }
+}
+
+/// `None` is pushed as `nil`.
+impl<T: Pushable> Pushable for Option<T> {
+    #[inline]
+    fn push(&self, pusher: Pusher) -> LuaResult<()> {
+        match self {
+            Some(value) => value.push(pusher),
+            None => LuaNil.push(pusher),
+        }
+    }
+}
+
+/// `nil` is converted to `None`.
+impl<T: FromLua> FromLua for Option<T> {
+    #[inline]
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<Option<T>> {
+        if unsafe { sys::lua_isnil(thread.as_raw().as_ptr(), index) } != 0 {
+            Ok(None)
+        } else {
+            T::from_lua(thread, index).map(Some)
+        }
+    }
+}
+
+impl<T: Pushable> IntoLuaMulti for T {
+    #[inline]
+    fn push_multi(self, pusher: Pusher) -> LuaResult<libc::c_int> {
+        self.push(pusher).map(|_| 1)
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for T {
+    #[inline]
+    fn from_lua_multi(thread: &mut Thread, start: libc::c_int, count: libc::c_int) -> LuaResult<T> {
+        from_lua_nth(thread, start, count, 0)
+    }
+}
+
+impl<T: Pushable> IntoLuaMulti for Variadic<T> {
+    fn push_multi(self, mut pusher: Pusher) -> LuaResult<libc::c_int> {
+        pusher.reserve(self.len())?;
+        for value in &self.0 {
+            value.push(pusher.reborrow())?;
+        }
+        Ok(self.len() as libc::c_int)
+    }
+}
+
+impl<T: FromLua> FromLuaMulti for Variadic<T> {
+    fn from_lua_multi(
+        thread: &mut Thread,
+        start: libc::c_int,
+        count: libc::c_int,
+    ) -> LuaResult<Variadic<T>> {
+        (0..count.max(0))
+            .map(|n| T::from_lua(thread, start + n))
+            .collect()
+    }
+}
+
+impl IntoLuaMulti for () {
+    #[inline]
+    fn push_multi(self, _: Pusher) -> LuaResult<libc::c_int> {
+        Ok(0)
+    }
+}
+
+impl FromLuaMulti for () {
+    #[inline]
+    fn from_lua_multi(_: &mut Thread, _: libc::c_int, _: libc::c_int) -> LuaResult<()> {
+        Ok(())
+    }
+}
+
+/// Implements the multi-value traits for tuples,
+/// the last element of a tuple may itself hold several values.
+macro_rules! tuple_multi_impl {
+    ($($name:ident),* ; $last:ident) => {
+        impl<$($name: Pushable,)* $last: IntoLuaMulti> IntoLuaMulti for ($($name,)* $last,) {
+            #[allow(non_snake_case, unused_mut)]
+            fn push_multi(self, mut pusher: Pusher) -> LuaResult<libc::c_int> {
+                let ($($name,)* $last,) = self;
+                let mut count = 0;
+                $(
+                    $name.push(pusher.reborrow())?;
+                    count += 1;
+                )*
+                Ok(count + $last.push_multi(pusher)?)
+            }
+        }
+
+        impl<$($name: FromLua,)* $last: FromLuaMulti> FromLuaMulti for ($($name,)* $last,) {
+            #[allow(non_snake_case, unused_mut, unused_assignments)]
+            fn from_lua_multi(
+                thread: &mut Thread,
+                start: libc::c_int,
+                count: libc::c_int,
+            ) -> LuaResult<Self> {
+                let mut n = 0;
+                $(
+                    let $name = from_lua_nth::<$name>(thread, start, count, n)?;
+                    n += 1;
+                )*
+                let $last = $last::from_lua_multi(thread, start + n, (count - n).max(0))?;
+                Ok(($($name,)* $last,))
+            }
+        }
+    };
+}
+
+macro_rules! tuple_multi_impls {
+    ($($name:ident),*) => {
+        tuple_multi_impls!(@step [] $($name)*);
+    };
+    (@step [$($done:ident)*] $next:ident $($rest:ident)*) => {
+        tuple_multi_impl!($($done),* ; $next);
+        tuple_multi_impls!(@step [$($done)* $next] $($rest)*);
+    };
+    (@step [$($done:ident)*]) => {};
+}
+
+tuple_multi_impls!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+
+    #[test]
+    fn test_multi_args() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let values = thread
+                .caller_load("return select('#', ...), ...", None, LoadingMode::Text)
+                .unwrap()
+                .args((1.5, "two", true, LuaNil, Variadic(vec![5, 6])))
+                .call()
+                .unwrap();
+            assert_eq!(values.len(), 7);
+            let (count, a, b, c, d, rest): (i32, f64, String, bool, Option<f64>, Variadic<u8>) =
+                values.unpack().unwrap();
+            assert_eq!(count, 6);
+            assert_eq!(a, 1.5);
+            assert_eq!(b, "two");
+            assert!(c);
+            assert_eq!(d, None);
+            assert_eq!(rest.0, [5, 6]);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_multi_missing_values() {
+        Thread::spawn(move |thread| {
+            let values = thread
+                .caller_load("return 1, 2", None, LoadingMode::Text)
+                .and_then(|c| c.call())
+                .unwrap();
+            let (a, b, c, d): (u32, String, Option<String>, Variadic<f64>) =
+                values.unpack().unwrap();
+            assert_eq!((a, b.as_str(), c, d.len()), (1, "2", None, 0));
+            assert_eq!(values.get_as::<i64>(1).unwrap(), 2);
+            assert_eq!(
+                values.unpack::<(f64, f64, f64)>().unwrap_err().kind(),
+                ErrorKind::Conversion
+            );
+            assert_eq!(
+                values.get_as::<String>(2).unwrap_err().kind(),
+                ErrorKind::Conversion
+            );
+            // numbers converted to strings stay numbers
+            assert_eq!(values[1], crate::value::ValueType::Number);
+        })
+        .unwrap()
+    }
+}
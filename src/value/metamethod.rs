@@ -0,0 +1,183 @@
+use std::{fmt, str::FromStr};
+
+/// The events of metatables, and the fields used by the standard library.
+///
+/// # Examples
+/// ```
+/// use pollua::value::Metamethod;
+///
+/// assert_eq!(Metamethod::NewIndex.name(), "__newindex");
+/// assert_eq!("__idiv".parse(), Ok(Metamethod::IDiv));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Metamethod {
+    /// `__index`, looking up a key that is absent from the table.
+    Index,
+    /// `__newindex`, assigning a key that is absent from the table.
+    NewIndex,
+    /// `__call`, calling the value.
+    Call,
+    /// `__gc`, finalizer.
+    Gc,
+    /// `__mode`, weakness of the table keys and values.
+    Mode,
+    /// `__len`, the `#` operator.
+    Len,
+    /// `__eq`, the `==` operator.
+    Eq,
+    /// `__lt`, the `<` operator.
+    Lt,
+    /// `__le`, the `<=` operator.
+    Le,
+    /// `__add`, the `+` operator.
+    Add,
+    /// `__sub`, the `-` operator.
+    Sub,
+    /// `__mul`, the `*` operator.
+    Mul,
+    /// `__div`, the `/` operator.
+    Div,
+    /// `__mod`, the `%` operator.
+    Mod,
+    /// `__pow`, the `^` operator.
+    Pow,
+    /// `__unm`, the unary `-` operator.
+    Unm,
+    /// `__idiv`, the `//` operator.
+    IDiv,
+    /// `__band`, the `&` operator.
+    BAnd,
+    /// `__bor`, the `|` operator.
+    BOr,
+    /// `__bxor`, the binary `~` operator.
+    BXor,
+    /// `__bnot`, the unary `~` operator.
+    BNot,
+    /// `__shl`, the `<<` operator.
+    Shl,
+    /// `__shr`, the `>>` operator.
+    Shr,
+    /// `__concat`, the `..` operator.
+    Concat,
+    /// `__close`, closing a to-be-closed variable.
+    #[cfg(LUA_VERSION = "5.4")]
+    Close,
+    /// `__tostring`, used by `tostring`.
+    ToString,
+    /// `__name`, the type name used by `tostring` and error messages.
+    Name,
+    /// `__pairs`, used by `pairs`.
+    Pairs,
+    /// `__metatable`, the value returned by `getmetatable`, which also protects the metatable.
+    Metatable,
+}
+
+impl Metamethod {
+    /// Every metamethod, in the order of the declaration.
+    pub const ALL: &'static [Metamethod] = &[
+        Metamethod::Index,
+        Metamethod::NewIndex,
+        Metamethod::Call,
+        Metamethod::Gc,
+        Metamethod::Mode,
+        Metamethod::Len,
+        Metamethod::Eq,
+        Metamethod::Lt,
+        Metamethod::Le,
+        Metamethod::Add,
+        Metamethod::Sub,
+        Metamethod::Mul,
+        Metamethod::Div,
+        Metamethod::Mod,
+        Metamethod::Pow,
+        Metamethod::Unm,
+        Metamethod::IDiv,
+        Metamethod::BAnd,
+        Metamethod::BOr,
+        Metamethod::BXor,
+        Metamethod::BNot,
+        Metamethod::Shl,
+        Metamethod::Shr,
+        Metamethod::Concat,
+        #[cfg(LUA_VERSION = "5.4")]
+        Metamethod::Close,
+        Metamethod::ToString,
+        Metamethod::Name,
+        Metamethod::Pairs,
+        Metamethod::Metatable,
+    ];
+
+    /// Returns the name of the metatable field, such as `"__index"`.
+    #[inline]
+    pub fn name(self) -> &'static str {
+        let name = self.c_name();
+        unsafe { std::str::from_utf8_unchecked(&name[..name.len() - 1]) }
+    }
+
+    /// Returns the name of the metatable field, with a trailing nul byte.
+    pub(crate) fn c_name(self) -> &'static [u8] {
+        match self {
+            Metamethod::Index => b"__index\0",
+            Metamethod::NewIndex => b"__newindex\0",
+            Metamethod::Call => b"__call\0",
+            Metamethod::Gc => b"__gc\0",
+            Metamethod::Mode => b"__mode\0",
+            Metamethod::Len => b"__len\0",
+            Metamethod::Eq => b"__eq\0",
+            Metamethod::Lt => b"__lt\0",
+            Metamethod::Le => b"__le\0",
+            Metamethod::Add => b"__add\0",
+            Metamethod::Sub => b"__sub\0",
+            Metamethod::Mul => b"__mul\0",
+            Metamethod::Div => b"__div\0",
+            Metamethod::Mod => b"__mod\0",
+            Metamethod::Pow => b"__pow\0",
+            Metamethod::Unm => b"__unm\0",
+            Metamethod::IDiv => b"__idiv\0",
+            Metamethod::BAnd => b"__band\0",
+            Metamethod::BOr => b"__bor\0",
+            Metamethod::BXor => b"__bxor\0",
+            Metamethod::BNot => b"__bnot\0",
+            Metamethod::Shl => b"__shl\0",
+            Metamethod::Shr => b"__shr\0",
+            Metamethod::Concat => b"__concat\0",
+            #[cfg(LUA_VERSION = "5.4")]
+            Metamethod::Close => b"__close\0",
+            Metamethod::ToString => b"__tostring\0",
+            Metamethod::Name => b"__name\0",
+            Metamethod::Pairs => b"__pairs\0",
+            Metamethod::Metatable => b"__metatable\0",
+        }
+    }
+}
+
+impl fmt::Display for Metamethod {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The error returned when parsing an unknown metamethod name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMetamethodError(());
+
+impl fmt::Display for ParseMetamethodError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("unknown metamethod")
+    }
+}
+
+impl std::error::Error for ParseMetamethodError {}
+
+impl FromStr for Metamethod {
+    type Err = ParseMetamethodError;
+
+    fn from_str(s: &str) -> Result<Metamethod, ParseMetamethodError> {
+        Metamethod::ALL
+            .iter()
+            .copied()
+            .find(|m| m.name() == s)
+            .ok_or(ParseMetamethodError(()))
+    }
+}
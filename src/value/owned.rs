@@ -0,0 +1,548 @@
+use crate::{
+    thread::{StackGuard, Thread, ThreadRef},
+    value::{
+        reserve, FromLua, LightUserdata, LuaNil, LuaNumber, LuaStr, Pushable, Pusher, ValueType,
+    },
+    Error, ErrorKind, LuaResult,
+};
+
+use std::{ffi::CStr, fmt, ptr::NonNull, slice};
+
+/// A deep copy of a Lua value that does not depend on any [`Thread`].
+///
+/// Tables are copied recursively as their list of key-value pairs, in traversal order.
+/// Functions, full userdata and threads cannot be copied
+/// and are replaced by opaque markers that cannot be pushed back,
+/// use [`OwnedValue::transferable`] to copy them when possible.
+///
+/// # Examples
+/// ```
+/// use pollua::{thread::{LoadingMode, Thread}, value::OwnedValue};
+///
+/// let value = Thread::spawn(move |thread| {
+///     let values = thread
+///         .caller_load("return { 1, 2, answer = 42 }", None, LoadingMode::Text)?
+///         .call()?;
+///     values.get_as::<OwnedValue>(0)
+/// }).unwrap().unwrap();
+///
+/// Thread::spawn(move |thread| {
+///     let values = thread
+///         .caller_load("return ...", None, LoadingMode::Text)?
+///         .arg(value.clone())
+///         .call()?;
+///     assert_eq!(values.get_as::<OwnedValue>(0)?, value);
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+///
+/// [`Thread`]: ../thread/struct.Thread.html
+/// [`OwnedValue::transferable`]: #method.transferable
+#[derive(Clone)]
+pub enum OwnedValue {
+    Nil,
+    Boolean(bool),
+    Integer(sys::lua_Integer),
+    Number(LuaNumber),
+    String(Vec<u8>),
+    LightUserdata(*mut libc::c_void),
+    Table(Vec<(OwnedValue, OwnedValue)>),
+    /// A Lua function dumped as a binary chunk.
+    Bytecode(Bytecode),
+    /// A function, which cannot be copied.
+    Function,
+    /// A full userdata, which cannot be copied.
+    Userdata,
+    /// A coroutine, which cannot be copied.
+    Thread,
+}
+
+/// A Lua function dumped as a binary chunk, only created by copying a Lua function.
+///
+/// Lua does not check binary chunks when loading them, so the bytes cannot be set from Rust:
+/// loading a malformed chunk is undefined behavior.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Bytecode(Vec<u8>);
+
+impl Bytecode {
+    /// Returns the binary chunk.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Bytecode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "function: {} bytes of bytecode", self.0.len())
+    }
+}
+
+impl OwnedValue {
+    /// Returns the type of the original value.
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            OwnedValue::Nil => ValueType::Nil,
+            OwnedValue::Boolean(_) => ValueType::Boolean,
+            OwnedValue::Integer(_) | OwnedValue::Number(_) => ValueType::Number,
+            OwnedValue::String(_) => ValueType::String,
+            OwnedValue::LightUserdata(_) => ValueType::LightUserdata,
+            OwnedValue::Table(_) => ValueType::Table,
+            OwnedValue::Bytecode(_) | OwnedValue::Function => ValueType::Function,
+            OwnedValue::Userdata => ValueType::Userdata,
+            OwnedValue::Thread => ValueType::Thread,
+        }
+    }
+
+    /// Returns `true` if this is a function, userdata or thread marker.
+    #[inline]
+    pub fn is_opaque(&self) -> bool {
+        matches!(
+            self,
+            OwnedValue::Function | OwnedValue::Userdata | OwnedValue::Thread
+        )
+    }
+}
+
+// light userdata pointers are only compared and pushed, never dereferenced
+unsafe impl Send for OwnedValue {}
+unsafe impl Sync for OwnedValue {}
+
+impl Default for OwnedValue {
+    #[inline]
+    fn default() -> OwnedValue {
+        OwnedValue::Nil
+    }
+}
+
+/// Tables are equal if they have the same pairs, in any order.
+impl PartialEq for OwnedValue {
+    fn eq(&self, other: &OwnedValue) -> bool {
+        match (self, other) {
+            (OwnedValue::Nil, OwnedValue::Nil) => true,
+            (OwnedValue::Boolean(a), OwnedValue::Boolean(b)) => a == b,
+            (OwnedValue::Integer(a), OwnedValue::Integer(b)) => a == b,
+            (OwnedValue::Number(a), OwnedValue::Number(b)) => a == b,
+            (OwnedValue::String(a), OwnedValue::String(b)) => a == b,
+            (OwnedValue::LightUserdata(a), OwnedValue::LightUserdata(b)) => a == b,
+            (OwnedValue::Table(a), OwnedValue::Table(b)) => {
+                // keys are unique, so finding every pair of `a` in `b` is enough
+                a.len() == b.len() && a.iter().all(|pair| b.contains(pair))
+            }
+            (OwnedValue::Bytecode(a), OwnedValue::Bytecode(b)) => a == b,
+            (OwnedValue::Function, OwnedValue::Function) => true,
+            (OwnedValue::Userdata, OwnedValue::Userdata) => true,
+            (OwnedValue::Thread, OwnedValue::Thread) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for OwnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OwnedValue::Nil => fmt::Display::fmt(&LuaNil, f),
+            OwnedValue::Boolean(b) => fmt::Debug::fmt(b, f),
+            OwnedValue::Integer(n) => fmt::Debug::fmt(n, f),
+            OwnedValue::Number(n) => fmt::Debug::fmt(&n.value, f),
+            OwnedValue::String(s) => fmt::Debug::fmt(LuaStr::from_bytes(s), f),
+            OwnedValue::LightUserdata(p) => write!(f, "lightuserdata: {:p}", p),
+            OwnedValue::Table(pairs) => f
+                .debug_map()
+                .entries(pairs.iter().map(|(k, v)| (k, v)))
+                .finish(),
+            OwnedValue::Bytecode(b) => fmt::Debug::fmt(b, f),
+            OwnedValue::Function => f.write_str("function"),
+            OwnedValue::Userdata => f.write_str("userdata"),
+            OwnedValue::Thread => f.write_str("thread"),
+        }
+    }
+}
+
+/// Copies the value at the absolute stack `index`, `ancestors` holds the tables being copied.
+///
+/// Functions, userdata and threads are copied according to `options`, or replaced by markers.
+unsafe fn snapshot(
+    raw: NonNull<sys::lua_State>,
+    index: libc::c_int,
+    ancestors: &mut Vec<*const libc::c_void>,
+    options: Option<&TransferOptions>,
+) -> LuaResult<OwnedValue> {
+    let l = raw.as_ptr();
+    Ok(match sys::lua_type(l, index) {
+        sys::LUA_TBOOLEAN => OwnedValue::Boolean(sys::lua_toboolean(l, index) != 0),
+        sys::LUA_TNUMBER if sys::lua_isinteger(l, index) != 0 => {
+            OwnedValue::Integer(sys::lua_tointeger(l, index))
+        }
+        sys::LUA_TNUMBER => OwnedValue::Number(LuaNumber::from(sys::lua_tonumber(l, index))),
+        sys::LUA_TSTRING => {
+            let mut len = 0usize;
+            let s = sys::lua_tolstring(l, index, &mut len);
+            OwnedValue::String(slice::from_raw_parts(s as *const u8, len).to_vec())
+        }
+        sys::LUA_TLIGHTUSERDATA => OwnedValue::LightUserdata(sys::lua_touserdata(l, index)),
+        sys::LUA_TTABLE => {
+            let table = sys::lua_topointer(l, index);
+            if ancestors.contains(&table) {
+                return Err(Error::new(
+                    ErrorKind::Conversion,
+                    Some("cannot copy a table that contains itself".to_owned()),
+                ));
+            }
+            reserve(raw, 2)?;
+            ancestors.push(table);
+            let mut pairs: Vec<(OwnedValue, OwnedValue)> = Vec::new();
+            sys::lua_pushnil(l);
+            while sys::lua_next(l, index) != 0 {
+                let top = sys::lua_gettop(l);
+                let key = snapshot(raw, top - 1, ancestors, options)?;
+                let value = snapshot(raw, top, ancestors, options)?;
+                pairs.push((key, value));
+                sys::lua_pop(l, 1);
+            }
+            ancestors.pop();
+            OwnedValue::Table(pairs)
+        }
+        sys::LUA_TFUNCTION => match options {
+            None => OwnedValue::Function,
+            Some(options) if options.dump_functions => dump_function(raw, index, options)?,
+            Some(_) => return Err(transfer_error("a function without dumping functions")),
+        },
+        sys::LUA_TUSERDATA => match options {
+            None => OwnedValue::Userdata,
+            Some(options) => transfer_userdata(raw, index, ancestors, options)?,
+        },
+        sys::LUA_TTHREAD => match options {
+            None => OwnedValue::Thread,
+            Some(_) => return Err(transfer_error("a thread")),
+        },
+        _ => OwnedValue::Nil,
+    })
+}
+
+fn transfer_error(what: &str) -> Error {
+    Error::new(
+        ErrorKind::Conversion,
+        Some(format!("cannot transfer {}", what)),
+    )
+}
+
+/// Dumps the function at `index`, whose only upvalue may be the global `_ENV`.
+unsafe fn dump_function(
+    raw: NonNull<sys::lua_State>,
+    index: libc::c_int,
+    // only used to strip debug information, which Lua 5.3 introduced
+    #[cfg_attr(not(LUA_VERSION = "5.3"), allow(unused_variables))] options: &TransferOptions,
+) -> LuaResult<OwnedValue> {
+    unsafe extern "C" fn write(
+        _: *mut sys::lua_State,
+        p: *const libc::c_void,
+        sz: usize,
+        ud: *mut libc::c_void,
+    ) -> libc::c_int {
+        (*(ud as *mut Vec<u8>)).extend_from_slice(slice::from_raw_parts(p as *const u8, sz));
+        0
+    }
+
+    let l = raw.as_ptr();
+    if sys::lua_iscfunction(l, index) != 0 {
+        return Err(transfer_error("a C function"));
+    }
+    reserve(raw, 2)?;
+    let mut n = 1;
+    loop {
+        let name = sys::lua_getupvalue(l, index, n);
+        if name.is_null() {
+            break;
+        }
+        // loading the chunk sets its first upvalue to the globals of the other thread
+        sys::lua_pushglobaltable(l);
+        let is_env = n == 1
+            && CStr::from_ptr(name).to_bytes() == b"_ENV"
+            && sys::lua_rawequal(l, -1, -2) != 0;
+        sys::lua_pop(l, 2);
+        if !is_env {
+            return Err(transfer_error("a function with upvalues"));
+        }
+        n += 1;
+    }
+
+    let mut bytecode = Vec::new();
+    sys::lua_pushvalue(l, index);
+    let data = &mut bytecode as *mut Vec<u8> as *mut libc::c_void;
+    #[cfg(LUA_VERSION = "5.3")]
+    sys::lua_dump(l, Some(write), data, options.strip as libc::c_int);
+    #[cfg(not(LUA_VERSION = "5.3"))]
+    sys::lua_dump(l, Some(write), data);
+    sys::lua_pop(l, 1);
+    Ok(OwnedValue::Bytecode(Bytecode(bytecode)))
+}
+
+/// Copies the value returned by the `__transfer` metamethod of the userdata at `index`.
+unsafe fn transfer_userdata(
+    raw: NonNull<sys::lua_State>,
+    index: libc::c_int,
+    ancestors: &mut Vec<*const libc::c_void>,
+    options: &TransferOptions,
+) -> LuaResult<OwnedValue> {
+    let l = raw.as_ptr();
+    reserve(raw, 2)?;
+    if sys::luaL_getmetafield(l, index, b"__transfer\0".as_ptr() as *const _) == sys::LUA_TNIL {
+        return Err(transfer_error("a userdata without a __transfer metamethod"));
+    }
+    sys::lua_pushvalue(l, index);
+    let code = sys::lua_pcall(l, 1, 1, 0);
+    ThreadRef::from_raw(raw).get_error(code)?;
+
+    // a userdata returning itself is detected as a cycle
+    let userdata = sys::lua_topointer(l, index);
+    if ancestors.contains(&userdata) {
+        return Err(transfer_error("a userdata whose __transfer returns itself"));
+    }
+    ancestors.push(userdata);
+    let value = snapshot(raw, sys::lua_gettop(l), ancestors, Some(options))?;
+    ancestors.pop();
+    sys::lua_pop(l, 1);
+    Ok(value)
+}
+
+/// Options for copying values that are transferred between threads.
+///
+/// See [`OwnedValue::transferable`].
+///
+/// [`OwnedValue::transferable`]: enum.OwnedValue.html#method.transferable
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TransferOptions {
+    dump_functions: bool,
+    strip: bool,
+}
+
+impl TransferOptions {
+    /// Creates the default options: functions are not transferred.
+    #[inline]
+    pub fn new() -> TransferOptions {
+        TransferOptions::default()
+    }
+
+    /// Sets whether Lua functions are dumped as bytecode.
+    ///
+    /// Only functions without upvalues, other than the global `_ENV`, can be dumped.
+    /// The transferred functions use the globals of the receiving thread.
+    #[inline]
+    pub fn dump_functions(mut self, dump_functions: bool) -> TransferOptions {
+        self.dump_functions = dump_functions;
+        self
+    }
+
+    /// Sets whether debug information is removed from dumped functions.
+    ///
+    /// Ignored before Lua 5.3, which always keeps it.
+    #[inline]
+    pub fn strip_debug_info(mut self, strip: bool) -> TransferOptions {
+        self.strip = strip;
+        self
+    }
+}
+
+impl OwnedValue {
+    /// Copies the value at `index` so that it can be pushed into another thread.
+    ///
+    /// Unlike [`from_lua`], values that cannot be copied are errors of kind
+    /// [`ErrorKind::Conversion`] instead of markers:
+    /// - Lua functions are dumped if enabled by `options`, C functions cannot be copied.
+    /// - Full userdata must have a `__transfer` metamethod,
+    ///   the value it returns is copied in place of the userdata.
+    /// - Threads cannot be copied.
+    ///
+    /// [`from_lua`]: trait.FromLua.html#tymethod.from_lua
+    /// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+    pub fn transferable(
+        thread: &mut Thread,
+        index: libc::c_int,
+        options: &TransferOptions,
+    ) -> LuaResult<OwnedValue> {
+        let mut guard = StackGuard::new(thread);
+        let raw = guard.as_raw();
+        unsafe {
+            let index = sys::lua_absindex(raw.as_ptr(), index);
+            snapshot(raw, index, &mut Vec::new(), Some(options))
+        }
+    }
+}
+
+/// Copies any value, returns an error of kind [`ErrorKind::Conversion`]
+/// if a table contains itself.
+///
+/// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+impl FromLua for OwnedValue {
+    fn from_lua(thread: &mut Thread, index: libc::c_int) -> LuaResult<OwnedValue> {
+        // the table traversal is abandoned on error, the guard discards its keys
+        let mut guard = StackGuard::new(thread);
+        unsafe { snapshot(guard.as_raw(), index, &mut Vec::new(), None) }
+    }
+}
+
+/// Returns an error of kind [`ErrorKind::Conversion`] for opaque markers
+/// and for table keys that are `nil` or NaN.
+///
+/// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+impl Pushable for OwnedValue {
+    fn push(&self, mut pusher: Pusher) -> LuaResult<()> {
+        match self {
+            OwnedValue::Nil => LuaNil.push(pusher),
+            OwnedValue::Boolean(b) => b.push(pusher),
+            OwnedValue::Integer(n) => n.push(pusher),
+            OwnedValue::Number(n) => n.push(pusher),
+            OwnedValue::String(s) => s.push(pusher),
+            OwnedValue::LightUserdata(p) => LightUserdata::new(*p).push(pusher),
+            OwnedValue::Bytecode(Bytecode(bytecode)) => {
+                pusher.reserve(1)?;
+                let code = unsafe {
+                    sys::luaL_loadbufferx(
+                        pusher.0.as_raw().as_ptr(),
+                        bytecode.as_ptr() as *const libc::c_char,
+                        bytecode.len(),
+                        b"=transfer\0".as_ptr() as *const _,
+                        b"b\0".as_ptr() as *const _,
+                    )
+                };
+                pusher.0.get_error(code)
+            }
+            OwnedValue::Table(pairs) => {
+                pusher.reserve(3)?;
+                let raw = pusher.0.as_raw();
+                unsafe {
+                    sys::lua_createtable(
+                        raw.as_ptr(),
+                        0,
+                        pairs.len().min(libc::c_int::MAX as usize) as libc::c_int,
+                    )
+                };
+                for (key, value) in pairs {
+                    match key {
+                        OwnedValue::Nil => return Err(invalid_key("nil")),
+                        OwnedValue::Number(n) if n.value.is_nan() => {
+                            return Err(invalid_key("NaN"))
+                        }
+                        _ => {}
+                    }
+                    key.push(pusher.reborrow())?;
+                    value.push(pusher.reborrow())?;
+                    unsafe { sys::lua_rawset(raw.as_ptr(), -3) };
+                }
+                Ok(())
+            }
+            OwnedValue::Function | OwnedValue::Userdata | OwnedValue::Thread => Err(Error::new(
+                ErrorKind::Conversion,
+                Some(format!(
+                    "cannot push a copy of a {}",
+                    self.value_type().name()
+                )),
+            )),
+        }
+    }
+}
+
+fn invalid_key(key: &str) -> Error {
+    Error::new(
+        ErrorKind::Conversion,
+        Some(format!("cannot push a table with a {} key", key)),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thread::LoadingMode;
+
+    fn eval(thread: &mut Thread, code: &str) -> LuaResult<OwnedValue> {
+        let values = thread.caller_load(code, None, LoadingMode::Text)?.call()?;
+        values.get_as::<OwnedValue>(0)
+    }
+
+    #[test]
+    fn test_owned_value_transfer() {
+        let value = Thread::spawn(move |thread| {
+            eval(
+                thread,
+                "return { 1, 2.5, 'three', nested = { [true] = false }, f = function() end }",
+            )
+            .unwrap()
+        })
+        .unwrap();
+        assert_eq!(
+            value,
+            OwnedValue::Table(vec![
+                (
+                    OwnedValue::String(b"nested".to_vec()),
+                    OwnedValue::Table(vec![(
+                        OwnedValue::Boolean(true),
+                        OwnedValue::Boolean(false)
+                    )])
+                ),
+                (
+                    OwnedValue::Integer(3),
+                    OwnedValue::String(b"three".to_vec())
+                ),
+                (OwnedValue::String(b"f".to_vec()), OwnedValue::Function),
+                (OwnedValue::Integer(1), OwnedValue::Integer(1)),
+                (OwnedValue::Integer(2), OwnedValue::Number(2.5.into())),
+            ])
+        );
+
+        Thread::spawn(move |thread| {
+            let top = unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) };
+            let err = thread
+                .caller_load("return ...", None, LoadingMode::Text)
+                .unwrap()
+                .arg(value.clone())
+                .call()
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+
+            let mut value = value;
+            if let OwnedValue::Table(pairs) = &mut value {
+                pairs.retain(|(_, v)| !v.is_opaque());
+            }
+            let values = thread
+                .caller_load(
+                    "local t = ... return t, t[3], t.nested[true]",
+                    None,
+                    LoadingMode::Text,
+                )
+                .unwrap()
+                .arg(value.clone())
+                .call()
+                .unwrap();
+            assert_eq!(values.get_as::<OwnedValue>(0).unwrap(), value);
+            assert_eq!(values.get_as::<String>(1).unwrap(), "three");
+            assert!(!values.get_as::<bool>(2).unwrap());
+            drop(values);
+            assert_eq!(unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }, top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_owned_value_cycle() {
+        Thread::spawn(move |thread| {
+            let top = unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) };
+            let err = eval(thread, "local t = { {} } t[1][1] = t return t").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+            assert_eq!(unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }, top);
+
+            // a table referenced twice is not a cycle
+            let value = eval(thread, "local t = {} return { t, t }").unwrap();
+            let empty = OwnedValue::Table(Vec::new());
+            assert_eq!(
+                value,
+                OwnedValue::Table(vec![
+                    (OwnedValue::Integer(1), empty.clone()),
+                    (OwnedValue::Integer(2), empty),
+                ])
+            );
+            assert_eq!(format!("{:?}", value), "{1: {}, 2: {}}");
+        })
+        .unwrap()
+    }
+}
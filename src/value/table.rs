@@ -0,0 +1,858 @@
+use crate::{
+    thread::{StackGuard, Thread, ThreadRef},
+    value::{
+        borrow_str, reserve, type_error, FromLua, FromLuaMulti, IntoLuaMulti, LuaStr, Metamethod,
+        Pushable, Pusher,
+    },
+    Error, ErrorKind, LuaResult,
+};
+
+use std::{fmt, iter::FusedIterator, marker::PhantomData};
+
+/// A table on the stack of a thread.
+///
+/// The table is kept in its own stack slot, which is popped when the `Table` is dropped.
+/// It borrows the thread mutably, so that the stack cannot be changed under its feet.
+///
+/// In a C function, the arguments can be accessed through a [`ThreadRef`]
+/// created from the `lua_State`.
+///
+/// # Examples
+/// ```
+/// use pollua::thread::{LoadingMode, Thread};
+///
+/// Thread::spawn(move |thread| {
+///     let mut values = thread
+///         .caller_load("return { 1, 2, 3, n = 3 }", None, LoadingMode::Text)?
+///         .call()?;
+///     let mut table = values.get_table(0)?;
+///     let sum = table.sequence_values::<i64>().sum::<Result<i64, _>>()?;
+///     assert_eq!(sum, 6);
+///     assert_eq!(table.raw_get::<_, i64>("n")?, 3);
+///     Ok::<_, pollua::Error>(())
+/// }).unwrap().unwrap()
+/// ```
+///
+/// [`ThreadRef`]: ../thread/struct.ThreadRef.html
+pub struct Table<'a> {
+    thread: StackGuard<'a>,
+    /// Absolute stack index of the table.
+    index: libc::c_int,
+}
+
+impl<'a> Table<'a> {
+    /// Pushes a new empty table.
+    pub fn new(thread: &'a mut Thread) -> LuaResult<Table<'a>> {
+        let mut thread = StackGuard::new(thread);
+        let raw = thread.as_raw();
+        reserve(raw, 1)?;
+        unsafe { sys::lua_newtable(raw.as_ptr()) };
+        let index = thread.top() + 1;
+        Ok(Table { thread, index })
+    }
+
+    /// Copies the table at `index` to a new stack slot.
+    ///
+    /// Returns an error of kind [`ErrorKind::Conversion`] if the value at `index`
+    /// is not a table.
+    ///
+    /// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+    pub fn from_stack(thread: &'a mut Thread, index: libc::c_int) -> LuaResult<Table<'a>> {
+        let mut thread = StackGuard::new(thread);
+        let raw = thread.as_raw();
+        if unsafe { sys::lua_type(raw.as_ptr(), index) } != sys::LUA_TTABLE {
+            return Err(type_error(&mut thread, index, "table"));
+        }
+        reserve(raw, 1)?;
+        unsafe { sys::lua_pushvalue(raw.as_ptr(), index) };
+        let index = thread.top() + 1;
+        Ok(Table { thread, index })
+    }
+
+    /// Returns the absolute stack index of the table.
+    #[inline]
+    pub fn stack_index(&self) -> libc::c_int {
+        self.index
+    }
+
+    /// Returns the length of the table without invoking the `__len` metamethod.
+    #[inline]
+    pub fn raw_len(&mut self) -> usize {
+        unsafe { sys::lua_rawlen(self.thread.as_raw().as_ptr(), self.index) }
+    }
+
+    /// Gets the value of `key` without invoking metamethods.
+    pub fn raw_get<K: Pushable, V: FromLua>(&mut self, key: K) -> LuaResult<V> {
+        let mut guard = StackGuard::new(&mut self.thread);
+        key.push(Pusher(ThreadRef::from_ref(&mut guard)))?;
+        let raw = guard.as_raw();
+        unsafe { sys::lua_rawget(raw.as_ptr(), self.index) };
+        let index = guard.top() + 1;
+        V::from_lua(&mut guard, index)
+    }
+
+    /// Borrows the string value of `key` without invoking metamethods or copying it.
+    ///
+    /// The string is kept alive by the table, which cannot be modified while it is borrowed.
+    /// Returns an error of kind [`ErrorKind::Conversion`] if the value is not a string,
+    /// numbers are not converted.
+    ///
+    /// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+    pub fn raw_get_str<K: Pushable>(&mut self, key: K) -> LuaResult<&LuaStr> {
+        let mut guard = StackGuard::new(&mut self.thread);
+        key.push(Pusher(ThreadRef::from_ref(&mut guard)))?;
+        let raw = guard.as_raw();
+        unsafe { sys::lua_rawget(raw.as_ptr(), self.index) };
+        let index = guard.top() + 1;
+        unsafe { borrow_str(&mut guard, index) }
+    }
+
+    /// Sets the value of `key` without invoking metamethods.
+    ///
+    /// Returns an error of kind [`ErrorKind::Runtime`] if `key` is `nil` or NaN.
+    ///
+    /// [`ErrorKind::Runtime`]: ../enum.ErrorKind.html#variant.Runtime
+    pub fn raw_set<K: Pushable, V: Pushable>(&mut self, key: K, value: V) -> LuaResult<()> {
+        let mut guard = StackGuard::new(&mut self.thread);
+        key.push(Pusher(ThreadRef::from_ref(&mut guard)))?;
+        let raw = guard.as_raw();
+        let valid = unsafe {
+            match sys::lua_type(raw.as_ptr(), -1) {
+                sys::LUA_TNIL => false,
+                sys::LUA_TNUMBER => {
+                    let n = sys::lua_tonumber(raw.as_ptr(), -1);
+                    !n.is_nan()
+                }
+                _ => true,
+            }
+        };
+        if !valid {
+            return Err(Error::new(
+                ErrorKind::Runtime,
+                Some("table index is nil or NaN".to_owned()),
+            ));
+        }
+        value.push(Pusher(ThreadRef::from_ref(&mut guard)))?;
+        unsafe { sys::lua_rawset(raw.as_ptr(), self.index) };
+        Ok(())
+    }
+
+    /// Returns an iterator over the keys and values of the table, in the order of `lua_next`,
+    /// without invoking the `__pairs` metamethod.
+    ///
+    /// Each pair is converted with [`FromLua`], a pair that cannot be converted
+    /// is yielded as an error and the traversal goes on.
+    ///
+    /// [`FromLua`]: trait.FromLua.html
+    #[inline]
+    pub fn pairs<K: FromLua, V: FromLua>(&mut self) -> Pairs<'_, K, V> {
+        Pairs {
+            table: self.index,
+            thread: StackGuard::new(&mut self.thread),
+            state: PairsState::Start,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the keys and values of the table like the `pairs` function,
+    /// using the `__pairs` metamethod if there is one.
+    ///
+    /// Errors raised by the metamethod or by the iterator function it returns
+    /// are yielded and end the iteration.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::thread::{LoadingMode, Thread};
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     unsafe { pollua::sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+    ///     let mut values = thread
+    ///         .caller_load(
+    ///             "return setmetatable({}, { __pairs = function(t)
+    ///                 return function(_, k) if not k then return 'only', 1 end end, t, nil
+    ///             end })",
+    ///             None,
+    ///             LoadingMode::Text,
+    ///         )?
+    ///         .call()?;
+    ///     let mut table = values.get_table(0)?;
+    ///     let pairs = table.meta_pairs::<String, i64>()?.collect::<Result<Vec<_>, _>>()?;
+    ///     assert_eq!(pairs, [("only".to_owned(), 1)]);
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    pub fn meta_pairs<K: FromLua, V: FromLua>(&mut self) -> LuaResult<Pairs<'_, K, V>> {
+        let raw = self.thread.as_raw();
+        let l = raw.as_ptr();
+        reserve(raw, 4)?;
+        let top = unsafe { sys::lua_gettop(l) };
+        if unsafe { sys::luaL_getmetafield(l, self.index, b"__pairs\0".as_ptr() as *const _) }
+            == sys::LUA_TNIL
+        {
+            return Ok(self.pairs());
+        }
+        let mut thread = StackGuard::with_top(ThreadRef::from_ref(&mut self.thread), top);
+        unsafe {
+            sys::lua_pushvalue(l, self.index);
+            let status = sys::lua_pcall(l, 1, 3, 0);
+            thread.get_error(status)?;
+        }
+        Ok(Pairs {
+            thread,
+            table: self.index,
+            state: PairsState::Meta,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns an iterator over the values of the sequence part of the table,
+    /// from `1` to its raw length, without invoking metamethods.
+    ///
+    /// Each value is converted with [`FromLua`], a value that cannot be converted
+    /// is yielded as an error and the traversal goes on.
+    ///
+    /// [`FromLua`]: trait.FromLua.html
+    #[inline]
+    pub fn sequence_values<V: FromLua>(&mut self) -> SequenceValues<'_, V> {
+        let len = self.raw_len() as sys::lua_Integer;
+        SequenceValues {
+            thread: ThreadRef::from_ref(&mut self.thread),
+            table: self.index,
+            next: 1,
+            len,
+            marker: PhantomData,
+        }
+    }
+
+    /// Returns the metatable of the table, if it has one.
+    pub fn metatable(&mut self) -> LuaResult<Option<Table<'_>>> {
+        let raw = self.thread.as_raw();
+        reserve(raw, 1)?;
+        let top = unsafe { sys::lua_gettop(raw.as_ptr()) };
+        if unsafe { sys::lua_getmetatable(raw.as_ptr(), self.index) } == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Table {
+            thread: StackGuard::with_top(ThreadRef::from_ref(&mut self.thread), top),
+            index: top + 1,
+        }))
+    }
+
+    /// Sets the metatable of the table, `metatable` must push a table, or `nil` to remove it.
+    ///
+    /// Returns an error of kind [`ErrorKind::Conversion`] if `metatable` pushes another type.
+    ///
+    /// [`ErrorKind::Conversion`]: ../enum.ErrorKind.html#variant.Conversion
+    pub fn set_metatable<M: Pushable>(&mut self, metatable: M) -> LuaResult<()> {
+        let mut guard = StackGuard::new(&mut self.thread);
+        metatable.push(Pusher(ThreadRef::from_ref(&mut guard)))?;
+        let raw = guard.as_raw();
+        match unsafe { sys::lua_type(raw.as_ptr(), -1) } {
+            sys::LUA_TTABLE | sys::LUA_TNIL => {}
+            _ => return Err(type_error(&mut guard, -1, "table or nil")),
+        }
+        unsafe { sys::lua_setmetatable(raw.as_ptr(), self.index) };
+        Ok(())
+    }
+
+    /// Sets the metatable of the table to a new empty table, and returns it.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::{
+    ///     thread::{LoadingMode, Thread},
+    ///     value::{Metamethod, OwnedValue},
+    /// };
+    ///
+    /// Thread::spawn(move |thread| {
+    ///     // undefined globals are resolved by a Rust closure
+    ///     thread
+    ///         .globals()?
+    ///         .new_metatable()?
+    ///         .set_metamethod(Metamethod::Index, |(_, name): (OwnedValue, String)| {
+    ///             Ok(name.to_uppercase())
+    ///         })?;
+    ///     let values = thread
+    ///         .caller_load("return hello", None, LoadingMode::Text)?
+    ///         .call()?;
+    ///     assert_eq!(values.get_as::<String>(0)?, "HELLO");
+    ///     Ok::<_, pollua::Error>(())
+    /// }).unwrap().unwrap()
+    /// ```
+    pub fn new_metatable(&mut self) -> LuaResult<Table<'_>> {
+        let raw = self.thread.as_raw();
+        reserve(raw, 2)?;
+        let l = raw.as_ptr();
+        let top = unsafe { sys::lua_gettop(l) };
+        unsafe {
+            sys::lua_newtable(l);
+            sys::lua_pushvalue(l, -1);
+            sys::lua_setmetatable(l, self.index);
+        }
+        Ok(Table {
+            thread: StackGuard::with_top(ThreadRef::from_ref(&mut self.thread), top),
+            index: top + 1,
+        })
+    }
+
+    /// Sets the metatable of the table to the one registered under `name`
+    /// by [`Thread::named_metatable`], like `luaL_setmetatable`.
+    ///
+    /// Returns an error of kind [`ErrorKind::Runtime`] if there is no such metatable.
+    ///
+    /// [`Thread::named_metatable`]: ../thread/struct.Thread.html#method.named_metatable
+    /// [`ErrorKind::Runtime`]: ../enum.ErrorKind.html#variant.Runtime
+    pub fn set_named_metatable(&mut self, name: &str) -> LuaResult<()> {
+        let mut guard = StackGuard::new(&mut self.thread);
+        let raw = guard.as_raw();
+        reserve(raw, 2)?;
+        let l = raw.as_ptr();
+        unsafe {
+            sys::lua_pushlstring(l, name.as_ptr() as *const libc::c_char, name.len());
+            if sys::lua_rawget(l, sys::LUA_REGISTRYINDEX) != sys::LUA_TTABLE {
+                return Err(Error::new(
+                    ErrorKind::Runtime,
+                    Some(format!("no metatable named '{}'", name)),
+                ));
+            }
+            sys::lua_setmetatable(l, self.index);
+        }
+        Ok(())
+    }
+
+    /// Gets the field `event` of the metatable of the table, or `None` if the table has
+    /// no metatable or the field is `nil`.
+    pub fn metafield<V: FromLua>(&mut self, event: Metamethod) -> LuaResult<Option<V>> {
+        let mut guard = StackGuard::new(&mut self.thread);
+        let raw = guard.as_raw();
+        reserve(raw, 2)?;
+        let field = event.c_name().as_ptr() as *const libc::c_char;
+        if unsafe { sys::luaL_getmetafield(raw.as_ptr(), self.index, field) } == sys::LUA_TNIL {
+            return Ok(None);
+        }
+        let index = guard.top() + 1;
+        V::from_lua(&mut guard, index).map(Some)
+    }
+
+    /// Calls the metamethod `event` of the table with the table as its only argument,
+    /// like `luaL_callmeta` but in protected mode.
+    ///
+    /// Returns `None` if the table has no such metamethod, otherwise the first result of the call.
+    pub fn call_metamethod<R: FromLua>(&mut self, event: Metamethod) -> LuaResult<Option<R>> {
+        let mut guard = StackGuard::new(&mut self.thread);
+        let raw = guard.as_raw();
+        reserve(raw, 2)?;
+        let l = raw.as_ptr();
+        let field = event.c_name().as_ptr() as *const libc::c_char;
+        if unsafe { sys::luaL_getmetafield(l, self.index, field) } == sys::LUA_TNIL {
+            return Ok(None);
+        }
+        unsafe {
+            sys::lua_pushvalue(l, self.index);
+            let status = sys::lua_pcall(l, 1, 1, 0);
+            guard.get_error(status)?;
+        }
+        let index = guard.top() + 1;
+        R::from_lua(&mut guard, index).map(Some)
+    }
+
+    /// Sets the field `event` of the table, used as a metatable, to a Lua function that calls `f`.
+    ///
+    /// The arguments are converted with [`FromLuaMulti`] and the results with [`IntoLuaMulti`].
+    /// Errors returned by `f`, as well as panics, are raised as Lua errors.
+    ///
+    /// [`FromLuaMulti`]: trait.FromLuaMulti.html
+    /// [`IntoLuaMulti`]: trait.IntoLuaMulti.html
+    pub fn set_metamethod<A, R, F>(&mut self, event: Metamethod, f: F) -> LuaResult<()>
+    where
+        A: FromLuaMulti,
+        R: IntoLuaMulti,
+        F: Fn(A) -> LuaResult<R> + Send + 'static,
+    {
+        let mut guard = StackGuard::new(&mut self.thread);
+        let raw = guard.as_raw();
+        reserve(raw, 1)?;
+        let name = event.name();
+        unsafe { sys::lua_pushlstring(raw.as_ptr(), name.as_ptr() as *const _, name.len()) };
+        guard.push_fn(f)?;
+        unsafe { sys::lua_rawset(raw.as_ptr(), self.index) };
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Table<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Table")
+            .field("thread", &self.thread)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl Thread {
+    /// Returns the table of the global variables.
+    pub fn globals(&mut self) -> LuaResult<Table<'_>> {
+        let mut thread = StackGuard::new(self);
+        let raw = thread.as_raw();
+        reserve(raw, 1)?;
+        unsafe { sys::lua_pushglobaltable(raw.as_ptr()) };
+        let index = thread.top() + 1;
+        Ok(Table { thread, index })
+    }
+
+    /// Returns the metatable registered under `name`, creating it with its `__name` field
+    /// if it does not exist yet, like `luaL_newmetatable`.
+    ///
+    /// Tables can use it with [`Table::set_named_metatable`].
+    ///
+    /// [`Table::set_named_metatable`]: ../value/struct.Table.html#method.set_named_metatable
+    pub fn named_metatable(&mut self, name: &str) -> LuaResult<Table<'_>> {
+        let mut thread = StackGuard::new(self);
+        let raw = thread.as_raw();
+        reserve(raw, 4)?;
+        let l = raw.as_ptr();
+        unsafe {
+            sys::lua_pushlstring(l, name.as_ptr() as *const libc::c_char, name.len());
+            if sys::lua_rawget(l, sys::LUA_REGISTRYINDEX) != sys::LUA_TTABLE {
+                sys::lua_pop(l, 1);
+                sys::lua_createtable(l, 0, 2);
+                sys::lua_pushlstring(l, name.as_ptr() as *const libc::c_char, name.len());
+                sys::lua_setfield(l, -2, Metamethod::Name.c_name().as_ptr() as *const _);
+                sys::lua_pushlstring(l, name.as_ptr() as *const libc::c_char, name.len());
+                sys::lua_pushvalue(l, -2);
+                sys::lua_rawset(l, sys::LUA_REGISTRYINDEX);
+            }
+        }
+        let index = thread.top() + 1;
+        Ok(Table { thread, index })
+    }
+}
+
+/// The traversal state of [`Pairs`].
+///
+/// [`Pairs`]: struct.Pairs.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PairsState {
+    /// `lua_next` traversal that has not started yet.
+    Start,
+    /// `lua_next` traversal, the last key is at the top of the stack.
+    Raw,
+    /// `__pairs` traversal, the iterator function, state and control variable are at the top.
+    Meta,
+    Done,
+}
+
+/// An iterator over the keys and values of a table.
+///
+/// This struct is created by the [`pairs`] and [`meta_pairs`] methods on [`Table`],
+/// the values it pushes during the traversal are popped when it is dropped.
+///
+/// [`pairs`]: struct.Table.html#method.pairs
+/// [`meta_pairs`]: struct.Table.html#method.meta_pairs
+/// [`Table`]: struct.Table.html
+pub struct Pairs<'t, K, V> {
+    thread: StackGuard<'t>,
+    table: libc::c_int,
+    state: PairsState,
+    marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: FromLua, V: FromLua> Pairs<'_, K, V> {
+    /// Converts the key and the value at the top of the stack.
+    fn convert(&mut self) -> LuaResult<(K, V)> {
+        let l = self.thread.as_raw().as_ptr();
+        let top = unsafe { sys::lua_gettop(l) };
+        // the key is converted from a copy, as it must not change for the next step
+        reserve(self.thread.as_raw(), 1)?;
+        unsafe { sys::lua_pushvalue(l, top - 1) };
+        let key = K::from_lua(&mut self.thread, top + 1);
+        unsafe { sys::lua_pop(l, 1) };
+        Ok((key?, V::from_lua(&mut self.thread, top)?))
+    }
+
+    fn next_raw(&mut self) -> Option<LuaResult<(K, V)>> {
+        let l = self.thread.as_raw().as_ptr();
+        if let Err(e) = reserve(self.thread.as_raw(), 2) {
+            self.state = PairsState::Done;
+            return Some(Err(e));
+        }
+        if unsafe { sys::lua_next(l, self.table) } == 0 {
+            self.state = PairsState::Done;
+            return None;
+        }
+        let pair = self.convert();
+        unsafe { sys::lua_pop(l, 1) };
+        Some(pair)
+    }
+
+    fn next_meta(&mut self) -> Option<LuaResult<(K, V)>> {
+        let l = self.thread.as_raw().as_ptr();
+        if let Err(e) = reserve(self.thread.as_raw(), 3) {
+            self.state = PairsState::Done;
+            return Some(Err(e));
+        }
+        unsafe {
+            for _ in 0..3 {
+                sys::lua_pushvalue(l, -3);
+            }
+            let status = sys::lua_pcall(l, 2, 2, 0);
+            if let Err(e) = self.thread.get_error(status) {
+                self.state = PairsState::Done;
+                return Some(Err(e));
+            }
+            if sys::lua_isnil(l, -2) != 0 {
+                self.state = PairsState::Done;
+                sys::lua_pop(l, 2);
+                return None;
+            }
+        }
+        let pair = self.convert();
+        unsafe {
+            // the key becomes the new control variable
+            sys::lua_pop(l, 1);
+            sys::lua_replace(l, -2);
+        }
+        Some(pair)
+    }
+}
+
+impl<K: FromLua, V: FromLua> Iterator for Pairs<'_, K, V> {
+    type Item = LuaResult<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.state {
+            PairsState::Start => {
+                if let Err(e) = reserve(self.thread.as_raw(), 1) {
+                    self.state = PairsState::Done;
+                    return Some(Err(e));
+                }
+                unsafe { sys::lua_pushnil(self.thread.as_raw().as_ptr()) };
+                self.state = PairsState::Raw;
+                self.next_raw()
+            }
+            PairsState::Raw => self.next_raw(),
+            PairsState::Meta => self.next_meta(),
+            PairsState::Done => None,
+        }
+    }
+}
+
+impl<K: FromLua, V: FromLua> FusedIterator for Pairs<'_, K, V> {}
+
+impl<K, V> fmt::Debug for Pairs<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pairs")
+            .field("thread", &self.thread)
+            .field("table", &self.table)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+/// An iterator over the values of the sequence part of a table.
+///
+/// This struct is created by the [`sequence_values`] method on [`Table`].
+///
+/// [`sequence_values`]: struct.Table.html#method.sequence_values
+/// [`Table`]: struct.Table.html
+pub struct SequenceValues<'t, V> {
+    thread: ThreadRef<'t>,
+    table: libc::c_int,
+    next: sys::lua_Integer,
+    len: sys::lua_Integer,
+    marker: PhantomData<fn() -> V>,
+}
+
+impl<V: FromLua> Iterator for SequenceValues<'_, V> {
+    type Item = LuaResult<V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next > self.len {
+            return None;
+        }
+        let n = self.next;
+        self.next += 1;
+        let raw = self.thread.as_raw();
+        Some(reserve(raw, 1).and_then(|()| {
+            unsafe { sys::lua_rawgeti(raw.as_ptr(), self.table, n) };
+            let top = unsafe { sys::lua_gettop(raw.as_ptr()) };
+            let value = V::from_lua(&mut self.thread, top);
+            unsafe { sys::lua_pop(raw.as_ptr(), 1) };
+            value
+        }))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len();
+        (len, Some(len))
+    }
+}
+
+impl<V: FromLua> ExactSizeIterator for SequenceValues<'_, V> {
+    #[inline]
+    fn len(&self) -> usize {
+        (self.len - self.next + 1).max(0) as usize
+    }
+}
+
+impl<V: FromLua> FusedIterator for SequenceValues<'_, V> {}
+
+impl<V> fmt::Debug for SequenceValues<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SequenceValues")
+            .field("thread", &self.thread)
+            .field("table", &self.table)
+            .field("next", &self.next)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        thread::LoadingMode,
+        value::{LuaNil, OwnedValue},
+    };
+    use std::collections::BTreeMap;
+
+    fn stack_top(thread: &mut Thread) -> libc::c_int {
+        unsafe { sys::lua_gettop(thread.as_raw().as_ptr()) }
+    }
+
+    #[test]
+    fn test_table_pairs() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            {
+                let mut values = thread
+                    .caller_load(
+                        "return { a = 1, b = 2, c = 3, [10] = 'ten' }",
+                        None,
+                        LoadingMode::Text,
+                    )
+                    .and_then(|c| c.call())
+                    .unwrap();
+                let mut table = values.get_table(0).unwrap();
+                let table_top = stack_top(&mut table.thread);
+
+                let (pairs, errors): (Vec<_>, Vec<_>) =
+                    table.pairs::<String, i64>().partition(Result::is_ok);
+                let pairs: BTreeMap<_, _> = pairs.into_iter().map(Result::unwrap).collect();
+                assert_eq!(pairs.len(), 3);
+                assert_eq!(pairs["b"], 2);
+                assert_eq!(errors.len(), 1);
+                assert_eq!(stack_top(&mut table.thread), table_top);
+
+                // the number key stays a number
+                let keys = table
+                    .pairs::<OwnedValue, OwnedValue>()
+                    .filter_map(Result::ok)
+                    .filter(|(k, _)| k.value_type() == crate::value::ValueType::Number)
+                    .count();
+                assert_eq!(keys, 1);
+
+                let first = table.pairs::<String, Option<i64>>().next();
+                assert!(first.is_some());
+                assert_eq!(stack_top(&mut table.thread), table_top);
+
+                assert_eq!(table.raw_get_str(10).unwrap().as_bytes(), b"ten");
+                assert_eq!(
+                    table.raw_get_str("a").unwrap_err().kind(),
+                    ErrorKind::Conversion
+                );
+                table.raw_set("d", 4).unwrap();
+                assert_eq!(table.raw_get::<_, i64>("d").unwrap(), 4);
+                assert_eq!(
+                    table.raw_set(LuaNil, 1).unwrap_err().kind(),
+                    ErrorKind::Runtime
+                );
+                assert_eq!(stack_top(&mut table.thread), table_top);
+            }
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_table_sequence() {
+        Thread::spawn(move |thread| {
+            let top = stack_top(thread);
+            {
+                let mut table = Table::new(thread).unwrap();
+                for (i, v) in [1.5, 2.5, 3.5].iter().enumerate() {
+                    table.raw_set(i as i64 + 1, *v).unwrap();
+                }
+                table.raw_set(4, "x").unwrap();
+                let mut values = table.sequence_values::<f64>();
+                assert_eq!(values.len(), 4);
+                assert_eq!(values.next().unwrap().unwrap(), 1.5);
+                let rest: Vec<_> = values.collect();
+                assert_eq!(rest.len(), 3);
+                assert_eq!(rest[2].as_ref().unwrap_err().kind(), ErrorKind::Conversion);
+                assert_eq!(table.raw_len(), 4);
+            }
+            assert_eq!(stack_top(thread), top);
+
+            let err = Table::from_stack(thread, top + 1).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Conversion);
+            let mut globals = thread.globals().unwrap();
+            globals.raw_set("answer", 42).unwrap();
+            assert_eq!(globals.raw_get::<_, i32>("answer").unwrap(), 42);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_table_meta_pairs() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let top = stack_top(thread);
+            {
+                let mut values = thread
+                    .caller_load(
+                        "local keys = { 'x', 'y', 'z' }
+                         local proxy = setmetatable({}, { __pairs = function(t)
+                             return function(_, k)
+                                 local i = (k and k:byte() - 119 or 0) + 1
+                                 if keys[i] then return keys[i], i end
+                             end, t, nil
+                         end })
+                         local failing = setmetatable({}, { __pairs = function(t)
+                             return function() error('broken iterator') end, t, nil
+                         end })
+                         return proxy, failing, { 1 }",
+                        None,
+                        LoadingMode::Text,
+                    )
+                    .and_then(|c| c.call())
+                    .unwrap();
+                {
+                    let mut proxy = values.get_table(0).unwrap();
+                    assert_eq!(proxy.pairs::<String, i64>().count(), 0);
+                    let pairs: Vec<_> = proxy
+                        .meta_pairs::<String, i64>()
+                        .unwrap()
+                        .map(Result::unwrap)
+                        .collect();
+                    assert_eq!(
+                        pairs,
+                        [
+                            ("x".to_owned(), 1),
+                            ("y".to_owned(), 2),
+                            ("z".to_owned(), 3)
+                        ]
+                    );
+                    let mut pairs = proxy.meta_pairs::<String, i64>().unwrap();
+                    pairs.next().unwrap().unwrap();
+                }
+                {
+                    let mut failing = values.get_table(1).unwrap();
+                    let mut pairs = failing.meta_pairs::<String, i64>().unwrap();
+                    let err = pairs.next().unwrap().unwrap_err();
+                    assert!(err.msg().unwrap().contains("broken iterator"));
+                    assert!(pairs.next().is_none());
+                }
+                {
+                    let mut plain = values.get_table(2).unwrap();
+                    let pairs: Vec<_> = plain.meta_pairs::<i64, i64>().unwrap().collect();
+                    assert_eq!(pairs.len(), 1);
+                }
+                assert!(values.get_table(3).is_err());
+            }
+            assert_eq!(stack_top(thread), top);
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_table_metatable() {
+        Thread::spawn(move |thread| {
+            unsafe { sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+            let top = stack_top(thread);
+            {
+                let mut table = Table::new(thread).unwrap();
+                assert!(table.metatable().unwrap().is_none());
+                assert_eq!(table.metafield::<String>(Metamethod::Name).unwrap(), None);
+                {
+                    let mut meta = table.new_metatable().unwrap();
+                    meta.raw_set(Metamethod::Name.name(), "Proxy").unwrap();
+                    meta.set_metamethod(Metamethod::Len, |_: OwnedValue| Ok(42))
+                        .unwrap();
+                    meta.set_metamethod(Metamethod::ToString, |_: OwnedValue| {
+                        Err::<(), _>(Error::new(ErrorKind::Runtime, Some("no".to_owned())))
+                    })
+                    .unwrap();
+                }
+                let mut meta = table.metatable().unwrap().unwrap();
+                assert_eq!(meta.raw_get::<_, String>("__name").unwrap(), "Proxy");
+                drop(meta);
+                assert_eq!(
+                    table.metafield::<String>(Metamethod::Name).unwrap(),
+                    Some("Proxy".to_owned())
+                );
+                assert_eq!(
+                    table.call_metamethod::<i64>(Metamethod::Len).unwrap(),
+                    Some(42)
+                );
+                let err = table
+                    .call_metamethod::<String>(Metamethod::ToString)
+                    .unwrap_err();
+                assert_eq!(err.msg(), Some("no"));
+                assert_eq!(
+                    table.call_metamethod::<i64>(Metamethod::Call).unwrap(),
+                    None
+                );
+
+                assert_eq!(
+                    table.set_metatable(1).unwrap_err().kind(),
+                    ErrorKind::Conversion
+                );
+                table.set_metatable(LuaNil).unwrap();
+                assert!(table.metatable().unwrap().is_none());
+                assert_eq!(
+                    table.set_named_metatable("Point").unwrap_err().kind(),
+                    ErrorKind::Runtime
+                );
+            }
+            assert_eq!(stack_top(thread), top);
+
+            {
+                let mut meta = thread.named_metatable("Point").unwrap();
+                meta.set_metamethod(Metamethod::Add, |(a, b): (i64, OwnedValue)| {
+                    Ok(format!("{} + {:?}", a, b.value_type()))
+                })
+                .unwrap();
+            }
+            let mut globals = thread.globals().unwrap();
+            globals.set_named_metatable("Point").unwrap();
+            assert_eq!(
+                globals.metafield::<String>(Metamethod::Name).unwrap(),
+                Some("Point".to_owned())
+            );
+            drop(globals);
+            assert_eq!(stack_top(thread), top);
+            let values = thread
+                .caller_load(
+                    "return 1 + setmetatable({}, getmetatable(_G))",
+                    None,
+                    LoadingMode::Text,
+                )
+                .and_then(|c| c.call())
+                .unwrap();
+            assert_eq!(values.get_as::<String>(0).unwrap(), "1 + Table");
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_metamethod_names() {
+        for &event in Metamethod::ALL {
+            assert_eq!(event.name().parse(), Ok(event));
+            assert_eq!(event.c_name().last(), Some(&0));
+        }
+        assert!("__foo".parse::<Metamethod>().is_err());
+    }
+}
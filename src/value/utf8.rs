@@ -0,0 +1,202 @@
+use crate::value::LuaStr;
+
+use std::{error, fmt, iter::FusedIterator};
+
+/// Largest code point accepted by Lua 5.3.
+const MAXUNICODE: u32 = 0x10_FFFF;
+
+/// Largest code point accepted by the lax mode of Lua 5.4.
+const MAXUTF: u32 = 0x7FFF_FFFF;
+
+/// An invalid UTF-8 sequence, with the error message of Lua's `utf8` library.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LuaUtf8Error {
+    position: usize,
+}
+
+impl LuaUtf8Error {
+    /// Returns the byte offset of the invalid sequence.
+    ///
+    /// `utf8.len` returns this offset plus one.
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for LuaUtf8Error {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid UTF-8 code at byte {}", self.position)
+    }
+}
+
+impl error::Error for LuaUtf8Error {}
+
+#[inline]
+fn is_continuation(c: u8) -> bool {
+    c & 0xC0 == 0x80
+}
+
+/// Decodes the sequence at the start of `s` like `utf8_decode` in `lutf8lib.c`,
+/// returns the code point and the length of the sequence.
+fn decode(s: &[u8], lax: bool) -> Option<(u32, usize)> {
+    // the smallest code point of each length, longer sequences are overlong
+    const LIMITS: [u32; 6] = [!0, 0x80, 0x800, 0x1_0000, 0x20_0000, 0x400_0000];
+    let mut c = u32::from(*s.first()?);
+    if c < 0x80 {
+        return Some((c, 1));
+    }
+    let mut res = 0;
+    let mut count = 0;
+    while c & 0x40 != 0 {
+        count += 1;
+        // the end of the string reads as the terminator of a C string
+        let cc = s.get(count).copied().unwrap_or(0);
+        if !is_continuation(cc) {
+            return None;
+        }
+        res = (res << 6) | u32::from(cc & 0x3F);
+        c <<= 1;
+    }
+    let (max_count, max) = if lax { (5, MAXUTF) } else { (3, MAXUNICODE) };
+    if count > max_count {
+        return None;
+    }
+    res |= (c & 0x7F) << (count * 5);
+    if res > max || res < LIMITS[count] {
+        return None;
+    }
+    Some((res, count + 1))
+}
+
+/// An iterator over the code points of a [`LuaStr`] and their byte offsets.
+///
+/// This struct is created by the [`code_points`] and [`code_points_lax`] methods on [`LuaStr`].
+/// An invalid sequence yields an error and ends the iteration.
+///
+/// [`LuaStr`]: struct.LuaStr.html
+/// [`code_points`]: struct.LuaStr.html#method.code_points
+/// [`code_points_lax`]: struct.LuaStr.html#method.code_points_lax
+#[derive(Debug, Clone)]
+pub struct CodePoints<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    lax: bool,
+}
+
+impl Iterator for CodePoints<'_> {
+    type Item = Result<(usize, u32), LuaUtf8Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = self.position;
+        let rest = self.bytes.get(position..).filter(|rest| !rest.is_empty())?;
+        match decode(rest, self.lax) {
+            Some((code, len)) => {
+                self.position += len;
+                Some(Ok((position, code)))
+            }
+            None => {
+                self.position = self.bytes.len() + 1;
+                Some(Err(LuaUtf8Error { position }))
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.bytes.len().saturating_sub(self.position);
+        (len.div_ceil(6), Some(len))
+    }
+}
+
+impl FusedIterator for CodePoints<'_> {}
+
+impl LuaStr {
+    /// Returns an iterator over the code points and their byte offsets,
+    /// following the rules of the `utf8` library of Lua 5.3.
+    ///
+    /// Lua 5.3 accepts sequences of up to 4 bytes encoding at most `U+10FFFF`,
+    /// including surrogates, and rejects overlong encodings.
+    ///
+    /// # Examples
+    /// ```
+    /// use pollua::value::LuaStr;
+    ///
+    /// let s = LuaStr::from_bytes("añ€");
+    /// let codes = s.code_points().collect::<Result<Vec<_>, _>>().unwrap();
+    /// assert_eq!(codes, [(0, 0x61), (1, 0xF1), (3, 0x20AC)]);
+    /// assert_eq!(LuaStr::from_bytes(b"a\xFF").utf8_len().unwrap_err().position(), 1);
+    /// ```
+    #[inline]
+    pub fn code_points(&self) -> CodePoints<'_> {
+        CodePoints {
+            bytes: self.as_bytes(),
+            position: 0,
+            lax: false,
+        }
+    }
+
+    /// Returns an iterator over the code points and their byte offsets,
+    /// following the lax rules of the `utf8` library of Lua 5.4.
+    ///
+    /// These accept the original UTF-8 encoding, sequences of up to 6 bytes
+    /// encoding at most `0x7FFFFFFF`.
+    #[inline]
+    pub fn code_points_lax(&self) -> CodePoints<'_> {
+        CodePoints {
+            bytes: self.as_bytes(),
+            position: 0,
+            lax: true,
+        }
+    }
+
+    /// Returns the number of code points like `utf8.len`,
+    /// or the position of the first invalid sequence.
+    pub fn utf8_len(&self) -> Result<usize, LuaUtf8Error> {
+        self.code_points()
+            .try_fold(0, |len, code| code.map(|_| len + 1))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn codes(bytes: &[u8], lax: bool) -> Result<Vec<u32>, usize> {
+        let s = LuaStr::from_bytes(bytes);
+        let codes = if lax {
+            s.code_points_lax()
+        } else {
+            s.code_points()
+        };
+        codes
+            .map(|code| code.map(|(_, code)| code).map_err(|e| e.position()))
+            .collect()
+    }
+
+    #[test]
+    fn test_code_points() {
+        assert_eq!(
+            codes("héllo €𝄞".as_bytes(), false),
+            Ok("héllo €𝄞".chars().map(u32::from).collect())
+        );
+        // surrogates are accepted
+        assert_eq!(codes(b"\xED\xA0\x80", false), Ok(vec![0xD800]));
+        // overlong encodings, stray continuations and truncated sequences are not
+        assert_eq!(codes(b"\xC0\x80", false), Err(0));
+        assert_eq!(codes(b"ab\x80", false), Err(2));
+        assert_eq!(codes(b"a\xE2\x82", false), Err(1));
+        assert_eq!(codes(b"\xF4\x90\x80\x80", false), Err(0));
+        // 5 and 6 byte sequences are only accepted in lax mode
+        assert_eq!(codes(b"\xF4\x90\x80\x80", true), Ok(vec![0x11_0000]));
+        assert_eq!(codes(b"\xF8\x88\x80\x80\x80", false), Err(0));
+        assert_eq!(codes(b"\xF8\x88\x80\x80\x80", true), Ok(vec![0x20_0000]));
+        assert_eq!(codes(b"\xFD\xBF\xBF\xBF\xBF\xBF", true), Ok(vec![MAXUTF]));
+        assert_eq!(codes(b"\xFE\xBF\xBF\xBF\xBF\xBF\xBF", true), Err(0));
+        assert_eq!(codes(b"\xFC\x83\xBF\xBF\xBF\xBF", true), Err(0));
+
+        assert_eq!(LuaStr::from_bytes("日本").utf8_len(), Ok(2));
+        assert_eq!(LuaStr::from_bytes(b"").utf8_len(), Ok(0));
+    }
+}
@@ -0,0 +1,256 @@
+#![cfg(feature = "derive")]
+
+use pollua::{
+    lua_function, lua_methods,
+    module::Module,
+    thread::{LoadingMode, Thread},
+    userdata,
+    value::{FromLua, LuaStr},
+    Error, ErrorKind, FromLua, IntoLua, LuaResult,
+};
+
+fn eval<T: FromLua>(thread: &mut Thread, code: &str) -> LuaResult<T> {
+    thread
+        .caller_load(code, None, LoadingMode::Text)?
+        .call()?
+        .get_as::<T>(0)
+}
+
+#[lua_function]
+fn area(width: f64, height: Option<f64>) -> f64 {
+    width * height.unwrap_or(width)
+}
+
+#[lua_function(name = "checked_div")]
+fn divide(a: i64, b: i64) -> LuaResult<i64> {
+    a.checked_div(b)
+        .ok_or_else(|| Error::new(ErrorKind::Runtime, Some("division by zero".to_owned())))
+}
+
+#[lua_function]
+fn count_lines(text: &LuaStr) -> u32 {
+    text.as_bytes().split(|&b| b == b'\n').count() as u32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Vector {
+    x: f64,
+    y: f64,
+}
+
+#[lua_methods(name = "Vec2")]
+impl Vector {
+    fn new(x: f64, y: f64) -> Vector {
+        Vector { x, y }
+    }
+
+    fn length(&self) -> f64 {
+        self.x.hypot(self.y)
+    }
+
+    fn scale(&mut self, factor: f64) {
+        self.x *= factor;
+        self.y *= factor;
+    }
+
+    fn add(&self, other: Vector) -> Self {
+        Vector::new(self.x + other.x, self.y + other.y)
+    }
+
+    fn __tostring(&self) -> String {
+        format!("({}, {})", self.x, self.y)
+    }
+}
+
+#[derive(Debug, PartialEq, FromLua, IntoLua)]
+struct Config {
+    name: String,
+    size: Size,
+    tags: Option<Tags>,
+}
+
+#[derive(Debug, PartialEq, FromLua, IntoLua)]
+struct Size {
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, PartialEq, FromLua, IntoLua)]
+struct Tags(String, String);
+
+#[derive(Debug, PartialEq, FromLua, IntoLua)]
+struct Id(u32);
+
+#[derive(Debug, PartialEq, FromLua, IntoLua)]
+enum Shape {
+    Empty,
+    Circle { radius: f64 },
+    Rect(f64, f64),
+    Named(String),
+}
+
+#[test]
+fn test_lua_function() {
+    Thread::spawn(move |thread| {
+        let module = Module::new("geometry")
+            .c_function("area", Some(lua_area))
+            .c_function("checked_div", Some(lua_divide))
+            .c_function("count_lines", Some(lua_count_lines));
+        thread.register_module(module).unwrap();
+
+        assert_eq!(
+            eval::<f64>(thread, "return geometry.area(2, 3)").unwrap(),
+            6.0
+        );
+        assert_eq!(eval::<f64>(thread, "return geometry.area(2)").unwrap(), 4.0);
+        let err = eval::<f64>(thread, "return geometry.area(2, 'x')").unwrap_err();
+        assert_eq!(
+            err.msg(),
+            Some("bad argument #2 'height' to 'area' (expected number, got string)")
+        );
+        let err = eval::<f64>(thread, "return geometry.area()").unwrap_err();
+        assert!(err
+            .msg()
+            .unwrap()
+            .contains("bad argument #1 'width' to 'area'"));
+
+        assert_eq!(
+            eval::<i64>(thread, "return geometry.checked_div(7, 2)").unwrap(),
+            3
+        );
+        let err = eval::<i64>(thread, "return geometry.checked_div(1, 0)").unwrap_err();
+        assert_eq!(err.msg(), Some("division by zero"));
+        let err = eval::<i64>(thread, "return geometry.checked_div(1.5, 1)").unwrap_err();
+        assert!(err.msg().unwrap().contains("'a' to 'checked_div'"));
+
+        assert_eq!(
+            eval::<u32>(thread, "return geometry.count_lines('a\\nb\\nc')").unwrap(),
+            3
+        );
+        let err = eval::<u32>(thread, "return geometry.count_lines(1)").unwrap_err();
+        assert_eq!(
+            err.msg(),
+            Some("bad argument #1 'text' to 'count_lines' (expected string, got number)")
+        );
+        let err = eval::<u32>(thread, "return geometry.count_lines()").unwrap_err();
+        assert!(err.msg().unwrap().contains("got no value"));
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_lua_methods() {
+    Thread::spawn(move |thread| {
+        unsafe { pollua::sys::luaL_openlibs(thread.as_raw().as_ptr()) };
+        thread.register_module(userdata::class::<Vector>()).unwrap();
+
+        assert_eq!(
+            eval::<String>(
+                thread,
+                "local v = Vec2.new(3, 4) v:scale(2) return ('%.1f %s'):format(v:length(), tostring(v))"
+            )
+            .unwrap(),
+            "10.0 (6, 8)"
+        );
+        assert_eq!(
+            eval::<Vector>(thread, "return Vec2.new(1, 2):add(Vec2.new(3, 4))").unwrap(),
+            Vector { x: 4.0, y: 6.0 }
+        );
+
+        let err = eval::<f64>(thread, "return Vec2.new(1, 2).length(42)").unwrap_err();
+        assert_eq!(
+            err.msg(),
+            Some("bad argument #1 'self' to 'length' (expected Vec2, got number)")
+        );
+        let err = eval::<f64>(thread, "return Vec2.new(1, 2):add({})").unwrap_err();
+        assert!(err
+            .msg()
+            .unwrap()
+            .contains("bad argument #2 'other' to 'add' (expected Vec2, got table)"));
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_derive_tables() {
+    Thread::spawn(move |thread| {
+        let config = eval::<Config>(
+            thread,
+            "return { name = 'main', size = { width = 640, height = 480 }, tags = { 'a', 'b' } }",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: "main".to_owned(),
+                size: Size {
+                    width: 640,
+                    height: 480,
+                },
+                tags: Some(Tags("a".to_owned(), "b".to_owned())),
+            }
+        );
+        let err = eval::<Config>(thread, "return { name = 'main', size = 1 }").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Conversion);
+        assert!(err.msg().unwrap().contains("field 'size' of Config"));
+
+        let shapes = thread
+            .caller_load(
+                "return 'Empty', { Circle = { radius = 1 } }, { Rect = { 2, 3 } }, \
+                 { Named = 'x' }, 'Square'",
+                None,
+                LoadingMode::Text,
+            )
+            .and_then(|c| c.call())
+            .unwrap();
+        assert_eq!(
+            shapes.unpack::<(Shape, Shape, Shape, Shape)>().unwrap(),
+            (
+                Shape::Empty,
+                Shape::Circle { radius: 1.0 },
+                Shape::Rect(2.0, 3.0),
+                Shape::Named("x".to_owned())
+            )
+        );
+        let err = shapes.get_as::<Shape>(4).unwrap_err();
+        assert_eq!(err.msg(), Some("unknown variant 'Square' of Shape"));
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_derive_round_trip() {
+    Thread::spawn(move |thread| {
+        let values = (
+            Config {
+                name: "round".to_owned(),
+                size: Size {
+                    width: 1,
+                    height: 2,
+                },
+                tags: None,
+            },
+            Id(7),
+            Shape::Circle { radius: 0.5 },
+            Shape::Empty,
+            Shape::Rect(1.0, 2.0),
+        );
+        let result = thread
+            .caller_load("return ...", None, LoadingMode::Text)
+            .unwrap()
+            .args(values)
+            .call()
+            .unwrap();
+        let (config, id, circle, empty, rect): (Config, Id, Shape, Shape, Shape) =
+            result.unpack().unwrap();
+        assert_eq!(config.name, "round");
+        assert_eq!(config.size.height, 2);
+        assert_eq!(id, Id(7));
+        assert_eq!(circle, Shape::Circle { radius: 0.5 });
+        assert_eq!(empty, Shape::Empty);
+        assert_eq!(rect, Shape::Rect(1.0, 2.0));
+        // newtypes are pushed as their inner value
+        assert_eq!(result.get_as::<u32>(1).unwrap(), 7);
+    })
+    .unwrap()
+}